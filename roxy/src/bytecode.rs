@@ -0,0 +1,174 @@
+use crate::expr::{MethodKind, SourceLocation};
+
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Copy, Clone)]
+pub enum UpvalueLoc {
+    /// a local slot of the immediately enclosing function
+    Local(usize),
+    /// an upvalue already captured by the immediately enclosing function
+    Upvalue(usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum Op {
+    Constant(usize),
+    Nil,
+    True,
+    False,
+    Pop,
+    /// pushes a copy of the value that many slots below the top
+    Dup(usize),
+    /// drops that many values from under the one on top
+    PopBelow(usize),
+    GetLocal(usize),
+    SetLocal(usize),
+    GetGlobal(usize),
+    DefineGlobal(usize),
+    SetGlobal(usize),
+    GetUpval(usize),
+    SetUpval(usize),
+    GetProperty(usize),
+    SetProperty(usize),
+    GetSuper(usize),
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Not,
+    Negate,
+    BitNot,
+    /// joins that many values into a string, each as `print` shows it
+    Interpolate(usize),
+    Print,
+    Jump(usize),
+    JumpIfFalse(usize),
+    Call(u8),
+    Closure(usize, Vec<UpvalueLoc>),
+    CloseUpvalue,
+    Return,
+    Class(usize),
+    /// copies the methods of the class below the one on top into it, popping the one on top;
+    /// the constant is the superclass's name, for the error if it isn't a class
+    Inherit(usize),
+    /// copies the methods of the class below the one on top into it, popping both; the
+    /// constant is the mixin's name
+    Mixin(usize),
+    Method(usize, MethodKind),
+    BuildList(usize),
+    /// builds a map from that many key and value pairs
+    BuildMap(usize),
+    Subscript,
+    SetItem,
+    /// replaces an instance on top of the stack with what its `iter()` returns
+    GetIter,
+    /// pushes the next element of the iteration whose source and position are held in the
+    /// local slot and the one after it, or jumps to the target once there are no more
+    Next(usize, usize),
+    /// installs a handler that catches errors raised before the matching `PopHandler`, jumping
+    /// to the target with the caught value pushed
+    PushHandler(usize),
+    /// like `PushHandler`, but the error is kept for the `Rethrow` that ends the finally block
+    PushFinally(usize),
+    PopHandler,
+    Throw,
+    Rethrow,
+    /// suspends the generator running in the current frame, handing the value on top of the
+    /// stack to whoever resumed it; the frame carries on with what it is resumed with pushed
+    Yield,
+}
+
+#[derive(Debug, Clone)]
+pub enum Constant {
+    Number(f64),
+    String(String),
+    Function(Rc<Function>),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constant::Number(n) => write!(f, "{}", n),
+            Constant::String(s) => write!(f, "\"{}\"", s),
+            Constant::Function(func) => write!(f, "<fn {}>", func.name),
+        }
+    }
+}
+
+/// A compiled function body. `locations[i]` is where in the source `code[i]` came from.
+#[derive(Debug, Default, Clone)]
+pub struct Chunk {
+    pub code: Vec<Op>,
+    pub locations: Vec<SourceLocation>,
+    pub constants: Vec<Constant>,
+}
+
+impl Chunk {
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        self.constants.push(constant);
+        self.constants.len() - 1
+    }
+
+    pub fn add_constant_string(&mut self, s: &str) -> usize {
+        let existing = self
+            .constants
+            .iter()
+            .position(|c| matches!(c, Constant::String(existing) if existing == s));
+        match existing {
+            Some(idx) => idx,
+            None => self.add_constant(Constant::String(s.to_string())),
+        }
+    }
+
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut out = format!("== {} ==\n", name);
+        for (idx, op) in self.code.iter().enumerate() {
+            let loc = &self.locations[idx];
+            let operand = match op {
+                Op::Constant(c)
+                | Op::GetGlobal(c)
+                | Op::DefineGlobal(c)
+                | Op::SetGlobal(c)
+                | Op::GetProperty(c)
+                | Op::SetProperty(c)
+                | Op::GetSuper(c)
+                | Op::Class(c)
+                | Op::Inherit(c)
+                | Op::Mixin(c)
+                | Op::Method(c, _)
+                | Op::Closure(c, _) => format!(" ({})", self.constants[*c]),
+                _ => String::new(),
+            };
+            out.push_str(&format!(
+                "{:04} {:>4}:{:<4} {:?}{}\n",
+                idx, loc.line, loc.col, op, operand
+            ));
+        }
+        for constant in &self.constants {
+            if let Constant::Function(func) = constant {
+                out.push_str(&func.chunk.disassemble(&func.name));
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Function {
+    pub arity: u8,
+    pub chunk: Chunk,
+    pub name: String,
+    /// calling it makes a generator that runs the chunk a `yield` at a time
+    pub generator: bool,
+}
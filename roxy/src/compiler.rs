@@ -0,0 +1,1022 @@
+use crate::bytecode::{Chunk, Constant, Function, Op, UpvalueLoc};
+use crate::expr;
+
+use std::rc::Rc;
+
+#[derive(Copy, Clone, PartialEq)]
+enum FunctionType {
+    Script,
+    Function,
+    Method,
+    Initializer,
+    Setter,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    is_captured: bool,
+}
+
+/// A loop around the code being compiled, for the `break` and `continue` statements in it.
+struct Loop {
+    label: Option<String>,
+    // the scope depth the loop body is in, whose locals a jump out of the body pops
+    scope_depth: usize,
+    // how many handlers were active around the loop
+    handlers: usize,
+    // where `continue` goes, if that has been compiled already; if not, the jumps waiting
+    // for it are in `continues`
+    start: Option<usize>,
+    continues: Vec<usize>,
+    breaks: Vec<usize>,
+}
+
+struct Level {
+    function: Function,
+    function_type: FunctionType,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueLoc>,
+    scope_depth: usize,
+    // one entry per handler active around the code being compiled, innermost last, holding
+    // the finally block a `return` has to run on its way out
+    handlers: Vec<Option<Vec<expr::Stmt>>>,
+    // innermost last
+    loops: Vec<Loop>,
+}
+
+struct Compiler {
+    levels: Vec<Level>,
+    location: expr::SourceLocation,
+}
+
+/// Compiles a program into the function the VM runs as its top-level script. Programs are
+/// expected to have passed `resolver::resolve` already, so this never fails.
+pub fn compile(stmts: &[expr::Stmt]) -> Function {
+    let mut compiler = Compiler {
+        levels: Vec::new(),
+        location: expr::SourceLocation { line: 1, col: 0 },
+    };
+    compiler.begin_function("script".to_string(), FunctionType::Script);
+    for stmt in stmts {
+        compiler.statement(stmt);
+    }
+    compiler.end_function()
+}
+
+impl Compiler {
+    fn level(&mut self) -> &mut Level {
+        self.levels.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.level().function.chunk
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let location = self.location;
+        let chunk = self.chunk();
+        chunk.code.push(op);
+        chunk.locations.push(location);
+        chunk.code.len() - 1
+    }
+
+    fn locate(&mut self, line: usize, col: i64) {
+        self.location = expr::SourceLocation { line, col };
+    }
+
+    fn patch_jump(&mut self, jump: usize) {
+        let target = self.chunk().code.len();
+        match &mut self.chunk().code[jump] {
+            Op::Jump(to)
+            | Op::JumpIfFalse(to)
+            | Op::PushHandler(to)
+            | Op::PushFinally(to)
+            | Op::Next(_, to) => *to = target,
+            op => panic!("internal compiler error: patching non-jump {:?}", op),
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &str) -> usize {
+        self.chunk().add_constant_string(name)
+    }
+
+    fn begin_function(&mut self, name: String, function_type: FunctionType) {
+        // slot zero holds the receiver for methods and the callee otherwise
+        let slot_zero = match function_type {
+            FunctionType::Method | FunctionType::Initializer | FunctionType::Setter => "this",
+            FunctionType::Script | FunctionType::Function => "",
+        };
+        self.levels.push(Level {
+            function: Function {
+                name,
+                ..Default::default()
+            },
+            function_type,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            handlers: Vec::new(),
+            loops: Vec::new(),
+        });
+    }
+
+    fn end_function(&mut self) -> Function {
+        self.emit_return();
+        self.levels.pop().unwrap().function
+    }
+
+    fn emit_return(&mut self) {
+        self.emit_implicit_return_value();
+        self.emit(Op::Return);
+    }
+
+    fn emit_implicit_return_value(&mut self) {
+        match self.level().function_type {
+            FunctionType::Initializer => self.emit(Op::GetLocal(0)),
+            // the copy `function` made of the value being assigned
+            FunctionType::Setter => self.emit(Op::GetLocal(2)),
+            _ => self.emit(Op::Nil),
+        };
+    }
+
+    /// Runs the finally blocks between a `return` and the end of its function, with the value
+    /// being returned on top of the stack.
+    fn unwind_handlers(&mut self) {
+        if self.level().handlers.is_empty() {
+            return;
+        }
+        self.begin_scope();
+        self.add_local("");
+        let slot = self.level().locals.len() - 1;
+        self.exit_handlers(0);
+        self.emit(Op::GetLocal(slot));
+        self.abandon_scope();
+    }
+
+    /// Removes the handlers beyond the first `keep`, innermost first, running the finally
+    /// blocks among them. Each handler is removed before its finally block runs, so an error
+    /// raised there goes to the handlers further out.
+    fn exit_handlers(&mut self, keep: usize) {
+        let handlers = std::mem::take(&mut self.level().handlers);
+        for (idx, handler) in handlers.iter().enumerate().skip(keep).rev() {
+            self.emit(Op::PopHandler);
+            if let Some(finally) = handler {
+                // a finally block only sees the handlers and loops around its try statement
+                self.level().handlers = handlers[..idx].to_vec();
+                let level = self.level();
+                let outside = level.loops.iter().take_while(|l| l.handlers <= idx).count();
+                let inside = level.loops.split_off(outside);
+                self.block(finally);
+                self.level().loops.extend(inside);
+            }
+        }
+        self.level().handlers = handlers;
+    }
+
+    fn begin_scope(&mut self) {
+        self.level().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let level = self.level();
+        level.scope_depth -= 1;
+        let depth = level.scope_depth;
+
+        while let Some(local) = self.level().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            if local.is_captured {
+                self.emit(Op::CloseUpvalue);
+            } else {
+                self.emit(Op::Pop);
+            }
+            self.level().locals.pop();
+        }
+    }
+
+    /// Leaves a scope whose end is never reached, so its locals need no popping.
+    fn abandon_scope(&mut self) {
+        let level = self.level();
+        level.scope_depth -= 1;
+        let depth = level.scope_depth;
+        level.locals.retain(|local| local.depth <= depth);
+    }
+
+    fn block(&mut self, stmts: &[expr::Stmt]) {
+        self.begin_scope();
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+        self.end_scope();
+    }
+
+    fn add_local(&mut self, name: &str) {
+        let depth = self.level().scope_depth;
+        self.level().locals.push(Local {
+            name: name.to_string(),
+            depth,
+            is_captured: false,
+        });
+    }
+
+    /// Declares `name` in the current scope. At global scope the value on top of the stack is
+    /// moved into a global; in a local scope it simply stays on the stack as the new local.
+    fn define_variable(&mut self, name: &str) {
+        if self.level().scope_depth > 0 {
+            self.add_local(name);
+            return;
+        }
+        let constant = self.identifier_constant(name);
+        self.emit(Op::DefineGlobal(constant));
+    }
+
+    fn resolve_local(level: &Level, name: &str) -> Option<usize> {
+        level.locals.iter().rposition(|local| local.name == name)
+    }
+
+    fn resolve_upvalue(&mut self, level_idx: usize, name: &str) -> Option<usize> {
+        if level_idx == 0 {
+            return None;
+        }
+
+        let enclosing = &mut self.levels[level_idx - 1];
+        if let Some(local) = Compiler::resolve_local(enclosing, name) {
+            enclosing.locals[local].is_captured = true;
+            return Some(self.add_upvalue(level_idx, UpvalueLoc::Local(local)));
+        }
+
+        self.resolve_upvalue(level_idx - 1, name)
+            .map(|upvalue| self.add_upvalue(level_idx, UpvalueLoc::Upvalue(upvalue)))
+    }
+
+    fn add_upvalue(&mut self, level_idx: usize, upvalue: UpvalueLoc) -> usize {
+        let upvalues = &mut self.levels[level_idx].upvalues;
+        let existing = upvalues
+            .iter()
+            .position(|existing| match (existing, &upvalue) {
+                (UpvalueLoc::Local(a), UpvalueLoc::Local(b)) => a == b,
+                (UpvalueLoc::Upvalue(a), UpvalueLoc::Upvalue(b)) => a == b,
+                _ => false,
+            });
+        match existing {
+            Some(idx) => idx,
+            None => {
+                upvalues.push(upvalue);
+                upvalues.len() - 1
+            }
+        }
+    }
+
+    fn get_variable(&mut self, name: &str) {
+        let level_idx = self.levels.len() - 1;
+        if let Some(local) = Compiler::resolve_local(self.level(), name) {
+            self.emit(Op::GetLocal(local));
+        } else if let Some(upvalue) = self.resolve_upvalue(level_idx, name) {
+            self.emit(Op::GetUpval(upvalue));
+        } else {
+            let constant = self.identifier_constant(name);
+            self.emit(Op::GetGlobal(constant));
+        }
+    }
+
+    fn set_variable(&mut self, name: &str) {
+        let level_idx = self.levels.len() - 1;
+        if let Some(local) = Compiler::resolve_local(self.level(), name) {
+            self.emit(Op::SetLocal(local));
+        } else if let Some(upvalue) = self.resolve_upvalue(level_idx, name) {
+            self.emit(Op::SetUpval(upvalue));
+        } else {
+            let constant = self.identifier_constant(name);
+            self.emit(Op::SetGlobal(constant));
+        }
+    }
+
+    fn statement(&mut self, stmt: &expr::Stmt) {
+        match stmt {
+            expr::Stmt::Expr(e) => {
+                self.expression(e);
+                self.emit(Op::Pop);
+            }
+            expr::Stmt::FunDecl(fundecl) => {
+                self.locate(fundecl.name.line, fundecl.name.col);
+                // mark the name initialized before compiling the body so it can recurse
+                if self.level().scope_depth > 0 {
+                    self.add_local(&fundecl.name.name);
+                    self.function(
+                        &fundecl.name.name,
+                        &fundecl.params,
+                        &fundecl.body,
+                        FunctionType::Function,
+                        fundecl.generator,
+                    );
+                } else {
+                    self.function(
+                        &fundecl.name.name,
+                        &fundecl.params,
+                        &fundecl.body,
+                        FunctionType::Function,
+                        fundecl.generator,
+                    );
+                    self.define_variable(&fundecl.name.name);
+                }
+            }
+            expr::Stmt::ClassDecl(classdecl) => self.class_decl(classdecl),
+            expr::Stmt::If(cond, then_branch, maybe_else_branch) => {
+                self.expression(cond);
+                let then_jump = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.statement(then_branch);
+                let else_jump = self.emit(Op::Jump(0));
+                self.patch_jump(then_jump);
+                self.emit(Op::Pop);
+                if let Some(else_branch) = maybe_else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            expr::Stmt::Print(_, e) => {
+                self.expression(e);
+                self.emit(Op::Print);
+            }
+            expr::Stmt::VarDecl(sym, _, maybe_initializer) => {
+                self.locate(sym.line, sym.col);
+                match maybe_initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.define_variable(&sym.name);
+            }
+            expr::Stmt::Block(_, stmts) => self.block(stmts),
+            expr::Stmt::Return(loc, maybe_res) => {
+                self.locate(loc.line, loc.col);
+                match maybe_res {
+                    Some(res) => self.expression(res),
+                    None => self.emit_implicit_return_value(),
+                }
+                self.unwind_handlers();
+                self.locate(loc.line, loc.col);
+                self.emit(Op::Return);
+            }
+            expr::Stmt::While(..) | expr::Stmt::For { .. } | expr::Stmt::ForIn { .. } => {
+                self.loop_statement(stmt, None)
+            }
+            expr::Stmt::Labeled(label, body) => self.loop_statement(body, Some(&label.name)),
+            expr::Stmt::Break(loc, label) => {
+                self.locate(loc.line, loc.col);
+                self.loop_jump(label, true);
+            }
+            expr::Stmt::Continue(loc, label) => {
+                self.locate(loc.line, loc.col);
+                self.loop_jump(label, false);
+            }
+            // modules have already run by the time the importing program does
+            expr::Stmt::Import(_) => {}
+            expr::Stmt::Throw(loc, e) => {
+                self.expression(e);
+                self.locate(loc.line, loc.col);
+                self.emit(Op::Throw);
+            }
+            expr::Stmt::Try {
+                location,
+                body,
+                catch,
+                finally,
+            } => {
+                self.locate(location.line, location.col);
+                self.try_statement(body, catch, finally);
+            }
+        }
+    }
+
+    /// Compiles a `while`, `for` or for-in loop, which `label` names if it is labeled.
+    fn loop_statement(&mut self, stmt: &expr::Stmt, label: Option<&str>) {
+        match stmt {
+            expr::Stmt::While(cond, body) => {
+                let loop_start = self.chunk().code.len();
+                self.expression(cond);
+                let exit_jump = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.begin_loop(label, Some(loop_start));
+                self.statement(body);
+                self.emit(Op::Jump(loop_start));
+                self.patch_jump(exit_jump);
+                self.emit(Op::Pop);
+                self.end_loop();
+            }
+            expr::Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.statement(initializer);
+                }
+                let loop_start = self.chunk().code.len();
+                let exit_jump = condition.as_ref().map(|condition| {
+                    self.expression(condition);
+                    let exit_jump = self.emit(Op::JumpIfFalse(0));
+                    self.emit(Op::Pop);
+                    exit_jump
+                });
+                // `continue` goes on to the increment, which comes after the body
+                self.begin_loop(label, None);
+                self.statement(body);
+                let continues = std::mem::take(&mut self.current_loop().continues);
+                for jump in continues {
+                    self.patch_jump(jump);
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                    self.emit(Op::Pop);
+                }
+                self.emit(Op::Jump(loop_start));
+                if let Some(exit_jump) = exit_jump {
+                    self.patch_jump(exit_jump);
+                    self.emit(Op::Pop);
+                }
+                self.end_loop();
+                self.end_scope();
+            }
+            expr::Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                let loc = iterable.location();
+                self.begin_scope();
+                self.expression(iterable);
+                self.locate(loc.line, loc.col);
+                self.emit(Op::GetIter);
+                self.add_local("");
+                let slot = self.level().locals.len() - 1;
+                let start = self.chunk().add_constant(Constant::Number(0.0));
+                self.emit(Op::Constant(start));
+                self.add_local("");
+
+                let loop_start = self.chunk().code.len();
+                self.locate(loc.line, loc.col);
+                let exit_jump = self.emit(Op::Next(slot, 0));
+                self.begin_loop(label, Some(loop_start));
+                // a scope per element, so closures capture each element separately
+                self.begin_scope();
+                self.add_local(&variable.name);
+                self.statement(body);
+                self.end_scope();
+                self.emit(Op::Jump(loop_start));
+                self.patch_jump(exit_jump);
+                self.end_loop();
+                self.end_scope();
+            }
+            _ => panic!("internal compiler error: {:?} is not a loop", stmt),
+        }
+    }
+
+    /// Starts the loop whose body is about to be compiled, at the current scope depth.
+    fn begin_loop(&mut self, label: Option<&str>, start: Option<usize>) {
+        let level = self.level();
+        let (scope_depth, handlers) = (level.scope_depth, level.handlers.len());
+        level.loops.push(Loop {
+            label: label.map(str::to_string),
+            scope_depth,
+            handlers,
+            start,
+            continues: Vec::new(),
+            breaks: Vec::new(),
+        });
+    }
+
+    /// Ends the innermost loop, making its `break`s jump to here.
+    fn end_loop(&mut self) {
+        let finished = self.level().loops.pop().unwrap();
+        for jump in finished.breaks {
+            self.patch_jump(jump);
+        }
+    }
+
+    fn current_loop(&mut self) -> &mut Loop {
+        self.level().loops.last_mut().unwrap()
+    }
+
+    /// A `break` (or a `continue`) out to the loop `label` names, or to the innermost loop.
+    /// The handlers and locals inside that loop's body are left behind on the way.
+    fn loop_jump(&mut self, label: &Option<expr::Symbol>, is_break: bool) {
+        let level = self.level();
+        // the resolver has made sure there is such a loop
+        let target = match label {
+            Some(label) => level
+                .loops
+                .iter()
+                .rposition(|l| l.label.as_ref() == Some(&label.name))
+                .unwrap(),
+            None => level.loops.len() - 1,
+        };
+        let (scope_depth, handlers) = (
+            level.loops[target].scope_depth,
+            level.loops[target].handlers,
+        );
+
+        self.exit_handlers(handlers);
+        // the locals stay declared for the rest of the body; they are only gone at runtime.
+        // Whether one is captured may not be known yet, since a closure further down the body
+        // can have captured it on an earlier time round an inner loop, so all are closed.
+        let locals = self.level().locals.iter().rev();
+        let inside = locals.take_while(|local| local.depth > scope_depth).count();
+        for _ in 0..inside {
+            self.emit(Op::CloseUpvalue);
+        }
+
+        let start = self.level().loops[target].start;
+        match (is_break, start) {
+            (false, Some(start)) => {
+                self.emit(Op::Jump(start));
+            }
+            (false, None) => {
+                let jump = self.emit(Op::Jump(0));
+                self.level().loops[target].continues.push(jump);
+            }
+            (true, _) => {
+                let jump = self.emit(Op::Jump(0));
+                self.level().loops[target].breaks.push(jump);
+            }
+        }
+    }
+
+    /// The finally block is compiled twice: once where the try and catch blocks fall through
+    /// to it, and once behind a handler that runs it for an error and then raises the error
+    /// again.
+    fn try_statement(
+        &mut self,
+        body: &[expr::Stmt],
+        catch: &Option<(expr::Symbol, Vec<expr::Stmt>)>,
+        finally: &Option<Vec<expr::Stmt>>,
+    ) {
+        let finally_handler = finally.as_ref().map(|finally| {
+            self.level().handlers.push(Some(finally.clone()));
+            self.emit(Op::PushFinally(0))
+        });
+
+        match catch {
+            Some((name, handler)) => {
+                self.level().handlers.push(None);
+                let catch_handler = self.emit(Op::PushHandler(0));
+                self.block(body);
+                self.emit(Op::PopHandler);
+                self.level().handlers.pop();
+                let end_jump = self.emit(Op::Jump(0));
+
+                self.patch_jump(catch_handler);
+                self.begin_scope();
+                self.add_local(&name.name);
+                for stmt in handler {
+                    self.statement(stmt);
+                }
+                self.end_scope();
+                self.patch_jump(end_jump);
+            }
+            None => self.block(body),
+        }
+
+        if let (Some(finally_handler), Some(finally)) = (finally_handler, finally) {
+            self.emit(Op::PopHandler);
+            self.level().handlers.pop();
+            self.block(finally);
+            let end_jump = self.emit(Op::Jump(0));
+
+            self.patch_jump(finally_handler);
+            self.begin_scope();
+            self.add_local("");
+            let slot = self.level().locals.len() - 1;
+            for stmt in finally {
+                self.statement(stmt);
+            }
+            self.emit(Op::GetLocal(slot));
+            self.emit(Op::Rethrow);
+            self.abandon_scope();
+            self.patch_jump(end_jump);
+        }
+    }
+
+    fn function(
+        &mut self,
+        name: &str,
+        params: &[expr::Param],
+        body: &[expr::Stmt],
+        function_type: FunctionType,
+        generator: bool,
+    ) {
+        self.begin_function(name.to_string(), function_type);
+        self.level().function.arity = params.len() as u8;
+        self.level().function.generator = generator;
+        self.begin_scope();
+        for param in params {
+            self.add_local(&param.name.name);
+        }
+        if function_type == FunctionType::Setter {
+            // an assignment evaluates to the value assigned, even if the setter changes its
+            // parameter
+            self.emit(Op::GetLocal(1));
+            self.add_local("");
+        }
+        for stmt in body {
+            self.statement(stmt);
+        }
+        let upvalues = std::mem::take(&mut self.level().upvalues);
+        let function = self.end_function();
+
+        let constant = self
+            .chunk()
+            .add_constant(Constant::Function(Rc::new(function)));
+        self.emit(Op::Closure(constant, upvalues));
+    }
+
+    fn class_decl(&mut self, classdecl: &expr::ClassDecl) {
+        let name = &classdecl.name.name;
+        self.locate(classdecl.name.line, classdecl.name.col);
+        let name_constant = self.identifier_constant(name);
+        self.emit(Op::Class(name_constant));
+        self.define_variable(name);
+
+        if let Some(superclass) = &classdecl.superclass {
+            self.locate(superclass.line, superclass.col);
+            self.get_variable(&superclass.name);
+            self.begin_scope();
+            self.add_local("super");
+            self.get_variable(name);
+            let superclass_constant = self.identifier_constant(&superclass.name);
+            self.emit(Op::Inherit(superclass_constant));
+        }
+
+        for mixin in &classdecl.mixins {
+            self.locate(mixin.line, mixin.col);
+            self.get_variable(&mixin.name);
+            self.get_variable(name);
+            let mixin_constant = self.identifier_constant(&mixin.name);
+            self.emit(Op::Mixin(mixin_constant));
+        }
+
+        self.get_variable(name);
+        for method in &classdecl.methods {
+            let decl = &method.decl;
+            self.locate(decl.name.line, decl.name.col);
+            let function_type = match method.kind {
+                expr::MethodKind::Method if decl.name.name == "init" => FunctionType::Initializer,
+                expr::MethodKind::Setter => FunctionType::Setter,
+                _ => FunctionType::Method,
+            };
+            let name = &decl.name.name;
+            self.function(
+                name,
+                &decl.params,
+                &decl.body,
+                function_type,
+                decl.generator,
+            );
+            let method_constant = self.identifier_constant(&decl.name.name);
+            self.emit(Op::Method(method_constant, method.kind));
+        }
+        self.emit(Op::Pop);
+
+        if classdecl.superclass.is_some() {
+            self.end_scope();
+        }
+    }
+
+    fn expression(&mut self, e: &expr::Expr) {
+        match e {
+            expr::Expr::Literal(_, lit) => match lit {
+                expr::Literal::Number(n) => {
+                    let constant = self.chunk().add_constant(Constant::Number(*n));
+                    self.emit(Op::Constant(constant));
+                }
+                expr::Literal::String(s) => {
+                    let constant = self.chunk().add_constant_string(s);
+                    self.emit(Op::Constant(constant));
+                }
+                expr::Literal::True => {
+                    self.emit(Op::True);
+                }
+                expr::Literal::False => {
+                    self.emit(Op::False);
+                }
+                expr::Literal::Nil => {
+                    self.emit(Op::Nil);
+                }
+            },
+            expr::Expr::This(loc) => {
+                self.locate(loc.line, loc.col);
+                self.get_variable("this");
+            }
+            expr::Expr::Unary(op, e) => {
+                self.expression(e);
+                self.locate(op.line, op.col);
+                match op.ty {
+                    expr::UnaryOpTy::Minus => self.emit(Op::Negate),
+                    expr::UnaryOpTy::Bang => self.emit(Op::Not),
+                    expr::UnaryOpTy::Tilde => self.emit(Op::BitNot),
+                };
+            }
+            expr::Expr::Binary(lhs, op, rhs) => {
+                self.expression(lhs);
+                self.expression(rhs);
+                self.locate(op.line, op.col);
+                self.binary_op(op.ty);
+            }
+            expr::Expr::Call(callee, loc, args) => {
+                self.expression(callee);
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.locate(loc.line, loc.col);
+                self.emit(Op::Call(args.len() as u8));
+            }
+            expr::Expr::Get(e, attr) => {
+                self.expression(e);
+                self.locate(attr.line, attr.col);
+                let constant = self.identifier_constant(&attr.name);
+                self.emit(Op::GetProperty(constant));
+            }
+            expr::Expr::Grouping(e) => self.expression(e),
+            expr::Expr::Variable(sym) => {
+                self.locate(sym.line, sym.col);
+                self.get_variable(&sym.name);
+            }
+            expr::Expr::Assign(sym, val) => {
+                self.expression(val);
+                self.locate(sym.line, sym.col);
+                self.set_variable(&sym.name);
+            }
+            expr::Expr::Logical(lhs, op, rhs) => {
+                self.expression(lhs);
+                match op {
+                    expr::LogicalOp::And => {
+                        let end_jump = self.emit(Op::JumpIfFalse(0));
+                        self.emit(Op::Pop);
+                        self.expression(rhs);
+                        self.patch_jump(end_jump);
+                    }
+                    expr::LogicalOp::Or => {
+                        let else_jump = self.emit(Op::JumpIfFalse(0));
+                        let end_jump = self.emit(Op::Jump(0));
+                        self.patch_jump(else_jump);
+                        self.emit(Op::Pop);
+                        self.expression(rhs);
+                        self.patch_jump(end_jump);
+                    }
+                }
+            }
+            expr::Expr::Set(e, attr, val) => {
+                self.expression(e);
+                self.expression(val);
+                self.locate(attr.line, attr.col);
+                let constant = self.identifier_constant(&attr.name);
+                self.emit(Op::SetProperty(constant));
+            }
+            expr::Expr::Super(loc, method) => {
+                self.locate(loc.line, loc.col);
+                self.get_variable("this");
+                self.get_variable("super");
+                self.locate(method.line, method.col);
+                let constant = self.identifier_constant(&method.name);
+                self.emit(Op::GetSuper(constant));
+            }
+            expr::Expr::List(_, elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+                self.emit(Op::BuildList(elements.len()));
+            }
+            expr::Expr::Map(loc, entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                self.locate(loc.line, loc.col);
+                self.emit(Op::BuildMap(entries.len()));
+            }
+            expr::Expr::Subscript {
+                value,
+                slice,
+                source_location,
+            } => {
+                self.expression(value);
+                self.expression(slice);
+                self.locate(source_location.line, source_location.col);
+                self.emit(Op::Subscript);
+            }
+            expr::Expr::SetItem {
+                lhs,
+                slice,
+                rhs,
+                source_location,
+            } => {
+                self.expression(lhs);
+                self.expression(slice);
+                self.expression(rhs);
+                self.locate(source_location.line, source_location.col);
+                self.emit(Op::SetItem);
+            }
+            expr::Expr::Lambda(lambda) => self.function(
+                "lambda",
+                &lambda.params,
+                &lambda.body,
+                FunctionType::Function,
+                false,
+            ),
+            expr::Expr::CompoundAssign(target, op, val) => {
+                let operands = self.target_operands(target);
+                self.load_target(target, operands);
+                self.expression(val);
+                self.locate(op.line, op.col);
+                self.binary_op(op.ty);
+                self.store_target(target);
+            }
+            expr::Expr::Increment { target, op, prefix } => {
+                let operands = self.target_operands(target);
+                self.load_target(target, operands);
+                if !prefix {
+                    // keep the operands and the old value underneath the store, so the old
+                    // value is what remains
+                    for _ in 0..=operands {
+                        self.emit(Op::Dup(operands));
+                    }
+                }
+                self.locate(op.line, op.col);
+                let one = self.chunk().add_constant(Constant::Number(1.0));
+                self.emit(Op::Constant(one));
+                self.binary_op(op.ty);
+                self.store_target(target);
+                if !prefix {
+                    self.emit(Op::Pop);
+                    if operands > 0 {
+                        self.emit(Op::PopBelow(operands));
+                    }
+                }
+            }
+            expr::Expr::Ternary(cond, then_branch, else_branch) => {
+                self.expression(cond);
+                let else_jump = self.emit(Op::JumpIfFalse(0));
+                self.emit(Op::Pop);
+                self.expression(then_branch);
+                let end_jump = self.emit(Op::Jump(0));
+                self.patch_jump(else_jump);
+                self.emit(Op::Pop);
+                self.expression(else_branch);
+                self.patch_jump(end_jump);
+            }
+            expr::Expr::Interpolation(loc, parts) => {
+                for part in parts {
+                    self.expression(part);
+                }
+                self.locate(loc.line, loc.col);
+                self.emit(Op::Interpolate(parts.len()));
+            }
+            expr::Expr::Yield(loc, val) => {
+                match val {
+                    Some(val) => self.expression(val),
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.locate(loc.line, loc.col);
+                self.emit(Op::Yield);
+            }
+        }
+    }
+
+    fn binary_op(&mut self, ty: expr::BinaryOpTy) {
+        match ty {
+            expr::BinaryOpTy::EqualEqual => {
+                self.emit(Op::Equal);
+            }
+            expr::BinaryOpTy::NotEqual => {
+                self.emit(Op::Equal);
+                self.emit(Op::Not);
+            }
+            expr::BinaryOpTy::Less => {
+                self.emit(Op::Less);
+            }
+            expr::BinaryOpTy::LessEqual => {
+                self.emit(Op::Greater);
+                self.emit(Op::Not);
+            }
+            expr::BinaryOpTy::Greater => {
+                self.emit(Op::Greater);
+            }
+            expr::BinaryOpTy::GreaterEqual => {
+                self.emit(Op::Less);
+                self.emit(Op::Not);
+            }
+            expr::BinaryOpTy::Plus => {
+                self.emit(Op::Add);
+            }
+            expr::BinaryOpTy::Minus => {
+                self.emit(Op::Subtract);
+            }
+            expr::BinaryOpTy::Star => {
+                self.emit(Op::Multiply);
+            }
+            expr::BinaryOpTy::Slash => {
+                self.emit(Op::Divide);
+            }
+            expr::BinaryOpTy::Percent => {
+                self.emit(Op::Modulo);
+            }
+            expr::BinaryOpTy::StarStar => {
+                self.emit(Op::Power);
+            }
+            expr::BinaryOpTy::Ampersand => {
+                self.emit(Op::BitAnd);
+            }
+            expr::BinaryOpTy::Pipe => {
+                self.emit(Op::BitOr);
+            }
+            expr::BinaryOpTy::Caret => {
+                self.emit(Op::BitXor);
+            }
+            expr::BinaryOpTy::LessLess => {
+                self.emit(Op::ShiftLeft);
+            }
+            expr::BinaryOpTy::GreaterGreater => {
+                self.emit(Op::ShiftRight);
+            }
+        }
+    }
+
+    /// Compiles the parts of an assignment target that are evaluated only once, a property's
+    /// object or a subscript's list and index, and returns how many values that left.
+    fn target_operands(&mut self, target: &expr::Expr) -> usize {
+        match target {
+            expr::Expr::Variable(_) => 0,
+            expr::Expr::Get(object, _) => {
+                self.expression(object);
+                1
+            }
+            expr::Expr::Subscript { value, slice, .. } => {
+                self.expression(value);
+                self.expression(slice);
+                2
+            }
+            _ => panic!("internal compiler error: cannot assign to {:?}", target),
+        }
+    }
+
+    /// Pushes what `target` holds, leaving its operands where they are.
+    fn load_target(&mut self, target: &expr::Expr, operands: usize) {
+        for _ in 0..operands {
+            self.emit(Op::Dup(operands - 1));
+        }
+        match target {
+            expr::Expr::Variable(sym) => {
+                self.locate(sym.line, sym.col);
+                self.get_variable(&sym.name);
+            }
+            expr::Expr::Get(_, attr) => {
+                self.locate(attr.line, attr.col);
+                let constant = self.identifier_constant(&attr.name);
+                self.emit(Op::GetProperty(constant));
+            }
+            expr::Expr::Subscript {
+                source_location, ..
+            } => {
+                self.locate(source_location.line, source_location.col);
+                self.emit(Op::Subscript);
+            }
+            _ => panic!("internal compiler error: cannot assign to {:?}", target),
+        }
+    }
+
+    /// Stores the value on top of the stack into `target`, whose operands are underneath it,
+    /// leaving just the value.
+    fn store_target(&mut self, target: &expr::Expr) {
+        match target {
+            expr::Expr::Variable(sym) => {
+                self.locate(sym.line, sym.col);
+                self.set_variable(&sym.name);
+            }
+            expr::Expr::Get(_, attr) => {
+                self.locate(attr.line, attr.col);
+                let constant = self.identifier_constant(&attr.name);
+                self.emit(Op::SetProperty(constant));
+            }
+            expr::Expr::Subscript {
+                source_location, ..
+            } => {
+                self.locate(source_location.line, source_location.col);
+                self.emit(Op::SetItem);
+            }
+            _ => panic!("internal compiler error: cannot assign to {:?}", target),
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! `roxy --debug`: a line-oriented debugger for the tree-walking interpreter. Before each
+//! statement runs, the interpreter asks the debugger whether to stop there; when it does,
+//! commands are read from stdin until one resumes the program.
+
+use crate::expr;
+use crate::interpreter::Interpreter;
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::process::exit;
+use std::ptr;
+
+const HELP: &str = "\
+step, s          run until the next statement, entering calls
+next, n          run until the next statement in this function or its callers
+out, o           run until the current function returns
+continue, c      run until the next breakpoint
+break, b LINE    stop whenever LINE is reached
+delete, d LINE   remove the breakpoint on LINE
+breakpoints      list breakpoints
+backtrace, bt    print the call stack
+locals           print the variables in scope, innermost first
+globals          print the globals the program defined
+print, p NAME    print the value of the variable NAME
+list, l          print the source around the current line
+quit, q          stop the program and exit
+an empty line repeats the last command";
+
+enum Mode {
+    StepInto,
+    // stop at the next statement in a frame at most this deep
+    StepOver(usize),
+    // stop at the next statement in a frame shallower than this
+    StepOut(usize),
+    Continue,
+}
+
+struct Frame {
+    name: String,
+    line: usize,
+    // the statement the current visit to `line` began with
+    first: *const expr::Stmt,
+}
+
+pub struct Debugger {
+    source: Vec<String>,
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    // the script's own frame is at the bottom
+    frames: Vec<Frame>,
+    last_command: String,
+}
+
+impl Debugger {
+    /// A debugger for the program `source`, which stops before its first statement.
+    pub fn new(source: &str) -> Debugger {
+        println!("roxy debugger, type help for commands");
+        Debugger {
+            source: source.lines().map(str::to_string).collect(),
+            breakpoints: BTreeSet::new(),
+            mode: Mode::StepInto,
+            frames: vec![Frame {
+                name: "<script>".to_string(),
+                line: 0,
+                first: ptr::null(),
+            }],
+            last_command: String::new(),
+        }
+    }
+
+    pub fn enter(&mut self, name: &str) {
+        self.frames.push(Frame {
+            name: name.to_string(),
+            line: 0,
+            first: ptr::null(),
+        });
+    }
+
+    pub fn leave(&mut self) {
+        self.frames.pop();
+    }
+
+    /// Called before `stmt` executes; returns once the user resumes the program.
+    pub fn before(&mut self, interpreter: &Interpreter, stmt: &expr::Stmt) {
+        let line = match stmt_line(stmt) {
+            Some(line) => line,
+            None => return,
+        };
+        let depth = self.frames.len();
+        let frame = self.frames.last_mut().unwrap();
+        // a breakpoint fires once per visit to its line, not for every statement on it. Coming
+        // back round to the statement a visit began with, as a loop does, starts a new one.
+        let arrived = frame.line != line || ptr::eq(frame.first, stmt);
+        if arrived {
+            frame.line = line;
+            frame.first = stmt;
+        }
+
+        let stop = match self.mode {
+            Mode::StepInto => true,
+            Mode::StepOver(max_depth) => depth <= max_depth,
+            Mode::StepOut(max_depth) => depth < max_depth,
+            Mode::Continue => false,
+        };
+        if stop {
+            self.show_line(line);
+        } else if arrived && self.breakpoints.contains(&line) {
+            println!("breakpoint at line {}", line);
+            self.show_line(line);
+        } else {
+            return;
+        }
+
+        self.prompt(interpreter, depth, line);
+    }
+
+    fn prompt(&mut self, interpreter: &Interpreter, depth: usize, line: usize) {
+        loop {
+            print!("(debug) ");
+            let _ = io::stdout().flush();
+
+            let mut input = String::new();
+            match io::stdin().read_line(&mut input) {
+                Ok(0) | Err(_) => {
+                    // nobody is left to give commands, so let the program finish
+                    self.breakpoints.clear();
+                    self.mode = Mode::Continue;
+                    return;
+                }
+                Ok(_) => {}
+            }
+            let input = match input.trim() {
+                "" => self.last_command.clone(),
+                input => input.to_string(),
+            };
+            self.last_command = input.clone();
+
+            let mut words = input.split_whitespace();
+            let command = words.next().unwrap_or_default();
+            let arg = words.next();
+            match command {
+                "step" | "s" => {
+                    self.mode = Mode::StepInto;
+                    return;
+                }
+                "next" | "n" => {
+                    self.mode = Mode::StepOver(depth);
+                    return;
+                }
+                "out" | "o" => {
+                    self.mode = Mode::StepOut(depth);
+                    return;
+                }
+                "continue" | "c" => {
+                    self.mode = Mode::Continue;
+                    return;
+                }
+                "break" | "b" => match arg.and_then(|arg| arg.parse::<usize>().ok()) {
+                    Some(line) if line >= 1 && line <= self.source.len() => {
+                        self.breakpoints.insert(line);
+                        println!("breakpoint set at line {}", line);
+                    }
+                    Some(line) => println!("line {} is not in the program", line),
+                    None => println!("usage: break LINE"),
+                },
+                "delete" | "d" => match arg.and_then(|arg| arg.parse::<usize>().ok()) {
+                    Some(line) if self.breakpoints.remove(&line) => {
+                        println!("breakpoint at line {} deleted", line)
+                    }
+                    Some(line) => println!("no breakpoint at line {}", line),
+                    None => println!("usage: delete LINE"),
+                },
+                "breakpoints" => {
+                    if self.breakpoints.is_empty() {
+                        println!("no breakpoints");
+                    }
+                    for line in &self.breakpoints {
+                        println!("line {}", line);
+                    }
+                }
+                "backtrace" | "bt" => {
+                    for (i, frame) in self.frames.iter().rev().enumerate() {
+                        println!("#{} {} at line {}", i, frame.name, frame.line);
+                    }
+                }
+                "locals" => {
+                    let locals = interpreter.visible_locals();
+                    if locals.is_empty() {
+                        println!("no locals");
+                    }
+                    for (name, val) in locals {
+                        println!("{} = {:?}", name, val);
+                    }
+                }
+                "globals" => {
+                    for (name, val) in interpreter.user_globals() {
+                        println!("{} = {:?}", name, val);
+                    }
+                }
+                "print" | "p" => match arg {
+                    Some(name) => match interpreter.lookup(name) {
+                        Some(val) => println!("{} = {:?}", name, val),
+                        None => println!("no variable '{}' in scope", name),
+                    },
+                    None => println!("usage: print NAME"),
+                },
+                "list" | "l" => {
+                    let first = line.saturating_sub(5).max(1);
+                    for n in first..=(line + 5).min(self.source.len()) {
+                        let marker = if n == line { "->" } else { "  " };
+                        println!("{} {:>4} | {}", marker, n, self.source[n - 1]);
+                    }
+                }
+                "help" | "h" => println!("{}", HELP),
+                "quit" | "q" => exit(0),
+                command => println!("unknown command '{}', type help for commands", command),
+            }
+        }
+    }
+
+    fn show_line(&self, line: usize) {
+        let frame = self.frames.last().unwrap();
+        let text = self.source.get(line - 1).map(String::as_str).unwrap_or("");
+        println!("{} at line {}", frame.name, line);
+        println!("{:>4} | {}", line, text.trim_end());
+    }
+}
+
+/// The line a statement starts on. Blocks don't count: the debugger stops at the statements
+/// inside instead.
+fn stmt_line(stmt: &expr::Stmt) -> Option<usize> {
+    match stmt {
+        expr::Stmt::Block(..) => None,
+        // statements the driver makes up are at line 0
+        _ => Some(stmt.location().line).filter(|line| *line > 0),
+    }
+}
@@ -0,0 +1,588 @@
+use crate::err::{Frame, RoxyErr};
+use crate::parser;
+use crate::resolver;
+use crate::scanner;
+use crate::syntax;
+use crate::typecheck;
+
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a roxy program, located by the 1-based line and 0-based column its
+/// offending token starts at.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub line: usize,
+    pub col: i64,
+    /// how many columns the caret underline spans
+    pub len: usize,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: String, line: usize, col: i64) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message,
+            line,
+            col,
+            len: 1,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(code: &'static str, message: String, line: usize, col: i64) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, message, line, col)
+        }
+    }
+
+    pub fn with_len(mut self, len: usize) -> Diagnostic {
+        self.len = len.max(1);
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Diagnostic {
+        self.notes.push(note);
+        self
+    }
+
+    /// Renders the diagnostic against the source it was found in, e.g.
+    ///
+    /// ```text
+    /// error[E0102]: expected Semicolon, found 'print'
+    ///  --> script.lox:3:1
+    ///   |
+    /// 3 | print a
+    ///   | ^^^^^
+    ///   = note: Expected ; after expression
+    /// ```
+    pub fn render(&self, source_name: &str, source: &str) -> String {
+        let line_text = source
+            .lines()
+            .nth(self.line.saturating_sub(1))
+            .unwrap_or("");
+        let gutter = " ".repeat(self.line.to_string().len());
+        let col = self.col.max(0) as usize;
+
+        let mut out = format!("{}[{}]: {}\n", self.severity, self.code, self.message);
+        out.push_str(&format!(
+            "{}--> {}:{}:{}\n",
+            gutter,
+            source_name,
+            self.line,
+            col + 1
+        ));
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", self.line, line_text));
+        // a tab wherever the source has one, so the carets line up however wide tabs are shown
+        let indent: String = line_text
+            .chars()
+            .chain(std::iter::repeat(' '))
+            .take(col)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        out.push_str(&format!(
+            "{} | {}{}\n",
+            gutter,
+            indent,
+            "^".repeat(self.len)
+        ));
+        for note in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", gutter, note));
+        }
+        out
+    }
+}
+
+impl From<&scanner::Error> for Diagnostic {
+    fn from(err: &scanner::Error) -> Diagnostic {
+        let code = if err.what == "Unterminated string" {
+            "E0002"
+        } else {
+            "E0001"
+        };
+        Diagnostic::error(code, err.what.clone(), err.line, err.col)
+    }
+}
+
+fn describe(tok: &scanner::Token) -> String {
+    match tok.ty {
+        scanner::TokenType::Eof => "end of input".to_string(),
+        _ => format!("'{}'", String::from_utf8_lossy(&tok.lexeme)),
+    }
+}
+
+impl From<&parser::Error> for Diagnostic {
+    fn from(err: &parser::Error) -> Diagnostic {
+        match err {
+            parser::Error::UnexpectedToken(tok) => Diagnostic::error(
+                "E0101",
+                format!("unexpected {}", describe(tok)),
+                tok.line,
+                tok.col,
+            )
+            .with_len(tok.width()),
+            parser::Error::TokenMismatch {
+                expected,
+                found,
+                maybe_on_err_string,
+            } => {
+                let diag = Diagnostic::error(
+                    "E0102",
+                    format!("expected {:?}, found {}", expected, describe(found)),
+                    found.line,
+                    found.col,
+                )
+                .with_len(found.width());
+                match maybe_on_err_string {
+                    Some(on_err_string) => diag.with_note(on_err_string.clone()),
+                    None => diag,
+                }
+            }
+            parser::Error::MaxParamsExceeded { kind, line, col } => Diagnostic::error(
+                "E0103",
+                format!("cannot have more than 255 parameters in a {:?}", kind),
+                *line,
+                *col,
+            ),
+            parser::Error::InvalidAssignment { line, col } => Diagnostic::error(
+                "E0104",
+                "invalid assignment target".to_string(),
+                *line,
+                *col,
+            )
+            .with_note("only variables, fields and list items can be assigned to".to_string()),
+            parser::Error::TooManyArguments { line, col } => Diagnostic::error(
+                "E0105",
+                "cannot have more than 255 arguments to a call".to_string(),
+                *line,
+                *col,
+            ),
+            parser::Error::ExpectedExpression {
+                token_type,
+                line,
+                col,
+            } => Diagnostic::error(
+                "E0106",
+                format!("expected expression, found {:?}", token_type),
+                *line,
+                *col,
+            ),
+            parser::Error::InvalidTokenInUnaryOp {
+                token_type,
+                line,
+                col,
+            } => Diagnostic::error(
+                "E0107",
+                format!("invalid token in unary op {:?}", token_type),
+                *line,
+                *col,
+            ),
+            parser::Error::InvalidTokenInBinaryOp {
+                token_type,
+                line,
+                col,
+            } => Diagnostic::error(
+                "E0108",
+                format!("invalid token in binary op {:?}", token_type),
+                *line,
+                *col,
+            ),
+        }
+    }
+}
+
+impl From<&syntax::Error> for Diagnostic {
+    fn from(err: &syntax::Error) -> Diagnostic {
+        match err {
+            syntax::Error::Skipped {
+                what,
+                line,
+                col,
+                len,
+            } => Diagnostic::from(&scanner::Error {
+                what: what.clone(),
+                line: *line,
+                col: *col,
+            })
+            .with_len(*len),
+            syntax::Error::Missing { expected, found } => {
+                let (code, message) = match expected {
+                    syntax::Expected::Token(ty) => (
+                        "E0102",
+                        format!("expected {:?}, found {}", ty, describe(found)),
+                    ),
+                    syntax::Expected::Expression => (
+                        "E0106",
+                        format!("expected expression, found {:?}", found.ty),
+                    ),
+                    syntax::Expected::Statement => (
+                        "E0109",
+                        format!("expected statement, found {}", describe(found)),
+                    ),
+                };
+                Diagnostic::error(code, message, found.line, found.col).with_len(found.width())
+            }
+            syntax::Error::Unexpected(tok) => Diagnostic::error(
+                "E0101",
+                format!("unexpected {}", describe(tok)),
+                tok.line,
+                tok.col,
+            )
+            .with_len(tok.width()),
+        }
+    }
+}
+
+impl From<&resolver::Error> for Diagnostic {
+    fn from(err: &resolver::Error) -> Diagnostic {
+        match err {
+            resolver::Error::ReadLocalInOwnInitializer { name, line, col } => Diagnostic::error(
+                "E0201",
+                format!(
+                    "cannot read local variable '{}' in its own initializer",
+                    name
+                ),
+                *line,
+                *col,
+            )
+            .with_len(name.chars().count()),
+            resolver::Error::AlreadyDeclared { name, line, col } => Diagnostic::error(
+                "E0202",
+                format!("variable '{}' is already declared in this scope", name),
+                *line,
+                *col,
+            )
+            .with_len(name.chars().count()),
+            resolver::Error::ReturnNotInFun { line, col } => Diagnostic::error(
+                "E0203",
+                "cannot return from top-level code".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("return".len()),
+            resolver::Error::ReturnValueFromInitializer { line, col } => Diagnostic::error(
+                "E0204",
+                "cannot return a value from an initializer".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("return".len())
+            .with_note("init always returns the instance being initialized".to_string()),
+            resolver::Error::ThisOutsideClass { line, col } => Diagnostic::error(
+                "E0205",
+                "cannot use 'this' outside of a class".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("this".len()),
+            resolver::Error::SuperOutsideClass { line, col } => Diagnostic::error(
+                "E0206",
+                "cannot use 'super' outside of a class".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("super".len()),
+            resolver::Error::SuperWithoutSuperclass { line, col } => Diagnostic::error(
+                "E0207",
+                "cannot use 'super' in a class with no superclass".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("super".len()),
+            resolver::Error::ClassInheritsFromItself { name, line, col } => Diagnostic::error(
+                "E0208",
+                format!("class '{}' cannot inherit from itself", name),
+                *line,
+                *col,
+            )
+            .with_len(name.chars().count()),
+            resolver::Error::ImportNotAtTopLevel { line, col } => Diagnostic::error(
+                "E0209",
+                "import is only allowed at the top level".to_string(),
+                *line,
+                *col,
+            )
+            .with_note("modules run once, before the program that imports them".to_string()),
+            resolver::Error::BreakNotInLoop { line, col } => Diagnostic::error(
+                "E0210",
+                "cannot break outside of a loop".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("break".len()),
+            resolver::Error::ContinueNotInLoop { line, col } => Diagnostic::error(
+                "E0211",
+                "cannot continue outside of a loop".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("continue".len()),
+            resolver::Error::UndefinedLabel { name, line, col } => Diagnostic::error(
+                "E0212",
+                format!("no enclosing loop is labeled '{}'", name),
+                *line,
+                *col,
+            )
+            .with_len(name.chars().count()),
+            resolver::Error::LabelOutsideFunction { name, line, col } => Diagnostic::error(
+                "E0212",
+                format!("no enclosing loop is labeled '{}'", name),
+                *line,
+                *col,
+            )
+            .with_len(name.chars().count())
+            .with_note("a function cannot leave the loops around it".to_string()),
+            resolver::Error::SuperInStaticMethod { line, col } => Diagnostic::error(
+                "E0213",
+                "cannot use 'super' in a static method".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("super".len()),
+            resolver::Error::InitializerNotMethod { line, col } => Diagnostic::error(
+                "E0214",
+                "'init' cannot be a static method, generator, getter or setter".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("init".len()),
+            resolver::Error::ReturnValueFromSetter { line, col } => Diagnostic::error(
+                "E0215",
+                "cannot return a value from a setter".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("return".len())
+            .with_note("an assignment evaluates to the value assigned".to_string()),
+            resolver::Error::YieldNotInGenerator { line, col } => Diagnostic::error(
+                "E0216",
+                "cannot use 'yield' outside of a generator".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("yield".len())
+            .with_note("declare the function with `fun*` to make it a generator".to_string()),
+            resolver::Error::YieldInExpression { line, col } => Diagnostic::error(
+                "E0217",
+                "cannot use 'yield' inside a larger expression".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("yield".len())
+            .with_note(
+                "a yield can be a statement of its own, a variable's initializer or the value \
+                 assigned to a variable; yield into a variable first and use that"
+                    .to_string(),
+            ),
+            resolver::Error::ReturnValueFromGenerator { line, col } => Diagnostic::error(
+                "E0218",
+                "cannot return a value from a generator".to_string(),
+                *line,
+                *col,
+            )
+            .with_len("return".len())
+            .with_note("a generator hands out its values with yield".to_string()),
+        }
+    }
+}
+
+impl From<&typecheck::Error> for Diagnostic {
+    fn from(err: &typecheck::Error) -> Diagnostic {
+        match err {
+            typecheck::Error::Mismatch {
+                expected,
+                found,
+                reason,
+                line,
+                col,
+            } => {
+                let diag = Diagnostic::error(
+                    "E0501",
+                    format!("expected {}, found {}", expected, found),
+                    *line,
+                    *col,
+                );
+                match reason {
+                    Some(reason) => diag.with_note(reason.clone()),
+                    None => diag,
+                }
+            }
+            typecheck::Error::UnknownType { name, line, col } => {
+                Diagnostic::error("E0502", format!("unknown type '{}'", name), *line, *col)
+                    .with_len(name.chars().count())
+                    .with_note(
+                        "types are any, nil, bool, num, str, list, map, fun and class names"
+                            .to_string(),
+                    )
+            }
+            typecheck::Error::InvalidOperands {
+                op,
+                types,
+                line,
+                col,
+            } => {
+                let types: Vec<String> = types.iter().map(|ty| ty.to_string()).collect();
+                Diagnostic::error(
+                    "E0503",
+                    format!("cannot apply '{}' to {}", op, types.join(" and ")),
+                    *line,
+                    *col,
+                )
+                .with_len(op.len())
+            }
+            typecheck::Error::Uninitialized {
+                name,
+                ty,
+                line,
+                col,
+            } => Diagnostic::error(
+                "E0504",
+                format!("'{}' is declared {} but has no initializer", name, ty),
+                *line,
+                *col,
+            )
+            .with_len(name.chars().count())
+            .with_note("a variable without an initializer starts out nil".to_string()),
+            typecheck::Error::NotCallable { found, line, col } => Diagnostic::error(
+                "E0505",
+                format!("cannot call a value of type {}", found),
+                *line,
+                *col,
+            ),
+            typecheck::Error::WrongArity {
+                name,
+                expected,
+                found,
+                line,
+                col,
+            } => Diagnostic::error(
+                "E0506",
+                format!(
+                    "'{}' takes {} {} but {} {} given",
+                    name,
+                    expected,
+                    if *expected == 1 {
+                        "argument"
+                    } else {
+                        "arguments"
+                    },
+                    found,
+                    if *found == 1 { "was" } else { "were" }
+                ),
+                *line,
+                *col,
+            ),
+            typecheck::Error::MissingReturn {
+                name,
+                ty,
+                line,
+                col,
+            } => Diagnostic::error(
+                "E0507",
+                format!("'{}' may finish without returning {}", name, ty),
+                *line,
+                *col,
+            )
+            .with_len(name.chars().count())
+            .with_note("falling off the end of a function returns nil".to_string()),
+        }
+    }
+}
+
+impl From<&RoxyErr> for Diagnostic {
+    fn from(err: &RoxyErr) -> Diagnostic {
+        match err {
+            RoxyErr::RuntimeError {
+                what,
+                line,
+                col,
+                trace,
+            } => with_trace(Diagnostic::error("E0301", what.clone(), *line, *col), trace),
+            RoxyErr::Thrown {
+                what,
+                line,
+                col,
+                trace,
+            } => with_trace(
+                Diagnostic::error(
+                    "E0302",
+                    format!("uncaught exception: {}", what),
+                    *line,
+                    *col,
+                ),
+                trace,
+            ),
+            RoxyErr::LimitExceeded { limit, line, col } => {
+                Diagnostic::error("E0303", limit.to_string(), *line, *col)
+                    .with_note("the program was stopped".to_string())
+            }
+            RoxyErr::NativeError(what) => Diagnostic::error("E0301", what.clone(), 0, 0),
+            err => Diagnostic::error("E0300", format!("{:?}", err), 0, 0),
+        }
+    }
+}
+
+/// How many calls at either end of a long stack trace are noted.
+const TRACE_ENDS: usize = 10;
+
+/// Notes the calls an error unwound through, innermost first. Runs of the same call, as
+/// recursion leaves behind, are noted once, and of a trace still longer than `2 * TRACE_ENDS`
+/// only the calls at either end.
+fn with_trace(diagnostic: Diagnostic, trace: &[Frame]) -> Diagnostic {
+    let mut calls: Vec<(&Frame, usize)> = Vec::new();
+    for frame in trace {
+        match calls.last_mut() {
+            Some((last, times)) if *last == frame => *times += 1,
+            _ => calls.push((frame, 1)),
+        }
+    }
+    let note = |(frame, times): &(&Frame, usize)| {
+        let call = format!(
+            "in {}, called at {}:{}",
+            frame.function,
+            frame.line,
+            frame.col + 1
+        );
+        match times {
+            1 => call,
+            times => format!("{} ({} times)", call, times),
+        }
+    };
+
+    if calls.len() <= 2 * TRACE_ENDS {
+        return calls.iter().fold(diagnostic, |diagnostic, call| {
+            diagnostic.with_note(note(call))
+        });
+    }
+    let (inner, rest) = calls.split_at(TRACE_ENDS);
+    let (elided, outer) = rest.split_at(rest.len() - TRACE_ENDS);
+    let diagnostic = inner.iter().fold(diagnostic, |diagnostic, call| {
+        diagnostic.with_note(note(call))
+    });
+    let hidden: usize = elided.iter().map(|(_, times)| times).sum();
+    let diagnostic = diagnostic.with_note(format!("... {} more calls", hidden));
+    outer.iter().fold(diagnostic, |diagnostic, call| {
+        diagnostic.with_note(note(call))
+    })
+}
@@ -0,0 +1,876 @@
+//! `--dump-tokens` and `--dump-ast`: what the scanner and parser make of a program, as JSON
+//! for tools written outside Rust, or as pretty-printed S-expressions for people and for
+//! golden tests of the grammar. Tokens from a file are printed as they are scanned.
+//!
+//! `--dump-syntax` prints the lossless syntax tree instead, which keeps every token, comment
+//! and mistake; in S-expressions tokens are their quoted text and whitespace is left out.
+//!
+//! Every AST node has a `type` and a `location`. The location is the one the parser recorded
+//! for the node (its operator, keyword or name), or, for nodes that have none of their own,
+//! that of their first child.
+
+use crate::expr;
+use crate::scanner::{self, Token, Trivia, TriviaKind};
+use crate::syntax;
+
+use serde_json::{json, Map, Value as Json};
+
+#[derive(clap::ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DumpFormat {
+    Json,
+    Sexpr,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Dump {
+    Tokens,
+    Ast,
+    Syntax,
+    Bytecode,
+}
+
+// how wide the S-expression printer lets a line get before breaking a node up
+const WIDTH: usize = 80;
+
+pub fn tokens(tokens: &[Token], format: DumpFormat) -> String {
+    match format {
+        DumpFormat::Json => {
+            let tokens: Vec<Json> = tokens.iter().map(token_json).collect();
+            serde_json::to_string_pretty(&tokens).unwrap() + "\n"
+        }
+        DumpFormat::Sexpr => tokens
+            .iter()
+            .map(|token| render(&token_sexpr(token), 0) + "\n")
+            .collect(),
+    }
+}
+
+/// `tokens` for tokens still being scanned: the same text, in pieces that can be printed as
+/// each token arrives. A scanner error ends the pieces.
+pub fn stream_tokens(
+    tokens: impl Iterator<Item = Result<Token, scanner::Error>>,
+    format: DumpFormat,
+) -> impl Iterator<Item = Result<String, scanner::Error>> {
+    let (open, close) = match format {
+        DumpFormat::Json => ("[\n", "\n]\n"),
+        DumpFormat::Sexpr => ("", ""),
+    };
+    let mut first = true;
+    let pieces = tokens.map(move |token| {
+        let token = token?;
+        Ok(match format {
+            DumpFormat::Json => {
+                let separator = if first { "" } else { ",\n" };
+                first = false;
+                // indented as an element of the array `tokens` prints
+                let element: Vec<String> = serde_json::to_string_pretty(&token_json(&token))
+                    .unwrap()
+                    .lines()
+                    .map(|line| format!("  {}", line))
+                    .collect();
+                format!("{}{}", separator, element.join("\n"))
+            }
+            DumpFormat::Sexpr => render(&token_sexpr(&token), 0) + "\n",
+        })
+    });
+    std::iter::once(Ok(open.to_string()))
+        .chain(pieces)
+        .chain(std::iter::once(Ok(close.to_string())))
+}
+
+pub fn ast(stmts: &[expr::Stmt], format: DumpFormat) -> String {
+    let nodes: Vec<Node> = stmts.iter().map(stmt).collect();
+    match format {
+        DumpFormat::Json => {
+            let nodes: Vec<Json> = nodes.iter().map(Node::json).collect();
+            serde_json::to_string_pretty(&nodes).unwrap() + "\n"
+        }
+        DumpFormat::Sexpr => nodes
+            .iter()
+            .map(|node| render(&node.sexpr(), 0) + "\n")
+            .collect(),
+    }
+}
+
+pub fn syntax(tree: &syntax::Tree, format: DumpFormat) -> String {
+    match format {
+        DumpFormat::Json => serde_json::to_string_pretty(&node_json(tree.root())).unwrap() + "\n",
+        DumpFormat::Sexpr => {
+            // the program's children one to a line, like the statements of an AST dump
+            let mut items = Vec::new();
+            for child in tree.root().children() {
+                element_sexpr(child, &mut items);
+            }
+            items.iter().map(|item| render(item, 0) + "\n").collect()
+        }
+    }
+}
+
+fn node_json(node: &syntax::Node) -> Json {
+    json!({
+        "kind": format!("{:?}", node.kind),
+        "children": node.children().iter().map(element_json).collect::<Vec<_>>(),
+    })
+}
+
+fn element_json(element: &syntax::Element) -> Json {
+    match element {
+        syntax::Element::Node(node) => node_json(node),
+        syntax::Element::Token(token) => json!({
+            "kind": format!("{:?}", token.kind),
+            "text": String::from_utf8_lossy(&token.text),
+            "leading": token.leading.iter().map(trivia_json).collect::<Vec<_>>(),
+        }),
+        syntax::Element::Missing(expected) => {
+            json!({ "kind": "Missing", "expected": expected_name(expected) })
+        }
+    }
+}
+
+fn trivia_json(trivia: &Trivia) -> Json {
+    let text = String::from_utf8_lossy(&trivia.text);
+    match &trivia.kind {
+        TriviaKind::Whitespace => json!({ "kind": "Whitespace", "text": text }),
+        TriviaKind::Comment => json!({ "kind": "Comment", "text": text }),
+        TriviaKind::Skipped(what) => json!({ "kind": "Skipped", "text": text, "error": what }),
+    }
+}
+
+fn expected_name(expected: &syntax::Expected) -> String {
+    match expected {
+        syntax::Expected::Token(ty) => format!("{:?}", ty),
+        _ => format!("{:?}", expected),
+    }
+}
+
+/// Pushes the S-expressions for `element` onto `items`: a token's comments and skipped text
+/// go in front of it.
+fn element_sexpr(element: &syntax::Element, items: &mut Vec<Sexp>) {
+    match element {
+        syntax::Element::Node(node) => {
+            let mut children = vec![Sexp::Atom(kebab(&format!("{:?}", node.kind)))];
+            for child in node.children() {
+                element_sexpr(child, &mut children);
+            }
+            items.push(Sexp::List(children));
+        }
+        syntax::Element::Token(token) => {
+            for trivia in &token.leading {
+                let text = Sexp::Atom(quote(&String::from_utf8_lossy(&trivia.text)));
+                match trivia.kind {
+                    TriviaKind::Whitespace => {}
+                    TriviaKind::Comment => {
+                        items.push(Sexp::List(vec![Sexp::Atom("comment".to_string()), text]))
+                    }
+                    TriviaKind::Skipped(_) => {
+                        items.push(Sexp::List(vec![Sexp::Atom("skipped".to_string()), text]))
+                    }
+                }
+            }
+            if token.kind != scanner::TokenType::Eof {
+                items.push(Sexp::Atom(quote(&String::from_utf8_lossy(&token.text))));
+            }
+        }
+        syntax::Element::Missing(expected) => items.push(Sexp::List(vec![
+            Sexp::Atom("missing".to_string()),
+            Sexp::Atom(kebab(&expected_name(expected))),
+        ])),
+    }
+}
+
+fn location_json(line: usize, col: i64) -> Json {
+    json!({ "line": line, "col": col })
+}
+
+fn token_literal(token: &Token) -> Json {
+    match &token.literal {
+        Some(scanner::Literal::Number(n)) => json!(n),
+        Some(scanner::Literal::Str(s)) => json!(s),
+        Some(scanner::Literal::Identifier(_)) | None => Json::Null,
+    }
+}
+
+fn token_json(token: &Token) -> Json {
+    json!({
+        "type": format!("{:?}", token.ty),
+        "lexeme": String::from_utf8_lossy(&token.lexeme),
+        "literal": token_literal(token),
+        "location": location_json(token.line, token.col),
+    })
+}
+
+fn token_sexpr(token: &Token) -> Sexp {
+    let mut items = vec![
+        Sexp::Atom(kebab(&format!("{:?}", token.ty))),
+        Sexp::Atom(format!("@{}:{}", token.line, token.col)),
+        Sexp::Atom(quote(&String::from_utf8_lossy(&token.lexeme))),
+    ];
+    match &token.literal {
+        Some(scanner::Literal::Number(n)) => items.push(Sexp::Atom(n.to_string())),
+        Some(scanner::Literal::Str(s)) => items.push(Sexp::Atom(quote(s))),
+        Some(scanner::Literal::Identifier(_)) | None => {}
+    }
+    Sexp::List(items)
+}
+
+/// A syntax tree node, in a shape both output formats can be produced from.
+struct Node {
+    kind: &'static str,
+    location: Option<expr::SourceLocation>,
+    fields: Vec<(&'static str, Field)>,
+}
+
+enum Field {
+    /// identifiers and operators
+    Name(String),
+    Str(String),
+    Number(f64),
+    Bool(bool),
+    /// the nil literal, or a part of the node that is missing
+    Nil,
+    /// a name with a location of its own
+    Symbol(expr::Symbol),
+    Symbols(Vec<expr::Symbol>),
+    /// a type annotation, written `:num` in S-expressions so a `nil` type isn't mistaken for
+    /// a missing one
+    Annotation(expr::Symbol),
+    /// parameters, each with its type annotation
+    Params(Vec<expr::Param>),
+    Node(Box<Node>),
+    Nodes(Vec<Node>),
+}
+
+impl Node {
+    fn new(
+        kind: &'static str,
+        location: Option<expr::SourceLocation>,
+        fields: Vec<(&'static str, Field)>,
+    ) -> Node {
+        let location = location.or_else(|| {
+            fields.iter().find_map(|(_, field)| match field {
+                Field::Node(node) => node.location,
+                Field::Nodes(nodes) => nodes.iter().find_map(|node| node.location),
+                _ => None,
+            })
+        });
+        Node {
+            kind,
+            location,
+            fields,
+        }
+    }
+
+    fn json(&self) -> Json {
+        let mut object = Map::new();
+        object.insert("type".to_string(), json!(self.kind));
+        object.insert(
+            "location".to_string(),
+            match self.location {
+                Some(loc) => location_json(loc.line, loc.col),
+                None => Json::Null,
+            },
+        );
+        for (name, field) in &self.fields {
+            object.insert(name.to_string(), field.json());
+        }
+        Json::Object(object)
+    }
+
+    fn sexpr(&self) -> Sexp {
+        let mut items = vec![Sexp::Atom(kebab(self.kind))];
+        if let Some(loc) = self.location {
+            items.push(Sexp::Atom(format!("@{}:{}", loc.line, loc.col)));
+        }
+        items.extend(self.fields.iter().map(|(_, field)| field.sexpr()));
+        Sexp::List(items)
+    }
+}
+
+impl Field {
+    fn json(&self) -> Json {
+        match self {
+            Field::Name(s) | Field::Str(s) => json!(s),
+            Field::Number(n) => json!(n),
+            Field::Bool(b) => json!(b),
+            Field::Nil => Json::Null,
+            Field::Symbol(sym) | Field::Annotation(sym) => symbol_json(sym),
+            Field::Symbols(syms) => syms.iter().map(symbol_json).collect(),
+            Field::Params(params) => params
+                .iter()
+                .map(|param| {
+                    let mut json = symbol_json(&param.name);
+                    json["annotation"] = param.ty.as_ref().map_or(Json::Null, symbol_json);
+                    json
+                })
+                .collect(),
+            Field::Node(node) => node.json(),
+            Field::Nodes(nodes) => nodes.iter().map(Node::json).collect(),
+        }
+    }
+
+    fn sexpr(&self) -> Sexp {
+        match self {
+            Field::Name(s) => Sexp::Atom(s.clone()),
+            Field::Str(s) => Sexp::Atom(quote(s)),
+            Field::Number(n) => Sexp::Atom(n.to_string()),
+            Field::Bool(b) => Sexp::Atom(b.to_string()),
+            Field::Nil => Sexp::Atom("nil".to_string()),
+            Field::Symbol(sym) => Sexp::Atom(sym.name.clone()),
+            Field::Symbols(syms) => Sexp::Vector(
+                syms.iter()
+                    .map(|sym| Sexp::Atom(sym.name.clone()))
+                    .collect(),
+            ),
+            Field::Annotation(ty) => Sexp::Atom(format!(":{}", ty.name)),
+            Field::Params(params) => Sexp::Vector(
+                params
+                    .iter()
+                    .map(|param| match &param.ty {
+                        Some(ty) => Sexp::List(vec![
+                            Sexp::Atom(param.name.name.clone()),
+                            Sexp::Atom(format!(":{}", ty.name)),
+                        ]),
+                        None => Sexp::Atom(param.name.name.clone()),
+                    })
+                    .collect(),
+            ),
+            Field::Node(node) => node.sexpr(),
+            Field::Nodes(nodes) => Sexp::Vector(nodes.iter().map(Node::sexpr).collect()),
+        }
+    }
+}
+
+fn symbol_json(sym: &expr::Symbol) -> Json {
+    json!({ "name": sym.name, "location": location_json(sym.line, sym.col) })
+}
+
+fn symbol_location(sym: &expr::Symbol) -> Option<expr::SourceLocation> {
+    Some(expr::SourceLocation {
+        line: sym.line,
+        col: sym.col,
+    })
+}
+
+fn boxed(node: Node) -> Field {
+    Field::Node(Box::new(node))
+}
+
+fn maybe<T>(val: &Option<T>, f: impl Fn(&T) -> Node) -> Field {
+    match val {
+        Some(val) => boxed(f(val)),
+        None => Field::Nil,
+    }
+}
+
+fn jump_label(label: &Option<expr::Symbol>) -> Field {
+    match label {
+        Some(label) => Field::Symbol(label.clone()),
+        None => Field::Nil,
+    }
+}
+
+fn annotation(ty: &Option<expr::Symbol>) -> Field {
+    match ty {
+        Some(ty) => Field::Annotation(ty.clone()),
+        None => Field::Nil,
+    }
+}
+
+fn stmts(stmts: &[expr::Stmt]) -> Field {
+    Field::Nodes(stmts.iter().map(stmt).collect())
+}
+
+fn fun_decl(kind: &'static str, fundecl: &expr::FunDecl) -> Node {
+    let mut fields = vec![("name", Field::Name(fundecl.name.name.clone()))];
+    // only generators say so, which keeps every other function's dump as it was
+    if fundecl.generator {
+        fields.push(("generator", Field::Bool(true)));
+    }
+    fields.extend([
+        ("params", Field::Params(fundecl.params.clone())),
+        ("returns", annotation(&fundecl.return_type)),
+        ("body", stmts(&fundecl.body)),
+    ]);
+    Node::new(kind, symbol_location(&fundecl.name), fields)
+}
+
+fn stmt(stmt: &expr::Stmt) -> Node {
+    match stmt {
+        expr::Stmt::Expr(e) => Node::new("Expr", None, vec![("expr", boxed(expr(e)))]),
+        expr::Stmt::FunDecl(fundecl) => fun_decl("FunDecl", fundecl),
+        expr::Stmt::ClassDecl(classdecl) => Node::new(
+            "ClassDecl",
+            symbol_location(&classdecl.name),
+            vec![
+                ("name", Field::Name(classdecl.name.name.clone())),
+                (
+                    "superclass",
+                    match &classdecl.superclass {
+                        Some(superclass) => Field::Symbol(superclass.clone()),
+                        None => Field::Nil,
+                    },
+                ),
+                ("mixins", Field::Symbols(classdecl.mixins.clone())),
+                (
+                    "methods",
+                    Field::Nodes(
+                        classdecl
+                            .methods
+                            .iter()
+                            .map(|method| {
+                                let kind = match method.kind {
+                                    expr::MethodKind::Method => "Method",
+                                    expr::MethodKind::Static => "StaticMethod",
+                                    expr::MethodKind::Getter => "Getter",
+                                    expr::MethodKind::Setter => "Setter",
+                                };
+                                fun_decl(kind, &method.decl)
+                            })
+                            .collect(),
+                    ),
+                ),
+            ],
+        ),
+        expr::Stmt::If(cond, then_branch, else_branch) => Node::new(
+            "If",
+            None,
+            vec![
+                ("condition", boxed(expr(cond))),
+                ("then", boxed(self::stmt(then_branch))),
+                ("else", maybe(else_branch, |s| self::stmt(s))),
+            ],
+        ),
+        expr::Stmt::Print(loc, e) => Node::new("Print", Some(*loc), vec![("expr", boxed(expr(e)))]),
+        expr::Stmt::VarDecl(sym, ty, initializer) => Node::new(
+            "VarDecl",
+            symbol_location(sym),
+            vec![
+                ("name", Field::Name(sym.name.clone())),
+                ("annotation", annotation(ty)),
+                ("initializer", maybe(initializer, expr)),
+            ],
+        ),
+        expr::Stmt::Block(loc, body) => Node::new("Block", Some(*loc), vec![("body", stmts(body))]),
+        expr::Stmt::Return(loc, val) => Node::new(
+            "Return",
+            // the REPL's made-up returns have no place in the source
+            Some(*loc).filter(|loc| loc.line > 0),
+            vec![("value", maybe(val, expr))],
+        ),
+        expr::Stmt::While(cond, body) => Node::new(
+            "While",
+            None,
+            vec![
+                ("condition", boxed(expr(cond))),
+                ("body", boxed(self::stmt(body))),
+            ],
+        ),
+        expr::Stmt::For {
+            location,
+            initializer,
+            condition,
+            increment,
+            body,
+        } => Node::new(
+            "For",
+            Some(*location),
+            vec![
+                ("initializer", maybe(initializer, |s| self::stmt(s))),
+                ("condition", maybe(condition, expr)),
+                ("increment", maybe(increment, expr)),
+                ("body", boxed(self::stmt(body))),
+            ],
+        ),
+        expr::Stmt::ForIn {
+            location,
+            variable,
+            iterable,
+            body,
+        } => Node::new(
+            "ForIn",
+            Some(*location),
+            vec![
+                ("variable", Field::Symbol(variable.clone())),
+                ("iterable", boxed(expr(iterable))),
+                ("body", boxed(self::stmt(body))),
+            ],
+        ),
+        expr::Stmt::Labeled(label, body) => Node::new(
+            "Labeled",
+            symbol_location(label),
+            vec![
+                ("label", Field::Name(label.name.clone())),
+                ("body", boxed(self::stmt(body))),
+            ],
+        ),
+        expr::Stmt::Break(loc, label) => {
+            Node::new("Break", Some(*loc), vec![("label", jump_label(label))])
+        }
+        expr::Stmt::Continue(loc, label) => {
+            Node::new("Continue", Some(*loc), vec![("label", jump_label(label))])
+        }
+        expr::Stmt::Import(import) => Node::new(
+            "Import",
+            Some(import.location),
+            vec![
+                ("path", Field::Str(import.path.clone())),
+                (
+                    "name",
+                    match &import.name {
+                        Some(name) => Field::Symbol(name.clone()),
+                        None => Field::Nil,
+                    },
+                ),
+            ],
+        ),
+        expr::Stmt::Throw(loc, e) => {
+            Node::new("Throw", Some(*loc), vec![("value", boxed(expr(e)))])
+        }
+        expr::Stmt::Try {
+            location,
+            body,
+            catch,
+            finally,
+        } => Node::new(
+            "Try",
+            Some(*location),
+            vec![
+                ("body", stmts(body)),
+                (
+                    "catch",
+                    match catch {
+                        Some((name, _)) => Field::Symbol(name.clone()),
+                        None => Field::Nil,
+                    },
+                ),
+                (
+                    "handler",
+                    match catch {
+                        Some((_, handler)) => stmts(handler),
+                        None => Field::Nil,
+                    },
+                ),
+                (
+                    "finally",
+                    match finally {
+                        Some(finally) => stmts(finally),
+                        None => Field::Nil,
+                    },
+                ),
+            ],
+        ),
+    }
+}
+
+fn unary_op(ty: expr::UnaryOpTy) -> &'static str {
+    match ty {
+        expr::UnaryOpTy::Minus => "-",
+        expr::UnaryOpTy::Bang => "!",
+        expr::UnaryOpTy::Tilde => "~",
+    }
+}
+
+fn binary_op(ty: expr::BinaryOpTy) -> &'static str {
+    match ty {
+        expr::BinaryOpTy::EqualEqual => "==",
+        expr::BinaryOpTy::NotEqual => "!=",
+        expr::BinaryOpTy::Less => "<",
+        expr::BinaryOpTy::LessEqual => "<=",
+        expr::BinaryOpTy::Greater => ">",
+        expr::BinaryOpTy::GreaterEqual => ">=",
+        expr::BinaryOpTy::Plus => "+",
+        expr::BinaryOpTy::Minus => "-",
+        expr::BinaryOpTy::Star => "*",
+        expr::BinaryOpTy::Slash => "/",
+        expr::BinaryOpTy::Percent => "%",
+        expr::BinaryOpTy::StarStar => "**",
+        expr::BinaryOpTy::Ampersand => "&",
+        expr::BinaryOpTy::Pipe => "|",
+        expr::BinaryOpTy::Caret => "^",
+        expr::BinaryOpTy::LessLess => "<<",
+        expr::BinaryOpTy::GreaterGreater => ">>",
+    }
+}
+
+fn expr(e: &expr::Expr) -> Node {
+    match e {
+        expr::Expr::Literal(loc, literal) => {
+            let value = match literal {
+                expr::Literal::Number(n) => Field::Number(*n),
+                expr::Literal::String(s) => Field::Str(s.clone()),
+                expr::Literal::True => Field::Bool(true),
+                expr::Literal::False => Field::Bool(false),
+                expr::Literal::Nil => Field::Nil,
+            };
+            Node::new("Literal", Some(*loc), vec![("value", value)])
+        }
+        expr::Expr::This(loc) => Node::new("This", Some(*loc), vec![]),
+        expr::Expr::Unary(op, e) => Node::new(
+            "Unary",
+            Some(expr::SourceLocation {
+                line: op.line,
+                col: op.col,
+            }),
+            vec![
+                ("op", Field::Name(unary_op(op.ty).to_string())),
+                ("operand", boxed(expr(e))),
+            ],
+        ),
+        expr::Expr::Binary(lhs, op, rhs) => Node::new(
+            "Binary",
+            Some(expr::SourceLocation {
+                line: op.line,
+                col: op.col,
+            }),
+            vec![
+                ("op", Field::Name(binary_op(op.ty).to_string())),
+                ("lhs", boxed(expr(lhs))),
+                ("rhs", boxed(expr(rhs))),
+            ],
+        ),
+        expr::Expr::Call(callee, loc, args) => Node::new(
+            "Call",
+            Some(*loc),
+            vec![
+                ("callee", boxed(expr(callee))),
+                ("args", Field::Nodes(args.iter().map(expr).collect())),
+            ],
+        ),
+        expr::Expr::Get(object, attr) => Node::new(
+            "Get",
+            symbol_location(attr),
+            vec![
+                ("object", boxed(expr(object))),
+                ("name", Field::Name(attr.name.clone())),
+            ],
+        ),
+        expr::Expr::Grouping(e) => Node::new("Grouping", None, vec![("expr", boxed(expr(e)))]),
+        expr::Expr::Variable(sym) => Node::new(
+            "Variable",
+            symbol_location(sym),
+            vec![("name", Field::Name(sym.name.clone()))],
+        ),
+        expr::Expr::Assign(sym, val) => Node::new(
+            "Assign",
+            symbol_location(sym),
+            vec![
+                ("name", Field::Name(sym.name.clone())),
+                ("value", boxed(expr(val))),
+            ],
+        ),
+        expr::Expr::Logical(lhs, op, rhs) => Node::new(
+            "Logical",
+            None,
+            vec![
+                (
+                    "op",
+                    Field::Name(
+                        match op {
+                            expr::LogicalOp::And => "and",
+                            expr::LogicalOp::Or => "or",
+                        }
+                        .to_string(),
+                    ),
+                ),
+                ("lhs", boxed(expr(lhs))),
+                ("rhs", boxed(expr(rhs))),
+            ],
+        ),
+        expr::Expr::Set(object, attr, val) => Node::new(
+            "Set",
+            symbol_location(attr),
+            vec![
+                ("object", boxed(expr(object))),
+                ("name", Field::Name(attr.name.clone())),
+                ("value", boxed(expr(val))),
+            ],
+        ),
+        expr::Expr::Super(loc, method) => Node::new(
+            "Super",
+            Some(*loc),
+            vec![("method", Field::Symbol(method.clone()))],
+        ),
+        expr::Expr::List(loc, elements) => Node::new(
+            "List",
+            Some(*loc),
+            vec![(
+                "elements",
+                Field::Nodes(elements.iter().map(expr).collect()),
+            )],
+        ),
+        expr::Expr::Map(loc, entries) => Node::new(
+            "Map",
+            Some(*loc),
+            vec![(
+                "entries",
+                Field::Nodes(
+                    entries
+                        .iter()
+                        .map(|(key, value)| {
+                            Node::new(
+                                "Entry",
+                                None,
+                                vec![("key", boxed(expr(key))), ("value", boxed(expr(value)))],
+                            )
+                        })
+                        .collect(),
+                ),
+            )],
+        ),
+        expr::Expr::Subscript {
+            value,
+            slice,
+            source_location,
+        } => Node::new(
+            "Subscript",
+            Some(*source_location),
+            vec![("value", boxed(expr(value))), ("index", boxed(expr(slice)))],
+        ),
+        expr::Expr::SetItem {
+            lhs,
+            slice,
+            rhs,
+            source_location,
+        } => Node::new(
+            "SetItem",
+            Some(*source_location),
+            vec![
+                ("value", boxed(expr(lhs))),
+                ("index", boxed(expr(slice))),
+                ("rhs", boxed(expr(rhs))),
+            ],
+        ),
+        expr::Expr::Lambda(lambda) => Node::new(
+            "Lambda",
+            Some(lambda.location),
+            vec![
+                ("params", Field::Params(lambda.params.clone())),
+                ("returns", annotation(&lambda.return_type)),
+                ("body", stmts(&lambda.body)),
+            ],
+        ),
+        expr::Expr::CompoundAssign(target, op, val) => Node::new(
+            "CompoundAssign",
+            Some(expr::SourceLocation {
+                line: op.line,
+                col: op.col,
+            }),
+            vec![
+                ("op", Field::Name(format!("{}=", binary_op(op.ty)))),
+                ("target", boxed(expr(target))),
+                ("value", boxed(expr(val))),
+            ],
+        ),
+        expr::Expr::Increment { target, op, prefix } => Node::new(
+            "Increment",
+            Some(expr::SourceLocation {
+                line: op.line,
+                col: op.col,
+            }),
+            vec![
+                ("op", Field::Name(binary_op(op.ty).repeat(2))),
+                ("prefix", Field::Bool(*prefix)),
+                ("target", boxed(expr(target))),
+            ],
+        ),
+        expr::Expr::Ternary(cond, then_branch, else_branch) => Node::new(
+            "Ternary",
+            None,
+            vec![
+                ("condition", boxed(expr(cond))),
+                ("then", boxed(expr(then_branch))),
+                ("else", boxed(expr(else_branch))),
+            ],
+        ),
+        expr::Expr::Interpolation(loc, parts) => Node::new(
+            "Interpolation",
+            Some(*loc),
+            vec![("parts", Field::Nodes(parts.iter().map(expr).collect()))],
+        ),
+        expr::Expr::Yield(loc, val) => Node::new(
+            "Yield",
+            Some(*loc),
+            vec![("value", maybe(val, |val| expr(val)))],
+        ),
+    }
+}
+
+/// `VarDecl` becomes `var-decl`.
+fn kebab(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            out.push('-');
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+enum Sexp {
+    Atom(String),
+    /// `(head ...)`
+    List(Vec<Sexp>),
+    /// `[...]`, for sequences inside a node
+    Vector(Vec<Sexp>),
+}
+
+fn flat(sexp: &Sexp) -> String {
+    match sexp {
+        Sexp::Atom(atom) => atom.clone(),
+        Sexp::List(items) => format!("({})", items.iter().map(flat).collect::<Vec<_>>().join(" ")),
+        Sexp::Vector(items) => {
+            format!("[{}]", items.iter().map(flat).collect::<Vec<_>>().join(" "))
+        }
+    }
+}
+
+/// Prints `sexp` on one line if it fits, otherwise a list keeps its leading atoms on the
+/// first line and puts each remaining item on a line of its own, indented under it.
+fn render(sexp: &Sexp, indent: usize) -> String {
+    let one_line = flat(sexp);
+    if indent + one_line.len() <= WIDTH {
+        return one_line;
+    }
+
+    match sexp {
+        Sexp::Atom(atom) => atom.clone(),
+        Sexp::List(items) => {
+            let head_len = items
+                .iter()
+                .take_while(|item| matches!(item, Sexp::Atom(_)))
+                .count();
+            let head: Vec<String> = items[..head_len].iter().map(flat).collect();
+            let mut out = format!("({}", head.join(" "));
+            for item in &items[head_len..] {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent + 2));
+                out.push_str(&render(item, indent + 2));
+            }
+            out.push(')');
+            out
+        }
+        Sexp::Vector(items) => {
+            let rendered: Vec<String> = items.iter().map(|item| render(item, indent + 1)).collect();
+            format!(
+                "[{}]",
+                rendered.join(&format!("\n{}", " ".repeat(indent + 1)))
+            )
+        }
+    }
+}
@@ -0,0 +1,380 @@
+//! Hosting roxy inside a Rust program: an `Engine` evaluates source strings against a
+//! persistent set of globals, and `FromValue`/`IntoValue` move data across the boundary.
+
+use crate::diagnostic::Diagnostic;
+use crate::err::RoxyErr;
+use crate::expr;
+use crate::extensions::Extensions;
+use crate::interpreter::Interpreter;
+use crate::limits::Limits;
+use crate::modules::{LoadError, Loader};
+use crate::parser;
+use crate::resolver;
+use crate::scanner;
+use crate::typecheck;
+use crate::value::{NativeFunction, Value};
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub enum Error {
+    Scan(scanner::Error),
+    Parse(Vec<parser::Error>),
+    Resolve(resolver::Error),
+    Type(Vec<typecheck::Error>),
+    Runtime(RoxyErr),
+    Import(LoadError),
+}
+
+impl Error {
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Error::Scan(err) => vec![Diagnostic::from(err)],
+            Error::Parse(errs) => errs.iter().map(Diagnostic::from).collect(),
+            Error::Resolve(err) => vec![Diagnostic::from(err)],
+            Error::Type(errs) => errs.iter().map(Diagnostic::from).collect(),
+            Error::Runtime(err) => vec![Diagnostic::from(err)],
+            Error::Import(err) => err.diagnostics.clone(),
+        }
+    }
+
+    /// Renders every diagnostic against `source`, which should be what was evaluated.
+    pub fn render(&self, source_name: &str, source: &str) -> String {
+        self.diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.render(source_name, source))
+            .collect()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let messages: Vec<String> = self
+            .diagnostics()
+            .into_iter()
+            .map(|diagnostic| match diagnostic.line {
+                // errors raised from the Rust side have no place in the source
+                0 => diagnostic.message,
+                line => format!("{}:{}: {}", line, diagnostic.col + 1, diagnostic.message),
+            })
+            .collect();
+        write!(f, "{}", messages.join("\n"))
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Converts a roxy value into a Rust type. Failures are `RoxyErr::NativeError`s, so natives
+/// can use `?` on their arguments and have the error reported at the roxy call site.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, RoxyErr>;
+}
+
+/// Converts a Rust value into a roxy one.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+fn mismatch(expected: &str, value: &Value) -> RoxyErr {
+    RoxyErr::NativeError(format!(
+        "expected {}, found {:?}",
+        expected,
+        value.type_of()
+    ))
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, RoxyErr> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, RoxyErr> {
+        match value {
+            Value::Number(n) => Ok(*n),
+            _ => Err(mismatch("Number", value)),
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<Self, RoxyErr> {
+        match value {
+            Value::Number(n) if n.fract() == 0.0 => Ok(*n as i64),
+            _ => Err(mismatch("whole Number", value)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, RoxyErr> {
+        match value {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(mismatch("Bool", value)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, RoxyErr> {
+        match value {
+            Value::String(s) => Ok(s.clone()),
+            _ => Err(mismatch("String", value)),
+        }
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: &Value) -> Result<Self, RoxyErr> {
+        match value {
+            Value::Nil => Ok(()),
+            _ => Err(mismatch("Nil", value)),
+        }
+    }
+}
+
+/// `nil` converts to `None`, anything else has to convert to `T`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, RoxyErr> {
+        match value {
+            Value::Nil => Ok(None),
+            _ => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, RoxyErr> {
+        match value {
+            Value::List(elements) => elements.borrow().iter().map(T::from_value).collect(),
+            _ => Err(mismatch("List", value)),
+        }
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Number(self as f64)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::String(self)
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(val) => val.into_value(),
+            None => Value::Nil,
+        }
+    }
+}
+
+/// Lists need `Extensions.lists` to be useful from roxy code, but can always be passed in.
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        let elements = self.into_iter().map(IntoValue::into_value).collect();
+        Value::List(Rc::new(RefCell::new(elements)))
+    }
+}
+
+/// Turns a trailing expression statement into a top-level return, so running the program
+/// hands back that expression's value.
+pub(crate) fn return_trailing_expr(stmts: &mut [expr::Stmt]) {
+    if let Some(last) = stmts.last_mut() {
+        if let expr::Stmt::Expr(e) = last {
+            let loc = expr::SourceLocation { line: 0, col: -1 };
+            *last = expr::Stmt::Return(loc, Some(e.clone()));
+        }
+    }
+}
+
+/// A roxy interpreter for use from Rust. Globals defined by one `eval` are visible to the
+/// next, so an engine can load a script once and then query or call into it.
+///
+/// ```
+/// use roxy::{Engine, Value};
+///
+/// let mut engine = Engine::new();
+/// engine.register("double", 1, |args| {
+///     let n: f64 = roxy::FromValue::from_value(&args[0])?;
+///     Ok(Value::Number(n * 2.0))
+/// });
+/// engine.eval("var limit = double(21);").unwrap();
+/// assert_eq!(engine.get::<f64>("limit").unwrap(), 42.0);
+/// ```
+pub struct Engine {
+    extensions: Extensions,
+    interpreter: Interpreter,
+    loader: Loader,
+    checker: typecheck::Checker,
+}
+
+impl Default for Engine {
+    fn default() -> Engine {
+        Engine::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::with_extensions(Extensions::default())
+    }
+
+    pub fn with_extensions(extensions: Extensions) -> Engine {
+        Engine {
+            extensions,
+            interpreter: Interpreter::new(extensions),
+            loader: Loader::new(extensions),
+            checker: typecheck::Checker::new(),
+        }
+    }
+
+    /// Holds every later `eval` and `call` to `limits`, each counting its steps, time, output
+    /// and allocations from nothing. Running out of a limit is a `RoxyErr::LimitExceeded`.
+    ///
+    /// ```
+    /// use roxy::{Engine, Limits, RoxyErr};
+    /// use roxy::embed::Error;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.set_limits(Limits {
+    ///     steps: Some(10_000),
+    ///     io: false,
+    ///     ..Limits::default()
+    /// });
+    /// match engine.eval("while (true) {}") {
+    ///     Err(Error::Runtime(RoxyErr::LimitExceeded { .. })) => {}
+    ///     res => panic!("expected the step limit to stop the loop, got {:?}", res),
+    /// }
+    /// ```
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interpreter.set_limits(limits);
+        self.loader.set_limits(limits);
+    }
+
+    /// Tells the engine how big the stack of the thread calling into it is, when that is not
+    /// the 2MB Rust gives the threads it spawns. Runaway recursion is an `Err` with a stack
+    /// overflow error either way; a bigger stack lets calls nest deeper before that.
+    pub fn set_stack_size(&mut self, bytes: usize) {
+        self.interpreter.set_stack_size(bytes);
+    }
+
+    /// Defines a global native function backed by `func`, replacing any global of that name.
+    pub fn register<F>(&mut self, name: &str, arity: u8, func: F)
+    where
+        F: Fn(&[Value]) -> Result<Value, RoxyErr> + 'static,
+    {
+        self.interpreter.define_native(NativeFunction {
+            name: name.to_string(),
+            arity,
+            callable: Rc::new(move |_, args| func(args)),
+        });
+    }
+
+    /// Runs `source` and returns the value of its final expression statement, or nil. Like
+    /// at the REPL, a lone expression such as `1 + 2` may leave off its semicolon. Imports
+    /// are relative to the current directory.
+    pub fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        self.interpreter.restart_limits();
+        let tokens = scanner::scan_tokens(source.to_string()).map_err(Error::Scan)?;
+        let stmts = match parser::parse(self.extensions, tokens) {
+            Ok(stmts) => stmts,
+            Err(errs) => scanner::scan_tokens(format!("{};", source))
+                .ok()
+                .and_then(|tokens| parser::parse(self.extensions, tokens).ok())
+                .ok_or(Error::Parse(errs))?,
+        };
+
+        let modules = self
+            .loader
+            .load_imports(None, "<eval>", source, &stmts)
+            .map_err(Error::Import)?;
+        for module in modules {
+            self.run(module.stmts, false)?;
+        }
+
+        self.run(stmts, true).map(|val| val.unwrap_or(Value::Nil))
+    }
+
+    fn run(&mut self, mut stmts: Vec<expr::Stmt>, echo: bool) -> Result<Option<Value>, Error> {
+        let locals = resolver::resolve(&stmts).map_err(Error::Resolve)?;
+        self.checker.check(&stmts).map_err(Error::Type)?;
+        if echo {
+            return_trailing_expr(&mut stmts);
+        }
+        self.interpreter.resolve(locals);
+        self.interpreter.interpret(&stmts).map_err(Error::Runtime)
+    }
+
+    /// Reads the global `name` as a `T`.
+    pub fn get<T: FromValue>(&self, name: &str) -> Result<T, Error> {
+        let sym = expr::Symbol {
+            name: name.to_string(),
+            line: 0,
+            col: -1,
+        };
+        let val = self
+            .interpreter
+            .globals
+            .borrow()
+            .get(&sym)
+            .map_err(Error::Runtime)?;
+        T::from_value(&val).map_err(Error::Runtime)
+    }
+
+    /// Defines (or overwrites) the global `name`.
+    pub fn set<T: IntoValue>(&mut self, name: &str, value: T) {
+        self.interpreter
+            .globals
+            .borrow_mut()
+            .define(name.to_string(), value.into_value());
+    }
+
+    /// Calls the global function `name` and converts what it returns.
+    pub fn call<T: FromValue>(&mut self, name: &str, args: Vec<Value>) -> Result<T, Error> {
+        let callee = self.get::<Value>(name)?;
+        self.interpreter.restart_limits();
+        let val = self
+            .interpreter
+            .call_function(&callee, args)
+            .map_err(Error::Runtime)?;
+        T::from_value(&val).map_err(Error::Runtime)
+    }
+}
@@ -1,3 +1,5 @@
+use crate::limits::Limit;
+
 #[derive(Debug, PartialEq)]
 pub enum RoxyErr {
     CharNotFound,
@@ -8,4 +10,38 @@ pub enum RoxyErr {
     Utf8Error(std::str::Utf8Error),
     UnterminatedString,
     ParseFloatError(std::num::ParseFloatError),
+
+    /// raised by a native function; the call that invoked it fills in the location
+    NativeError(String),
+
+    RuntimeError {
+        what: String,
+        line: usize,
+        col: i64,
+        trace: Vec<Frame>,
+    },
+
+    /// a value raised by `throw` that no `catch` handled; `what` is the value as `print` shows it
+    Thrown {
+        what: String,
+        line: usize,
+        col: i64,
+        trace: Vec<Frame>,
+    },
+
+    /// the program ran out of one of its `Limits`, at the statement or instruction it had got to
+    LimitExceeded {
+        limit: Limit,
+        line: usize,
+        col: i64,
+    },
+}
+
+/// A call that was still running when an error unwound through it: the function and the place
+/// it was called from. Traces list the innermost call first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub function: String,
+    pub line: usize,
+    pub col: i64,
 }
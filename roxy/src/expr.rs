@@ -1,6 +1,6 @@
 #[derive(Debug, Clone)]
 pub enum Expr {
-    Literal(Literal),
+    Literal(SourceLocation, Literal),
     This(SourceLocation),
     Unary(UnaryOp, Box<Expr>),
     Binary(Box<Expr>, BinaryOp, Box<Expr>),
@@ -12,7 +12,7 @@ pub enum Expr {
     Logical(Box<Expr>, LogicalOp, Box<Expr>),
     Set(Box<Expr>, Symbol, Box<Expr>),
     Super(SourceLocation, Symbol),
-    List(Vec<Expr>),
+    List(SourceLocation, Vec<Expr>),
     Subscript {
         value: Box<Expr>,
         slice: Box<Expr>,
@@ -25,6 +25,68 @@ pub enum Expr {
         source_location: SourceLocation,
     },
     Lambda(LambdaDecl),
+    /// `target op= value`, where the target is a variable, a property or a subscript and is
+    /// evaluated only once
+    CompoundAssign(Box<Expr>, BinaryOp, Box<Expr>),
+    /// `++` or `--` before or after a target like `CompoundAssign`'s; `op` is the `+` or `-` it
+    /// stands for, located at the operator
+    Increment {
+        target: Box<Expr>,
+        op: BinaryOp,
+        prefix: bool,
+    },
+    /// `condition ? then : else`
+    Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// A string with `${}` holes, located at its opening quote. The parts alternate between the
+    /// string literals around the holes and the expressions in them, starting and ending with a
+    /// literal.
+    Interpolation(SourceLocation, Vec<Expr>),
+    /// `{key: value, ...}`, located at its opening brace
+    Map(SourceLocation, Vec<(Expr, Expr)>),
+    /// `yield value`, located at the keyword. The resolver only lets a generator use one as a
+    /// statement of its own, a variable's initializer or the value assigned to a variable.
+    Yield(SourceLocation, Option<Box<Expr>>),
+}
+
+impl Expr {
+    /// Roughly where the expression starts: a grouping reports what is inside its parentheses.
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            Expr::Literal(loc, _)
+            | Expr::This(loc)
+            | Expr::Super(loc, _)
+            | Expr::List(loc, _)
+            | Expr::Interpolation(loc, _)
+            | Expr::Map(loc, _)
+            | Expr::Yield(loc, _) => *loc,
+            Expr::Increment {
+                op, prefix: true, ..
+            } => SourceLocation {
+                line: op.line,
+                col: op.col,
+            },
+            Expr::Unary(op, _) => SourceLocation {
+                line: op.line,
+                col: op.col,
+            },
+            Expr::Variable(sym) | Expr::Assign(sym, _) => SourceLocation {
+                line: sym.line,
+                col: sym.col,
+            },
+            Expr::Binary(e, _, _)
+            | Expr::Call(e, _, _)
+            | Expr::Get(e, _)
+            | Expr::Grouping(e)
+            | Expr::Logical(e, _, _)
+            | Expr::Set(e, _, _)
+            | Expr::Subscript { value: e, .. }
+            | Expr::SetItem { lhs: e, .. }
+            | Expr::CompoundAssign(e, _, _)
+            | Expr::Increment { target: e, .. }
+            | Expr::Ternary(e, _, _) => e.location(),
+            Expr::Lambda(lambda) => lambda.location,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -46,24 +108,80 @@ pub struct Symbol {
     pub col: i64,
 }
 
+/// A parameter, with the name of the type it is annotated with, as in `a: num`.
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: Symbol,
+    pub ty: Option<Symbol>,
+}
+
 #[derive(Debug, Clone)]
 pub struct FunDecl {
     pub name: Symbol,
-    pub params: Vec<Symbol>,
+    pub params: Vec<Param>,
+    /// the annotation after the parameter list, as in `fun f(): num`
+    pub return_type: Option<Symbol>,
     pub body: Vec<Stmt>,
+    /// declared with `fun*`, or with `*` before a method's name: a call makes a generator that
+    /// runs the body a `yield` at a time
+    pub generator: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct LambdaDecl {
-    pub params: Vec<Symbol>,
+    /// where the `lambda` keyword is
+    pub location: SourceLocation,
+    pub params: Vec<Param>,
+    pub return_type: Option<Symbol>,
     pub body: Vec<Stmt>,
 }
 
+/// What a member of a class declaration is, going by the word in front of its name: nothing,
+/// `static`, `get` or `set`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MethodKind {
+    Method,
+    /// called on the class itself, with `this` bound to the class
+    Static,
+    /// `get name { ... }`, called without arguments when the property is read
+    Getter,
+    /// `set name(value) { ... }`, called when the property is assigned to
+    Setter,
+}
+
+impl MethodKind {
+    pub const ALL: [MethodKind; 4] = [
+        MethodKind::Method,
+        MethodKind::Static,
+        MethodKind::Getter,
+        MethodKind::Setter,
+    ];
+}
+
+#[derive(Debug, Clone)]
+pub struct Method {
+    pub kind: MethodKind,
+    pub decl: FunDecl,
+}
+
 #[derive(Debug, Clone)]
 pub struct ClassDecl {
     pub name: Symbol,
     pub superclass: Option<Symbol>,
-    pub methods: Vec<FunDecl>,
+    /// the classes after `with`, whose methods are copied in ahead of the class's own
+    pub mixins: Vec<Symbol>,
+    pub methods: Vec<Method>,
+}
+
+/// `import "path";` or `import name from "path";`. The driver loads and runs the module
+/// before the importing program, so backends treat the statement itself as a no-op. `name`
+/// only has to be declared by the module; it binds nothing the plain form does not.
+#[derive(Debug, Clone)]
+pub struct ImportDecl {
+    pub path: String,
+    /// where the path string starts
+    pub location: SourceLocation,
+    pub name: Option<Symbol>,
 }
 
 #[derive(Debug, Clone)]
@@ -72,17 +190,76 @@ pub enum Stmt {
     FunDecl(FunDecl),
     ClassDecl(ClassDecl),
     If(Expr, Box<Stmt>, Option<Box<Stmt>>),
-    Print(Expr),
-    VarDecl(Symbol, Option<Expr>),
-    Block(Vec<Stmt>),
+    Print(SourceLocation, Expr),
+    /// the name, its type annotation and its initializer
+    VarDecl(Symbol, Option<Symbol>, Option<Expr>),
+    /// located at its opening brace
+    Block(SourceLocation, Vec<Stmt>),
     Return(SourceLocation, Option<Expr>),
     While(Expr, Box<Stmt>),
+    For {
+        location: SourceLocation,
+        initializer: Option<Box<Stmt>>,
+        condition: Option<Expr>,
+        increment: Option<Expr>,
+        body: Box<Stmt>,
+    },
+    /// `for (variable in iterable) body`; the variable is declared afresh for every element
+    ForIn {
+        location: SourceLocation,
+        variable: Symbol,
+        iterable: Expr,
+        body: Box<Stmt>,
+    },
+    /// `label: loop`; the parser only lets loops be labeled
+    Labeled(Symbol, Box<Stmt>),
+    /// with the label of the loop to leave, if it names one
+    Break(SourceLocation, Option<Symbol>),
+    /// with the label of the loop to go round again, if it names one
+    Continue(SourceLocation, Option<Symbol>),
+    Import(ImportDecl),
+    Throw(SourceLocation, Expr),
+    /// `catch` binds the thrown value, or a runtime error's message, to its symbol; the parser
+    /// makes sure there is a `catch` or a `finally`
+    Try {
+        location: SourceLocation,
+        body: Vec<Stmt>,
+        catch: Option<(Symbol, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    },
+}
+
+impl Stmt {
+    /// Roughly where the statement starts; for declarations, where the declared name is.
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            Stmt::Expr(e) | Stmt::If(e, _, _) | Stmt::While(e, _) => e.location(),
+            Stmt::FunDecl(FunDecl { name, .. })
+            | Stmt::ClassDecl(ClassDecl { name, .. })
+            | Stmt::VarDecl(name, _, _)
+            | Stmt::Labeled(name, _) => SourceLocation {
+                line: name.line,
+                col: name.col,
+            },
+            Stmt::Print(loc, _)
+            | Stmt::Block(loc, _)
+            | Stmt::Return(loc, _)
+            | Stmt::Break(loc, _)
+            | Stmt::Continue(loc, _)
+            | Stmt::For { location: loc, .. }
+            | Stmt::ForIn { location: loc, .. }
+            | Stmt::Throw(loc, _)
+            | Stmt::Try { location: loc, .. } => *loc,
+            Stmt::Import(import) => import.location,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub enum UnaryOpTy {
     Minus,
     Bang,
+    Tilde,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -104,6 +281,13 @@ pub enum BinaryOpTy {
     Minus,
     Star,
     Slash,
+    Percent,
+    StarStar,
+    Ampersand,
+    Pipe,
+    Caret,
+    LessLess,
+    GreaterGreater,
 }
 
 #[derive(Debug, Copy, Clone)]
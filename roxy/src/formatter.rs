@@ -0,0 +1,708 @@
+//! Pretty-prints roxy source in one canonical layout. The syntax tree decides the layout; the
+//! printer walks the source tokens in step with it, which is how comments, blank lines and the
+//! spelling of literals survive.
+
+use crate::diagnostic::Diagnostic;
+use crate::expr;
+use crate::extensions::Extensions;
+use crate::parser;
+use crate::scanner::{self, Comment, Token, TokenType};
+
+const INDENT: &str = "    ";
+
+/// Formats a whole file. Source that doesn't parse is left alone and its errors returned.
+pub fn format_source(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let (tokens, comments) = scanner::scan_tokens_with_comments(source.to_string())
+        .map_err(|err| vec![Diagnostic::from(&err)])?;
+    // formatting shouldn't depend on which extensions the script is run with
+    let extensions = Extensions {
+        lists: true,
+        lambdas: true,
+    };
+    let stmts = parser::parse(extensions, tokens.clone())
+        .map_err(|errs| errs.iter().map(Diagnostic::from).collect::<Vec<_>>())?;
+
+    let mut printer = Printer {
+        tokens: &tokens,
+        pos: 0,
+        comments: &comments,
+        next_comment: 0,
+        out: String::new(),
+        indent: 0,
+        at_line_start: true,
+        continuation: false,
+        last_line: 0,
+        block_start: true,
+    };
+    for stmt in &stmts {
+        printer.stmt(stmt);
+    }
+    printer.flush_comments_before(usize::MAX, 0);
+    printer.end_line();
+    Ok(printer.out)
+}
+
+struct Printer<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    comments: &'a [Comment],
+    next_comment: usize,
+    out: String,
+    indent: usize,
+    at_line_start: bool,
+    // a comment has broken the current statement over lines, so the lines after the first are
+    // indented one more level
+    continuation: bool,
+    // the source line the last thing printed ended on
+    last_line: usize,
+    // nothing has been printed yet in the current block, so no blank line is wanted
+    block_start: bool,
+}
+
+impl<'a> Printer<'a> {
+    fn peek(&self) -> &'a Token {
+        &self.tokens[self.pos]
+    }
+
+    fn peek_or_last(&self) -> &'a Token {
+        self.tokens
+            .get(self.pos)
+            .unwrap_or(self.tokens.last().unwrap())
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.at_line_start {
+            let indent = self.indent + usize::from(self.continuation);
+            self.out.push_str(&INDENT.repeat(indent));
+            self.at_line_start = false;
+        }
+        self.out.push_str(text);
+        self.block_start = false;
+    }
+
+    fn space(&mut self) {
+        self.write(" ");
+    }
+
+    /// Moves to a fresh line, taking along a comment that ends the current one.
+    fn end_line(&mut self) {
+        if self.at_line_start {
+            return;
+        }
+        // no trailing whitespace, and always the one space before a comment
+        self.out.truncate(self.out.trim_end_matches(' ').len());
+        if let Some(comment) = self.comments.get(self.next_comment) {
+            let next = self.peek_or_last();
+            if comment.line == self.last_line && (comment.line, comment.col) < (next.line, next.col)
+            {
+                self.write(" ");
+                self.write(&comment.text);
+                self.next_comment += 1;
+            }
+        }
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    /// Keeps a single blank line where the source had one or more before `line`.
+    fn blank_line_before(&mut self, line: usize) {
+        if !self.block_start && self.last_line != 0 && line > self.last_line + 1 {
+            self.out.push('\n');
+        }
+    }
+
+    /// Prints the next source token, which has to be of type `ty`.
+    fn tok(&mut self, ty: TokenType) {
+        let token = self.peek();
+        // statements and braces flush the comments before them, so any still waiting here sit
+        // inside a statement, which carries on on the line after them
+        if self
+            .comments
+            .get(self.next_comment)
+            .is_some_and(|comment| (comment.line, comment.col) < (token.line, token.col))
+        {
+            self.continuation = true;
+        }
+        self.flush_comments_before(token.line, token.col);
+        debug_assert_eq!(
+            token.ty, ty,
+            "internal formatter error: printer and source out of step"
+        );
+        let lexeme = String::from_utf8_lossy(&token.lexeme);
+        self.write(&lexeme);
+        // string literals may span lines
+        self.last_line = token.line + lexeme.matches('\n').count();
+        self.pos += 1;
+    }
+
+    /// Prints the comments that come before `line`/`col`. One sharing a line with code that
+    /// was already printed stays at the end of that line, anything else gets its own.
+    fn flush_comments_before(&mut self, line: usize, col: i64) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if (comment.line, comment.col) >= (line, col) {
+                break;
+            }
+            if comment.line == self.last_line && !self.at_line_start {
+                // `end_line` takes it along
+                self.end_line();
+                continue;
+            }
+            self.end_line();
+            self.blank_line_before(comment.line);
+            self.write(&comment.text);
+            self.last_line = comment.line;
+            self.next_comment += 1;
+            self.end_line();
+        }
+    }
+
+    /// `else`, `catch` or `finally`. A comment before one is part of the statement around it,
+    /// so it doesn't make the keyword a continuation line.
+    fn clause(&mut self, ty: TokenType) {
+        let next = self.peek();
+        self.flush_comments_before(next.line, next.col);
+        self.tok(ty);
+    }
+
+    fn stmt(&mut self, stmt: &expr::Stmt) {
+        self.continuation = false;
+        let next = self.peek();
+        self.flush_comments_before(next.line, next.col);
+        self.end_line();
+        self.blank_line_before(next.line);
+        self.stmt_inline(stmt);
+    }
+
+    /// Prints a statement from wherever the output currently is.
+    fn stmt_inline(&mut self, stmt: &expr::Stmt) {
+        match stmt {
+            expr::Stmt::Expr(e) => {
+                self.expr(e);
+                self.tok(TokenType::Semicolon);
+            }
+            expr::Stmt::FunDecl(fundecl) => {
+                self.tok(TokenType::Fun);
+                if fundecl.generator {
+                    self.tok(TokenType::Star);
+                }
+                self.space();
+                self.tok(TokenType::Identifier);
+                self.params_and_body(&fundecl.params, &fundecl.return_type, &fundecl.body);
+            }
+            expr::Stmt::ClassDecl(classdecl) => {
+                self.tok(TokenType::Class);
+                self.space();
+                self.tok(TokenType::Identifier);
+                if classdecl.superclass.is_some() {
+                    self.space();
+                    self.tok(TokenType::Less);
+                    self.space();
+                    self.tok(TokenType::Identifier);
+                }
+                if !classdecl.mixins.is_empty() {
+                    // `with`
+                    self.space();
+                    self.tok(TokenType::Identifier);
+                    for idx in 0..classdecl.mixins.len() {
+                        if idx > 0 {
+                            self.tok(TokenType::Comma);
+                        }
+                        self.space();
+                        self.tok(TokenType::Identifier);
+                    }
+                }
+                self.space();
+                if classdecl.methods.is_empty() && !self.comments_before_closing_brace() {
+                    self.tok(TokenType::LeftBrace);
+                    self.tok(TokenType::RightBrace);
+                    return;
+                }
+                self.open_brace();
+                for method in &classdecl.methods {
+                    let next = self.peek();
+                    self.flush_comments_before(next.line, next.col);
+                    self.end_line();
+                    self.blank_line_before(next.line);
+                    if method.kind != expr::MethodKind::Method {
+                        // `static`, `get` or `set`
+                        self.tok(TokenType::Identifier);
+                        self.space();
+                    }
+                    let decl = &method.decl;
+                    if decl.generator {
+                        self.tok(TokenType::Star);
+                    }
+                    self.tok(TokenType::Identifier);
+                    if method.kind == expr::MethodKind::Getter {
+                        self.annotation(&decl.return_type);
+                        self.space();
+                        self.block(&decl.body);
+                    } else {
+                        self.params_and_body(&decl.params, &decl.return_type, &decl.body);
+                    }
+                }
+                self.close_brace();
+            }
+            expr::Stmt::If(cond, then_branch, maybe_else_branch) => {
+                self.tok(TokenType::If);
+                self.space();
+                self.tok(TokenType::LeftParen);
+                self.expr(cond);
+                self.tok(TokenType::RightParen);
+                self.body(then_branch);
+                if let Some(else_branch) = maybe_else_branch {
+                    match **then_branch {
+                        expr::Stmt::Block(..) => self.space(),
+                        _ => self.end_line(),
+                    }
+                    self.clause(TokenType::Else);
+                    self.body(else_branch);
+                }
+            }
+            expr::Stmt::Print(_, e) => {
+                self.tok(TokenType::Print);
+                self.space();
+                self.expr(e);
+                self.tok(TokenType::Semicolon);
+            }
+            expr::Stmt::VarDecl(_, ty, maybe_initializer) => {
+                self.tok(TokenType::Var);
+                self.space();
+                self.tok(TokenType::Identifier);
+                self.annotation(ty);
+                if let Some(initializer) = maybe_initializer {
+                    self.space();
+                    self.tok(TokenType::Equal);
+                    self.space();
+                    self.expr(initializer);
+                }
+                self.tok(TokenType::Semicolon);
+            }
+            expr::Stmt::Block(_, stmts) => self.block(stmts),
+            expr::Stmt::Return(_, maybe_res) => {
+                self.tok(TokenType::Return);
+                if let Some(res) = maybe_res {
+                    self.space();
+                    self.expr(res);
+                }
+                self.tok(TokenType::Semicolon);
+            }
+            expr::Stmt::While(cond, body) => {
+                self.tok(TokenType::While);
+                self.space();
+                self.tok(TokenType::LeftParen);
+                self.expr(cond);
+                self.tok(TokenType::RightParen);
+                self.body(body);
+            }
+            expr::Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.tok(TokenType::For);
+                self.space();
+                self.tok(TokenType::LeftParen);
+                match initializer {
+                    Some(initializer) => self.stmt_inline(initializer),
+                    None => self.tok(TokenType::Semicolon),
+                }
+                if let Some(condition) = condition {
+                    self.space();
+                    self.expr(condition);
+                }
+                self.tok(TokenType::Semicolon);
+                if let Some(increment) = increment {
+                    self.space();
+                    self.expr(increment);
+                }
+                self.tok(TokenType::RightParen);
+                self.body(body);
+            }
+            expr::Stmt::ForIn { iterable, body, .. } => {
+                self.tok(TokenType::For);
+                self.space();
+                self.tok(TokenType::LeftParen);
+                self.tok(TokenType::Identifier);
+                self.space();
+                self.tok(TokenType::In);
+                self.space();
+                self.expr(iterable);
+                self.tok(TokenType::RightParen);
+                self.body(body);
+            }
+            expr::Stmt::Labeled(_, body) => {
+                self.tok(TokenType::Identifier);
+                self.tok(TokenType::Colon);
+                self.space();
+                self.stmt_inline(body);
+            }
+            expr::Stmt::Break(_, label) => {
+                self.tok(TokenType::Break);
+                if label.is_some() {
+                    self.space();
+                    self.tok(TokenType::Identifier);
+                }
+                self.tok(TokenType::Semicolon);
+            }
+            expr::Stmt::Continue(_, label) => {
+                self.tok(TokenType::Continue);
+                if label.is_some() {
+                    self.space();
+                    self.tok(TokenType::Identifier);
+                }
+                self.tok(TokenType::Semicolon);
+            }
+            expr::Stmt::Import(import) => {
+                self.tok(TokenType::Import);
+                self.space();
+                if import.name.is_some() {
+                    self.tok(TokenType::Identifier);
+                    self.space();
+                    // the contextual `from`
+                    self.tok(TokenType::Identifier);
+                    self.space();
+                }
+                self.tok(TokenType::String);
+                self.tok(TokenType::Semicolon);
+            }
+            expr::Stmt::Throw(_, e) => {
+                self.tok(TokenType::Throw);
+                self.space();
+                self.expr(e);
+                self.tok(TokenType::Semicolon);
+            }
+            expr::Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.tok(TokenType::Try);
+                self.space();
+                self.block(body);
+                if let Some((_, handler)) = catch {
+                    self.space();
+                    self.clause(TokenType::Catch);
+                    self.space();
+                    self.tok(TokenType::LeftParen);
+                    self.tok(TokenType::Identifier);
+                    self.tok(TokenType::RightParen);
+                    self.space();
+                    self.block(handler);
+                }
+                if let Some(finally) = finally {
+                    self.space();
+                    self.clause(TokenType::Finally);
+                    self.space();
+                    self.block(finally);
+                }
+            }
+        }
+    }
+
+    /// The body of an `if`, `else`, `while` or `for`: a block, or one statement on the same line.
+    fn body(&mut self, stmt: &expr::Stmt) {
+        self.space();
+        self.stmt_inline(stmt);
+    }
+
+    fn open_brace(&mut self) {
+        self.tok(TokenType::LeftBrace);
+        self.end_line();
+        self.continuation = false;
+        self.indent += 1;
+        self.block_start = true;
+    }
+
+    fn close_brace(&mut self) {
+        self.continuation = false;
+        let next = self.peek();
+        self.flush_comments_before(next.line, next.col);
+        self.end_line();
+        self.indent -= 1;
+        self.tok(TokenType::RightBrace);
+    }
+
+    fn block(&mut self, stmts: &[expr::Stmt]) {
+        if stmts.is_empty() && !self.comments_before_closing_brace() {
+            self.tok(TokenType::LeftBrace);
+            self.tok(TokenType::RightBrace);
+            return;
+        }
+
+        self.open_brace();
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.close_brace();
+    }
+
+    /// Whether any comments sit between the `{` that is the next token and its `}`.
+    fn comments_before_closing_brace(&self) -> bool {
+        match self.comments.get(self.next_comment) {
+            Some(comment) => {
+                let close = self.closing_brace();
+                (comment.line, comment.col) < (close.line, close.col)
+            }
+            None => false,
+        }
+    }
+
+    /// The `}` matching the `{` that is the next token.
+    fn closing_brace(&self) -> &'a Token {
+        let mut depth = 0;
+        for token in &self.tokens[self.pos..] {
+            match token.ty {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return token;
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.tokens.last().unwrap()
+    }
+
+    fn params_and_body(
+        &mut self,
+        params: &[expr::Param],
+        return_type: &Option<expr::Symbol>,
+        body: &[expr::Stmt],
+    ) {
+        self.tok(TokenType::LeftParen);
+        for (idx, param) in params.iter().enumerate() {
+            if idx > 0 {
+                self.tok(TokenType::Comma);
+                self.space();
+            }
+            self.tok(TokenType::Identifier);
+            self.annotation(&param.ty);
+        }
+        self.tok(TokenType::RightParen);
+        self.annotation(return_type);
+        self.space();
+        self.block(body);
+    }
+
+    /// `: type`, tight against what it annotates.
+    fn annotation(&mut self, ty: &Option<expr::Symbol>) {
+        if ty.is_some() {
+            self.tok(TokenType::Colon);
+            self.space();
+            // the type's name may be the `nil` or `fun` keyword
+            let name = self.peek().ty;
+            self.tok(name);
+        }
+    }
+
+    fn comma_separated(&mut self, exprs: &[expr::Expr]) {
+        for (idx, e) in exprs.iter().enumerate() {
+            if idx > 0 {
+                self.tok(TokenType::Comma);
+                self.space();
+            }
+            self.expr(e);
+        }
+    }
+
+    fn expr(&mut self, e: &expr::Expr) {
+        match e {
+            expr::Expr::Literal(_, literal) => self.tok(match literal {
+                expr::Literal::Number(_) => TokenType::Number,
+                expr::Literal::String(_) => TokenType::String,
+                expr::Literal::True => TokenType::True,
+                expr::Literal::False => TokenType::False,
+                expr::Literal::Nil => TokenType::Nil,
+            }),
+            expr::Expr::This(_) => self.tok(TokenType::This),
+            expr::Expr::Unary(op, e) => {
+                self.tok(match op.ty {
+                    expr::UnaryOpTy::Minus => TokenType::Minus,
+                    expr::UnaryOpTy::Bang => TokenType::Bang,
+                    expr::UnaryOpTy::Tilde => TokenType::Tilde,
+                });
+                self.expr(e);
+            }
+            expr::Expr::Binary(lhs, op, rhs) => {
+                self.expr(lhs);
+                self.space();
+                self.tok(binary_op_token(op.ty));
+                self.space();
+                self.expr(rhs);
+            }
+            expr::Expr::Call(callee, _, args) => {
+                self.expr(callee);
+                self.tok(TokenType::LeftParen);
+                self.comma_separated(args);
+                self.tok(TokenType::RightParen);
+            }
+            expr::Expr::Get(e, _) => {
+                self.expr(e);
+                self.tok(TokenType::Dot);
+                self.tok(TokenType::Identifier);
+            }
+            expr::Expr::Grouping(e) => {
+                self.tok(TokenType::LeftParen);
+                self.expr(e);
+                self.tok(TokenType::RightParen);
+            }
+            expr::Expr::Variable(_) => self.tok(TokenType::Identifier),
+            expr::Expr::Assign(_, e) => {
+                self.tok(TokenType::Identifier);
+                self.space();
+                self.tok(TokenType::Equal);
+                self.space();
+                self.expr(e);
+            }
+            expr::Expr::Logical(lhs, op, rhs) => {
+                self.expr(lhs);
+                self.space();
+                self.tok(match op {
+                    expr::LogicalOp::Or => TokenType::Or,
+                    expr::LogicalOp::And => TokenType::And,
+                });
+                self.space();
+                self.expr(rhs);
+            }
+            expr::Expr::Set(e, _, value) => {
+                self.expr(e);
+                self.tok(TokenType::Dot);
+                self.tok(TokenType::Identifier);
+                self.space();
+                self.tok(TokenType::Equal);
+                self.space();
+                self.expr(value);
+            }
+            expr::Expr::Super(_, _) => {
+                self.tok(TokenType::Super);
+                self.tok(TokenType::Dot);
+                self.tok(TokenType::Identifier);
+            }
+            expr::Expr::List(_, elements) => {
+                self.tok(TokenType::LeftBracket);
+                self.comma_separated(elements);
+                self.tok(TokenType::RightBracket);
+            }
+            expr::Expr::Map(_, entries) => {
+                self.tok(TokenType::LeftBrace);
+                for (idx, (key, value)) in entries.iter().enumerate() {
+                    if idx > 0 {
+                        self.tok(TokenType::Comma);
+                        self.space();
+                    }
+                    self.expr(key);
+                    self.tok(TokenType::Colon);
+                    self.space();
+                    self.expr(value);
+                }
+                self.tok(TokenType::RightBrace);
+            }
+            expr::Expr::Subscript { value, slice, .. } => {
+                self.expr(value);
+                self.tok(TokenType::LeftBracket);
+                self.expr(slice);
+                self.tok(TokenType::RightBracket);
+            }
+            expr::Expr::SetItem {
+                lhs, slice, rhs, ..
+            } => {
+                self.expr(lhs);
+                self.tok(TokenType::LeftBracket);
+                self.expr(slice);
+                self.tok(TokenType::RightBracket);
+                self.space();
+                self.tok(TokenType::Equal);
+                self.space();
+                self.expr(rhs);
+            }
+            expr::Expr::Lambda(lambda) => {
+                self.tok(TokenType::Lambda);
+                self.space();
+                self.params_and_body(&lambda.params, &lambda.return_type, &lambda.body);
+            }
+            expr::Expr::CompoundAssign(target, op, val) => {
+                self.expr(target);
+                self.space();
+                self.tok(match op.ty {
+                    expr::BinaryOpTy::Plus => TokenType::PlusEqual,
+                    expr::BinaryOpTy::Minus => TokenType::MinusEqual,
+                    expr::BinaryOpTy::Star => TokenType::StarEqual,
+                    _ => TokenType::SlashEqual,
+                });
+                self.space();
+                self.expr(val);
+            }
+            expr::Expr::Increment { target, op, prefix } => {
+                let token = match op.ty {
+                    expr::BinaryOpTy::Plus => TokenType::PlusPlus,
+                    _ => TokenType::MinusMinus,
+                };
+                if *prefix {
+                    self.tok(token);
+                    self.expr(target);
+                } else {
+                    self.expr(target);
+                    self.tok(token);
+                }
+            }
+            expr::Expr::Ternary(cond, then_branch, else_branch) => {
+                self.expr(cond);
+                self.space();
+                self.tok(TokenType::Question);
+                self.space();
+                self.expr(then_branch);
+                self.space();
+                self.tok(TokenType::Colon);
+                self.space();
+                self.expr(else_branch);
+            }
+            expr::Expr::Interpolation(_, parts) => {
+                // the text around the holes prints as it was written, braces included
+                for (idx, part) in parts.iter().enumerate() {
+                    if idx % 2 == 1 {
+                        self.expr(part);
+                    } else if idx == parts.len() - 1 {
+                        self.tok(TokenType::String);
+                    } else {
+                        self.tok(TokenType::Interpolation);
+                    }
+                }
+            }
+            expr::Expr::Yield(_, val) => {
+                self.tok(TokenType::Yield);
+                if let Some(val) = val {
+                    self.space();
+                    self.expr(val);
+                }
+            }
+        }
+    }
+}
+
+fn binary_op_token(ty: expr::BinaryOpTy) -> TokenType {
+    match ty {
+        expr::BinaryOpTy::EqualEqual => TokenType::EqualEqual,
+        expr::BinaryOpTy::NotEqual => TokenType::BangEqual,
+        expr::BinaryOpTy::Less => TokenType::Less,
+        expr::BinaryOpTy::LessEqual => TokenType::LessEqual,
+        expr::BinaryOpTy::Greater => TokenType::Greater,
+        expr::BinaryOpTy::GreaterEqual => TokenType::GreaterEqual,
+        expr::BinaryOpTy::Plus => TokenType::Plus,
+        expr::BinaryOpTy::Minus => TokenType::Minus,
+        expr::BinaryOpTy::Star => TokenType::Star,
+        expr::BinaryOpTy::Slash => TokenType::Slash,
+        expr::BinaryOpTy::Percent => TokenType::Percent,
+        expr::BinaryOpTy::StarStar => TokenType::StarStar,
+        expr::BinaryOpTy::Ampersand => TokenType::Ampersand,
+        expr::BinaryOpTy::Pipe => TokenType::Pipe,
+        expr::BinaryOpTy::Caret => TokenType::Caret,
+        expr::BinaryOpTy::LessLess => TokenType::LessLess,
+        expr::BinaryOpTy::GreaterGreater => TokenType::GreaterGreater,
+    }
+}
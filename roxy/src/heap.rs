@@ -0,0 +1,590 @@
+use crate::bytecode;
+use crate::err::RoxyErr;
+use crate::expr::MethodKind;
+use crate::map::{Key, Map};
+use crate::vm::{Handler, Vm};
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub type HeapId = usize;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Value {
+    Number(f64),
+    Bool(bool),
+    Nil,
+    String(HeapId),
+    Closure(HeapId),
+    NativeFunction(NativeFunction),
+    Class(HeapId),
+    Instance(HeapId),
+    BoundMethod(HeapId),
+    List(HeapId),
+    Map(HeapId),
+    Generator(HeapId),
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "Number",
+            Value::Bool(_) => "Bool",
+            Value::Nil => "Nil",
+            Value::String(_) => "String",
+            Value::Closure(_) => "Function",
+            Value::NativeFunction(_) => "NativeFunction",
+            Value::Class(_) => "Class",
+            Value::Instance(_) => "Instance",
+            Value::BoundMethod(_) => "BoundMethod",
+            Value::List(_) => "List",
+            Value::Map(_) => "Map",
+            Value::Generator(_) => "Generator",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NativeFunction {
+    pub name: &'static str,
+    pub arity: u8,
+    pub func: fn(&mut Vm, &[Value]) -> Result<Value, RoxyErr>,
+}
+
+#[derive(Debug)]
+pub enum Upvalue {
+    /// still lives on the VM stack at this index
+    Open(usize),
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<bytecode::Function>,
+    pub upvalues: Vec<HeapId>,
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: String,
+    /// the methods of each kind, inherited and mixed-in ones included
+    pub methods: HashMap<MethodKind, HashMap<String, HeapId>>,
+}
+
+impl Class {
+    pub fn find(&self, kind: MethodKind, name: &str) -> Option<HeapId> {
+        self.methods.get(&kind)?.get(name).copied()
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: HeapId,
+    pub fields: HashMap<String, Value>,
+}
+
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: HeapId,
+}
+
+/// What a call to a generator function made: the closure it runs and how far it has got.
+#[derive(Debug)]
+pub struct Generator {
+    pub closure: HeapId,
+    pub state: GeneratorState,
+}
+
+#[derive(Debug)]
+pub enum GeneratorState {
+    /// waiting to be resumed, or to start if nothing has resumed it yet
+    Suspended(Suspended),
+    Running,
+    Done,
+}
+
+/// A generator's frame while it is off the VM stack.
+#[derive(Debug)]
+pub struct Suspended {
+    pub ip: usize,
+    pub slots: Vec<Value>,
+    /// with their stack lengths counted from the start of the slots
+    pub handlers: Vec<Handler>,
+    pub unwinding: Vec<RoxyErr>,
+    /// the upvalues that pointed into the slots, closed over while the frame is away, and
+    /// which slot each one reopens on
+    pub upvalues: Vec<(HeapId, usize)>,
+}
+
+#[derive(Debug)]
+pub enum Object {
+    String(String),
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+    List(Vec<Value>),
+    Map(Map<Value>),
+    Generator(Generator),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct GcConfig {
+    /// after a collection, the next one is triggered once the heap is this many times larger
+    /// than what survived
+    pub growth_factor: f64,
+    /// bytes the heap may grow to before the first collection
+    pub initial_threshold: usize,
+    /// collect at the first safe point after every allocation; for shaking out rooting bugs
+    pub stress: bool,
+    /// print a line to stderr after every collection
+    pub log: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> GcConfig {
+        GcConfig {
+            growth_factor: 2.0,
+            initial_threshold: 1024 * 1024,
+            stress: false,
+            log: false,
+        }
+    }
+}
+
+struct HeapEntry {
+    obj: Object,
+    marked: bool,
+}
+
+/// Owns every object the VM allocates; values refer to objects by `HeapId`. Unreachable
+/// objects are reclaimed by `collect`, which the VM calls whenever `should_collect` says so.
+pub struct Heap {
+    objects: Vec<Option<HeapEntry>>,
+    free_ids: Vec<HeapId>,
+    gray: Vec<HeapId>,
+    // string constants, each allocated the first time it is loaded and kept from then on
+    interned: HashMap<String, HeapId>,
+    bytes_allocated: usize,
+    allocations_since_gc: usize,
+    next_gc: usize,
+    config: GcConfig,
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap::new(GcConfig::default())
+    }
+}
+
+impl Heap {
+    pub fn new(config: GcConfig) -> Heap {
+        Heap {
+            objects: Vec::new(),
+            free_ids: Vec::new(),
+            gray: Vec::new(),
+            interned: HashMap::new(),
+            bytes_allocated: 0,
+            allocations_since_gc: 0,
+            next_gc: config.initial_threshold,
+            config,
+        }
+    }
+
+    pub fn alloc(&mut self, obj: Object) -> HeapId {
+        self.bytes_allocated += Heap::size_of(&obj);
+        self.allocations_since_gc += 1;
+        let entry = Some(HeapEntry { obj, marked: false });
+        match self.free_ids.pop() {
+            Some(id) => {
+                self.objects[id] = entry;
+                id
+            }
+            None => {
+                self.objects.push(entry);
+                self.objects.len() - 1
+            }
+        }
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.bytes_allocated > self.next_gc || (self.config.stress && self.allocations_since_gc > 0)
+    }
+
+    /// The size of every object allocated and not yet freed, as of the last collection plus
+    /// whatever has been allocated since.
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    pub fn live_objects(&self) -> usize {
+        self.objects.len() - self.free_ids.len()
+    }
+
+    /// Frees everything not reachable from `roots`.
+    pub fn collect(&mut self, roots: &[Value], root_ids: &[HeapId]) {
+        let before = self.bytes_allocated;
+        let live_before = self.live_objects();
+
+        for root in roots {
+            self.mark_value(root);
+        }
+        for id in root_ids {
+            self.mark_object(*id);
+        }
+        let interned: Vec<HeapId> = self.interned.values().copied().collect();
+        for id in interned {
+            self.mark_object(id);
+        }
+        while let Some(id) = self.gray.pop() {
+            self.blacken(id);
+        }
+        self.sweep();
+        self.allocations_since_gc = 0;
+
+        self.next_gc = ((self.bytes_allocated as f64 * self.config.growth_factor) as usize)
+            .max(self.config.initial_threshold);
+
+        if self.config.log {
+            eprintln!(
+                "gc: freed {} objects, {} -> {} bytes, next at {}",
+                live_before - self.live_objects(),
+                before,
+                self.bytes_allocated,
+                self.next_gc
+            );
+        }
+    }
+
+    fn mark_value(&mut self, val: &Value) {
+        match val {
+            Value::String(id)
+            | Value::Closure(id)
+            | Value::Class(id)
+            | Value::Instance(id)
+            | Value::BoundMethod(id)
+            | Value::List(id)
+            | Value::Map(id)
+            | Value::Generator(id) => self.mark_object(*id),
+            Value::Number(_) | Value::Bool(_) | Value::Nil | Value::NativeFunction(_) => {}
+        }
+    }
+
+    fn mark_object(&mut self, id: HeapId) {
+        if let Some(entry) = &mut self.objects[id] {
+            if !entry.marked {
+                entry.marked = true;
+                self.gray.push(id);
+            }
+        }
+    }
+
+    fn blacken(&mut self, id: HeapId) {
+        let mut children = Vec::new();
+        let mut child_ids = Vec::new();
+        match self.get(id) {
+            Object::String(_) => {}
+            Object::Closure(closure) => child_ids.extend(closure.upvalues.iter().copied()),
+            Object::Upvalue(Upvalue::Open(_)) => {}
+            Object::Upvalue(Upvalue::Closed(val)) => children.push(*val),
+            Object::Class(class) => {
+                child_ids.extend(class.methods.values().flat_map(|methods| methods.values()))
+            }
+            Object::Instance(instance) => {
+                child_ids.push(instance.class);
+                children.extend(instance.fields.values().copied());
+            }
+            Object::BoundMethod(bound) => {
+                children.push(bound.receiver);
+                child_ids.push(bound.method);
+            }
+            Object::List(elements) => children.extend(elements.iter().copied()),
+            Object::Map(map) => {
+                for (key, value) in map.entries() {
+                    children.push(*key);
+                    children.push(*value);
+                }
+            }
+            Object::Generator(generator) => {
+                child_ids.push(generator.closure);
+                if let GeneratorState::Suspended(suspended) = &generator.state {
+                    children.extend(suspended.slots.iter().copied());
+                    child_ids.extend(suspended.upvalues.iter().map(|(upvalue, _)| *upvalue));
+                }
+            }
+        }
+        for child in children {
+            self.mark_value(&child);
+        }
+        for child in child_ids {
+            self.mark_object(child);
+        }
+    }
+
+    fn sweep(&mut self) {
+        self.bytes_allocated = 0;
+        for (id, slot) in self.objects.iter_mut().enumerate() {
+            match slot {
+                Some(entry) if entry.marked => {
+                    entry.marked = false;
+                    self.bytes_allocated += Heap::size_of(&entry.obj);
+                }
+                Some(_) => {
+                    *slot = None;
+                    self.free_ids.push(id);
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn size_of(obj: &Object) -> usize {
+        let dynamic = match obj {
+            Object::String(s) => s.capacity(),
+            Object::Closure(closure) => closure.upvalues.capacity() * std::mem::size_of::<HeapId>(),
+            Object::Upvalue(_) => 0,
+            Object::Class(class) => {
+                class.methods.values().map(HashMap::capacity).sum::<usize>()
+                    * (std::mem::size_of::<String>() + std::mem::size_of::<HeapId>())
+            }
+            Object::Instance(instance) => {
+                instance.fields.capacity()
+                    * (std::mem::size_of::<String>() + std::mem::size_of::<Value>())
+            }
+            Object::BoundMethod(_) => 0,
+            Object::List(elements) => elements.capacity() * std::mem::size_of::<Value>(),
+            Object::Map(map) => {
+                map.len() * (2 * std::mem::size_of::<Value>() + std::mem::size_of::<Key>())
+            }
+            Object::Generator(generator) => match &generator.state {
+                GeneratorState::Suspended(suspended) => {
+                    suspended.slots.capacity() * std::mem::size_of::<Value>()
+                }
+                GeneratorState::Running | GeneratorState::Done => 0,
+            },
+        };
+        std::mem::size_of::<HeapEntry>() + dynamic
+    }
+
+    pub fn alloc_string(&mut self, s: String) -> Value {
+        Value::String(self.alloc(Object::String(s)))
+    }
+
+    /// The one string object holding `s`, for string constants: strings never change once
+    /// made, so every load of a constant can share it rather than allocate a copy.
+    pub fn intern(&mut self, s: &str) -> Value {
+        if let Some(id) = self.interned.get(s) {
+            return Value::String(*id);
+        }
+        let id = self.alloc(Object::String(s.to_string()));
+        self.interned.insert(s.to_string(), id);
+        Value::String(id)
+    }
+
+    pub fn get(&self, id: HeapId) -> &Object {
+        match &self.objects[id] {
+            Some(entry) => &entry.obj,
+            None => panic!("internal vm error: dangling heap id {}", id),
+        }
+    }
+
+    pub fn get_mut(&mut self, id: HeapId) -> &mut Object {
+        match &mut self.objects[id] {
+            Some(entry) => &mut entry.obj,
+            None => panic!("internal vm error: dangling heap id {}", id),
+        }
+    }
+
+    pub fn get_str(&self, id: HeapId) -> &String {
+        match self.get(id) {
+            Object::String(s) => s,
+            obj => panic!("internal vm error: expected string, found {:?}", obj),
+        }
+    }
+
+    pub fn get_closure(&self, id: HeapId) -> &Closure {
+        match self.get(id) {
+            Object::Closure(closure) => closure,
+            obj => panic!("internal vm error: expected closure, found {:?}", obj),
+        }
+    }
+
+    pub fn get_upvalue(&self, id: HeapId) -> &Upvalue {
+        match self.get(id) {
+            Object::Upvalue(upvalue) => upvalue,
+            obj => panic!("internal vm error: expected upvalue, found {:?}", obj),
+        }
+    }
+
+    pub fn get_upvalue_mut(&mut self, id: HeapId) -> &mut Upvalue {
+        match self.get_mut(id) {
+            Object::Upvalue(upvalue) => upvalue,
+            obj => panic!("internal vm error: expected upvalue, found {:?}", obj),
+        }
+    }
+
+    pub fn get_class(&self, id: HeapId) -> &Class {
+        match self.get(id) {
+            Object::Class(class) => class,
+            obj => panic!("internal vm error: expected class, found {:?}", obj),
+        }
+    }
+
+    pub fn get_class_mut(&mut self, id: HeapId) -> &mut Class {
+        match self.get_mut(id) {
+            Object::Class(class) => class,
+            obj => panic!("internal vm error: expected class, found {:?}", obj),
+        }
+    }
+
+    pub fn get_instance(&self, id: HeapId) -> &Instance {
+        match self.get(id) {
+            Object::Instance(instance) => instance,
+            obj => panic!("internal vm error: expected instance, found {:?}", obj),
+        }
+    }
+
+    pub fn get_instance_mut(&mut self, id: HeapId) -> &mut Instance {
+        match self.get_mut(id) {
+            Object::Instance(instance) => instance,
+            obj => panic!("internal vm error: expected instance, found {:?}", obj),
+        }
+    }
+
+    pub fn get_bound_method(&self, id: HeapId) -> &BoundMethod {
+        match self.get(id) {
+            Object::BoundMethod(bound) => bound,
+            obj => panic!("internal vm error: expected bound method, found {:?}", obj),
+        }
+    }
+
+    pub fn get_list(&self, id: HeapId) -> &Vec<Value> {
+        match self.get(id) {
+            Object::List(elements) => elements,
+            obj => panic!("internal vm error: expected list, found {:?}", obj),
+        }
+    }
+
+    pub fn get_list_mut(&mut self, id: HeapId) -> &mut Vec<Value> {
+        match self.get_mut(id) {
+            Object::List(elements) => elements,
+            obj => panic!("internal vm error: expected list, found {:?}", obj),
+        }
+    }
+
+    pub fn get_map(&self, id: HeapId) -> &Map<Value> {
+        match self.get(id) {
+            Object::Map(map) => map,
+            obj => panic!("internal vm error: expected map, found {:?}", obj),
+        }
+    }
+
+    pub fn get_map_mut(&mut self, id: HeapId) -> &mut Map<Value> {
+        match self.get_mut(id) {
+            Object::Map(map) => map,
+            obj => panic!("internal vm error: expected map, found {:?}", obj),
+        }
+    }
+
+    pub fn get_generator(&self, id: HeapId) -> &Generator {
+        match self.get(id) {
+            Object::Generator(generator) => generator,
+            obj => panic!("internal vm error: expected generator, found {:?}", obj),
+        }
+    }
+
+    pub fn get_generator_mut(&mut self, id: HeapId) -> &mut Generator {
+        match self.get_mut(id) {
+            Object::Generator(generator) => generator,
+            obj => panic!("internal vm error: expected generator, found {:?}", obj),
+        }
+    }
+
+    /// What `val` is looked up by as a map key; only nil, booleans, numbers and strings can be
+    /// keys.
+    pub fn to_key(&self, val: &Value) -> Option<Key> {
+        match val {
+            Value::Nil => Some(Key::Nil),
+            Value::Bool(b) => Some(Key::Bool(*b)),
+            Value::Number(n) => Some(Key::number(*n)),
+            Value::String(id) => Some(Key::String(self.get_str(*id).clone())),
+            _ => None,
+        }
+    }
+
+    pub fn values_equal(&self, lhs: &Value, rhs: &Value) -> bool {
+        match (lhs, rhs) {
+            (Value::Number(n1), Value::Number(n2)) => n1 == n2,
+            (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
+            (Value::Nil, Value::Nil) => true,
+            (Value::String(s1), Value::String(s2)) => self.get_str(*s1) == self.get_str(*s2),
+            (Value::NativeFunction(f1), Value::NativeFunction(f2)) => f1.name == f2.name,
+            (Value::Closure(id1), Value::Closure(id2))
+            | (Value::Class(id1), Value::Class(id2))
+            | (Value::Instance(id1), Value::Instance(id2))
+            | (Value::BoundMethod(id1), Value::BoundMethod(id2))
+            | (Value::List(id1), Value::List(id2))
+            | (Value::Map(id1), Value::Map(id2))
+            | (Value::Generator(id1), Value::Generator(id2)) => id1 == id2,
+            (_, _) => false,
+        }
+    }
+
+    /// Renders a value the same way the tree-walking interpreter's `Debug` impl does.
+    pub fn format_value(&self, val: &Value) -> String {
+        match val {
+            Value::Number(n) => format!("{}", n),
+            Value::Bool(b) => format!("{}", b),
+            Value::Nil => "nil".to_string(),
+            Value::String(id) => format!("\"{}\"", self.get_str(*id)),
+            Value::Closure(id) => format!("<fn {}>", self.get_closure(*id).function.name),
+            Value::NativeFunction(func) => format!("<native fn {}>", func.name),
+            Value::Class(id) => format!("<class {}>", self.get_class(*id).name),
+            Value::Instance(id) => format!(
+                "<{} instance>",
+                self.get_class(self.get_instance(*id).class).name
+            ),
+            Value::BoundMethod(id) => {
+                let method = self.get_bound_method(*id).method;
+                format!("<fn {}>", self.get_closure(method).function.name)
+            }
+            Value::List(id) => {
+                let elements: Vec<String> = self
+                    .get_list(*id)
+                    .iter()
+                    .map(|element| self.format_value(element))
+                    .collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Value::Map(id) => {
+                let entries: Vec<String> = self
+                    .get_map(*id)
+                    .entries()
+                    .iter()
+                    .map(|(key, value)| {
+                        format!("{}: {}", self.format_value(key), self.format_value(value))
+                    })
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            Value::Generator(id) => {
+                let closure = self.get_generator(*id).closure;
+                format!("<generator {}>", self.get_closure(closure).function.name)
+            }
+        }
+    }
+
+    /// Like `format_value`, except that strings are printed without quotes.
+    pub fn display_value(&self, val: &Value) -> String {
+        match val {
+            Value::String(id) => self.get_str(*id).clone(),
+            _ => self.format_value(val),
+        }
+    }
+}
@@ -0,0 +1,1657 @@
+use crate::debugger::Debugger;
+use crate::err::{Frame, RoxyErr};
+use crate::expr;
+use crate::extensions::Extensions;
+use crate::limits::{Budget, Limits};
+use crate::map::{Key, Map};
+use crate::resolver::Locals;
+use crate::stdlib;
+use crate::value::{
+    self, Callable, GeneratorState, NativeFunction, RoxyClass, RoxyFunction, RoxyGenerator,
+    RoxyInstance, Value,
+};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// How many roxy calls may be in progress at once, as on the VM.
+const FRAMES_MAX: usize = 1024;
+
+/// How much stack `Interpreter::new` assumes the thread running it has: what Rust gives the
+/// threads it spawns.
+const DEFAULT_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+#[derive(Default)]
+pub struct Environment {
+    enclosing: Option<Rc<RefCell<Environment>>>,
+    values: HashMap<String, Value>,
+}
+
+impl Environment {
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Environment {
+        Environment {
+            enclosing: Some(enclosing),
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn define(&mut self, name: String, val: Value) {
+        self.values.insert(name, val);
+    }
+
+    pub fn get(&self, sym: &expr::Symbol) -> Result<Value, RoxyErr> {
+        match self.values.get(&sym.name) {
+            Some(val) => Ok(val.clone()),
+            None => match &self.enclosing {
+                Some(enclosing) => enclosing.borrow().get(sym),
+                None => Err(RoxyErr::RuntimeError {
+                    what: format!("undefined variable '{}'", sym.name),
+                    line: sym.line,
+                    col: sym.col,
+                    trace: Vec::new(),
+                }),
+            },
+        }
+    }
+
+    pub fn assign(&mut self, sym: &expr::Symbol, val: Value) -> Result<(), RoxyErr> {
+        if self.values.contains_key(&sym.name) {
+            self.values.insert(sym.name.clone(), val);
+            return Ok(());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(sym, val),
+            None => Err(RoxyErr::RuntimeError {
+                what: format!("attempting to assign to undeclared variable '{}'", sym.name),
+                line: sym.line,
+                col: sym.col,
+                trace: Vec::new(),
+            }),
+        }
+    }
+
+    fn ancestor(env: &Rc<RefCell<Environment>>, distance: usize) -> Rc<RefCell<Environment>> {
+        let mut env = env.clone();
+        for _ in 0..distance {
+            let enclosing = env.borrow().enclosing.clone().expect(
+                "internal interpreter error: resolved scope depth exceeds environment chain",
+            );
+            env = enclosing;
+        }
+        env
+    }
+
+    pub fn get_at(
+        env: &Rc<RefCell<Environment>>,
+        distance: usize,
+        sym: &expr::Symbol,
+    ) -> Result<Value, RoxyErr> {
+        match Environment::ancestor(env, distance)
+            .borrow()
+            .values
+            .get(&sym.name)
+        {
+            Some(val) => Ok(val.clone()),
+            None => Err(RoxyErr::RuntimeError {
+                what: format!("undefined variable '{}'", sym.name),
+                line: sym.line,
+                col: sym.col,
+                trace: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn assign_at(
+        env: &Rc<RefCell<Environment>>,
+        distance: usize,
+        sym: &expr::Symbol,
+        val: Value,
+    ) {
+        Environment::ancestor(env, distance)
+            .borrow_mut()
+            .define(sym.name.clone(), val);
+    }
+
+    /// Looks up the instance bound by `RoxyFunction::bind`.
+    pub fn get_this(&self) -> Value {
+        match self.values.get("this") {
+            Some(val) => val.clone(),
+            None => match &self.enclosing {
+                Some(enclosing) => enclosing.borrow().get_this(),
+                None => Value::Nil,
+            },
+        }
+    }
+}
+
+/// A `break` or `continue` on its way out to the loop it is aimed at, with that loop's label
+/// if it names one.
+pub enum Jump {
+    Break(Option<String>),
+    Continue(Option<String>),
+}
+
+/// What a statement a generator was suspended in needs to carry on from where it stopped. A
+/// suspended generator keeps one for each statement between its body and the `yield`.
+pub enum Resume {
+    /// the index of the statement it stopped in, and the block's scope
+    Block(usize, Rc<RefCell<Environment>>),
+    /// whether it was in the `then` branch
+    If(bool),
+    While,
+    /// the loop's scope, and whether it was in the body rather than the initializer
+    For(Rc<RefCell<Environment>>, bool),
+    /// what it was iterating over, how far it had got and the scope holding the element
+    ForIn {
+        source: Value,
+        idx: usize,
+        env: Rc<RefCell<Environment>>,
+    },
+    Try(TryPhase),
+    /// what the `yield` evaluates to once the generator is resumed
+    Yield(Value),
+}
+
+/// The part of a `try` statement a generator was suspended in.
+pub enum TryPhase {
+    Body,
+    Catch,
+    /// with what the finally block was run after
+    Finally {
+        res: Result<(), RoxyErr>,
+        thrown: Option<Value>,
+        retval: Option<Value>,
+        jump: Option<Jump>,
+    },
+}
+
+pub struct Interpreter {
+    pub globals: Rc<RefCell<Environment>>,
+    env: Rc<RefCell<Environment>>,
+    // the scope depths of the program, or of the function's program, being run
+    locals: Rc<Locals>,
+    retval: Option<Value>,
+    jump: Option<Jump>,
+    // the value behind the `RoxyErr::Thrown` currently unwinding
+    thrown: Option<Value>,
+    // where the roxy call in progress was made, for stack traces
+    call_site: Option<expr::SourceLocation>,
+    // how many roxy calls are in progress
+    calls: usize,
+    // how much of its thread's stack nested calls may use, and roughly where the stack was when
+    // the outermost of them started
+    stack_size: usize,
+    stack_base: usize,
+    budget: Budget,
+    debugger: Option<Box<Debugger>>,
+    // the value a generator is suspending with, while the statements it was running unwind
+    yielded: Option<Value>,
+    // what those statements need to carry on from where they stopped, innermost first
+    unwound: Vec<Resume>,
+    // the same for the generator being resumed, which each statement pops as it is re-entered
+    resuming: Vec<Resume>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new(Extensions::default())
+    }
+}
+
+impl Interpreter {
+    /// An interpreter whose globals hold the natives from `stdlib::natives`.
+    pub fn new(extensions: Extensions) -> Interpreter {
+        let globals = Rc::new(RefCell::new(Environment::default()));
+        let mut interpreter = Interpreter {
+            globals: globals.clone(),
+            env: globals,
+            locals: Rc::new(Locals::new()),
+            retval: None,
+            jump: None,
+            thrown: None,
+            call_site: None,
+            calls: 0,
+            stack_size: DEFAULT_STACK_SIZE / 2,
+            stack_base: 0,
+            budget: Budget::default(),
+            debugger: None,
+            yielded: None,
+            unwound: Vec::new(),
+            resuming: Vec::new(),
+        };
+        for native in stdlib::natives(extensions) {
+            interpreter.define_native(native);
+        }
+        interpreter
+    }
+
+    pub fn define_native(&mut self, native: NativeFunction) {
+        self.globals
+            .borrow_mut()
+            .define(native.name.clone(), Value::NativeFunction(native));
+    }
+
+    /// Hands every statement run from now on to `debugger` before it executes.
+    pub fn attach_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(Box::new(debugger));
+    }
+
+    /// The value `name` has in the current scope, wherever it was declared.
+    pub fn lookup(&self, name: &str) -> Option<Value> {
+        let sym = expr::Symbol {
+            name: name.to_string(),
+            line: 0,
+            col: -1,
+        };
+        self.env.borrow().get(&sym).ok()
+    }
+
+    /// The variables visible from the current scope, innermost first, leaving out globals.
+    pub fn visible_locals(&self) -> Vec<(String, Value)> {
+        let mut visible: Vec<(String, Value)> = Vec::new();
+        let mut env = self.env.clone();
+        while !Rc::ptr_eq(&env, &self.globals) {
+            let mut scope: Vec<_> = env.borrow().values.clone().into_iter().collect();
+            scope.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (name, val) in scope {
+                // an inner declaration shadows outer ones
+                if !visible.iter().any(|(seen, _)| *seen == name) {
+                    visible.push((name, val));
+                }
+            }
+            let enclosing = env.borrow().enclosing.clone();
+            match enclosing {
+                Some(enclosing) => env = enclosing,
+                None => break,
+            }
+        }
+        visible
+    }
+
+    /// The globals the program defined, by name; natives are left out.
+    pub fn user_globals(&self) -> Vec<(String, Value)> {
+        let mut globals: Vec<_> = self
+            .globals
+            .borrow()
+            .values
+            .iter()
+            .filter(|(_, val)| !matches!(val, Value::NativeFunction(_)))
+            .map(|(name, val)| (name.clone(), val.clone()))
+            .collect();
+        globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+        globals
+    }
+
+    /// Tells the interpreter how big the stack of the thread running it is. Calls recurse on
+    /// that stack, so once they have used half of it the next one fails with a stack overflow
+    /// error, as it does after `FRAMES_MAX` calls, instead of crashing the process.
+    pub fn set_stack_size(&mut self, bytes: usize) {
+        self.stack_size = bytes / 2;
+    }
+
+    /// Holds every program run from now on to `limits`, counting from now. Without I/O the
+    /// natives in `stdlib::IO_NATIVES` are removed from the globals.
+    pub fn set_limits(&mut self, limits: Limits) {
+        if !limits.io {
+            let mut globals = self.globals.borrow_mut();
+            for name in stdlib::IO_NATIVES {
+                globals.values.remove(name);
+            }
+        }
+        self.budget = Budget::new(limits);
+    }
+
+    /// Starts counting steps, time, output and allocations towards the limits afresh, e.g. for
+    /// each program a host runs.
+    pub fn restart_limits(&mut self) {
+        self.budget.restart();
+    }
+
+    /// Counts `bytes` a native is about to allocate against the heap limit. The error, if
+    /// the limit is exceeded, is located at the call of the native.
+    pub fn allocate(&mut self, bytes: usize) -> Result<(), RoxyErr> {
+        let loc = self
+            .call_site
+            .unwrap_or(expr::SourceLocation { line: 0, col: -1 });
+        self.budget
+            .allocate(bytes)
+            .map_err(|limit| limit.exceeded_at(loc))
+    }
+
+    /// Counts a value that has just been created, at `loc`, against the heap limit.
+    fn created(&mut self, val: Value, loc: expr::SourceLocation) -> Result<Value, RoxyErr> {
+        let slot = std::mem::size_of::<Value>();
+        let bytes = match &val {
+            Value::String(s) => s.len(),
+            Value::List(elements) => elements.borrow().len() * slot,
+            Value::Map(map) => map.borrow().len() * 2 * slot,
+            Value::Instance(instance) => instance.borrow().fields.len() * slot,
+            _ => return Ok(val),
+        };
+        self.budget
+            .allocate(slot + bytes)
+            .map_err(|limit| limit.exceeded_at(loc))?;
+        Ok(val)
+    }
+
+    /// Records the scope depths `resolver::resolve` computed for the program about to be
+    /// interpreted; must happen before `interpret`. Each program has its own, which the
+    /// functions it declares keep for their calls: another program, like the next input at
+    /// the REPL, can have variables at the same places.
+    pub fn resolve(&mut self, locals: Locals) {
+        self.locals = Rc::new(locals);
+    }
+
+    /// Runs a program. A `return` at the top level (which only the REPL produces, to echo a
+    /// trailing expression) stops execution and its value is handed back.
+    pub fn interpret(&mut self, stmts: &[expr::Stmt]) -> Result<Option<Value>, RoxyErr> {
+        let res = stmts.iter().try_for_each(|stmt| self.execute(stmt));
+        let retval = self.retval.take();
+        res.map(|_| retval)
+    }
+
+    /// Runs the body of the function called `name` in `env`, with the scope depths of the
+    /// program that declared it, and hands back whatever it returned.
+    pub fn call_body(
+        &mut self,
+        name: &str,
+        body: &[expr::Stmt],
+        env: Environment,
+        locals: &Rc<Locals>,
+    ) -> Result<Option<Value>, RoxyErr> {
+        self.run_body(name, body, Rc::new(RefCell::new(env)), locals)?;
+        Ok(self.retval.take())
+    }
+
+    /// Resumes `generator` with `sent` as the value of the `yield` it stopped at, and runs it to
+    /// its next `yield`. `None` means it finished instead, now or before.
+    pub fn resume(
+        &mut self,
+        generator: &Rc<RefCell<RoxyGenerator>>,
+        sent: Value,
+    ) -> Result<Option<Value>, RoxyErr> {
+        let (name, body, locals, mut resuming) = {
+            let mut generator = generator.borrow_mut();
+            match std::mem::replace(&mut generator.state, GeneratorState::Running) {
+                GeneratorState::Suspended(resuming) => (
+                    generator.name.clone(),
+                    generator.body.clone(),
+                    generator.locals.clone(),
+                    resuming,
+                ),
+                GeneratorState::Running => {
+                    return Err(RoxyErr::NativeError(format!(
+                        "generator '{}' is already running",
+                        generator.name
+                    )))
+                }
+                GeneratorState::Done => {
+                    generator.state = GeneratorState::Done;
+                    return Ok(None);
+                }
+            }
+        };
+        // a generator that has not started yet has no `yield` to hand `sent` to
+        if let Some(Resume::Yield(slot)) = resuming.first_mut() {
+            *slot = sent;
+        }
+        let env = match resuming.last() {
+            Some(Resume::Block(_, env)) => env.clone(),
+            _ => panic!("internal interpreter error: generator suspended outside its body"),
+        };
+
+        self.resuming = resuming;
+        let res = self.run_body(&name, &body, env, &locals);
+        self.retval = None;
+        let (state, res) = match (res, self.yielded.take()) {
+            (Ok(()), Some(val)) => (
+                GeneratorState::Suspended(std::mem::take(&mut self.unwound)),
+                Ok(Some(val)),
+            ),
+            (Ok(()), None) => (GeneratorState::Done, Ok(None)),
+            (Err(err), _) => {
+                self.unwound.clear();
+                self.resuming.clear();
+                (GeneratorState::Done, Err(err))
+            }
+        };
+        generator.borrow_mut().state = state;
+        res
+    }
+
+    /// Runs the body of the function called `name` in `env`, with `locals` for its scope
+    /// depths, leaving what it returned in `retval`. Errors that leave the body have the call
+    /// added to their trace; a call refused before its body starts is not part of it.
+    fn run_body(
+        &mut self,
+        name: &str,
+        body: &[expr::Stmt],
+        env: Rc<RefCell<Environment>>,
+        locals: &Rc<Locals>,
+    ) -> Result<(), RoxyErr> {
+        let call_site = self.call_site;
+        let loc = call_site.unwrap_or(expr::SourceLocation { line: 0, col: -1 });
+        if let Err(limit) = self.budget.call(self.calls + 1) {
+            return Err(limit.exceeded_at(loc));
+        }
+        if self.calls == 0 {
+            self.stack_base = stack_position();
+        }
+        // the program itself takes up a frame on the VM
+        if self.calls + 1 >= FRAMES_MAX
+            || self.stack_base.saturating_sub(stack_position()) > self.stack_size
+        {
+            return Err(RoxyErr::RuntimeError {
+                what: "stack overflow".to_string(),
+                line: loc.line,
+                col: loc.col,
+                trace: Vec::new(),
+            });
+        }
+        if let Some(debugger) = &mut self.debugger {
+            debugger.enter(name);
+        }
+        self.calls += 1;
+        let saved_locals = std::mem::replace(&mut self.locals, locals.clone());
+        let res = self.execute_block(body, env);
+        self.locals = saved_locals;
+        self.calls -= 1;
+        if let Some(debugger) = &mut self.debugger {
+            debugger.leave();
+        }
+        res.map_err(|mut err| {
+            if let (
+                RoxyErr::RuntimeError { trace, .. } | RoxyErr::Thrown { trace, .. },
+                Some(call_site),
+            ) = (&mut err, call_site)
+            {
+                trace.push(Frame {
+                    function: name.to_string(),
+                    line: call_site.line,
+                    col: call_site.col,
+                });
+            }
+            err
+        })
+    }
+
+    fn execute(&mut self, stmt: &expr::Stmt) -> Result<(), RoxyErr> {
+        if self.retval.is_some() || self.jump.is_some() || self.yielded.is_some() {
+            return Ok(());
+        }
+        // statements being re-entered by a generator were counted and shown when they started
+        if self.resuming.is_empty() {
+            self.budget
+                .step()
+                .map_err(|limit| limit.exceeded_at(stmt.location()))?;
+            if let Some(mut debugger) = self.debugger.take() {
+                debugger.before(self, stmt);
+                self.debugger = Some(debugger);
+            }
+        }
+
+        match stmt {
+            expr::Stmt::Expr(e) => {
+                self.evaluate(e)?;
+            }
+            expr::Stmt::FunDecl(fundecl) => {
+                let func = self.make_function(fundecl, false);
+                self.env
+                    .borrow_mut()
+                    .define(fundecl.name.name.clone(), Value::Function(Rc::new(func)));
+            }
+            expr::Stmt::ClassDecl(classdecl) => self.class_decl(classdecl)?,
+            expr::Stmt::If(cond, then_branch, maybe_else_branch) => {
+                let taken = match self.resuming.pop() {
+                    Some(Resume::If(taken)) => taken,
+                    None => self.evaluate(cond)?.is_truthy(),
+                    Some(_) => mismatched_resume(stmt),
+                };
+                if taken {
+                    self.execute(then_branch)?
+                } else if let Some(else_branch) = maybe_else_branch {
+                    self.execute(else_branch)?
+                }
+                if self.yielded.is_some() {
+                    self.unwound.push(Resume::If(taken));
+                }
+            }
+            expr::Stmt::Print(loc, e) => {
+                let text = self.evaluate(e)?.to_string();
+                self.budget
+                    .output(text.len() + 1)
+                    .map_err(|limit| limit.exceeded_at(*loc))?;
+                println!("{}", text);
+            }
+            expr::Stmt::VarDecl(sym, _, maybe_initializer) => {
+                let val = match maybe_initializer {
+                    Some(initializer) => self.evaluate(initializer)?,
+                    None => Value::Nil,
+                };
+                // a variable initialized with a `yield` is defined once the generator resumes
+                if self.yielded.is_none() {
+                    self.env.borrow_mut().define(sym.name.clone(), val);
+                }
+            }
+            expr::Stmt::Block(_, stmts) => {
+                let env = Environment::with_enclosing(self.env.clone());
+                self.execute_block(stmts, Rc::new(RefCell::new(env)))?
+            }
+            expr::Stmt::Return(_, maybe_res) => {
+                self.retval = Some(match maybe_res {
+                    Some(res) => self.evaluate(res)?,
+                    None => Value::Nil,
+                })
+            }
+            expr::Stmt::While(..) | expr::Stmt::For { .. } | expr::Stmt::ForIn { .. } => {
+                self.loop_statement(stmt, None)?
+            }
+            expr::Stmt::Labeled(label, body) => self.loop_statement(body, Some(&label.name))?,
+            expr::Stmt::Break(_, label) => {
+                self.jump = Some(Jump::Break(label.as_ref().map(|l| l.name.clone())))
+            }
+            expr::Stmt::Continue(_, label) => {
+                self.jump = Some(Jump::Continue(label.as_ref().map(|l| l.name.clone())))
+            }
+            // modules have already run by the time the importing program does
+            expr::Stmt::Import(_) => {}
+            expr::Stmt::Throw(loc, e) => {
+                let val = self.evaluate(e)?;
+                let what = val.to_string();
+                self.thrown = Some(val);
+                return Err(RoxyErr::Thrown {
+                    what,
+                    line: loc.line,
+                    col: loc.col,
+                    trace: Vec::new(),
+                });
+            }
+            expr::Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => self.try_statement(body, catch, finally)?,
+        }
+        Ok(())
+    }
+
+    fn try_statement(
+        &mut self,
+        body: &[expr::Stmt],
+        catch: &Option<(expr::Symbol, Vec<expr::Stmt>)>,
+        finally: &Option<Vec<expr::Stmt>>,
+    ) -> Result<(), RoxyErr> {
+        let (res, pending) = match self.resuming.pop() {
+            Some(Resume::Try(TryPhase::Finally {
+                res,
+                thrown,
+                retval,
+                jump,
+            })) => {
+                self.thrown = thrown;
+                (res, Some((retval, jump)))
+            }
+            resumed => {
+                let in_catch = match resumed {
+                    Some(Resume::Try(TryPhase::Catch)) => true,
+                    Some(Resume::Try(TryPhase::Body)) | None => false,
+                    Some(_) => panic!("internal interpreter error: resumed into the wrong try"),
+                };
+                let res = self.try_and_catch(body, catch, in_catch);
+                if self.yielded.is_some() {
+                    return res;
+                }
+                (res, None)
+            }
+        };
+
+        // a program that has run out of one of its limits stops at once
+        if let Err(RoxyErr::LimitExceeded { .. }) = res {
+            return res;
+        }
+        if let Some(finally) = finally {
+            // a pending return, break or continue waits for the finally block, and one inside
+            // it wins over whatever the try and catch blocks did
+            let (retval, jump) = pending.unwrap_or_else(|| (self.retval.take(), self.jump.take()));
+            let env = Environment::with_enclosing(self.env.clone());
+            self.execute_block(finally, Rc::new(RefCell::new(env)))?;
+            if self.yielded.is_some() {
+                self.unwound.push(Resume::Try(TryPhase::Finally {
+                    res,
+                    thrown: self.thrown.take(),
+                    retval,
+                    jump,
+                }));
+                return Ok(());
+            }
+            if self.retval.is_some() || self.jump.is_some() {
+                return Ok(());
+            }
+            self.retval = retval;
+            self.jump = jump;
+        }
+        res
+    }
+
+    /// Runs the body of a `try` and, if it fails, the `catch` block; a generator suspended in
+    /// the `catch` block goes straight back into it.
+    fn try_and_catch(
+        &mut self,
+        body: &[expr::Stmt],
+        catch: &Option<(expr::Symbol, Vec<expr::Stmt>)>,
+        in_catch: bool,
+    ) -> Result<(), RoxyErr> {
+        let mut res = Ok(());
+        if !in_catch {
+            let env = Environment::with_enclosing(self.env.clone());
+            res = self.execute_block(body, Rc::new(RefCell::new(env)));
+            if self.yielded.is_some() {
+                self.unwound.push(Resume::Try(TryPhase::Body));
+                return res;
+            }
+        }
+
+        let (name, handler) = match catch {
+            Some(catch) => catch,
+            None => return res,
+        };
+        let caught = match &res {
+            // the scope binding the caught value is waiting in the block being resumed
+            _ if in_catch => Some(Value::Nil),
+            Err(RoxyErr::Thrown { .. }) => self.thrown.take(),
+            Err(RoxyErr::RuntimeError { what, .. }) => Some(Value::String(what.clone())),
+            _ => None,
+        };
+        if let Some(caught) = caught {
+            let mut env = Environment::with_enclosing(self.env.clone());
+            env.define(name.name.clone(), caught);
+            res = self.execute_block(handler, Rc::new(RefCell::new(env)));
+            if self.yielded.is_some() {
+                self.unwound.push(Resume::Try(TryPhase::Catch));
+            }
+        }
+        res
+    }
+
+    /// Runs a `while`, `for` or for-in loop, which `label` names if it is labeled.
+    fn loop_statement(&mut self, stmt: &expr::Stmt, label: Option<&str>) -> Result<(), RoxyErr> {
+        match stmt {
+            expr::Stmt::While(cond, body) => {
+                // a generator suspended in the body goes back into it without checking again
+                let mut resumed = match self.resuming.pop() {
+                    Some(Resume::While) => true,
+                    None => false,
+                    Some(_) => mismatched_resume(stmt),
+                };
+                while resumed || self.evaluate(cond)?.is_truthy() {
+                    resumed = false;
+                    self.execute(body)?;
+                    if self.yielded.is_some() {
+                        self.unwound.push(Resume::While);
+                        break;
+                    }
+                    if self.loop_done(label) {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+            expr::Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                // the loop variable lives in a scope of its own, around the whole loop
+                let (env, in_body) = match self.resuming.pop() {
+                    Some(Resume::For(env, in_body)) => (env, Some(in_body)),
+                    None => {
+                        let env = Environment::with_enclosing(self.env.clone());
+                        (Rc::new(RefCell::new(env)), None)
+                    }
+                    Some(_) => mismatched_resume(stmt),
+                };
+                let saved_env = std::mem::replace(&mut self.env, env);
+                let res = self.for_loop(initializer, condition, increment, body, label, in_body);
+                self.env = saved_env;
+                res
+            }
+            expr::Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+                ..
+            } => self.for_in(variable, iterable, body, label, stmt),
+            _ => panic!("internal interpreter error: {:?} is not a loop", stmt),
+        }
+    }
+
+    /// Called after each run of a loop body to tell whether the loop is over. A `break` or
+    /// `continue` aimed at this loop stops here; anything else still unwinding leaves it.
+    fn loop_done(&mut self, label: Option<&str>) -> bool {
+        let aimed_here =
+            |target: &Option<String>| target.as_deref().is_none_or(|target| Some(target) == label);
+        match self.jump.take() {
+            None => self.retval.is_some(),
+            Some(Jump::Break(target)) if aimed_here(&target) => true,
+            Some(Jump::Continue(target)) if aimed_here(&target) => false,
+            jump => {
+                self.jump = jump;
+                true
+            }
+        }
+    }
+
+    /// Runs a `for` loop in its scope. `in_body` says where a generator being resumed was
+    /// suspended: in the body or, if false, in the initializer.
+    fn for_loop(
+        &mut self,
+        initializer: &Option<Box<expr::Stmt>>,
+        condition: &Option<expr::Expr>,
+        increment: &Option<expr::Expr>,
+        body: &expr::Stmt,
+        label: Option<&str>,
+        in_body: Option<bool>,
+    ) -> Result<(), RoxyErr> {
+        let mut resumed = in_body == Some(true);
+        if let (Some(initializer), false) = (initializer, resumed) {
+            self.execute(initializer)?;
+            if self.yielded.is_some() {
+                self.unwound.push(Resume::For(self.env.clone(), false));
+                return Ok(());
+            }
+        }
+        loop {
+            if let (Some(condition), false) = (condition, resumed) {
+                if !self.evaluate(condition)?.is_truthy() {
+                    break;
+                }
+            }
+            resumed = false;
+            self.execute(body)?;
+            if self.yielded.is_some() {
+                self.unwound.push(Resume::For(self.env.clone(), true));
+                break;
+            }
+            if self.loop_done(label) {
+                break;
+            }
+            if let Some(increment) = increment {
+                self.evaluate(increment)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists are iterated over by element, strings by character and maps by key, each
+    /// looking at the collection afresh on every step. An instance's `iter()` is called for
+    /// what to iterate over instead; if that is an instance too, its `next()` is called for
+    /// each element until it returns nil. A generator is resumed for each element until it
+    /// finishes.
+    fn for_in(
+        &mut self,
+        variable: &expr::Symbol,
+        iterable: &expr::Expr,
+        body: &expr::Stmt,
+        label: Option<&str>,
+        stmt: &expr::Stmt,
+    ) -> Result<(), RoxyErr> {
+        let loc = iterable.location();
+        let (source, start, mut resumed_env) = match self.resuming.pop() {
+            Some(Resume::ForIn { source, idx, env }) => (source, idx, Some(env)),
+            None => {
+                let mut source = self.evaluate(iterable)?;
+                if let Value::Instance(instance) = &source {
+                    source = self.call_method(instance, "iter", &loc)?;
+                }
+                if let Value::String(s) = &source {
+                    let chars = s.chars().map(|c| Value::String(c.to_string())).collect();
+                    source = Value::List(Rc::new(RefCell::new(chars)));
+                }
+                (source, 0, None)
+            }
+            Some(_) => mismatched_resume(stmt),
+        };
+
+        for idx in start.. {
+            // a generator suspended in the body goes back into it with the element it had
+            if let Some(env) = resumed_env.take() {
+                let saved_env = std::mem::replace(&mut self.env, env);
+                let res = self.execute(body);
+                let env = std::mem::replace(&mut self.env, saved_env);
+                res?;
+                if self.yielded.is_some() {
+                    self.unwound.push(Resume::ForIn { source, idx, env });
+                    break;
+                }
+                if self.loop_done(label) {
+                    break;
+                }
+                continue;
+            }
+            let element = match &source {
+                Value::List(elements) => elements.borrow().get(idx).cloned(),
+                Value::Map(map) => map.borrow().entries().get(idx).map(|(key, _)| key.clone()),
+                Value::Instance(iterator) => match self.call_method(iterator, "next", &loc)? {
+                    Value::Nil => None,
+                    element => Some(element),
+                },
+                Value::Generator(generator) => {
+                    let saved_call_site = self.call_site.replace(loc);
+                    let res = self.resume(generator, Value::Nil);
+                    self.call_site = saved_call_site;
+                    res.map_err(|err| at_call(err, &loc))?
+                }
+                _ => {
+                    return Err(RoxyErr::RuntimeError {
+                        what: format!("cannot iterate over {:?}", source.type_of()),
+                        line: loc.line,
+                        col: loc.col,
+                        trace: Vec::new(),
+                    })
+                }
+            };
+            let element = match element {
+                Some(element) => element,
+                None => break,
+            };
+
+            let mut env = Environment::with_enclosing(self.env.clone());
+            env.define(variable.name.clone(), element);
+            let saved_env = std::mem::replace(&mut self.env, Rc::new(RefCell::new(env)));
+            let res = self.execute(body);
+            let env = std::mem::replace(&mut self.env, saved_env);
+            res?;
+            if self.yielded.is_some() {
+                self.unwound.push(Resume::ForIn { source, idx, env });
+                break;
+            }
+            if self.loop_done(label) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls the method `name` of `instance` with no arguments, as if from `loc`.
+    fn call_method(
+        &mut self,
+        instance: &Rc<RefCell<RoxyInstance>>,
+        name: &str,
+        loc: &expr::SourceLocation,
+    ) -> Result<Value, RoxyErr> {
+        let method = RoxyInstance::get(
+            instance,
+            &expr::Symbol {
+                name: name.to_string(),
+                line: loc.line,
+                col: loc.col,
+            },
+        )?;
+        let saved_call_site = self.call_site.replace(*loc);
+        let res = self.call_function(&method, Vec::new());
+        self.call_site = saved_call_site;
+        res.map_err(|err| at_call(err, loc))
+    }
+
+    /// Runs `stmts` in `env`, or, for a generator being resumed, from the statement it stopped
+    /// in and in the scope it had.
+    fn execute_block(
+        &mut self,
+        stmts: &[expr::Stmt],
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<(), RoxyErr> {
+        let (start, env) = match self.resuming.pop() {
+            Some(Resume::Block(start, env)) => (start, env),
+            None => (0, env),
+            Some(_) => panic!("internal interpreter error: resumed into the wrong block"),
+        };
+        let saved_env = std::mem::replace(&mut self.env, env);
+        let mut res = Ok(());
+        for (idx, stmt) in stmts.iter().enumerate().skip(start) {
+            res = self.execute(stmt);
+            if res.is_err() {
+                break;
+            }
+            if self.yielded.is_some() {
+                self.unwound.push(Resume::Block(idx, self.env.clone()));
+                break;
+            }
+        }
+        self.env = saved_env;
+        res
+    }
+
+    fn make_function(&self, fundecl: &expr::FunDecl, is_initializer: bool) -> RoxyFunction {
+        RoxyFunction {
+            name: fundecl.name.clone(),
+            params: param_names(&fundecl.params),
+            body: Rc::new(fundecl.body.clone()),
+            closure: self.env.clone(),
+            locals: self.locals.clone(),
+            is_initializer,
+            is_generator: fundecl.generator,
+        }
+    }
+
+    fn class_decl(&mut self, classdecl: &expr::ClassDecl) -> Result<(), RoxyErr> {
+        let superclass = match &classdecl.superclass {
+            Some(superclass_sym) => match self.lookup_variable(superclass_sym)? {
+                Value::Class(superclass) => Some(superclass),
+                val => {
+                    return Err(RoxyErr::RuntimeError {
+                        what: format!(
+                            "superclass '{}' must be a class, found {:?}",
+                            superclass_sym.name,
+                            val.type_of()
+                        ),
+                        line: superclass_sym.line,
+                        col: superclass_sym.col,
+                        trace: Vec::new(),
+                    })
+                }
+            },
+            None => None,
+        };
+        let mixins = classdecl
+            .mixins
+            .iter()
+            .map(|mixin_sym| match self.lookup_variable(mixin_sym)? {
+                Value::Class(mixin) => Ok(mixin),
+                val => Err(RoxyErr::RuntimeError {
+                    what: format!(
+                        "mixin '{}' must be a class, found {:?}",
+                        mixin_sym.name,
+                        val.type_of()
+                    ),
+                    line: mixin_sym.line,
+                    col: mixin_sym.col,
+                    trace: Vec::new(),
+                }),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.env
+            .borrow_mut()
+            .define(classdecl.name.name.clone(), Value::Nil);
+
+        let saved_env = self.env.clone();
+        if let Some(superclass) = &superclass {
+            let mut env = Environment::with_enclosing(self.env.clone());
+            env.define("super".to_string(), Value::Class(superclass.clone()));
+            self.env = Rc::new(RefCell::new(env));
+        }
+
+        let mut methods: HashMap<expr::MethodKind, value::Methods> = HashMap::new();
+        for mixin in &mixins {
+            for kind in expr::MethodKind::ALL {
+                methods
+                    .entry(kind)
+                    .or_default()
+                    .extend(mixin.all_methods(kind));
+            }
+        }
+        for method in &classdecl.methods {
+            let is_initializer =
+                method.kind == expr::MethodKind::Method && method.decl.name.name == "init";
+            methods.entry(method.kind).or_default().insert(
+                method.decl.name.name.clone(),
+                Rc::new(self.make_function(&method.decl, is_initializer)),
+            );
+        }
+        self.env = saved_env;
+
+        let class = Value::Class(Rc::new(RoxyClass {
+            name: classdecl.name.name.clone(),
+            superclass,
+            methods,
+        }));
+        self.env.borrow_mut().assign(&classdecl.name, class)
+    }
+
+    fn evaluate(&mut self, e: &expr::Expr) -> Result<Value, RoxyErr> {
+        match e {
+            expr::Expr::Literal(_, lit) => Ok(Interpreter::literal(lit)),
+            expr::Expr::This(source_location) => {
+                let this_sym = expr::Symbol {
+                    name: "this".to_string(),
+                    line: source_location.line,
+                    col: source_location.col,
+                };
+                self.lookup_variable(&this_sym)
+            }
+            expr::Expr::Unary(op, e) => self.unary(op, e),
+            expr::Expr::Binary(lhs, op, rhs) => {
+                let val = self.binary(lhs, op, rhs)?;
+                let loc = expr::SourceLocation {
+                    line: op.line,
+                    col: op.col,
+                };
+                self.created(val, loc)
+            }
+            expr::Expr::Call(callee, loc, args) => self.call(callee, loc, args),
+            expr::Expr::Get(e, attr) => {
+                let object = self.evaluate(e)?;
+                self.get_property(object, attr)
+            }
+            expr::Expr::Grouping(e) => self.evaluate(e),
+            expr::Expr::Variable(sym) => self.lookup_variable(sym),
+            expr::Expr::Assign(sym, val_expr) => {
+                let val = self.evaluate(val_expr)?;
+                // a variable assigned a `yield` keeps its value until the generator resumes
+                if self.yielded.is_none() {
+                    self.assign_variable(sym, val.clone())?;
+                }
+                Ok(val)
+            }
+            expr::Expr::Logical(lhs, op, rhs) => {
+                let left = self.evaluate(lhs)?;
+                match op {
+                    expr::LogicalOp::Or if left.is_truthy() => Ok(left),
+                    expr::LogicalOp::And if !left.is_truthy() => Ok(left),
+                    _ => self.evaluate(rhs),
+                }
+            }
+            expr::Expr::Set(e, attr, val_expr) => {
+                let instance = Interpreter::settable(self.evaluate(e)?, attr)?;
+                let val = self.evaluate(val_expr)?;
+                self.set_property(&instance, attr, val)
+            }
+            expr::Expr::Super(source_location, method) => {
+                self.super_method(source_location, method)
+            }
+            expr::Expr::List(loc, elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| self.evaluate(element))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.created(Value::List(Rc::new(RefCell::new(elements))), *loc)
+            }
+            expr::Expr::Map(loc, entries) => {
+                let mut map = Map::default();
+                for (key_expr, value_expr) in entries {
+                    let key = self.evaluate(key_expr)?;
+                    let value = self.evaluate(value_expr)?;
+                    map.insert(Interpreter::map_key(&key, loc)?, key, value);
+                }
+                self.created(Value::Map(Rc::new(RefCell::new(map))), *loc)
+            }
+            expr::Expr::Subscript {
+                value,
+                slice,
+                source_location,
+            } => {
+                let value = self.evaluate(value)?;
+                let slice = self.evaluate(slice)?;
+                Interpreter::subscript(value, slice, source_location)
+            }
+            expr::Expr::SetItem {
+                lhs,
+                slice,
+                rhs,
+                source_location,
+            } => {
+                let lhs = self.evaluate(lhs)?;
+                let slice = self.evaluate(slice)?;
+                let rhs = self.evaluate(rhs)?;
+                if let Value::Map(map) = &lhs {
+                    let key = slice.to_key();
+                    if key.is_some_and(|key| !map.borrow().contains(&key)) {
+                        let entry = 2 * std::mem::size_of::<Value>();
+                        self.budget
+                            .allocate(entry)
+                            .map_err(|limit| limit.exceeded_at(*source_location))?;
+                    }
+                }
+                Interpreter::set_item(lhs, slice, rhs.clone(), source_location)?;
+                Ok(rhs)
+            }
+            expr::Expr::Lambda(lambda) => Ok(Value::Function(Rc::new(RoxyFunction {
+                name: expr::Symbol {
+                    name: "lambda".to_string(),
+                    line: 0,
+                    col: -1,
+                },
+                params: param_names(&lambda.params),
+                body: Rc::new(lambda.body.clone()),
+                closure: self.env.clone(),
+                locals: self.locals.clone(),
+                is_initializer: false,
+                is_generator: false,
+            }))),
+            expr::Expr::CompoundAssign(target, op, val_expr) => {
+                let (_, new) = self.update(target, |interpreter, old| {
+                    let val = interpreter.evaluate(val_expr)?;
+                    let new = Interpreter::apply_binary(old, op, val)?;
+                    let loc = expr::SourceLocation {
+                        line: op.line,
+                        col: op.col,
+                    };
+                    interpreter.created(new, loc)
+                })?;
+                Ok(new)
+            }
+            expr::Expr::Increment { target, op, prefix } => {
+                let (old, new) = self.update(target, |_, old| {
+                    Interpreter::apply_binary(old, op, Value::Number(1.0))
+                })?;
+                Ok(if *prefix { new } else { old })
+            }
+            expr::Expr::Ternary(cond, then_branch, else_branch) => {
+                if self.evaluate(cond)?.is_truthy() {
+                    self.evaluate(then_branch)
+                } else {
+                    self.evaluate(else_branch)
+                }
+            }
+            expr::Expr::Interpolation(loc, parts) => {
+                let mut s = String::new();
+                for part in parts {
+                    s.push_str(&self.evaluate(part)?.to_string());
+                }
+                self.created(Value::String(s), *loc)
+            }
+            expr::Expr::Yield(_, val) => {
+                if let Some(Resume::Yield(sent)) = self.resuming.pop() {
+                    return Ok(sent);
+                }
+                let val = match val {
+                    Some(val) => self.evaluate(val)?,
+                    None => Value::Nil,
+                };
+                self.yielded = Some(val);
+                self.unwound.push(Resume::Yield(Value::Nil));
+                Ok(Value::Nil)
+            }
+        }
+    }
+
+    fn assign_variable(&mut self, sym: &expr::Symbol, val: Value) -> Result<(), RoxyErr> {
+        match self.locals.get(sym) {
+            Some(distance) => Environment::assign_at(&self.env, *distance, sym, val),
+            None => self.globals.borrow_mut().assign(sym, val)?,
+        }
+        Ok(())
+    }
+
+    /// Replaces what the variable, property or subscript `target` holds with what `f` makes of
+    /// it, evaluating the parts of the target only once. Hands back the old and new values.
+    fn update(
+        &mut self,
+        target: &expr::Expr,
+        f: impl FnOnce(&mut Interpreter, Value) -> Result<Value, RoxyErr>,
+    ) -> Result<(Value, Value), RoxyErr> {
+        match target {
+            expr::Expr::Variable(sym) => {
+                let old = self.lookup_variable(sym)?;
+                let new = f(self, old.clone())?;
+                self.assign_variable(sym, new.clone())?;
+                Ok((old, new))
+            }
+            expr::Expr::Get(e, attr) => {
+                let instance = Interpreter::settable(self.evaluate(e)?, attr)?;
+                let old = self.get_property(Value::Instance(instance.clone()), attr)?;
+                let new = f(self, old.clone())?;
+                self.set_property(&instance, attr, new.clone())?;
+                Ok((old, new))
+            }
+            expr::Expr::Subscript {
+                value,
+                slice,
+                source_location,
+            } => {
+                let value = self.evaluate(value)?;
+                let slice = self.evaluate(slice)?;
+                let old = Interpreter::subscript(value.clone(), slice.clone(), source_location)?;
+                let new = f(self, old.clone())?;
+                Interpreter::set_item(value, slice, new.clone(), source_location)?;
+                Ok((old, new))
+            }
+            _ => panic!("internal interpreter error: cannot assign to {:?}", target),
+        }
+    }
+
+    /// Reads the property `attr`: a getter, field or method of an instance, or a static method
+    /// of a class.
+    fn get_property(&mut self, object: Value, attr: &expr::Symbol) -> Result<Value, RoxyErr> {
+        match object {
+            Value::Instance(instance) => {
+                let class = instance.borrow().class.clone();
+                match class.find(expr::MethodKind::Getter, &attr.name) {
+                    Some(getter) => {
+                        self.call_accessor(&getter, Value::Instance(instance), Vec::new(), attr)
+                    }
+                    None => RoxyInstance::get(&instance, attr),
+                }
+            }
+            Value::Class(class) => match class.find(expr::MethodKind::Static, &attr.name) {
+                Some(method) => Ok(Value::Function(Rc::new(
+                    method.bind(Value::Class(class.clone())),
+                ))),
+                None => Err(RoxyErr::RuntimeError {
+                    what: format!(
+                        "undefined static method '{}' on class {}",
+                        attr.name, class.name
+                    ),
+                    line: attr.line,
+                    col: attr.col,
+                    trace: Vec::new(),
+                }),
+            },
+            val => Err(RoxyErr::RuntimeError {
+                what: format!(
+                    "only instances and classes have attributes, found {:?} when getting '{}'",
+                    val.type_of(),
+                    attr.name
+                ),
+                line: attr.line,
+                col: attr.col,
+                trace: Vec::new(),
+            }),
+        }
+    }
+
+    /// The instance whose property `attr` is about to be set.
+    fn settable(object: Value, attr: &expr::Symbol) -> Result<Rc<RefCell<RoxyInstance>>, RoxyErr> {
+        match object {
+            Value::Instance(instance) => Ok(instance),
+            val => Err(RoxyErr::RuntimeError {
+                what: format!(
+                    "only instances have fields, found {:?} when setting '{}'",
+                    val.type_of(),
+                    attr.name
+                ),
+                line: attr.line,
+                col: attr.col,
+                trace: Vec::new(),
+            }),
+        }
+    }
+
+    /// Sets the property `attr` through its setter, if it has one, and the field of that name
+    /// otherwise. A property with only a getter is read-only.
+    fn set_property(
+        &mut self,
+        instance: &Rc<RefCell<RoxyInstance>>,
+        attr: &expr::Symbol,
+        val: Value,
+    ) -> Result<Value, RoxyErr> {
+        let class = instance.borrow().class.clone();
+        if let Some(setter) = class.find(expr::MethodKind::Setter, &attr.name) {
+            let this = Value::Instance(instance.clone());
+            self.call_accessor(&setter, this, vec![val.clone()], attr)?;
+        } else if class.find(expr::MethodKind::Getter, &attr.name).is_some() {
+            return Err(RoxyErr::RuntimeError {
+                what: format!(
+                    "cannot set '{}' on {} instance, which has a getter but no setter",
+                    attr.name, class.name
+                ),
+                line: attr.line,
+                col: attr.col,
+                trace: Vec::new(),
+            });
+        } else {
+            instance
+                .borrow_mut()
+                .fields
+                .insert(attr.name.clone(), val.clone());
+        }
+        Ok(val)
+    }
+
+    /// Runs a getter or setter for `this`, as called from where `attr` is.
+    fn call_accessor(
+        &mut self,
+        accessor: &RoxyFunction,
+        this: Value,
+        args: Vec<Value>,
+        attr: &expr::Symbol,
+    ) -> Result<Value, RoxyErr> {
+        let bound = Value::Function(Rc::new(accessor.bind(this)));
+        let loc = expr::SourceLocation {
+            line: attr.line,
+            col: attr.col,
+        };
+        let saved_call_site = self.call_site.replace(loc);
+        let res = self.call_function(&bound, args);
+        self.call_site = saved_call_site;
+        res
+    }
+
+    fn lookup_variable(&self, sym: &expr::Symbol) -> Result<Value, RoxyErr> {
+        match self.locals.get(sym) {
+            Some(distance) => Environment::get_at(&self.env, *distance, sym),
+            None => self.globals.borrow().get(sym),
+        }
+    }
+
+    fn literal(lit: &expr::Literal) -> Value {
+        match lit {
+            expr::Literal::Number(n) => Value::Number(*n),
+            expr::Literal::String(s) => Value::String(s.clone()),
+            expr::Literal::True => Value::Bool(true),
+            expr::Literal::False => Value::Bool(false),
+            expr::Literal::Nil => Value::Nil,
+        }
+    }
+
+    fn super_method(
+        &mut self,
+        source_location: &expr::SourceLocation,
+        method: &expr::Symbol,
+    ) -> Result<Value, RoxyErr> {
+        let super_sym = expr::Symbol {
+            name: "super".to_string(),
+            line: source_location.line,
+            col: source_location.col,
+        };
+        let distance = match self.locals.get(&super_sym) {
+            Some(distance) => *distance,
+            None => panic!("internal interpreter error: 'super' was not resolved"),
+        };
+        let superclass = match Environment::get_at(&self.env, distance, &super_sym)? {
+            Value::Class(superclass) => superclass,
+            _ => panic!("internal interpreter error: 'super' is not bound to a class"),
+        };
+        let this_sym = expr::Symbol {
+            name: "this".to_string(),
+            line: source_location.line,
+            col: source_location.col,
+        };
+        // the environment binding `this` always sits directly inside the one binding `super`
+        let this = Environment::get_at(&self.env, distance - 1, &this_sym)?;
+
+        if let Some(getter) = superclass.find(expr::MethodKind::Getter, &method.name) {
+            return self.call_accessor(&getter, this, Vec::new(), method);
+        }
+        match superclass.find_method(&method.name) {
+            Some(func) => Ok(Value::Function(Rc::new(func.bind(this)))),
+            None => Err(RoxyErr::RuntimeError {
+                what: format!(
+                    "undefined property '{}' on superclass {}",
+                    method.name, superclass.name
+                ),
+                line: method.line,
+                col: method.col,
+                trace: Vec::new(),
+            }),
+        }
+    }
+
+    fn call(
+        &mut self,
+        callee: &expr::Expr,
+        loc: &expr::SourceLocation,
+        args: &[expr::Expr],
+    ) -> Result<Value, RoxyErr> {
+        let callee = self.evaluate(callee)?;
+        let args = args
+            .iter()
+            .map(|arg| self.evaluate(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let saved_call_site = self.call_site.replace(*loc);
+        let res = self.call_function(&callee, args);
+        self.call_site = saved_call_site;
+        // natives and classes hand back what they have just made
+        let res = match (res, &callee) {
+            (Ok(val), Value::NativeFunction(_) | Value::Class(_)) => self.created(val, *loc),
+            (res, _) => res,
+        };
+        res.map_err(|err| at_call(err, loc))
+    }
+
+    /// Calls `callee` with already evaluated arguments; natives use this to call back into
+    /// roxy code. Errors about the call itself come back as `RoxyErr::NativeError`.
+    pub fn call_function(&mut self, callee: &Value, args: Vec<Value>) -> Result<Value, RoxyErr> {
+        let arity = match callee {
+            Value::NativeFunction(func) => func.arity(),
+            Value::Function(func) => func.arity(),
+            Value::Class(class) => value::class_arity(class),
+            _ => {
+                return Err(RoxyErr::NativeError(format!(
+                    "value {:?} is not callable",
+                    callee
+                )))
+            }
+        };
+
+        if args.len() != arity as usize {
+            return Err(RoxyErr::NativeError(format!(
+                "invalid call: callee {:?} expected {} arguments, but got {}",
+                callee,
+                arity,
+                args.len()
+            )));
+        }
+
+        match callee {
+            Value::NativeFunction(func) => func.call(self, args),
+            Value::Function(func) => func.call(self, args),
+            Value::Class(class) => value::instantiate(class, self, args),
+            _ => unreachable!(),
+        }
+    }
+
+    fn subscript(
+        value: Value,
+        slice: Value,
+        source_location: &expr::SourceLocation,
+    ) -> Result<Value, RoxyErr> {
+        match value {
+            Value::List(elements) => {
+                let elements = elements.borrow();
+                let idx = Interpreter::list_index(&slice, elements.len(), source_location)?;
+                Ok(elements[idx].clone())
+            }
+            Value::String(s) => {
+                let chars: Vec<char> = s.chars().collect();
+                let idx = Interpreter::list_index(&slice, chars.len(), source_location)?;
+                Ok(Value::String(chars[idx].to_string()))
+            }
+            Value::Map(map) => {
+                let key = Interpreter::map_key(&slice, source_location)?;
+                match map.borrow().get(&key) {
+                    Some(value) => Ok(value.clone()),
+                    None => Err(RoxyErr::RuntimeError {
+                        what: format!("no entry for key {:?}", slice),
+                        line: source_location.line,
+                        col: source_location.col,
+                        trace: Vec::new(),
+                    }),
+                }
+            }
+            _ => Err(RoxyErr::RuntimeError {
+                what: format!("cannot subscript {:?}", value.type_of()),
+                line: source_location.line,
+                col: source_location.col,
+                trace: Vec::new(),
+            }),
+        }
+    }
+
+    fn set_item(
+        lhs: Value,
+        slice: Value,
+        rhs: Value,
+        source_location: &expr::SourceLocation,
+    ) -> Result<(), RoxyErr> {
+        match lhs {
+            Value::List(elements) => {
+                let len = elements.borrow().len();
+                let idx = Interpreter::list_index(&slice, len, source_location)?;
+                elements.borrow_mut()[idx] = rhs;
+                Ok(())
+            }
+            Value::Map(map) => {
+                let key = Interpreter::map_key(&slice, source_location)?;
+                map.borrow_mut().insert(key, slice, rhs);
+                Ok(())
+            }
+            _ => Err(RoxyErr::RuntimeError {
+                what: format!("cannot set items on {:?}", lhs.type_of()),
+                line: source_location.line,
+                col: source_location.col,
+                trace: Vec::new(),
+            }),
+        }
+    }
+
+    fn map_key(key: &Value, source_location: &expr::SourceLocation) -> Result<Key, RoxyErr> {
+        key.to_key().ok_or_else(|| RoxyErr::RuntimeError {
+            what: format!("invalid map key type {:?}", key.type_of()),
+            line: source_location.line,
+            col: source_location.col,
+            trace: Vec::new(),
+        })
+    }
+
+    fn list_index(
+        slice: &Value,
+        len: usize,
+        source_location: &expr::SourceLocation,
+    ) -> Result<usize, RoxyErr> {
+        match slice {
+            Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 && (*n as usize) < len => {
+                Ok(*n as usize)
+            }
+            Value::Number(n) => Err(RoxyErr::RuntimeError {
+                what: format!("index {} out of bounds for length {}", n, len),
+                line: source_location.line,
+                col: source_location.col,
+                trace: Vec::new(),
+            }),
+            _ => Err(RoxyErr::RuntimeError {
+                what: format!("invalid index type {:?}", slice.type_of()),
+                line: source_location.line,
+                col: source_location.col,
+                trace: Vec::new(),
+            }),
+        }
+    }
+
+    fn unary(&mut self, op: &expr::UnaryOp, e: &expr::Expr) -> Result<Value, RoxyErr> {
+        let val = self.evaluate(e)?;
+
+        match (op.ty, &val) {
+            (expr::UnaryOpTy::Minus, Value::Number(n)) => Ok(Value::Number(-n)),
+            (expr::UnaryOpTy::Bang, _) => Ok(Value::Bool(!val.is_truthy())),
+            (expr::UnaryOpTy::Tilde, Value::Number(n)) => match value::bitwise_not(*n) {
+                Ok(n) => Ok(Value::Number(n)),
+                Err(what) => Err(RoxyErr::RuntimeError {
+                    what,
+                    line: op.line,
+                    col: op.col,
+                    trace: Vec::new(),
+                }),
+            },
+            (expr::UnaryOpTy::Minus | expr::UnaryOpTy::Tilde, _) => Err(RoxyErr::RuntimeError {
+                what: format!("invalid application of unary op {:?} to {:?}", op.ty, val),
+                line: op.line,
+                col: op.col,
+                trace: Vec::new(),
+            }),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        lhs: &expr::Expr,
+        op: &expr::BinaryOp,
+        rhs: &expr::Expr,
+    ) -> Result<Value, RoxyErr> {
+        let left = self.evaluate(lhs)?;
+        let right = self.evaluate(rhs)?;
+        Interpreter::apply_binary(left, op, right)
+    }
+
+    fn apply_binary(left: Value, op: &expr::BinaryOp, right: Value) -> Result<Value, RoxyErr> {
+        match (&left, op.ty, &right) {
+            (_, expr::BinaryOpTy::EqualEqual, _) => Ok(Value::Bool(left.equals(&right))),
+            (_, expr::BinaryOpTy::NotEqual, _) => Ok(Value::Bool(!left.equals(&right))),
+            (Value::Number(n1), _, Value::Number(n2)) => match op.ty {
+                expr::BinaryOpTy::Less => Ok(Value::Bool(n1 < n2)),
+                expr::BinaryOpTy::LessEqual => Ok(Value::Bool(n1 <= n2)),
+                expr::BinaryOpTy::Greater => Ok(Value::Bool(n1 > n2)),
+                expr::BinaryOpTy::GreaterEqual => Ok(Value::Bool(n1 >= n2)),
+                expr::BinaryOpTy::Plus => Ok(Value::Number(n1 + n2)),
+                expr::BinaryOpTy::Minus => Ok(Value::Number(n1 - n2)),
+                expr::BinaryOpTy::Star => Ok(Value::Number(n1 * n2)),
+                expr::BinaryOpTy::Slash => Ok(Value::Number(n1 / n2)),
+                expr::BinaryOpTy::Percent => Ok(Value::Number(n1 % n2)),
+                expr::BinaryOpTy::StarStar => Ok(Value::Number(n1.powf(*n2))),
+                expr::BinaryOpTy::Ampersand
+                | expr::BinaryOpTy::Pipe
+                | expr::BinaryOpTy::Caret
+                | expr::BinaryOpTy::LessLess
+                | expr::BinaryOpTy::GreaterGreater => match value::bitwise(op.ty, *n1, *n2) {
+                    Ok(n) => Ok(Value::Number(n)),
+                    Err(what) => Err(RoxyErr::RuntimeError {
+                        what,
+                        line: op.line,
+                        col: op.col,
+                        trace: Vec::new(),
+                    }),
+                },
+                expr::BinaryOpTy::EqualEqual | expr::BinaryOpTy::NotEqual => unreachable!(),
+            },
+            (Value::String(s1), expr::BinaryOpTy::Plus, Value::String(s2)) => {
+                Ok(Value::String(format!("{}{}", s1, s2)))
+            }
+            (Value::List(l1), expr::BinaryOpTy::Plus, Value::List(l2)) => {
+                let mut elements = l1.borrow().clone();
+                elements.extend(l2.borrow().iter().cloned());
+                Ok(Value::List(Rc::new(RefCell::new(elements))))
+            }
+            _ => Err(RoxyErr::RuntimeError {
+                what: format!(
+                    "invalid operands in binary operator {:?} of types {:?} and {:?}",
+                    op.ty,
+                    left.type_of(),
+                    right.type_of()
+                ),
+                line: op.line,
+                col: op.col,
+                trace: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// Roughly how far down the stack of the current thread is, which grows towards lower
+/// addresses on every platform roxy runs on.
+fn stack_position() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+/// Locates an error about a call made from `loc` there.
+fn at_call(err: RoxyErr, loc: &expr::SourceLocation) -> RoxyErr {
+    match err {
+        RoxyErr::NativeError(what) => RoxyErr::RuntimeError {
+            what,
+            line: loc.line,
+            col: loc.col,
+            trace: Vec::new(),
+        },
+        err => err,
+    }
+}
+
+/// A statement a generator is being resumed in found a record left by a different kind of
+/// statement.
+fn mismatched_resume(stmt: &expr::Stmt) -> ! {
+    panic!(
+        "internal interpreter error: resumed into the wrong statement {:?}",
+        stmt
+    )
+}
+
+/// Functions only need their parameters' names at runtime; annotations are for the checker.
+fn param_names(params: &[expr::Param]) -> Vec<expr::Symbol> {
+    params.iter().map(|param| param.name.clone()).collect()
+}
@@ -1,7 +1,36 @@
-mod err;
-mod expr;
-mod parser;
-mod extensions;
-mod value;
+//! roxy, a Lox dialect with a tree-walking interpreter and a bytecode VM. Programs that want
+//! to run roxy code themselves should start from `Engine`.
 
-pub enum Result {}
+pub mod bytecode;
+pub mod compiler;
+pub mod debugger;
+pub mod diagnostic;
+pub mod dump;
+pub mod embed;
+pub mod err;
+pub mod expr;
+pub mod extensions;
+pub mod formatter;
+pub mod heap;
+pub mod interpreter;
+pub mod limits;
+pub mod lint;
+pub mod lsp;
+pub mod map;
+pub mod modules;
+pub mod optimize;
+pub mod parser;
+pub mod resolver;
+pub mod roxy;
+pub mod scanner;
+pub mod stdlib;
+pub mod syntax;
+pub mod typecheck;
+pub mod value;
+pub mod vm;
+
+pub use embed::{Engine, FromValue, IntoValue};
+pub use err::RoxyErr;
+pub use extensions::Extensions;
+pub use limits::Limits;
+pub use value::Value;
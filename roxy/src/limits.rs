@@ -0,0 +1,176 @@
+//! Bounds on what a program may do, for running scripts that aren't trusted. Both backends
+//! keep a `Budget` of their `Limits` and stop the program with `RoxyErr::LimitExceeded` as
+//! soon as it runs out; no `catch` sees that error and no finally block runs on the way out.
+
+use crate::err::RoxyErr;
+use crate::expr::SourceLocation;
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Everything is unlimited by default. A `None` leaves that resource unlimited.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Limits {
+    /// statements for the tree-walker, instructions for the VM
+    pub steps: Option<u64>,
+    /// wall-clock time
+    pub time: Option<Duration>,
+    /// roxy calls in progress at once. Without one, both backends still stop runaway
+    /// recursion with a catchable stack overflow error, so `None` is safe on either.
+    pub call_depth: Option<usize>,
+    /// The VM counts the objects still live after a collection. The tree-walker leaves
+    /// freeing to reference counting and can't tell what is still live, so there this is an
+    /// allocation budget instead: every string, list, map and instance it creates counts
+    /// towards it for good, and running out is reported as `Limit::AllocatedBytes`.
+    pub heap_bytes: Option<usize>,
+    /// what `print` writes, newlines included
+    pub output_bytes: Option<usize>,
+    /// whether imports and the natives in `stdlib::IO_NATIVES` are available
+    pub io: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            steps: None,
+            time: None,
+            call_depth: None,
+            heap_bytes: None,
+            output_bytes: None,
+            io: true,
+        }
+    }
+}
+
+/// The limit a program ran into, as it was set.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Limit {
+    Steps(u64),
+    Time(Duration),
+    CallDepth(usize),
+    HeapBytes(usize),
+    AllocatedBytes(usize),
+    OutputBytes(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Steps(steps) => write!(f, "exceeded the limit of {} steps", steps),
+            Limit::Time(time) => write!(f, "exceeded the time limit of {:?}", time),
+            Limit::CallDepth(depth) => write!(f, "exceeded the call depth limit of {}", depth),
+            Limit::HeapBytes(bytes) => write!(f, "exceeded the heap limit of {} bytes", bytes),
+            Limit::AllocatedBytes(bytes) => {
+                write!(f, "exceeded the allocation limit of {} bytes", bytes)
+            }
+            Limit::OutputBytes(bytes) => {
+                write!(f, "exceeded the output limit of {} bytes", bytes)
+            }
+        }
+    }
+}
+
+impl Limit {
+    pub fn exceeded_at(self, loc: SourceLocation) -> RoxyErr {
+        RoxyErr::LimitExceeded {
+            limit: self,
+            line: loc.line,
+            col: loc.col,
+        }
+    }
+}
+
+// reading the clock on every step would slow everything down for the sake of the time limit
+const STEPS_PER_CLOCK_READ: u64 = 1024;
+
+/// How much of its `Limits` a running program has used up.
+pub struct Budget {
+    limits: Limits,
+    steps: u64,
+    started: Instant,
+    output_bytes: usize,
+    allocated_bytes: usize,
+}
+
+impl Default for Budget {
+    fn default() -> Budget {
+        Budget::new(Limits::default())
+    }
+}
+
+impl Budget {
+    pub fn new(limits: Limits) -> Budget {
+        Budget {
+            limits,
+            steps: 0,
+            started: Instant::now(),
+            output_bytes: 0,
+            allocated_bytes: 0,
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Starts counting steps, time, output and allocations from nothing again.
+    pub fn restart(&mut self) {
+        *self = Budget::new(self.limits);
+    }
+
+    /// Takes one step. The clock is only read every so many steps, so a program can overrun
+    /// its time limit by as long as those take.
+    pub fn step(&mut self) -> Result<(), Limit> {
+        self.steps += 1;
+        if let Some(steps) = self.limits.steps {
+            if self.steps > steps {
+                return Err(Limit::Steps(steps));
+            }
+        }
+        if let Some(time) = self.limits.time {
+            if self.steps.is_multiple_of(STEPS_PER_CLOCK_READ) && self.started.elapsed() > time {
+                return Err(Limit::Time(time));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a call that would make `depth` calls in progress.
+    pub fn call(&self, depth: usize) -> Result<(), Limit> {
+        match self.limits.call_depth {
+            Some(call_depth) if depth > call_depth => Err(Limit::CallDepth(call_depth)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Accounts for `bytes` about to be printed, refusing them if they don't fit.
+    pub fn output(&mut self, bytes: usize) -> Result<(), Limit> {
+        self.output_bytes += bytes;
+        match self.limits.output_bytes {
+            Some(output_bytes) if self.output_bytes > output_bytes => {
+                Err(Limit::OutputBytes(output_bytes))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// For the tree-walker: accounts for a newly created object of `bytes`, which counts
+    /// against the heap limit even once it has been freed.
+    pub fn allocate(&mut self, bytes: usize) -> Result<(), Limit> {
+        self.allocated_bytes += bytes;
+        match self.limits.heap_bytes {
+            Some(heap_bytes) if self.allocated_bytes > heap_bytes => {
+                Err(Limit::AllocatedBytes(heap_bytes))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// For the VM: checks the `live` bytes left after a collection.
+    pub fn heap(&self, live: usize) -> Result<(), Limit> {
+        match self.limits.heap_bytes {
+            Some(heap_bytes) if live > heap_bytes => Err(Limit::HeapBytes(heap_bytes)),
+            _ => Ok(()),
+        }
+    }
+}
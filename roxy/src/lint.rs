@@ -0,0 +1,641 @@
+//! `roxy lint`: warnings about code that runs but is probably not what was meant. A warning
+//! is silenced by a comment on the line it points at: `// lint: allow` silences every lint
+//! there, `// lint: allow unused-variable, shadowing` just the ones named.
+
+use crate::diagnostic::Diagnostic;
+use crate::expr;
+use crate::extensions::Extensions;
+use crate::parser;
+use crate::resolver;
+use crate::scanner;
+use crate::typecheck;
+
+use std::collections::HashMap;
+
+/// Every lint's diagnostic code and the name suppression comments know it by.
+pub const LINTS: [(&str, &str); 7] = [
+    ("W0001", "unused-variable"),
+    ("W0002", "unused-parameter"),
+    ("W0003", "shadowing"),
+    ("W0004", "unreachable-code"),
+    ("W0005", "assignment-in-condition"),
+    ("W0006", "nil-comparison"),
+    ("W0007", "wrong-arity"),
+];
+
+fn lint_name(code: &str) -> &'static str {
+    LINTS
+        .iter()
+        .find(|(lint_code, _)| *lint_code == code)
+        .map(|(_, name)| *name)
+        .unwrap_or_default()
+}
+
+/// Lints `source` and returns its warnings in source order, or the errors that keep it from
+/// compiling.
+pub fn lint(extensions: Extensions, source: &str) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    let (tokens, comments) = scanner::scan_tokens_with_comments(source.to_string())
+        .map_err(|err| vec![Diagnostic::from(&err)])?;
+    let stmts = parser::parse(extensions, tokens)
+        .map_err(|errs| errs.iter().map(Diagnostic::from).collect::<Vec<_>>())?;
+    resolver::resolve(&stmts).map_err(|err| vec![Diagnostic::from(&err)])?;
+    typecheck::check(&stmts)
+        .map_err(|errs| errs.iter().map(Diagnostic::from).collect::<Vec<_>>())?;
+
+    let mut linter = Linter::default();
+    linter.program(&stmts);
+    let mut warnings = linter.finish();
+
+    let allowed = suppressions(&comments);
+    warnings.retain(|warning| match allowed.get(&warning.line) {
+        Some(names) => {
+            !names.is_empty() && !names.iter().any(|name| name == lint_name(warning.code))
+        }
+        None => true,
+    });
+    warnings.sort_by_key(|warning| (warning.line, warning.col));
+    for warning in &mut warnings {
+        let name = lint_name(warning.code);
+        warning
+            .notes
+            .push(format!("silence with `// lint: allow {}`", name));
+    }
+    Ok(warnings)
+}
+
+/// The lints each line allows; an empty list allows them all.
+fn suppressions(comments: &[scanner::Comment]) -> HashMap<usize, Vec<String>> {
+    comments
+        .iter()
+        .filter_map(|comment| {
+            let text = comment.text.strip_prefix("//")?.trim();
+            let rest = text.strip_prefix("lint:")?.trim().strip_prefix("allow")?;
+            if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                return None;
+            }
+            let names = rest
+                .split(',')
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect();
+            Some((comment.line, names))
+        })
+        .collect()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DeclKind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+    Import,
+    /// the name a `catch` binds, which may well go unused
+    Caught,
+}
+
+struct Decl {
+    sym: expr::Symbol,
+    kind: DeclKind,
+    used: bool,
+    reassigned: bool,
+    // every value it is given is a number, as far as literals and arithmetic tell
+    numeric: bool,
+    // how many arguments calling it takes, when that is known
+    arity: Option<usize>,
+}
+
+/// A value that is a number, or is one if the variable it comes from only ever holds numbers.
+enum Number {
+    Known,
+    IfNumeric(usize),
+}
+
+struct Call {
+    decl: usize,
+    args: usize,
+    callee: expr::Symbol,
+}
+
+#[derive(Default)]
+struct Linter {
+    decls: Vec<Decl>,
+    globals: HashMap<String, usize>,
+    scopes: Vec<HashMap<String, usize>>,
+    // checked once every assignment has been seen
+    calls: Vec<Call>,
+    nil_comparisons: Vec<(Number, expr::BinaryOp)>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl Linter {
+    fn warn(
+        &mut self,
+        code: &'static str,
+        message: String,
+        loc: expr::SourceLocation,
+        len: usize,
+    ) -> &mut Diagnostic {
+        let warning = Diagnostic::warning(code, message, loc.line, loc.col).with_len(len);
+        self.warnings.push(warning);
+        self.warnings.last_mut().unwrap()
+    }
+
+    fn program(&mut self, stmts: &[expr::Stmt]) {
+        // functions may call globals declared further down
+        for stmt in stmts {
+            let (sym, kind, arity) = match stmt {
+                expr::Stmt::VarDecl(sym, _, _) => (sym, DeclKind::Variable, None),
+                expr::Stmt::FunDecl(fundecl) => (
+                    &fundecl.name,
+                    DeclKind::Function,
+                    Some(fundecl.params.len()),
+                ),
+                expr::Stmt::ClassDecl(classdecl) => {
+                    (&classdecl.name, DeclKind::Class, class_arity(classdecl))
+                }
+                expr::Stmt::Import(expr::ImportDecl {
+                    name: Some(name), ..
+                }) => (name, DeclKind::Import, None),
+                _ => continue,
+            };
+            if !self.globals.contains_key(&sym.name) {
+                self.globals.insert(sym.name.clone(), self.decls.len());
+                self.decls.push(Decl {
+                    sym: sym.clone(),
+                    kind,
+                    used: false,
+                    reassigned: false,
+                    numeric: false,
+                    arity,
+                });
+            }
+        }
+        self.body(stmts);
+    }
+
+    fn finish(mut self) -> Vec<Diagnostic> {
+        for call in std::mem::take(&mut self.calls) {
+            let decl = &self.decls[call.decl];
+            let arity = match decl.arity {
+                Some(arity) if !decl.reassigned && arity != call.args => arity,
+                _ => continue,
+            };
+            let message = format!(
+                "'{}' takes {} but {} {} given",
+                call.callee.name,
+                plural(arity, "argument"),
+                call.args,
+                if call.args == 1 { "was" } else { "were" }
+            );
+            let note = format!("'{}' is declared on line {}", decl.sym.name, decl.sym.line);
+            let loc = symbol_location(&call.callee);
+            self.warn("W0007", message, loc, call.callee.name.chars().count())
+                .notes
+                .push(note);
+        }
+
+        for (number, op) in std::mem::take(&mut self.nil_comparisons) {
+            if let Number::IfNumeric(decl) = number {
+                if !self.decls[decl].numeric {
+                    continue;
+                }
+            }
+            let always = match op.ty {
+                expr::BinaryOpTy::EqualEqual => "false",
+                _ => "true",
+            };
+            let loc = expr::SourceLocation {
+                line: op.line,
+                col: op.col,
+            };
+            let message = format!("comparing a number with nil is always {}", always);
+            self.warn("W0006", message, loc, 2);
+        }
+        self.warnings
+    }
+
+    fn lookup(&self, sym: &expr::Symbol) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&sym.name))
+            .or_else(|| self.globals.get(&sym.name))
+            .copied()
+    }
+
+    fn declare(&mut self, sym: &expr::Symbol, kind: DeclKind, numeric: bool, arity: Option<usize>) {
+        if self.scopes.is_empty() {
+            let decl = &mut self.decls[self.globals[&sym.name]];
+            if decl.sym == *sym {
+                decl.numeric = numeric;
+            } else {
+                // declaring a global again just assigns it
+                decl.reassigned = true;
+                decl.numeric &= numeric;
+            }
+            return;
+        }
+
+        let (innermost, enclosing) = self.scopes.split_last().unwrap();
+        if !innermost.contains_key(&sym.name) {
+            if let Some(&shadowed) = enclosing
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(&sym.name))
+            {
+                let message = format!(
+                    "'{}' shadows the local declared on line {}",
+                    sym.name, self.decls[shadowed].sym.line
+                );
+                self.warn(
+                    "W0003",
+                    message,
+                    symbol_location(sym),
+                    sym.name.chars().count(),
+                );
+            }
+        }
+
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(sym.name.clone(), self.decls.len());
+        self.decls.push(Decl {
+            sym: sym.clone(),
+            kind,
+            used: false,
+            reassigned: false,
+            numeric,
+            arity,
+        });
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        let mut decls: Vec<usize> = self.scopes.pop().unwrap().into_values().collect();
+        decls.sort();
+        for decl in decls {
+            let decl = &self.decls[decl];
+            let (code, what) = match decl.kind {
+                _ if decl.used => continue,
+                DeclKind::Variable => ("W0001", "variable"),
+                DeclKind::Parameter => ("W0002", "parameter"),
+                _ => continue,
+            };
+            let message = format!("unused {} '{}'", what, decl.sym.name);
+            let (loc, len) = (symbol_location(&decl.sym), decl.sym.name.chars().count());
+            self.warn(code, message, loc, len);
+        }
+    }
+
+    /// Statements run one after the other, where a `return`, `throw`, `break` or `continue`
+    /// ends the run.
+    fn body(&mut self, stmts: &[expr::Stmt]) {
+        let mut returned = false;
+        let mut reported = false;
+        for stmt in stmts {
+            if returned && !reported {
+                let message = "unreachable statement".to_string();
+                let note =
+                    "nothing after a return, throw, break or continue in the same block runs"
+                        .to_string();
+                self.warn("W0004", message, stmt.location(), 1)
+                    .notes
+                    .push(note);
+                reported = true;
+            }
+            if let expr::Stmt::Return(..)
+            | expr::Stmt::Throw(..)
+            | expr::Stmt::Break(..)
+            | expr::Stmt::Continue(..) = stmt
+            {
+                returned = true;
+            }
+            self.stmt(stmt);
+        }
+    }
+
+    fn function(&mut self, params: &[expr::Param], body: &[expr::Stmt]) {
+        self.begin_scope();
+        for param in params {
+            self.declare(&param.name, DeclKind::Parameter, false, None);
+        }
+        self.body(body);
+        self.end_scope();
+    }
+
+    fn condition(&mut self, cond: &expr::Expr) {
+        if let expr::Expr::Assign(sym, _) = cond {
+            let message = format!("assignment to '{}' used as a condition", sym.name);
+            let note = "use == to compare, or put the assignment in parentheses if it is meant";
+            self.warn("W0005", message, cond.location(), sym.name.chars().count())
+                .notes
+                .push(note.to_string());
+        }
+        self.expr(cond);
+    }
+
+    fn stmt(&mut self, stmt: &expr::Stmt) {
+        match stmt {
+            expr::Stmt::Expr(e) | expr::Stmt::Print(_, e) => self.expr(e),
+            expr::Stmt::FunDecl(fundecl) => {
+                let arity = Some(fundecl.params.len());
+                self.declare(&fundecl.name, DeclKind::Function, false, arity);
+                self.function(&fundecl.params, &fundecl.body);
+            }
+            expr::Stmt::ClassDecl(classdecl) => {
+                if let Some(superclass) = &classdecl.superclass {
+                    self.reference(superclass);
+                }
+                for mixin in &classdecl.mixins {
+                    self.reference(mixin);
+                }
+                let arity = class_arity(classdecl);
+                self.declare(&classdecl.name, DeclKind::Class, false, arity);
+                for method in &classdecl.methods {
+                    self.function(&method.decl.params, &method.decl.body);
+                }
+            }
+            expr::Stmt::If(cond, then_branch, else_branch) => {
+                self.condition(cond);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            expr::Stmt::VarDecl(sym, _, initializer) => {
+                if let Some(initializer) = initializer {
+                    self.expr(initializer);
+                }
+                let numeric = initializer.as_ref().is_some_and(|e| self.is_number(e));
+                self.declare(sym, DeclKind::Variable, numeric, None);
+            }
+            expr::Stmt::Block(_, stmts) => self.block(stmts),
+            expr::Stmt::Return(_, val) => {
+                if let Some(val) = val {
+                    self.expr(val);
+                }
+            }
+            expr::Stmt::While(cond, body) => {
+                self.condition(cond);
+                self.stmt(body);
+            }
+            expr::Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.begin_scope();
+                if let Some(initializer) = initializer {
+                    self.stmt(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.condition(condition);
+                }
+                if let Some(increment) = increment {
+                    self.expr(increment);
+                }
+                self.stmt(body);
+                self.end_scope();
+            }
+            expr::Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                self.expr(iterable);
+                self.begin_scope();
+                self.declare(variable, DeclKind::Variable, false, None);
+                self.stmt(body);
+                self.end_scope();
+            }
+            expr::Stmt::Labeled(_, body) => self.stmt(body),
+            expr::Stmt::Break(..) | expr::Stmt::Continue(..) => {}
+            expr::Stmt::Import(import) => {
+                if let Some(name) = &import.name {
+                    self.declare(name, DeclKind::Import, false, None);
+                }
+            }
+            expr::Stmt::Throw(_, e) => self.expr(e),
+            expr::Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.block(body);
+                if let Some((name, handler)) = catch {
+                    self.begin_scope();
+                    self.declare(name, DeclKind::Caught, false, None);
+                    self.body(handler);
+                    self.end_scope();
+                }
+                if let Some(finally) = finally {
+                    self.block(finally);
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, stmts: &[expr::Stmt]) {
+        self.begin_scope();
+        self.body(stmts);
+        self.end_scope();
+    }
+
+    fn reference(&mut self, sym: &expr::Symbol) -> Option<usize> {
+        let decl = self.lookup(sym)?;
+        self.decls[decl].used = true;
+        Some(decl)
+    }
+
+    fn number(&self, e: &expr::Expr) -> Option<Number> {
+        match e {
+            expr::Expr::Literal(_, expr::Literal::Number(_)) => Some(Number::Known),
+            expr::Expr::Unary(op, _)
+                if matches!(op.ty, expr::UnaryOpTy::Minus | expr::UnaryOpTy::Tilde) =>
+            {
+                Some(Number::Known)
+            }
+            expr::Expr::Increment { .. } => Some(Number::Known),
+            expr::Expr::Binary(lhs, op, rhs) => match op.ty {
+                expr::BinaryOpTy::Minus
+                | expr::BinaryOpTy::Star
+                | expr::BinaryOpTy::Slash
+                | expr::BinaryOpTy::Percent
+                | expr::BinaryOpTy::StarStar
+                | expr::BinaryOpTy::Ampersand
+                | expr::BinaryOpTy::Pipe
+                | expr::BinaryOpTy::Caret
+                | expr::BinaryOpTy::LessLess
+                | expr::BinaryOpTy::GreaterGreater => Some(Number::Known),
+                // + also joins strings and lists
+                expr::BinaryOpTy::Plus if self.is_number(lhs) && self.is_number(rhs) => {
+                    Some(Number::Known)
+                }
+                _ => None,
+            },
+            expr::Expr::Grouping(e) => self.number(e),
+            expr::Expr::Variable(sym) => self.lookup(sym).map(Number::IfNumeric),
+            _ => None,
+        }
+    }
+
+    fn is_number(&self, e: &expr::Expr) -> bool {
+        matches!(self.number(e), Some(Number::Known))
+    }
+
+    fn expr(&mut self, e: &expr::Expr) {
+        match e {
+            expr::Expr::Literal(..) | expr::Expr::This(_) | expr::Expr::Super(..) => {}
+            expr::Expr::Unary(_, e) | expr::Expr::Grouping(e) | expr::Expr::Get(e, _) => {
+                self.expr(e)
+            }
+            expr::Expr::Binary(lhs, op, rhs) => {
+                if let expr::BinaryOpTy::EqualEqual | expr::BinaryOpTy::NotEqual = op.ty {
+                    let other = match (lhs.as_ref(), rhs.as_ref()) {
+                        (expr::Expr::Literal(_, expr::Literal::Nil), other)
+                        | (other, expr::Expr::Literal(_, expr::Literal::Nil)) => self.number(other),
+                        _ => None,
+                    };
+                    if let Some(number) = other {
+                        self.nil_comparisons.push((number, *op));
+                    }
+                }
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            expr::Expr::Logical(lhs, _, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            expr::Expr::Call(callee, _, args) => {
+                match callee.as_ref() {
+                    expr::Expr::Variable(sym) => {
+                        if let Some(decl) = self.reference(sym) {
+                            self.calls.push(Call {
+                                decl,
+                                args: args.len(),
+                                callee: sym.clone(),
+                            });
+                        }
+                    }
+                    callee => self.expr(callee),
+                }
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            expr::Expr::Variable(sym) => {
+                self.reference(sym);
+            }
+            expr::Expr::Assign(sym, val) => {
+                self.expr(val);
+                let numeric = self.is_number(val);
+                if let Some(decl) = self.lookup(sym) {
+                    let decl = &mut self.decls[decl];
+                    decl.reassigned = true;
+                    decl.numeric &= numeric;
+                }
+            }
+            expr::Expr::Set(object, _, val) => {
+                self.expr(object);
+                self.expr(val);
+            }
+            expr::Expr::List(_, elements) => {
+                for element in elements {
+                    self.expr(element);
+                }
+            }
+            expr::Expr::Map(_, entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            expr::Expr::Subscript { value, slice, .. } => {
+                self.expr(value);
+                self.expr(slice);
+            }
+            expr::Expr::SetItem {
+                lhs, slice, rhs, ..
+            } => {
+                self.expr(lhs);
+                self.expr(slice);
+                self.expr(rhs);
+            }
+            expr::Expr::Lambda(lambda) => self.function(&lambda.params, &lambda.body),
+            expr::Expr::CompoundAssign(target, op, val) => {
+                self.expr(val);
+                // only += can make a number variable something else
+                let numeric = !matches!(op.ty, expr::BinaryOpTy::Plus) || self.is_number(val);
+                self.update(target, numeric);
+            }
+            expr::Expr::Increment { target, .. } => self.update(target, true),
+            expr::Expr::Ternary(cond, then_branch, else_branch) => {
+                self.expr(cond);
+                self.expr(then_branch);
+                self.expr(else_branch);
+            }
+            expr::Expr::Interpolation(_, parts) => {
+                for part in parts {
+                    self.expr(part);
+                }
+            }
+            expr::Expr::Yield(_, val) => {
+                if let Some(val) = val {
+                    self.expr(val);
+                }
+            }
+        }
+    }
+
+    /// A target that is read and then assigned, like `x` in `x += 1`.
+    fn update(&mut self, target: &expr::Expr, numeric: bool) {
+        match target {
+            expr::Expr::Variable(sym) => {
+                if let Some(decl) = self.reference(sym) {
+                    let decl = &mut self.decls[decl];
+                    decl.reassigned = true;
+                    decl.numeric &= numeric;
+                }
+            }
+            target => self.expr(target),
+        }
+    }
+}
+
+/// How many arguments constructing the class takes, if an inherited or mixed-in `init` can't
+/// change it.
+fn class_arity(classdecl: &expr::ClassDecl) -> Option<usize> {
+    match classdecl
+        .methods
+        .iter()
+        .find(|method| method.kind == expr::MethodKind::Method && method.decl.name.name == "init")
+    {
+        Some(init) => Some(init.decl.params.len()),
+        None if classdecl.superclass.is_none() && classdecl.mixins.is_empty() => Some(0),
+        None => None,
+    }
+}
+
+fn symbol_location(sym: &expr::Symbol) -> expr::SourceLocation {
+    expr::SourceLocation {
+        line: sym.line,
+        col: sym.col,
+    }
+}
+
+fn plural(n: usize, what: &str) -> String {
+    match n {
+        1 => format!("1 {}", what),
+        n => format!("{} {}s", n, what),
+    }
+}
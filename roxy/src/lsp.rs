@@ -0,0 +1,908 @@
+//! `roxy lsp`: a Language Server Protocol server speaking JSON-RPC over stdio. Clients send
+//! each change as an edit, which is applied to the document's lossless syntax tree; the
+//! analysis is then redone in full on the new text. Columns are counted in characters inside
+//! the server and in UTF-16 code units, as the protocol has it, outside.
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::expr;
+use crate::extensions::Extensions;
+use crate::parser;
+use crate::resolver;
+use crate::scanner;
+use crate::stdlib;
+use crate::syntax;
+use crate::typecheck;
+
+use serde_json::{json, Value as Json};
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const SERVER_NOT_INITIALIZED: i64 = -32002;
+
+// LSP enumerations
+const SYMBOL_CLASS: u8 = 5;
+const SYMBOL_METHOD: u8 = 6;
+const SYMBOL_PROPERTY: u8 = 7;
+const SYMBOL_FUNCTION: u8 = 12;
+const COMPLETION_FUNCTION: u8 = 3;
+const COMPLETION_VARIABLE: u8 = 6;
+const COMPLETION_CLASS: u8 = 7;
+const COMPLETION_KEYWORD: u8 = 14;
+
+/// Serves requests read from `input` until the client sends `exit`, and returns the exit code
+/// the protocol asks for: 0 if the client shut the server down first, 1 otherwise.
+pub fn serve(
+    extensions: Extensions,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<i32> {
+    let mut server = Server {
+        extensions,
+        documents: HashMap::new(),
+        initialized: false,
+        shutdown: false,
+    };
+
+    while let Some(body) = read_message(&mut input)? {
+        let message: Json = match serde_json::from_slice(&body) {
+            Ok(message) => message,
+            Err(err) => {
+                write_message(
+                    &mut output,
+                    &error_response(Json::Null, PARSE_ERROR, err.to_string()),
+                )?;
+                continue;
+            }
+        };
+        if let Some(code) = server.handle(message, &mut output)? {
+            return Ok(code);
+        }
+    }
+    // the client went away without saying goodbye
+    Ok(1)
+}
+
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let len = content_length.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message without a Content-Length header",
+        )
+    })?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn error_response(id: Json, code: i64, message: String) -> Json {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+struct Server {
+    extensions: Extensions,
+    documents: HashMap<String, Document>,
+    initialized: bool,
+    shutdown: bool,
+}
+
+struct Document {
+    syntax: syntax::Tree,
+    // None while the text doesn't parse
+    index: Option<Index>,
+}
+
+impl Server {
+    /// Returns the exit code once the client has asked the server to exit.
+    fn handle(&mut self, message: Json, output: &mut impl Write) -> io::Result<Option<i32>> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => {
+                if method == "exit" {
+                    return Ok(Some(if self.shutdown { 0 } else { 1 }));
+                }
+                return self.notification(method, params, output).map(|_| None);
+            }
+        };
+        if message.get("method").is_none() {
+            // a response to a request we never make
+            return Ok(None);
+        }
+
+        let response = match self.request(method, params) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => error_response(id, code, message),
+        };
+        write_message(output, &response)?;
+        Ok(None)
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        if method == "initialize" {
+            self.initialized = true;
+            return Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 2,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": { "name": "roxy", "version": env!("CARGO_PKG_VERSION") },
+            }));
+        }
+        if !self.initialized {
+            return Err((SERVER_NOT_INITIALIZED, "server not initialized".to_string()));
+        }
+        if self.shutdown {
+            return Err((INVALID_REQUEST, "server is shutting down".to_string()));
+        }
+
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let index = self.documents.get(uri).and_then(|doc| doc.index.as_ref());
+        let position = index.map_or((1, 0), |index| index.lines.locate(&params["position"]));
+
+        match method {
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => Ok(index
+                .and_then(|index| {
+                    let decl = index.decl_at(position)?;
+                    Some(location(uri, &index.lines, &decl.symbol))
+                })
+                .unwrap_or(Json::Null)),
+            "textDocument/references" => {
+                let include_declaration = params["context"]["includeDeclaration"]
+                    .as_bool()
+                    .unwrap_or(true);
+                let references: Vec<Json> = index
+                    .map(|index| {
+                        index
+                            .references_at(position, include_declaration)
+                            .into_iter()
+                            .map(|symbol| location(uri, &index.lines, symbol))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                Ok(references.into())
+            }
+            "textDocument/hover" => Ok(index
+                .and_then(|index| self.hover(index, position))
+                .unwrap_or(Json::Null)),
+            "textDocument/documentSymbol" => Ok(index
+                .map(|index| index.symbols.clone())
+                .unwrap_or_default()
+                .into()),
+            "textDocument/completion" => Ok(self.completions(index).into()),
+            _ => Err((METHOD_NOT_FOUND, format!("method not found: {}", method))),
+        }
+    }
+
+    fn notification(
+        &mut self,
+        method: &str,
+        params: &Json,
+        output: &mut impl Write,
+    ) -> io::Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let syntax = match method {
+            "textDocument/didOpen" => match params["textDocument"]["text"].as_str() {
+                Some(text) => syntax::Tree::parse(text),
+                None => return Ok(()),
+            },
+            "textDocument/didChange" => {
+                let mut syntax = match self.documents.remove(uri) {
+                    Some(document) => document.syntax,
+                    None => syntax::Tree::parse(""),
+                };
+                for change in params["contentChanges"].as_array().into_iter().flatten() {
+                    apply_change(&mut syntax, change);
+                }
+                syntax
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return publish_diagnostics(output, uri, &Lines::new(""), &[]);
+            }
+            _ => return Ok(()),
+        };
+
+        let text = syntax.text();
+        let (diagnostics, index) = analyse(self.extensions, &text);
+        publish_diagnostics(output, uri, &Lines::new(&text), &diagnostics)?;
+        self.documents
+            .insert(uri.to_string(), Document { syntax, index });
+        Ok(())
+    }
+
+    fn hover(&self, index: &Index, position: (usize, i64)) -> Option<Json> {
+        let occurrence = index.occurrence_at(position)?;
+        let detail = match occurrence.decl {
+            Some(decl) => index.decls[decl].detail.clone(),
+            None => {
+                let native = stdlib::natives(self.extensions)
+                    .into_iter()
+                    .find(|native| native.name == occurrence.symbol.name)?;
+                format!("native fun {}, arity {}", native.name, native.arity)
+            }
+        };
+        Some(json!({
+            "contents": { "kind": "markdown", "value": format!("```roxy\n{}\n```", detail) },
+            "range": range(&index.lines, &occurrence.symbol),
+        }))
+    }
+
+    fn completions(&self, index: Option<&Index>) -> Vec<Json> {
+        let keywords = scanner::KEYWORDS
+            .iter()
+            .map(|(keyword, _)| json!({ "label": keyword, "kind": COMPLETION_KEYWORD }));
+        let natives = stdlib::natives(self.extensions).into_iter().map(|native| {
+            json!({ "label": native.name, "kind": COMPLETION_FUNCTION, "detail": "native" })
+        });
+        let globals = index.into_iter().flat_map(|index| {
+            index.globals().map(|decl| {
+                let kind = match decl.kind {
+                    DeclKind::Function => COMPLETION_FUNCTION,
+                    DeclKind::Class => COMPLETION_CLASS,
+                    _ => COMPLETION_VARIABLE,
+                };
+                json!({ "label": decl.symbol.name, "kind": kind, "detail": decl.detail })
+            })
+        });
+        keywords.chain(natives).chain(globals).collect()
+    }
+}
+
+fn publish_diagnostics(
+    output: &mut impl Write,
+    uri: &str,
+    lines: &Lines,
+    diagnostics: &[Diagnostic],
+) -> io::Result<()> {
+    let diagnostics: Vec<Json> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let (line, col) = (diagnostic.line, diagnostic.col.max(0));
+            let message = std::iter::once(diagnostic.message.clone())
+                .chain(diagnostic.notes.iter().cloned())
+                .collect::<Vec<_>>()
+                .join("\n");
+            json!({
+                "range": {
+                    "start": lines.position(line, col),
+                    "end": lines.position(line, col + diagnostic.len as i64),
+                },
+                "severity": match diagnostic.severity {
+                    Severity::Error => 1,
+                    Severity::Warning => 2,
+                },
+                "code": diagnostic.code,
+                "source": "roxy",
+                "message": message,
+            })
+        })
+        .collect();
+    write_message(
+        output,
+        &json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }),
+    )
+}
+
+/// A document's lines, for converting between the columns the server counts and the
+/// protocol's.
+struct Lines(Vec<String>);
+
+impl Lines {
+    fn new(text: &str) -> Lines {
+        Lines(text.split('\n').map(str::to_string).collect())
+    }
+
+    fn line(&self, line: usize) -> &str {
+        self.0
+            .get(line.saturating_sub(1))
+            .map_or("", String::as_str)
+    }
+
+    /// The protocol's position of a 1-based line and character column.
+    fn position(&self, line: usize, col: i64) -> Json {
+        json!({
+            "line": line.saturating_sub(1),
+            "character": scanner::utf16_col(self.line(line), col),
+        })
+    }
+
+    /// The 1-based line and character column of one of the protocol's positions.
+    fn locate(&self, position: &Json) -> (usize, i64) {
+        let line = position["line"].as_u64().unwrap_or_default() as usize + 1;
+        let character = position["character"].as_i64().unwrap_or_default();
+        (line, scanner::col_from_utf16(self.line(line), character))
+    }
+}
+
+fn range(lines: &Lines, symbol: &expr::Symbol) -> Json {
+    let len = symbol.name.chars().count() as i64;
+    json!({
+        "start": lines.position(symbol.line, symbol.col),
+        "end": lines.position(symbol.line, symbol.col + len),
+    })
+}
+
+fn location(uri: &str, lines: &Lines, symbol: &expr::Symbol) -> Json {
+    json!({ "uri": uri, "range": range(lines, symbol) })
+}
+
+/// Applies one of a `didChange`'s changes: an edit of a range, or without one, the whole new
+/// text.
+fn apply_change(syntax: &mut syntax::Tree, change: &Json) {
+    let text = change["text"].as_str().unwrap_or_default();
+    if change.get("range").is_none() {
+        *syntax = syntax::Tree::parse(text);
+        return;
+    }
+    let lines = Lines::new(&syntax.text());
+    let offset = |position: &Json| {
+        let (line, col) = lines.locate(position);
+        syntax.offset(line, col)
+    };
+    let start = offset(&change["range"]["start"]);
+    let end = offset(&change["range"]["end"]).max(start);
+    syntax.edit(start..end, text);
+}
+
+/// The diagnostics for `text`, and the index of it if it parses.
+fn analyse(extensions: Extensions, text: &str) -> (Vec<Diagnostic>, Option<Index>) {
+    let failed = |diagnostics| (diagnostics, None);
+    let tokens = match scanner::scan_tokens(text.to_string()) {
+        Ok(tokens) => tokens,
+        Err(err) => return failed(vec![Diagnostic::from(&err)]),
+    };
+    let stmts = match parser::parse(extensions, tokens) {
+        Ok(stmts) => stmts,
+        Err(errs) => return failed(errs.iter().map(Diagnostic::from).collect()),
+    };
+
+    let mut diagnostics: Vec<Diagnostic> = resolver::resolve(&stmts)
+        .err()
+        .iter()
+        .map(Diagnostic::from)
+        .collect();
+    if let Err(errs) = typecheck::check(&stmts) {
+        diagnostics.extend(errs.iter().map(Diagnostic::from));
+    }
+    (diagnostics, Some(Index::new(&stmts, Lines::new(text))))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DeclKind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+    Import,
+}
+
+struct Decl {
+    symbol: expr::Symbol,
+    kind: DeclKind,
+    global: bool,
+    // what hovering over it shows
+    detail: String,
+}
+
+/// An identifier in the source, and the declaration it names if there is one in the file.
+struct Occurrence {
+    symbol: expr::Symbol,
+    decl: Option<usize>,
+}
+
+/// What the server knows about one document that parsed.
+struct Index {
+    lines: Lines,
+    decls: Vec<Decl>,
+    occurrences: Vec<Occurrence>,
+    // the document's functions and classes as LSP DocumentSymbols
+    symbols: Vec<Json>,
+}
+
+impl Index {
+    fn new(stmts: &[expr::Stmt], lines: Lines) -> Index {
+        let mut indexer = Indexer {
+            decls: Vec::new(),
+            occurrences: Vec::new(),
+            globals: HashMap::new(),
+            scopes: Vec::new(),
+        };
+        // globals may be used above their declaration, from inside a function
+        for stmt in stmts {
+            if let Some((symbol, kind, detail)) = declaration(stmt) {
+                indexer.global(symbol, kind, detail);
+            }
+        }
+        for stmt in stmts {
+            indexer.stmt(stmt);
+        }
+
+        let mut symbols = Vec::new();
+        for stmt in stmts {
+            document_symbols(&lines, stmt, &mut symbols);
+        }
+
+        Index {
+            lines,
+            decls: indexer.decls,
+            occurrences: indexer.occurrences,
+            symbols,
+        }
+    }
+
+    /// The identifier under the cursor; the column just past its end still counts.
+    fn occurrence_at(&self, (line, col): (usize, i64)) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|occurrence| {
+            let symbol = &occurrence.symbol;
+            symbol.line == line
+                && symbol.col <= col
+                && col <= symbol.col + symbol.name.chars().count() as i64
+        })
+    }
+
+    fn decl_at(&self, position: (usize, i64)) -> Option<&Decl> {
+        let decl = self.occurrence_at(position)?.decl?;
+        Some(&self.decls[decl])
+    }
+
+    fn references_at(
+        &self,
+        position: (usize, i64),
+        include_declaration: bool,
+    ) -> Vec<&expr::Symbol> {
+        let decl = match self
+            .occurrence_at(position)
+            .and_then(|occurrence| occurrence.decl)
+        {
+            Some(decl) => decl,
+            None => return Vec::new(),
+        };
+        self.occurrences
+            .iter()
+            .filter(|occurrence| occurrence.decl == Some(decl))
+            .map(|occurrence| &occurrence.symbol)
+            .filter(|symbol| include_declaration || **symbol != self.decls[decl].symbol)
+            .collect()
+    }
+
+    fn globals(&self) -> impl Iterator<Item = &Decl> {
+        self.decls.iter().filter(|decl| decl.global)
+    }
+}
+
+/// The name a top-level statement declares.
+fn declaration(stmt: &expr::Stmt) -> Option<(&expr::Symbol, DeclKind, String)> {
+    match stmt {
+        expr::Stmt::VarDecl(sym, ty, _) => Some((
+            sym,
+            DeclKind::Variable,
+            format!("var {}", annotated(sym, ty)),
+        )),
+        expr::Stmt::FunDecl(fundecl) => {
+            Some((&fundecl.name, DeclKind::Function, fun_detail(fundecl)))
+        }
+        expr::Stmt::ClassDecl(classdecl) => {
+            let mut detail = match &classdecl.superclass {
+                Some(superclass) => format!("class {} < {}", classdecl.name.name, superclass.name),
+                None => format!("class {}", classdecl.name.name),
+            };
+            if !classdecl.mixins.is_empty() {
+                let mixins: Vec<&str> = classdecl
+                    .mixins
+                    .iter()
+                    .map(|mixin| mixin.name.as_str())
+                    .collect();
+                detail = format!("{} with {}", detail, mixins.join(", "));
+            }
+            Some((&classdecl.name, DeclKind::Class, detail))
+        }
+        expr::Stmt::Import(import) => import.name.as_ref().map(|name| {
+            let detail = format!("import {} from \"{}\"", name.name, import.path);
+            (name, DeclKind::Import, detail)
+        }),
+        _ => None,
+    }
+}
+
+fn fun_detail(fundecl: &expr::FunDecl) -> String {
+    let params: Vec<String> = fundecl
+        .params
+        .iter()
+        .map(|param| annotated(&param.name, &param.ty))
+        .collect();
+    let signature = format!("{}({})", fundecl.name.name, params.join(", "));
+    let keyword = if fundecl.generator { "fun*" } else { "fun" };
+    match &fundecl.return_type {
+        Some(ty) => format!("{} {}: {}", keyword, signature, ty.name),
+        None => format!("{} {}", keyword, signature),
+    }
+}
+
+/// A name with its type annotation, as it was declared.
+fn annotated(sym: &expr::Symbol, ty: &Option<expr::Symbol>) -> String {
+    match ty {
+        Some(ty) => format!("{}: {}", sym.name, ty.name),
+        None => sym.name.clone(),
+    }
+}
+
+/// Links every identifier to its declaration, with the same scoping rules as the resolver.
+struct Indexer {
+    decls: Vec<Decl>,
+    occurrences: Vec<Occurrence>,
+    globals: HashMap<String, usize>,
+    scopes: Vec<HashMap<String, usize>>,
+}
+
+impl Indexer {
+    /// Redeclaring a global names the same variable, so only the first declaration counts.
+    fn global(&mut self, symbol: &expr::Symbol, kind: DeclKind, detail: String) -> usize {
+        if let Some(&decl) = self.globals.get(&symbol.name) {
+            return decl;
+        }
+        self.decls.push(Decl {
+            symbol: symbol.clone(),
+            kind,
+            global: true,
+            detail,
+        });
+        self.globals
+            .insert(symbol.name.clone(), self.decls.len() - 1);
+        self.decls.len() - 1
+    }
+
+    fn declare(&mut self, symbol: &expr::Symbol, kind: DeclKind, detail: String) {
+        let decl = match self.scopes.last_mut() {
+            None => self.global(symbol, kind, detail),
+            Some(scope) => {
+                self.decls.push(Decl {
+                    symbol: symbol.clone(),
+                    kind,
+                    global: false,
+                    detail,
+                });
+                scope.insert(symbol.name.clone(), self.decls.len() - 1);
+                self.decls.len() - 1
+            }
+        };
+        self.occurrences.push(Occurrence {
+            symbol: symbol.clone(),
+            decl: Some(decl),
+        });
+    }
+
+    fn reference(&mut self, symbol: &expr::Symbol) {
+        let decl = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(&symbol.name))
+            .or_else(|| self.globals.get(&symbol.name))
+            .copied();
+        self.occurrences.push(Occurrence {
+            symbol: symbol.clone(),
+            decl,
+        });
+    }
+
+    fn stmt(&mut self, stmt: &expr::Stmt) {
+        match stmt {
+            expr::Stmt::Expr(e) | expr::Stmt::Print(_, e) => self.expr(e),
+            expr::Stmt::FunDecl(fundecl) => {
+                self.declare(&fundecl.name, DeclKind::Function, fun_detail(fundecl));
+                self.function(&fundecl.params, &fundecl.body);
+            }
+            expr::Stmt::ClassDecl(classdecl) => {
+                if let Some(superclass) = &classdecl.superclass {
+                    self.reference(superclass);
+                }
+                for mixin in &classdecl.mixins {
+                    self.reference(mixin);
+                }
+                let (_, kind, detail) = declaration(stmt).unwrap();
+                self.declare(&classdecl.name, kind, detail);
+                for method in &classdecl.methods {
+                    self.function(&method.decl.params, &method.decl.body);
+                }
+            }
+            expr::Stmt::If(cond, then_branch, else_branch) => {
+                self.expr(cond);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            expr::Stmt::VarDecl(sym, ty, initializer) => {
+                if let Some(initializer) = initializer {
+                    self.expr(initializer);
+                }
+                let detail = format!("var {}", annotated(sym, ty));
+                self.declare(sym, DeclKind::Variable, detail);
+            }
+            expr::Stmt::Block(_, stmts) => self.block(stmts),
+            expr::Stmt::Return(_, val) => {
+                if let Some(val) = val {
+                    self.expr(val);
+                }
+            }
+            expr::Stmt::While(cond, body) => {
+                self.expr(cond);
+                self.stmt(body);
+            }
+            expr::Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.scopes.push(HashMap::new());
+                if let Some(initializer) = initializer {
+                    self.stmt(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.expr(condition);
+                }
+                if let Some(increment) = increment {
+                    self.expr(increment);
+                }
+                self.stmt(body);
+                self.scopes.pop();
+            }
+            expr::Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                self.expr(iterable);
+                self.scopes.push(HashMap::new());
+                self.declare(
+                    variable,
+                    DeclKind::Variable,
+                    format!("var {}", variable.name),
+                );
+                self.stmt(body);
+                self.scopes.pop();
+            }
+            expr::Stmt::Labeled(_, body) => self.stmt(body),
+            expr::Stmt::Break(..) | expr::Stmt::Continue(..) => {}
+            expr::Stmt::Import(_) => {
+                if let Some((name, kind, detail)) = declaration(stmt) {
+                    self.declare(name, kind, detail);
+                }
+            }
+            expr::Stmt::Throw(_, e) => self.expr(e),
+            expr::Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.block(body);
+                if let Some((name, handler)) = catch {
+                    self.scopes.push(HashMap::new());
+                    self.declare(name, DeclKind::Variable, format!("caught {}", name.name));
+                    for stmt in handler {
+                        self.stmt(stmt);
+                    }
+                    self.scopes.pop();
+                }
+                if let Some(finally) = finally {
+                    self.block(finally);
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, stmts: &[expr::Stmt]) {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn function(&mut self, params: &[expr::Param], body: &[expr::Stmt]) {
+        self.scopes.push(HashMap::new());
+        for param in params {
+            self.declare(
+                &param.name,
+                DeclKind::Parameter,
+                format!("parameter {}", annotated(&param.name, &param.ty)),
+            );
+        }
+        for stmt in body {
+            self.stmt(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    fn expr(&mut self, expr: &expr::Expr) {
+        match expr {
+            expr::Expr::Literal(..) | expr::Expr::This(_) | expr::Expr::Super(..) => {}
+            expr::Expr::Unary(_, e) | expr::Expr::Grouping(e) | expr::Expr::Get(e, _) => {
+                self.expr(e)
+            }
+            expr::Expr::Binary(lhs, _, rhs)
+            | expr::Expr::Logical(lhs, _, rhs)
+            | expr::Expr::CompoundAssign(lhs, _, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            expr::Expr::Increment { target, .. } => self.expr(target),
+            expr::Expr::Ternary(cond, then_branch, else_branch) => {
+                self.expr(cond);
+                self.expr(then_branch);
+                self.expr(else_branch);
+            }
+            expr::Expr::Interpolation(_, parts) => {
+                for part in parts {
+                    self.expr(part);
+                }
+            }
+            expr::Expr::Yield(_, val) => {
+                if let Some(val) = val {
+                    self.expr(val);
+                }
+            }
+            expr::Expr::Call(callee, _, args) => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            expr::Expr::Variable(sym) => self.reference(sym),
+            expr::Expr::Assign(sym, val) => {
+                self.expr(val);
+                self.reference(sym);
+            }
+            expr::Expr::Set(object, _, val) => {
+                self.expr(object);
+                self.expr(val);
+            }
+            expr::Expr::List(_, elements) => {
+                for element in elements {
+                    self.expr(element);
+                }
+            }
+            expr::Expr::Map(_, entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            expr::Expr::Subscript { value, slice, .. } => {
+                self.expr(value);
+                self.expr(slice);
+            }
+            expr::Expr::SetItem {
+                lhs, slice, rhs, ..
+            } => {
+                self.expr(lhs);
+                self.expr(slice);
+                self.expr(rhs);
+            }
+            expr::Expr::Lambda(lambda) => self.function(&lambda.params, &lambda.body),
+        }
+    }
+}
+
+/// Collects the functions and classes `stmt` declares, each with the ones nested inside it.
+fn document_symbols(lines: &Lines, stmt: &expr::Stmt, out: &mut Vec<Json>) {
+    match stmt {
+        expr::Stmt::FunDecl(fundecl) => out.push(fun_symbol(lines, fundecl, SYMBOL_FUNCTION)),
+        expr::Stmt::ClassDecl(classdecl) => {
+            let methods: Vec<Json> = classdecl
+                .methods
+                .iter()
+                .map(|method| match method.kind {
+                    expr::MethodKind::Method | expr::MethodKind::Static => {
+                        fun_symbol(lines, &method.decl, SYMBOL_METHOD)
+                    }
+                    expr::MethodKind::Getter | expr::MethodKind::Setter => {
+                        fun_symbol(lines, &method.decl, SYMBOL_PROPERTY)
+                    }
+                })
+                .collect();
+            out.push(document_symbol(
+                lines,
+                &classdecl.name,
+                SYMBOL_CLASS,
+                methods,
+            ));
+        }
+        expr::Stmt::Block(_, stmts) => {
+            for stmt in stmts {
+                document_symbols(lines, stmt, out);
+            }
+        }
+        expr::Stmt::Try {
+            body,
+            catch,
+            finally,
+            ..
+        } => {
+            let handler = catch.iter().flat_map(|(_, handler)| handler);
+            for stmt in body.iter().chain(handler).chain(finally.iter().flatten()) {
+                document_symbols(lines, stmt, out);
+            }
+        }
+        expr::Stmt::If(_, then_branch, else_branch) => {
+            document_symbols(lines, then_branch, out);
+            if let Some(else_branch) = else_branch {
+                document_symbols(lines, else_branch, out);
+            }
+        }
+        expr::Stmt::While(_, body)
+        | expr::Stmt::For { body, .. }
+        | expr::Stmt::ForIn { body, .. }
+        | expr::Stmt::Labeled(_, body) => document_symbols(lines, body, out),
+        _ => {}
+    }
+}
+
+fn fun_symbol(lines: &Lines, fundecl: &expr::FunDecl, kind: u8) -> Json {
+    let mut children = Vec::new();
+    for stmt in &fundecl.body {
+        document_symbols(lines, stmt, &mut children);
+    }
+    document_symbol(lines, &fundecl.name, kind, children)
+}
+
+// the AST doesn't record where declarations end, so the whole range is just the name
+fn document_symbol(lines: &Lines, name: &expr::Symbol, kind: u8, children: Vec<Json>) -> Json {
+    json!({
+        "name": name.name,
+        "kind": kind,
+        "range": range(lines, name),
+        "selectionRange": range(lines, name),
+        "children": children,
+    })
+}
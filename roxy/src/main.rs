@@ -1,28 +1,192 @@
-mod err;
-mod roxy;
-mod scanner;
-
 use clap::Parser;
+use roxy::dump::{Dump, DumpFormat};
+use roxy::extensions::Extensions;
+use roxy::heap::GcConfig;
+use roxy::limits::Limits;
+use roxy::roxy::Backend;
 use std::path::PathBuf;
+use std::process::exit;
+use std::thread;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct RoxyArgs {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     #[clap(short, long, value_parser)]
     file: Option<PathBuf>,
+
+    /// enable list and map literals and subscripts
+    #[clap(long)]
+    lists: bool,
+
+    /// enable lambda expressions
+    #[clap(long)]
+    lambdas: bool,
+
+    /// step through the program in the debugger (treewalk backend only)
+    #[clap(long, requires = "file")]
+    debug: bool,
+
+    /// print the tokens the scanner produces for the file (or stdin) instead of running it
+    #[clap(long, conflicts_with_all = &["dump-ast", "dump-syntax", "dump-bytecode"])]
+    dump_tokens: bool,
+
+    /// print the syntax tree the parser produces for the file (or stdin) instead of running it
+    #[clap(long, conflicts_with_all = &["dump-syntax", "dump-bytecode"])]
+    dump_ast: bool,
+
+    /// print the lossless syntax tree editors use, comments and mistakes included, for the
+    /// file (or stdin) instead of running it
+    #[clap(long, conflicts_with = "dump-bytecode")]
+    dump_syntax: bool,
+
+    /// print the disassembled bytecode the vm backend would run for the file (or stdin)
+    /// instead of running it
+    #[clap(long)]
+    dump_bytecode: bool,
+
+    /// how --dump-tokens, --dump-ast and --dump-syntax print
+    #[clap(long, value_enum, default_value_t = DumpFormat::Json)]
+    dump_format: DumpFormat,
+
+    /// simplify the program before running it: fold constants, drop dead code and inline
+    /// trivial lambdas; with --dump-ast or --dump-bytecode, print the simplified program
+    #[clap(short = 'O', long)]
+    optimize: bool,
+
+    /// which execution engine runs the program
+    #[clap(long, value_enum, default_value_t = Backend::Treewalk)]
+    backend: Backend,
+
+    /// vm backend: collect garbage after every allocation
+    #[clap(long)]
+    gc_stress: bool,
+
+    /// vm backend: how much the heap may grow relative to the live set before collecting again
+    #[clap(long, value_parser, default_value_t = 2.0)]
+    gc_growth_factor: f64,
+
+    /// vm backend: report every garbage collection on stderr
+    #[clap(long)]
+    gc_log: bool,
+
+    /// stop the program after this many statements (treewalk) or instructions (vm)
+    #[clap(long, value_parser)]
+    max_steps: Option<u64>,
+
+    /// stop the program after this many milliseconds
+    #[clap(long, value_parser)]
+    max_time_ms: Option<u64>,
+
+    /// stop the program when it makes more than this many nested calls
+    #[clap(long, value_parser)]
+    max_call_depth: Option<usize>,
+
+    /// stop the program when its heap grows past this many bytes (vm), or once it has
+    /// allocated this many in all (treewalk)
+    #[clap(long, value_parser)]
+    max_heap_bytes: Option<usize>,
+
+    /// stop the program when it prints more than this many bytes
+    #[clap(long, value_parser)]
+    max_output_bytes: Option<usize>,
+
+    /// take away imports and the natives that read input
+    #[clap(long)]
+    no_io: bool,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// rewrite roxy files in the canonical layout
+    Fmt {
+        #[clap(value_parser, required = true)]
+        files: Vec<PathBuf>,
+
+        /// only report files that would change, exiting with 1 if there are any
+        #[clap(long)]
+        check: bool,
+    },
+    /// warn about likely mistakes in roxy files
+    Lint {
+        #[clap(value_parser, required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// serve the Language Server Protocol over stdin and stdout
+    Lsp,
 }
 
 fn main() {
     let args = RoxyArgs::parse();
-    let mut roxy = roxy::Roxy::new();
+    let extensions = Extensions {
+        lists: args.lists,
+        lambdas: args.lambdas,
+    };
+    match &args.command {
+        Some(Command::Fmt { files, check }) => exit(roxy::roxy::format_files(files, *check)),
+        Some(Command::Lint { files }) => exit(roxy::roxy::lint_files(extensions, files)),
+        Some(Command::Lsp) => exit(roxy::roxy::serve_lsp(extensions)),
+        None => {}
+    }
+    let dump = match (
+        args.dump_tokens,
+        args.dump_ast,
+        args.dump_syntax,
+        args.dump_bytecode,
+    ) {
+        (true, _, _, _) => Some(Dump::Tokens),
+        (_, true, _, _) => Some(Dump::Ast),
+        (_, _, true, _) => Some(Dump::Syntax),
+        (_, _, _, true) => Some(Dump::Bytecode),
+        _ => None,
+    };
+    if let Some(dump) = dump {
+        exit(roxy::roxy::dump_file(
+            extensions,
+            args.file.as_deref(),
+            dump,
+            args.dump_format,
+            args.optimize,
+        ));
+    }
+
+    // the tree-walker recurses on the Rust stack, so programs run on a thread with room for
+    // as many calls as the VM allows
+    let runner = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(args, extensions))
+        .expect("failed to start the interpreter thread");
+    if runner.join().is_err() {
+        exit(101);
+    }
+}
+
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+fn run(args: RoxyArgs, extensions: Extensions) {
+    let gc_config = GcConfig {
+        growth_factor: args.gc_growth_factor,
+        stress: args.gc_stress,
+        log: args.gc_log,
+        ..Default::default()
+    };
+    let mut roxy = roxy::roxy::Roxy::new(extensions, args.backend, gc_config);
+    roxy.set_optimize(args.optimize);
+    roxy.set_stack_size(STACK_SIZE);
+    roxy.set_limits(Limits {
+        steps: args.max_steps,
+        time: args.max_time_ms.map(Duration::from_millis),
+        call_depth: args.max_call_depth,
+        heap_bytes: args.max_heap_bytes,
+        output_bytes: args.max_output_bytes,
+        io: !args.no_io,
+    });
     match args.file {
-        None => {
-            println!("run prompt!");
-            roxy.run_prompt();
-        }
-        Some(path) => {
-            println!("run file: {}", path.display());
-            roxy.run_file(&path)
-        }
+        None => roxy.run_prompt(),
+        Some(path) if args.debug => roxy.debug_file(&path),
+        Some(path) => roxy.run_file(&path),
     }
 }
@@ -0,0 +1,85 @@
+//! The map behind `{key: value}` literals, shared by both backends. Keys are nil, booleans,
+//! numbers and strings, compared by value; entries keep the order they were first inserted in.
+
+use std::collections::HashMap;
+
+/// What a key is compared by. Each backend turns its own values into one, and keeps the
+/// original value next to the entry for iteration and printing.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Nil,
+    Bool(bool),
+    // the bits of the number, with -0 folded into 0 so that they compare equal
+    Number(u64),
+    String(String),
+}
+
+impl Key {
+    pub fn number(n: f64) -> Key {
+        Key::Number(if n == 0.0 {
+            0.0f64.to_bits()
+        } else {
+            n.to_bits()
+        })
+    }
+}
+
+/// Entries are `(key, value)` pairs of the backend's values, in insertion order.
+#[derive(Debug, Clone)]
+pub struct Map<V> {
+    index: HashMap<Key, usize>,
+    entries: Vec<(V, V)>,
+}
+
+impl<V> Default for Map<V> {
+    fn default() -> Map<V> {
+        Map {
+            index: HashMap::new(),
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<V> Map<V> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&V> {
+        self.index.get(key).map(|idx| &self.entries[*idx].1)
+    }
+
+    pub fn contains(&self, key: &Key) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Replaces the value of an existing entry in place, or appends a new one.
+    pub fn insert(&mut self, key: Key, key_value: V, value: V) {
+        match self.index.get(&key) {
+            Some(idx) => self.entries[*idx].1 = value,
+            None => {
+                self.index.insert(key, self.entries.len());
+                self.entries.push((key_value, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<V> {
+        let idx = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(idx);
+        for later in self.index.values_mut() {
+            if *later > idx {
+                *later -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn entries(&self) -> &[(V, V)] {
+        &self.entries
+    }
+}
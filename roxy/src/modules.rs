@@ -0,0 +1,243 @@
+//! Finding and loading the files a program imports. All modules share the one global
+//! namespace; each runs once, before the first program that imports it.
+//!
+//! `import name from "path";` is an assertion, not a selective import: it fails with E0403
+//! unless the module declares `name` at its top level, but every top-level declaration of the
+//! module becomes a global either way, just as with `import "path";`.
+
+use crate::diagnostic::Diagnostic;
+use crate::expr;
+use crate::extensions::Extensions;
+use crate::limits::Limits;
+use crate::parser;
+use crate::scanner;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub struct Module {
+    pub path: PathBuf,
+    pub source: String,
+    pub stmts: Vec<expr::Stmt>,
+}
+
+impl Module {
+    /// How diagnostics refer to this module.
+    pub fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+/// A module that could not be loaded, with the source its diagnostics point into.
+#[derive(Debug)]
+pub struct LoadError {
+    pub source_name: String,
+    pub source: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl LoadError {
+    pub fn render(&self) -> String {
+        self.diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.render(&self.source_name, &self.source))
+            .collect()
+    }
+}
+
+/// Remembers which modules have been loaded so that importing one twice runs it once.
+pub struct Loader {
+    extensions: Extensions,
+    // the top-level names each loaded module declares, by canonical path
+    loaded: HashMap<PathBuf, HashSet<String>>,
+    // the modules whose imports are being loaded right now, outermost first
+    loading: Vec<PathBuf>,
+    // reading module files is I/O, which `Limits` can take away
+    io: bool,
+}
+
+impl Loader {
+    pub fn new(extensions: Extensions) -> Loader {
+        Loader {
+            extensions,
+            loaded: HashMap::new(),
+            loading: Vec::new(),
+            io: true,
+        }
+    }
+
+    /// Refuses every import from now on if `limits` takes away I/O.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.io = limits.io;
+    }
+
+    /// Loads every module `stmts` imports, directly or not, that hasn't been loaded before, in
+    /// the order they have to run. Paths are relative to the directory of `path`, the file
+    /// `stmts` came from, or to the current directory when there is no such file.
+    pub fn load_imports(
+        &mut self,
+        path: Option<&Path>,
+        source_name: &str,
+        source: &str,
+        stmts: &[expr::Stmt],
+    ) -> Result<Vec<Module>, LoadError> {
+        let dir = match path {
+            Some(path) => {
+                if let Ok(canonical) = fs::canonicalize(path) {
+                    self.loading.push(canonical);
+                }
+                path.parent().map(Path::to_path_buf).unwrap_or_default()
+            }
+            None => PathBuf::new(),
+        };
+
+        let mut modules = Vec::new();
+        let res = self.load_into(&mut modules, &dir, source_name, source, stmts);
+        self.loading.clear();
+        res.map(|_| modules)
+    }
+
+    fn load_into(
+        &mut self,
+        modules: &mut Vec<Module>,
+        dir: &Path,
+        source_name: &str,
+        source: &str,
+        stmts: &[expr::Stmt],
+    ) -> Result<(), LoadError> {
+        let error = |diagnostic: Diagnostic| LoadError {
+            source_name: source_name.to_string(),
+            source: source.to_string(),
+            diagnostics: vec![diagnostic],
+        };
+
+        for stmt in stmts {
+            let import = match stmt {
+                expr::Stmt::Import(import) => import,
+                _ => continue,
+            };
+            let target = dir.join(&import.path);
+            // the quotes around the path are part of what gets underlined
+            let path_len = import.path.chars().count() + 2;
+
+            if !self.io {
+                return Err(error(
+                    Diagnostic::error(
+                        "E0404",
+                        format!("cannot import \"{}\": I/O is disabled", import.path),
+                        import.location.line,
+                        import.location.col,
+                    )
+                    .with_len(path_len),
+                ));
+            }
+
+            let canonical = fs::canonicalize(&target).map_err(|err| {
+                error(
+                    Diagnostic::error(
+                        "E0401",
+                        format!("cannot load module \"{}\": {}", import.path, err),
+                        import.location.line,
+                        import.location.col,
+                    )
+                    .with_len(path_len),
+                )
+            })?;
+
+            if self.loading.contains(&canonical) {
+                let chain: Vec<String> = self
+                    .loading
+                    .iter()
+                    .chain(std::iter::once(&canonical))
+                    .map(|path| path.display().to_string())
+                    .collect();
+                return Err(error(
+                    Diagnostic::error(
+                        "E0402",
+                        format!("cyclic import of \"{}\"", import.path),
+                        import.location.line,
+                        import.location.col,
+                    )
+                    .with_len(path_len)
+                    .with_note(format!("import chain: {}", chain.join(" -> "))),
+                ));
+            }
+
+            if !self.loaded.contains_key(&canonical) {
+                let module = self.load_module(modules, &target, &canonical, import, &error)?;
+                let declared = module.stmts.iter().filter_map(declared_name).collect();
+                self.loaded.insert(canonical.clone(), declared);
+                modules.push(module);
+            }
+
+            if let Some(name) = &import.name {
+                if !self.loaded[&canonical].contains(&name.name) {
+                    return Err(error(
+                        Diagnostic::error(
+                            "E0403",
+                            format!(
+                                "module \"{}\" has no top-level declaration '{}'",
+                                import.path, name.name
+                            ),
+                            name.line,
+                            name.col,
+                        )
+                        .with_len(name.name.chars().count()),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and parses one module, after loading everything it imports into `modules`.
+    fn load_module(
+        &mut self,
+        modules: &mut Vec<Module>,
+        target: &Path,
+        canonical: &Path,
+        import: &expr::ImportDecl,
+        error: &dyn Fn(Diagnostic) -> LoadError,
+    ) -> Result<Module, LoadError> {
+        let source = fs::read_to_string(target).map_err(|err| {
+            error(Diagnostic::error(
+                "E0401",
+                format!("cannot load module \"{}\": {}", import.path, err),
+                import.location.line,
+                import.location.col,
+            ))
+        })?;
+        let source_name = target.display().to_string();
+        let module_error = |diagnostics: Vec<Diagnostic>| LoadError {
+            source_name: source_name.clone(),
+            source: source.clone(),
+            diagnostics,
+        };
+
+        let tokens = scanner::scan_tokens(source.clone())
+            .map_err(|err| module_error(vec![Diagnostic::from(&err)]))?;
+        let stmts = parser::parse(self.extensions, tokens)
+            .map_err(|errs| module_error(errs.iter().map(Diagnostic::from).collect()))?;
+
+        self.loading.push(canonical.to_path_buf());
+        let dir = canonical.parent().unwrap_or(Path::new(""));
+        self.load_into(modules, dir, &source_name, &source, &stmts)?;
+        self.loading.pop();
+
+        Ok(Module {
+            path: target.to_path_buf(),
+            source,
+            stmts,
+        })
+    }
+}
+
+fn declared_name(stmt: &expr::Stmt) -> Option<String> {
+    match stmt {
+        expr::Stmt::VarDecl(sym, _, _) => Some(sym.name.clone()),
+        expr::Stmt::FunDecl(fundecl) => Some(fundecl.name.name.clone()),
+        expr::Stmt::ClassDecl(classdecl) => Some(classdecl.name.name.clone()),
+        _ => None,
+    }
+}
@@ -0,0 +1,434 @@
+//! `-O`: rewrites a program into a smaller one that does the same, after the resolver and the
+//! type checker have passed it and before a backend runs it. The rewrites are made together,
+//! in one walk from the leaves up, so that each sees what the others made of the code below:
+//!
+//! - constant folding: unary and binary operators, `and`, `or` and `?:` on literals are
+//!   worked out by the interpreter's rules. One that would be a runtime error is left for the
+//!   runtime to report;
+//! - dead branches: an `if` on a literal condition is replaced by the branch it takes, and a
+//!   `while` whose condition is a literal false value goes away;
+//! - inlining: a lambda called where it is written, whose body only returns an expression of
+//!   its parameters, is replaced by that expression with the arguments in place of the
+//!   parameters, when the arguments are literals;
+//! - unreachable code: statements after a `return`, `throw`, `break` or `continue` in the same
+//!   block are dropped.
+//!
+//! No rewrite adds or removes a scope around code that is kept, so what the resolver worked
+//! out for the original program holds for the optimized one. A folded expression is located
+//! where the one it replaces was.
+
+use crate::expr::{self, BinaryOpTy, Expr, Literal, LogicalOp, SourceLocation, Stmt, UnaryOpTy};
+use crate::value;
+
+pub fn optimize(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    block(stmts)
+}
+
+/// The statements of a block, without those that do nothing or can't be reached.
+fn block(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    let mut out = Vec::new();
+    for stmt in stmts.into_iter().filter_map(statement) {
+        let jumps = matches!(
+            stmt,
+            Stmt::Return(..) | Stmt::Throw(..) | Stmt::Break(..) | Stmt::Continue(..)
+        );
+        out.push(stmt);
+        if jumps {
+            break;
+        }
+    }
+    out
+}
+
+/// None if the statement does nothing at all.
+fn statement(stmt: Stmt) -> Option<Stmt> {
+    Some(match stmt {
+        Stmt::Expr(e) => Stmt::Expr(expression(e)),
+        Stmt::FunDecl(fundecl) => Stmt::FunDecl(function(fundecl)),
+        Stmt::ClassDecl(classdecl) => Stmt::ClassDecl(expr::ClassDecl {
+            methods: classdecl
+                .methods
+                .into_iter()
+                .map(|method| expr::Method {
+                    kind: method.kind,
+                    decl: function(method.decl),
+                })
+                .collect(),
+            ..classdecl
+        }),
+        Stmt::If(cond, then_branch, else_branch) => {
+            let cond = expression(cond);
+            match literal(&cond).map(is_truthy) {
+                Some(true) => return statement(*then_branch),
+                Some(false) => return else_branch.and_then(|stmt| statement(*stmt)),
+                None => Stmt::If(
+                    cond,
+                    Box::new(body(*then_branch)),
+                    else_branch.and_then(|stmt| statement(*stmt)).map(Box::new),
+                ),
+            }
+        }
+        Stmt::Print(loc, e) => Stmt::Print(loc, expression(e)),
+        Stmt::VarDecl(name, ty, initializer) => {
+            Stmt::VarDecl(name, ty, initializer.map(expression))
+        }
+        Stmt::Block(loc, stmts) => Stmt::Block(loc, block(stmts)),
+        Stmt::Return(loc, e) => Stmt::Return(loc, e.map(expression)),
+        Stmt::While(cond, loop_body) => {
+            let cond = expression(cond);
+            if literal(&cond).map(is_truthy) == Some(false) {
+                return None;
+            }
+            Stmt::While(cond, Box::new(body(*loop_body)))
+        }
+        Stmt::For {
+            location,
+            initializer,
+            condition,
+            increment,
+            body: loop_body,
+        } => Stmt::For {
+            location,
+            initializer: initializer.and_then(|stmt| statement(*stmt)).map(Box::new),
+            condition: condition.map(expression),
+            increment: increment.map(expression),
+            body: Box::new(body(*loop_body)),
+        },
+        Stmt::ForIn {
+            location,
+            variable,
+            iterable,
+            body: loop_body,
+        } => Stmt::ForIn {
+            location,
+            variable,
+            iterable: expression(iterable),
+            body: Box::new(body(*loop_body)),
+        },
+        // labels are only allowed on loops, so one whose loop went goes with it
+        Stmt::Labeled(label, loop_body) => {
+            return statement(*loop_body).map(|stmt| Stmt::Labeled(label, Box::new(stmt)))
+        }
+        Stmt::Throw(loc, e) => Stmt::Throw(loc, expression(e)),
+        Stmt::Try {
+            location,
+            body,
+            catch,
+            finally,
+        } => Stmt::Try {
+            location,
+            body: block(body),
+            catch: catch.map(|(name, handler)| (name, block(handler))),
+            finally: finally.map(block),
+        },
+        stmt @ (Stmt::Break(..) | Stmt::Continue(..) | Stmt::Import(_)) => stmt,
+    })
+}
+
+/// A statement that has to be there, as the body of a loop or an `if` is; an empty block
+/// stands in for one that does nothing.
+fn body(stmt: Stmt) -> Stmt {
+    let location = stmt.location();
+    statement(stmt).unwrap_or(Stmt::Block(location, Vec::new()))
+}
+
+fn function(fundecl: expr::FunDecl) -> expr::FunDecl {
+    expr::FunDecl {
+        body: block(fundecl.body),
+        ..fundecl
+    }
+}
+
+fn expression(e: Expr) -> Expr {
+    match e {
+        Expr::Lambda(lambda) => Expr::Lambda(expr::LambdaDecl {
+            body: block(lambda.body),
+            ..lambda
+        }),
+        e => {
+            let location = e.location();
+            fold(map_children(e, &mut expression), location)
+        }
+    }
+}
+
+/// `e` made as simple as it can be, given that everything in it already is.
+fn fold(e: Expr, location: SourceLocation) -> Expr {
+    match e {
+        Expr::Grouping(inner) if literal(&inner).is_some() => *inner,
+        Expr::Unary(op, operand) => match literal(&operand).and_then(|lit| unary(op.ty, lit)) {
+            Some(lit) => Expr::Literal(location, lit),
+            None => Expr::Unary(op, operand),
+        },
+        Expr::Binary(lhs, op, rhs) => {
+            let folded = match (literal(&lhs), literal(&rhs)) {
+                (Some(a), Some(b)) => binary(a, op.ty, b),
+                _ => None,
+            };
+            match folded {
+                Some(lit) => Expr::Literal(location, lit),
+                None => Expr::Binary(lhs, op, rhs),
+            }
+        }
+        // `and` and `or` give back one of their operands, not a boolean
+        Expr::Logical(lhs, op, rhs) => match literal(&lhs).map(is_truthy) {
+            Some(truthy) => match (op, truthy) {
+                (LogicalOp::Or, true) | (LogicalOp::And, false) => *lhs,
+                (LogicalOp::Or, false) | (LogicalOp::And, true) => *rhs,
+            },
+            None => Expr::Logical(lhs, op, rhs),
+        },
+        Expr::Ternary(cond, then_value, else_value) => match literal(&cond).map(is_truthy) {
+            Some(true) => *then_value,
+            Some(false) => *else_value,
+            None => Expr::Ternary(cond, then_value, else_value),
+        },
+        Expr::Call(callee, loc, args) => match inline(&callee, &args, location) {
+            Some(e) => e,
+            None => Expr::Call(callee, loc, args),
+        },
+        e => e,
+    }
+}
+
+fn literal(e: &Expr) -> Option<&Literal> {
+    match e {
+        Expr::Literal(_, lit) => Some(lit),
+        _ => None,
+    }
+}
+
+fn is_truthy(lit: &Literal) -> bool {
+    !matches!(lit, Literal::Nil | Literal::False)
+}
+
+fn boolean(b: bool) -> Literal {
+    if b {
+        Literal::True
+    } else {
+        Literal::False
+    }
+}
+
+fn unary(op: UnaryOpTy, operand: &Literal) -> Option<Literal> {
+    match (op, operand) {
+        (UnaryOpTy::Minus, Literal::Number(n)) => Some(Literal::Number(-n)),
+        (UnaryOpTy::Bang, _) => Some(boolean(!is_truthy(operand))),
+        (UnaryOpTy::Tilde, Literal::Number(n)) => value::bitwise_not(*n).ok().map(Literal::Number),
+        _ => None,
+    }
+}
+
+fn binary(lhs: &Literal, op: BinaryOpTy, rhs: &Literal) -> Option<Literal> {
+    match (lhs, op, rhs) {
+        (_, BinaryOpTy::EqualEqual, _) => Some(boolean(equals(lhs, rhs))),
+        (_, BinaryOpTy::NotEqual, _) => Some(boolean(!equals(lhs, rhs))),
+        (Literal::Number(a), _, Literal::Number(b)) => Some(match op {
+            BinaryOpTy::Less => boolean(a < b),
+            BinaryOpTy::LessEqual => boolean(a <= b),
+            BinaryOpTy::Greater => boolean(a > b),
+            BinaryOpTy::GreaterEqual => boolean(a >= b),
+            BinaryOpTy::Plus => Literal::Number(a + b),
+            BinaryOpTy::Minus => Literal::Number(a - b),
+            BinaryOpTy::Star => Literal::Number(a * b),
+            BinaryOpTy::Slash => Literal::Number(a / b),
+            BinaryOpTy::Percent => Literal::Number(a % b),
+            BinaryOpTy::StarStar => Literal::Number(a.powf(*b)),
+            BinaryOpTy::Ampersand
+            | BinaryOpTy::Pipe
+            | BinaryOpTy::Caret
+            | BinaryOpTy::LessLess
+            | BinaryOpTy::GreaterGreater => Literal::Number(value::bitwise(op, *a, *b).ok()?),
+            BinaryOpTy::EqualEqual | BinaryOpTy::NotEqual => unreachable!(),
+        }),
+        (Literal::String(a), BinaryOpTy::Plus, Literal::String(b)) => {
+            Some(Literal::String(format!("{}{}", a, b)))
+        }
+        _ => None,
+    }
+}
+
+/// `Value::equals` on literals.
+fn equals(lhs: &Literal, rhs: &Literal) -> bool {
+    match (lhs, rhs) {
+        (Literal::Number(a), Literal::Number(b)) => a == b,
+        (Literal::String(a), Literal::String(b)) => a == b,
+        (Literal::True, Literal::True)
+        | (Literal::False, Literal::False)
+        | (Literal::Nil, Literal::Nil) => true,
+        _ => false,
+    }
+}
+
+/// The expression a call of a lambda written in place comes to, when the lambda is trivial:
+/// it does nothing but return an expression that reads no variables besides its parameters.
+/// With literal arguments, putting them in for the parameters can't change what is evaluated
+/// or when, and with nothing else read, the expression means the same outside the lambda as
+/// in it.
+fn inline(callee: &Expr, args: &[Expr], location: SourceLocation) -> Option<Expr> {
+    let mut callee = callee;
+    while let Expr::Grouping(inner) = callee {
+        callee = inner;
+    }
+    let lambda = match callee {
+        Expr::Lambda(lambda) => lambda,
+        _ => return None,
+    };
+    if lambda.params.len() != args.len() || args.iter().any(|arg| literal(arg).is_none()) {
+        return None;
+    }
+    let returned = match lambda.body.as_slice() {
+        [] | [Stmt::Return(_, None)] => return Some(Expr::Literal(location, Literal::Nil)),
+        [Stmt::Return(_, Some(returned))] => returned,
+        _ => return None,
+    };
+
+    let param = |sym: &expr::Symbol| {
+        lambda
+            .params
+            .iter()
+            .position(|param| param.name.name == sym.name)
+    };
+    if !only_reads(returned, &|sym| param(sym).is_some()) {
+        return None;
+    }
+    Some(expression(substitute(returned.clone(), &|sym| {
+        param(sym).map(|i| args[i].clone())
+    })))
+}
+
+/// Whether the only variables `e` uses are ones `allowed` lets through, none of them
+/// assigned to, and it has no `this`, `super` or lambda, whose meaning depends on where they
+/// are.
+fn only_reads(e: &Expr, allowed: &dyn Fn(&expr::Symbol) -> bool) -> bool {
+    let ok = match e {
+        Expr::Variable(sym) => allowed(sym),
+        Expr::This(_)
+        | Expr::Super(..)
+        | Expr::Lambda(_)
+        | Expr::Assign(..)
+        | Expr::CompoundAssign(..)
+        | Expr::Increment { .. } => false,
+        _ => true,
+    };
+    ok && children(e)
+        .into_iter()
+        .all(|child| only_reads(child, allowed))
+}
+
+/// `e` with the variables `replacement` has something for replaced, located where the
+/// variable was.
+fn substitute(e: Expr, replacement: &dyn Fn(&expr::Symbol) -> Option<Expr>) -> Expr {
+    match e {
+        Expr::Variable(sym) => match replacement(&sym) {
+            Some(Expr::Literal(_, lit)) => Expr::Literal(
+                SourceLocation {
+                    line: sym.line,
+                    col: sym.col,
+                },
+                lit,
+            ),
+            Some(e) => e,
+            None => Expr::Variable(sym),
+        },
+        e => map_children(e, &mut |child| substitute(child, replacement)),
+    }
+}
+
+/// The expressions directly inside `e`. A lambda's body is statements, so it has none.
+fn children(e: &Expr) -> Vec<&Expr> {
+    match e {
+        Expr::Literal(..)
+        | Expr::This(_)
+        | Expr::Variable(_)
+        | Expr::Super(..)
+        | Expr::Lambda(_) => Vec::new(),
+        Expr::Unary(_, e) | Expr::Get(e, _) | Expr::Grouping(e) | Expr::Assign(_, e) => vec![e],
+        Expr::Increment { target, .. } => vec![target],
+        Expr::Binary(lhs, _, rhs)
+        | Expr::Logical(lhs, _, rhs)
+        | Expr::Set(lhs, _, rhs)
+        | Expr::CompoundAssign(lhs, _, rhs) => vec![lhs, rhs],
+        Expr::Subscript { value, slice, .. } => vec![value, slice],
+        Expr::SetItem {
+            lhs, slice, rhs, ..
+        } => vec![lhs, slice, rhs],
+        Expr::Ternary(cond, then_value, else_value) => vec![cond, then_value, else_value],
+        Expr::Call(callee, _, args) => std::iter::once(&**callee).chain(args).collect(),
+        Expr::List(_, elements) | Expr::Interpolation(_, elements) => elements.iter().collect(),
+        Expr::Map(_, entries) => entries.iter().flat_map(|(k, v)| [k, v]).collect(),
+        Expr::Yield(_, value) => value.iter().map(|value| &**value).collect(),
+    }
+}
+
+/// `e` with `f` applied to each of `children(e)`.
+fn map_children(e: Expr, f: &mut dyn FnMut(Expr) -> Expr) -> Expr {
+    let mut boxed = |e: Box<Expr>| Box::new(f(*e));
+    match e {
+        Expr::Literal(..)
+        | Expr::This(_)
+        | Expr::Variable(_)
+        | Expr::Super(..)
+        | Expr::Lambda(_) => e,
+        Expr::Unary(op, e) => Expr::Unary(op, boxed(e)),
+        Expr::Get(e, attr) => Expr::Get(boxed(e), attr),
+        Expr::Grouping(e) => Expr::Grouping(boxed(e)),
+        Expr::Assign(sym, e) => Expr::Assign(sym, boxed(e)),
+        Expr::Increment { target, op, prefix } => Expr::Increment {
+            target: boxed(target),
+            op,
+            prefix,
+        },
+        Expr::Binary(lhs, op, rhs) => Expr::Binary(boxed(lhs), op, boxed(rhs)),
+        Expr::Logical(lhs, op, rhs) => Expr::Logical(boxed(lhs), op, boxed(rhs)),
+        Expr::Set(lhs, attr, rhs) => Expr::Set(boxed(lhs), attr, boxed(rhs)),
+        Expr::CompoundAssign(lhs, op, rhs) => Expr::CompoundAssign(boxed(lhs), op, boxed(rhs)),
+        Expr::Subscript {
+            value,
+            slice,
+            source_location,
+        } => Expr::Subscript {
+            value: boxed(value),
+            slice: boxed(slice),
+            source_location,
+        },
+        Expr::SetItem {
+            lhs,
+            slice,
+            rhs,
+            source_location,
+        } => Expr::SetItem {
+            lhs: boxed(lhs),
+            slice: boxed(slice),
+            rhs: boxed(rhs),
+            source_location,
+        },
+        Expr::Ternary(cond, then_value, else_value) => {
+            Expr::Ternary(boxed(cond), boxed(then_value), boxed(else_value))
+        }
+        Expr::Call(callee, loc, args) => {
+            let callee = boxed(callee);
+            Expr::Call(
+                callee,
+                loc,
+                args.into_iter().map(|arg| *boxed(Box::new(arg))).collect(),
+            )
+        }
+        Expr::List(loc, elements) => Expr::List(
+            loc,
+            elements.into_iter().map(|e| *boxed(Box::new(e))).collect(),
+        ),
+        Expr::Interpolation(loc, parts) => Expr::Interpolation(
+            loc,
+            parts.into_iter().map(|e| *boxed(Box::new(e))).collect(),
+        ),
+        Expr::Map(loc, entries) => Expr::Map(
+            loc,
+            entries
+                .into_iter()
+                .map(|(k, v)| (*boxed(Box::new(k)), *boxed(Box::new(v))))
+                .collect(),
+        ),
+        Expr::Yield(loc, value) => Expr::Yield(loc, value.map(boxed)),
+    }
+}
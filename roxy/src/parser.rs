@@ -1,6 +1,6 @@
 use crate::expr;
-use crate::scanner;
 use crate::extensions;
+use crate::scanner;
 
 use std::fmt;
 
@@ -8,8 +8,8 @@ use std::fmt;
 struct Parser {
     tokens: Vec<scanner::Token>,
     current: usize,
-    in_fundec: bool, // in rust, booleans default to false: https://doc.rust-lang.org/std/primitive.bool.html#impl-Default
     extensions: extensions::Extensions,
+    errors: Vec<Error>,
 }
 
 pub enum Error {
@@ -24,10 +24,6 @@ pub enum Error {
         line: usize,
         col: i64,
     },
-    ReturnNotInFun {
-        line: usize,
-        col: i64,
-    },
     InvalidAssignment {
         line: usize,
         col: i64,
@@ -81,11 +77,6 @@ impl fmt::Debug for Error {
                 "Cannot have more than 255 parameters in a {:?} declaration. Line={},col={}",
                 kind, line, col
             ),
-            Error::ReturnNotInFun { line, col } => write!(
-                f,
-                "return statement not enclosed in a FunDecl at line={},col={}",
-                line, col
-            ),
             Error::InvalidAssignment { line, col } => {
                 write!(f, "invalid assignment target at line={},col={}", line, col)
             }
@@ -125,34 +116,35 @@ impl fmt::Debug for Error {
     }
 }
 
+// a function's parameters, return type annotation and body
+type FunctionParts = (Vec<expr::Param>, Option<expr::Symbol>, Vec<expr::Stmt>);
+
 #[derive(Debug)]
 pub enum FunctionKind {
     Function,
     Method,
     Lambda,
+    /// a `fun*` declaration or a method with `*` before its name
+    Generator,
 }
 
+/// Parses a whole program. On failure, every error found is returned: after each one the
+/// parser skips ahead to the next statement boundary and carries on.
 pub fn parse(
     extensions: extensions::Extensions,
     tokens: Vec<scanner::Token>,
-) -> Result<Vec<expr::Stmt>, Error> {
+) -> Result<Vec<expr::Stmt>, Vec<Error>> {
     let mut p = Parser {
         tokens,
         extensions,
         ..Default::default()
     };
-    let stmts_or_err = p.parse();
+    let stmts = p.parse();
 
-    match stmts_or_err {
-        Ok(stmts_or_err) => {
-            if !p.is_at_end() {
-                let tok = &p.tokens[p.current];
-                Err(Error::UnexpectedToken(tok.clone()))
-            } else {
-                Ok(stmts_or_err)
-            }
-        }
-        Err(err) => Err(err),
+    if p.errors.is_empty() {
+        Ok(stmts)
+    } else {
+        Err(p.errors)
     }
 }
 
@@ -164,14 +156,23 @@ program     → declaration* EOF ;
 declaration → classDecl
             | funDecl
             | varDecl
+            | importDecl
             | statement ;
 
-classDecl → "class" IDENTIFIER ( "<" IDENTIFIER )?
-            "{" function* "}" ;
+importDecl → "import" ( IDENTIFIER "from" )? STRING ";" ;
 
-funDecl  → "fun" function ;
-function → IDENTIFIER "(" parameters? ")" block ;
-parameters  → IDENTIFIER ( "," IDENTIFIER )* ;
+classDecl → "class" IDENTIFIER ( "<" IDENTIFIER )?
+            ( "with" IDENTIFIER ( "," IDENTIFIER )* )?
+            "{" member* "}" ;
+member   → "static" "*"? function
+         | "get" IDENTIFIER annotation? block
+         | "set" IDENTIFIER "(" IDENTIFIER annotation? ")" block
+         | "*"? function ;
+
+funDecl  → "fun" "*"? function ;
+function → IDENTIFIER "(" parameters? ")" annotation? block ;
+parameters  → IDENTIFIER annotation? ( "," IDENTIFIER annotation? )* ;
+annotation → ":" ( IDENTIFIER | "nil" | "fun" ) ;
 
 statement → exprStmt
           | forStmt
@@ -179,13 +180,27 @@ statement → exprStmt
           | printStmt
           | returnStmt
           | whileStmt
+          | throwStmt
+          | tryStmt
+          | breakStmt
+          | continueStmt
+          | labeledStmt
           | block ;
 
 returnStmt → "return" expression? ";" ;
 
+breakStmt    → "break" IDENTIFIER? ";" ;
+continueStmt → "continue" IDENTIFIER? ";" ;
+labeledStmt  → IDENTIFIER ":" ( forStmt | whileStmt ) ;
+
+throwStmt → "throw" expression ";" ;
+
+tryStmt   → "try" block ( "catch" "(" IDENTIFIER ")" block )? ( "finally" block )? ;
+
 forStmt   → "for" "(" ( varDecl | exprStmt | ";" )
                       expression? ";"
-                      expression? ")" statement ;
+                      expression? ")" statement
+          | "for" "(" IDENTIFIER "in" expression ")" statement ;
 
 whileStmt → "while" "(" expression ")" statement ;
 
@@ -193,41 +208,101 @@ ifStmt    → "if" "(" expression ")" statement ( "else" statement )? ;
 
 block     → "{" declaration* "}" ;
 
-varDecl → "var" IDENTIFIER ( "=" expression )? ";" ;
+varDecl → "var" IDENTIFIER annotation? ( "=" expression )? ";" ;
 
 exprStmt  → expression ";" ;
 printStmt → "print" expression ";" ;
 
 expression → assignment ;
-assignment → ( call "." )? IDENTIFIER "=" assignment
-           | logic_or;
+assignment → target ( "=" | "+=" | "-=" | "*=" | "/=" ) assignment
+           | "yield" expression?
+           | ternary ;
+target     → ( call "." )? IDENTIFIER | call "[" expression "]" ;
+ternary    → logic_or ( "?" expression ":" ternary )? ;
 logic_or   → logic_and ( "or" logic_and )* ;
 logic_and  → equality ( "and" equality )* ;
 
 equality       → comparison ( ( "!=" | "==" ) comparison )* ;
-comparison     → addition ( ( ">" | ">=" | "<" | "<=" ) addition )* ;
+comparison     → bit_or ( ( ">" | ">=" | "<" | "<=" ) bit_or )* ;
+bit_or         → bit_xor ( "|" bit_xor )* ;
+bit_xor        → bit_and ( "^" bit_and )* ;
+bit_and        → shift ( "&" shift )* ;
+shift          → addition ( ( "<<" | ">>" ) addition )* ;
 addition       → multiplication ( ( "-" | "+" ) multiplication )* ;
-multiplication → unary ( ( "/" | "*" ) unary )* ;
-unary → ( "!" | "-" ) unary | call ;
+multiplication → unary ( ( "/" | "*" | "%" ) unary )* ;
+unary → ( "!" | "-" | "~" ) unary | ( "++" | "--" ) target | power ;
+power → postfix ( "**" unary )? ;
+postfix → call | target ( "++" | "--" ) ;
 call → primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )* ;
 arguments → expression ( "," expression )* ;
 
 primary → "true" | "false" | "nil" | "this"
         | NUMBER | STRING | IDENTIFIER | "(" expression ")"
         | "super" "." IDENTIFIER
-        | "[" arguments? "]" ;
-
+        | "[" arguments? "]"
+        | "{" ( expression ":" expression ( "," expression ":" expression )* )? "}"
+        | ( INTERPOLATION expression )+ STRING ;
+
+A `yield` parses anywhere an assignment can, but the resolver only lets one be a statement of
+its own, a variable's initializer or the value assigned to a variable: `1 + (yield x)`,
+`f(yield 1)` and `[yield 1]` are refused. The tree-walker resumes a generator by running the
+statement it stopped in again, so nothing in that statement may run before the `yield`.
 */
 impl Parser {
-    pub fn parse(&mut self) -> Result<Vec<expr::Stmt>, Error> {
+    pub fn parse(&mut self) -> Vec<expr::Stmt> {
         let mut statements = Vec::new();
 
         while !self.is_at_end() {
-            let stmt = self.declaration()?;
-            statements.push(stmt);
+            if let Some(stmt) = self.declaration_or_synchronize() {
+                statements.push(stmt);
+            }
         }
 
-        Ok(statements)
+        statements
+    }
+
+    fn declaration_or_synchronize(&mut self) -> Option<expr::Stmt> {
+        match self.declaration() {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                self.errors.push(err);
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    /// Panic-mode recovery: discard tokens until something that looks like the start of the
+    /// next statement, so one mistake doesn't produce a cascade of errors.
+    fn synchronize(&mut self) {
+        self.advance();
+
+        while !self.is_at_end() {
+            if self.previous().ty == scanner::TokenType::Semicolon {
+                return;
+            }
+
+            match self.peek().ty {
+                scanner::TokenType::Class
+                | scanner::TokenType::Fun
+                | scanner::TokenType::Var
+                | scanner::TokenType::For
+                | scanner::TokenType::If
+                | scanner::TokenType::While
+                | scanner::TokenType::Print
+                | scanner::TokenType::Return
+                | scanner::TokenType::Import
+                | scanner::TokenType::Throw
+                | scanner::TokenType::Try
+                | scanner::TokenType::Break
+                | scanner::TokenType::Continue
+                | scanner::TokenType::Yield
+                | scanner::TokenType::RightBrace => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
     }
 
     fn declaration(&mut self) -> Result<expr::Stmt, Error> {
@@ -236,16 +311,71 @@ impl Parser {
         }
 
         if self.matches(scanner::TokenType::Fun) {
-            return Ok(expr::Stmt::FunDecl(self.fun_decl(FunctionKind::Function)?));
+            let kind = if self.matches(scanner::TokenType::Star) {
+                FunctionKind::Generator
+            } else {
+                FunctionKind::Function
+            };
+            return Ok(expr::Stmt::FunDecl(self.fun_decl(kind)?));
         }
 
         if self.matches(scanner::TokenType::Class) {
             return self.class_decl();
         }
 
+        if self.matches(scanner::TokenType::Import) {
+            return self.import_decl();
+        }
+
         self.statement()
     }
 
+    fn import_decl(&mut self) -> Result<expr::Stmt, Error> {
+        let name = if self.matches(scanner::TokenType::Identifier) {
+            let name_tok = self.previous().clone();
+            // `from` is only special here, so it stays usable as an ordinary identifier
+            let from_tok = self.peek().clone();
+            if from_tok.ty != scanner::TokenType::Identifier || from_tok.lexeme != b"from" {
+                return Err(Error::TokenMismatch {
+                    expected: scanner::TokenType::Identifier,
+                    found: from_tok,
+                    maybe_on_err_string: Some("Expected from after imported name".into()),
+                });
+            }
+            self.advance();
+            Some(expr::Symbol {
+                name: String::from_utf8(name_tok.lexeme).unwrap(),
+                line: name_tok.line,
+                col: name_tok.col,
+            })
+        } else {
+            None
+        };
+
+        let path_tok = self
+            .consume(scanner::TokenType::String, "Expected module path")?
+            .clone();
+        let path = match &path_tok.literal {
+            Some(scanner::Literal::Str(s)) => s.clone(),
+            Some(l) => panic!(
+                "internal error in parser: when parsing import path, found literal {:?}",
+                l
+            ),
+            None => panic!("internal error in parser: when parsing import path, found no literal"),
+        };
+
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after import")?;
+
+        Ok(expr::Stmt::Import(expr::ImportDecl {
+            path,
+            location: expr::SourceLocation {
+                line: path_tok.line,
+                col: path_tok.col,
+            },
+            name,
+        }))
+    }
+
     fn class_decl(&mut self) -> Result<expr::Stmt, Error> {
         let name_tok = self
             .consume(scanner::TokenType::Identifier, "Expected class name")?
@@ -269,11 +399,28 @@ impl Parser {
             None
         };
 
+        let mut mixins = Vec::new();
+        if self.check_word(b"with") {
+            self.advance();
+            loop {
+                let mixin_tok =
+                    self.consume(scanner::TokenType::Identifier, "Expected class name.")?;
+                mixins.push(expr::Symbol {
+                    name: String::from_utf8(mixin_tok.lexeme.clone()).unwrap(),
+                    line: mixin_tok.line,
+                    col: mixin_tok.col,
+                });
+                if !self.matches(scanner::TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
         self.consume(scanner::TokenType::LeftBrace, "Expected { after class name")?;
 
         let mut methods = Vec::new();
         while !self.check(scanner::TokenType::RightBrace) && !self.is_at_end() {
-            methods.push(self.fun_decl(FunctionKind::Method)?);
+            methods.push(self.method()?);
         }
         let methods = methods;
 
@@ -285,10 +432,108 @@ impl Parser {
         Ok(expr::Stmt::ClassDecl(expr::ClassDecl {
             name: class_symbol,
             superclass: superclass_maybe,
+            mixins,
             methods,
         }))
     }
 
+    fn method(&mut self) -> Result<expr::Method, Error> {
+        // the words are only special in front of a method's name, so methods can still be
+        // called `get` and the like
+        let kind = if self.check_next(scanner::TokenType::Identifier)
+            || self.check_next(scanner::TokenType::Star)
+        {
+            match self.peek().lexeme.as_slice() {
+                b"static" => expr::MethodKind::Static,
+                b"get" => expr::MethodKind::Getter,
+                b"set" => expr::MethodKind::Setter,
+                _ => expr::MethodKind::Method,
+            }
+        } else {
+            expr::MethodKind::Method
+        };
+        if kind != expr::MethodKind::Method {
+            self.advance();
+        }
+
+        let decl = match kind {
+            expr::MethodKind::Method | expr::MethodKind::Static => {
+                if self.matches(scanner::TokenType::Star) {
+                    self.fun_decl(FunctionKind::Generator)?
+                } else {
+                    self.fun_decl(FunctionKind::Method)?
+                }
+            }
+            expr::MethodKind::Getter => {
+                let name_tok = self
+                    .consume(scanner::TokenType::Identifier, "Expected getter name")?
+                    .clone();
+                let return_type = self.annotation()?;
+                self.consume(
+                    scanner::TokenType::LeftBrace,
+                    "Expected { before getter body",
+                )?;
+                let body = self.block()?;
+                expr::FunDecl {
+                    name: expr::Symbol {
+                        name: String::from_utf8(name_tok.lexeme).unwrap(),
+                        line: name_tok.line,
+                        col: name_tok.col,
+                    },
+                    params: Vec::new(),
+                    return_type,
+                    body,
+                    generator: false,
+                }
+            }
+            expr::MethodKind::Setter => {
+                let name_tok = self
+                    .consume(scanner::TokenType::Identifier, "Expected setter name")?
+                    .clone();
+                self.consume(
+                    scanner::TokenType::LeftParen,
+                    "Expected ( after setter name",
+                )?;
+                let param_tok = self
+                    .consume(
+                        scanner::TokenType::Identifier,
+                        "A setter takes exactly one parameter",
+                    )?
+                    .clone();
+                let ty = self.annotation()?;
+                self.consume(
+                    scanner::TokenType::RightParen,
+                    "A setter takes exactly one parameter",
+                )?;
+                self.consume(
+                    scanner::TokenType::LeftBrace,
+                    "Expected { before setter body",
+                )?;
+                let body = self.block()?;
+                expr::FunDecl {
+                    name: expr::Symbol {
+                        name: String::from_utf8(name_tok.lexeme).unwrap(),
+                        line: name_tok.line,
+                        col: name_tok.col,
+                    },
+                    params: vec![expr::Param {
+                        name: expr::Symbol {
+                            name: String::from_utf8(param_tok.lexeme).unwrap(),
+                            line: param_tok.line,
+                            col: param_tok.col,
+                        },
+                        ty,
+                    }],
+                    return_type: None,
+                    body,
+                    generator: false,
+                }
+            }
+        };
+
+        Ok(expr::Method { kind, decl })
+    }
+
     fn fun_decl(&mut self, kind: FunctionKind) -> Result<expr::FunDecl, Error> {
         let name_tok = self
             .consume(
@@ -303,19 +548,19 @@ impl Parser {
             col: name_tok.col,
         };
 
-        let (parameters, body) = self.params_and_body(kind)?;
+        let generator = matches!(kind, FunctionKind::Generator);
+        let (parameters, return_type, body) = self.params_and_body(kind)?;
 
         Ok(expr::FunDecl {
             name: fun_symbol,
             params: parameters,
+            return_type,
             body,
+            generator,
         })
     }
 
-    fn params_and_body(
-        &mut self,
-        kind: FunctionKind,
-    ) -> Result<(Vec<expr::Symbol>, Vec<expr::Stmt>), Error> {
+    fn params_and_body(&mut self, kind: FunctionKind) -> Result<FunctionParts, Error> {
         self.consume(
             scanner::TokenType::LeftParen,
             format!("Expected ( after {:?} name", kind).as_ref(),
@@ -338,11 +583,13 @@ impl Parser {
                     .consume(scanner::TokenType::Identifier, "Expected parameter name")?
                     .clone();
 
-                parameters.push(expr::Symbol {
+                let name = expr::Symbol {
                     name: String::from_utf8(tok.lexeme).unwrap(),
                     line: tok.line,
                     col: tok.col,
-                });
+                };
+                let ty = self.annotation()?;
+                parameters.push(expr::Param { name, ty });
 
                 if !self.matches(scanner::TokenType::Comma) {
                     break;
@@ -355,22 +602,38 @@ impl Parser {
             scanner::TokenType::RightParen,
             "Expected ) after parameter list",
         )?;
+        let return_type = self.annotation()?;
         self.consume(
             scanner::TokenType::LeftBrace,
             "Expected { before function body",
         )?;
-        let saved_is_in_fundec = self.in_fundec;
-        self.in_fundec = true;
         let body = self.block()?;
-        self.in_fundec = saved_is_in_fundec;
 
-        Ok((parameters, body))
+        Ok((parameters, return_type, body))
+    }
+
+    /// The `: type` after a variable or parameter name or a parameter list, if there is one.
+    /// `nil` and `fun` are keywords, but name types too.
+    fn annotation(&mut self) -> Result<Option<expr::Symbol>, Error> {
+        if !self.matches(scanner::TokenType::Colon) {
+            return Ok(None);
+        }
+        if !self.match_one_of(vec![scanner::TokenType::Nil, scanner::TokenType::Fun]) {
+            self.consume(scanner::TokenType::Identifier, "Expected type name after :")?;
+        }
+        let tok = self.previous();
+        Ok(Some(expr::Symbol {
+            name: String::from_utf8(tok.lexeme.clone()).unwrap(),
+            line: tok.line,
+            col: tok.col,
+        }))
     }
 
     fn var_decl(&mut self) -> Result<expr::Stmt, Error> {
         let name_token = self
             .consume(scanner::TokenType::Identifier, "Expected variable name")?
             .clone();
+        let ty = self.annotation()?;
 
         let maybe_initializer = if self.matches(scanner::TokenType::Equal) {
             Some(self.expression()?)
@@ -389,6 +652,7 @@ impl Parser {
                 line: name_token.line,
                 col: name_token.col,
             },
+            ty,
             maybe_initializer,
         ))
     }
@@ -403,7 +667,8 @@ impl Parser {
         }
 
         if self.matches(scanner::TokenType::LeftBrace) {
-            return Ok(expr::Stmt::Block(self.block()?));
+            let location = self.previous_location();
+            return Ok(expr::Stmt::Block(location, self.block()?));
         }
 
         if self.matches(scanner::TokenType::For) {
@@ -418,19 +683,139 @@ impl Parser {
             return self.return_statement();
         }
 
+        if self.matches(scanner::TokenType::Throw) {
+            return self.throw_statement();
+        }
+
+        if self.matches(scanner::TokenType::Try) {
+            return self.try_statement();
+        }
+
+        if self.matches(scanner::TokenType::Break) {
+            let location = self.previous_location();
+            return Ok(expr::Stmt::Break(location, self.jump_label("break")?));
+        }
+
+        if self.matches(scanner::TokenType::Continue) {
+            let location = self.previous_location();
+            return Ok(expr::Stmt::Continue(location, self.jump_label("continue")?));
+        }
+
+        if self.check(scanner::TokenType::Identifier) && self.check_next(scanner::TokenType::Colon)
+        {
+            return self.labeled_statement();
+        }
+
         self.expression_statement()
     }
 
-    fn return_statement(&mut self) -> Result<expr::Stmt, Error> {
-        let prev_tok = self.previous().clone();
+    /// The optional label after `break` or `continue`, and the `;` ending the statement.
+    fn jump_label(&mut self, keyword: &str) -> Result<Option<expr::Symbol>, Error> {
+        let label = if self.matches(scanner::TokenType::Identifier) {
+            let tok = self.previous();
+            Some(expr::Symbol {
+                name: String::from_utf8(tok.lexeme.clone()).unwrap(),
+                line: tok.line,
+                col: tok.col,
+            })
+        } else {
+            None
+        };
+        self.consume(
+            scanner::TokenType::Semicolon,
+            &format!("Expected ; after {}", keyword),
+        )?;
+        Ok(label)
+    }
 
-        if !self.in_fundec {
-            return Err(Error::ReturnNotInFun {
-                line: prev_tok.line,
-                col: prev_tok.col,
+    fn labeled_statement(&mut self) -> Result<expr::Stmt, Error> {
+        let name_tok = self.advance().clone();
+        let label = expr::Symbol {
+            name: String::from_utf8(name_tok.lexeme).unwrap(),
+            line: name_tok.line,
+            col: name_tok.col,
+        };
+        // the `:`, which `statement` has already seen
+        self.advance();
+
+        let body = if self.matches(scanner::TokenType::While) {
+            self.while_statement()?
+        } else if self.matches(scanner::TokenType::For) {
+            self.for_statement()?
+        } else {
+            return Err(Error::TokenMismatch {
+                expected: scanner::TokenType::While,
+                found: self.peek().clone(),
+                maybe_on_err_string: Some("Expected a loop after label".into()),
+            });
+        };
+        Ok(expr::Stmt::Labeled(label, Box::new(body)))
+    }
+
+    fn throw_statement(&mut self) -> Result<expr::Stmt, Error> {
+        let location = self.previous_location();
+        let value = self.expression()?;
+        self.consume(
+            scanner::TokenType::Semicolon,
+            "Expected ; after thrown value",
+        )?;
+        Ok(expr::Stmt::Throw(location, value))
+    }
+
+    fn try_statement(&mut self) -> Result<expr::Stmt, Error> {
+        let location = self.previous_location();
+        self.consume(scanner::TokenType::LeftBrace, "Expected { after try")?;
+        let body = self.block()?;
+
+        let catch = if self.matches(scanner::TokenType::Catch) {
+            self.consume(scanner::TokenType::LeftParen, "Expected ( after catch")?;
+            let name_tok = self
+                .consume(
+                    scanner::TokenType::Identifier,
+                    "Expected name to catch into",
+                )?
+                .clone();
+            self.consume(
+                scanner::TokenType::RightParen,
+                "Expected ) after caught name",
+            )?;
+            self.consume(scanner::TokenType::LeftBrace, "Expected { after catch")?;
+            let name = expr::Symbol {
+                name: String::from_utf8(name_tok.lexeme).unwrap(),
+                line: name_tok.line,
+                col: name_tok.col,
+            };
+            Some((name, self.block()?))
+        } else {
+            None
+        };
+
+        let finally = if self.matches(scanner::TokenType::Finally) {
+            self.consume(scanner::TokenType::LeftBrace, "Expected { after finally")?;
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(Error::TokenMismatch {
+                expected: scanner::TokenType::Catch,
+                found: self.peek().clone(),
+                maybe_on_err_string: Some("Expected catch or finally after try block".into()),
             });
         }
 
+        Ok(expr::Stmt::Try {
+            location,
+            body,
+            catch,
+            finally,
+        })
+    }
+
+    fn return_statement(&mut self) -> Result<expr::Stmt, Error> {
+        let prev_tok = self.previous().clone();
+
         let maybe_retval = if !self.matches(scanner::TokenType::Semicolon) {
             Some(self.expression()?)
         } else {
@@ -454,14 +839,19 @@ impl Parser {
     }
 
     fn for_statement(&mut self) -> Result<expr::Stmt, Error> {
+        let location = self.previous_location();
         self.consume(scanner::TokenType::LeftParen, "Expected ( after for.")?;
 
-        let mut maybe_initializer: Option<expr::Stmt> = None;
+        if self.check(scanner::TokenType::Identifier) && self.check_next(scanner::TokenType::In) {
+            return self.for_in_statement(location);
+        }
+
+        let mut maybe_initializer: Option<Box<expr::Stmt>> = None;
         if self.matches(scanner::TokenType::Semicolon) {
         } else if self.matches(scanner::TokenType::Var) {
-            maybe_initializer = Some(self.var_decl()?)
+            maybe_initializer = Some(Box::new(self.var_decl()?))
         } else {
-            maybe_initializer = Some(self.expression_statement()?)
+            maybe_initializer = Some(Box::new(self.expression_statement()?))
         }
         let maybe_initializer = maybe_initializer;
 
@@ -487,24 +877,39 @@ impl Parser {
             "Expected ) after for clauses",
         )?;
 
-        let mut body = self.statement()?;
+        let body = Box::new(self.statement()?);
 
-        if let Some(increment) = maybe_increment {
-            body = expr::Stmt::Block(vec![body, expr::Stmt::Expr(increment)])
-        }
+        Ok(expr::Stmt::For {
+            location,
+            initializer: maybe_initializer,
+            condition: maybe_condition,
+            increment: maybe_increment,
+            body,
+        })
+    }
 
-        let condition = match maybe_condition {
-            Some(cond) => cond,
-            None => expr::Expr::Literal(expr::Literal::True),
+    fn for_in_statement(&mut self, location: expr::SourceLocation) -> Result<expr::Stmt, Error> {
+        let name_token = self.advance().clone();
+        let variable = expr::Symbol {
+            name: String::from_utf8(name_token.lexeme).unwrap(),
+            line: name_token.line,
+            col: name_token.col,
         };
-        body = expr::Stmt::While(condition, Box::new(body));
-
-        if let Some(initializer) = maybe_initializer {
-            body = expr::Stmt::Block(vec![initializer, body])
-        }
-        let body = body;
+        // the `in`, which `for_statement` has already seen
+        self.advance();
+        let iterable = self.expression()?;
+        self.consume(
+            scanner::TokenType::RightParen,
+            "Expected ) after for-in iterable",
+        )?;
+        let body = Box::new(self.statement()?);
 
-        Ok(body)
+        Ok(expr::Stmt::ForIn {
+            location,
+            variable,
+            iterable,
+            body,
+        })
     }
 
     fn while_statement(&mut self) -> Result<expr::Stmt, Error> {
@@ -539,7 +944,9 @@ impl Parser {
         let mut stmts = Vec::new();
 
         while !self.check(scanner::TokenType::RightBrace) && !self.is_at_end() {
-            stmts.push(self.declaration()?)
+            if let Some(stmt) = self.declaration_or_synchronize() {
+                stmts.push(stmt)
+            }
         }
 
         self.consume(scanner::TokenType::RightBrace, "Expected } after block.")?;
@@ -548,14 +955,21 @@ impl Parser {
     }
 
     fn print_statement(&mut self) -> Result<expr::Stmt, Error> {
+        let print_tok = self.previous().clone();
         let expr = self.expression()?;
         self.consume(scanner::TokenType::Semicolon, "Expected ; after value")?;
-        Ok(expr::Stmt::Print(expr))
+        Ok(expr::Stmt::Print(
+            expr::SourceLocation {
+                line: print_tok.line,
+                col: print_tok.col,
+            },
+            expr,
+        ))
     }
 
     fn expression_statement(&mut self) -> Result<expr::Stmt, Error> {
         let expr = self.expression()?;
-        self.consume(scanner::TokenType::Semicolon, "Expected ; after value")?;
+        self.consume(scanner::TokenType::Semicolon, "Expected ; after expression")?;
         Ok(expr::Stmt::Expr(expr))
     }
 
@@ -564,7 +978,11 @@ impl Parser {
     }
 
     fn assignment(&mut self) -> Result<expr::Expr, Error> {
-        let expr = self.or()?;
+        if self.matches(scanner::TokenType::Yield) {
+            return self.yield_expression();
+        }
+
+        let expr = self.ternary()?;
 
         if self.matches(scanner::TokenType::Equal) {
             let equals = self.previous().clone();
@@ -595,6 +1013,74 @@ impl Parser {
             }
         }
 
+        if self.match_one_of(vec![
+            scanner::TokenType::PlusEqual,
+            scanner::TokenType::MinusEqual,
+            scanner::TokenType::StarEqual,
+            scanner::TokenType::SlashEqual,
+        ]) {
+            let operator_token = self.previous().clone();
+            let target = Parser::target(expr, &operator_token)?;
+            let new_value = self.assignment()?;
+            let binop = Parser::op_token_to_binop(&operator_token)?;
+            return Ok(expr::Expr::CompoundAssign(
+                Box::new(target),
+                binop,
+                Box::new(new_value),
+            ));
+        }
+
+        Ok(expr)
+    }
+
+    /// What follows `yield`: the value it hands out, unless the expression ends right away.
+    fn yield_expression(&mut self) -> Result<expr::Expr, Error> {
+        let location = self.previous_location();
+        let ends = [
+            scanner::TokenType::Semicolon,
+            scanner::TokenType::RightParen,
+            scanner::TokenType::RightBracket,
+            scanner::TokenType::RightBrace,
+            scanner::TokenType::Comma,
+            scanner::TokenType::Colon,
+        ];
+        if ends.iter().any(|ty| self.check(*ty)) {
+            return Ok(expr::Expr::Yield(location, None));
+        }
+        let value = self.expression()?;
+        Ok(expr::Expr::Yield(location, Some(Box::new(value))))
+    }
+
+    /// Checks that `expr` is something `op_token` can assign to.
+    fn target(expr: expr::Expr, op_token: &scanner::Token) -> Result<expr::Expr, Error> {
+        match expr {
+            expr::Expr::Variable(_) | expr::Expr::Get(_, _) | expr::Expr::Subscript { .. } => {
+                Ok(expr)
+            }
+            _ => Err(Error::InvalidAssignment {
+                line: op_token.line,
+                col: op_token.col,
+            }),
+        }
+    }
+
+    fn ternary(&mut self) -> Result<expr::Expr, Error> {
+        let expr = self.or()?;
+
+        if self.matches(scanner::TokenType::Question) {
+            let then_branch = self.expression()?;
+            self.consume(
+                scanner::TokenType::Colon,
+                "Expected : after the first branch of ?",
+            )?;
+            let else_branch = self.ternary()?;
+            return Ok(expr::Expr::Ternary(
+                Box::new(expr),
+                Box::new(then_branch),
+                Box::new(else_branch),
+            ));
+        }
+
         Ok(expr)
     }
 
@@ -621,7 +1107,7 @@ impl Parser {
     }
 
     fn comparison(&mut self) -> Result<expr::Expr, Error> {
-        let mut expr = self.addition()?;
+        let mut expr = self.bit_or()?;
 
         while self.match_one_of(vec![
             scanner::TokenType::Greater,
@@ -630,7 +1116,7 @@ impl Parser {
             scanner::TokenType::LessEqual,
         ]) {
             let operator_token = self.previous().clone();
-            let right = Box::new(self.addition()?);
+            let right = Box::new(self.bit_or()?);
             let binop_maybe = Parser::op_token_to_binop(&operator_token);
 
             match binop_maybe {
@@ -644,6 +1130,45 @@ impl Parser {
         Ok(expr)
     }
 
+    fn bit_or(&mut self) -> Result<expr::Expr, Error> {
+        self.left_associative(vec![scanner::TokenType::Pipe], Parser::bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> Result<expr::Expr, Error> {
+        self.left_associative(vec![scanner::TokenType::Caret], Parser::bit_and)
+    }
+
+    fn bit_and(&mut self) -> Result<expr::Expr, Error> {
+        self.left_associative(vec![scanner::TokenType::Ampersand], Parser::shift)
+    }
+
+    fn shift(&mut self) -> Result<expr::Expr, Error> {
+        self.left_associative(
+            vec![
+                scanner::TokenType::LessLess,
+                scanner::TokenType::GreaterGreater,
+            ],
+            Parser::addition,
+        )
+    }
+
+    /// Binary operators in `operators` between operands parsed by `operand`, grouping left.
+    fn left_associative(
+        &mut self,
+        operators: Vec<scanner::TokenType>,
+        operand: fn(&mut Parser) -> Result<expr::Expr, Error>,
+    ) -> Result<expr::Expr, Error> {
+        let mut expr = operand(self)?;
+
+        while self.match_one_of(operators.clone()) {
+            let operator_token = self.previous().clone();
+            let right = Box::new(operand(self)?);
+            let binop = Parser::op_token_to_binop(&operator_token)?;
+            expr = expr::Expr::Binary(Box::new(expr), binop, right);
+        }
+        Ok(expr)
+    }
+
     fn addition(&mut self) -> Result<expr::Expr, Error> {
         let mut expr = self.multiplication()?;
 
@@ -666,7 +1191,11 @@ impl Parser {
     fn multiplication(&mut self) -> Result<expr::Expr, Error> {
         let mut expr = self.unary()?;
 
-        while self.match_one_of(vec![scanner::TokenType::Slash, scanner::TokenType::Star]) {
+        while self.match_one_of(vec![
+            scanner::TokenType::Slash,
+            scanner::TokenType::Star,
+            scanner::TokenType::Percent,
+        ]) {
             let operator_token = self.previous().clone();
             let right = Box::new(self.unary()?);
             let binop_maybe = Parser::op_token_to_binop(&operator_token);
@@ -683,7 +1212,11 @@ impl Parser {
     }
 
     fn unary(&mut self) -> Result<expr::Expr, Error> {
-        if self.match_one_of(vec![scanner::TokenType::Bang, scanner::TokenType::Minus]) {
+        if self.match_one_of(vec![
+            scanner::TokenType::Bang,
+            scanner::TokenType::Minus,
+            scanner::TokenType::Tilde,
+        ]) {
             let operator_token = self.previous().clone();
             let right = Box::new(self.unary()?);
             let unary_op_maybe = Parser::op_token_to_unary_op(&operator_token);
@@ -693,7 +1226,52 @@ impl Parser {
                 Err(err) => Err(err),
             };
         }
-        self.call()
+        if self.match_one_of(vec![
+            scanner::TokenType::PlusPlus,
+            scanner::TokenType::MinusMinus,
+        ]) {
+            let operator_token = self.previous().clone();
+            let target = Parser::target(self.unary()?, &operator_token)?;
+            return Ok(expr::Expr::Increment {
+                target: Box::new(target),
+                op: Parser::op_token_to_binop(&operator_token)?,
+                prefix: true,
+            });
+        }
+        self.power()
+    }
+
+    /// `**` binds tighter than a unary operator on its left but not on its right, and groups
+    /// right, so `-2 ** -2 ** 2` is `-(2 ** (-(2 ** 2)))`.
+    fn power(&mut self) -> Result<expr::Expr, Error> {
+        let expr = self.postfix()?;
+
+        if self.matches(scanner::TokenType::StarStar) {
+            let operator_token = self.previous().clone();
+            let right = Box::new(self.unary()?);
+            let binop = Parser::op_token_to_binop(&operator_token)?;
+            return Ok(expr::Expr::Binary(Box::new(expr), binop, right));
+        }
+
+        Ok(expr)
+    }
+
+    fn postfix(&mut self) -> Result<expr::Expr, Error> {
+        let expr = self.call()?;
+
+        if self.match_one_of(vec![
+            scanner::TokenType::PlusPlus,
+            scanner::TokenType::MinusMinus,
+        ]) {
+            let operator_token = self.previous().clone();
+            return Ok(expr::Expr::Increment {
+                target: Box::new(Parser::target(expr, &operator_token)?),
+                op: Parser::op_token_to_binop(&operator_token)?,
+                prefix: false,
+            });
+        }
+
+        Ok(expr)
     }
 
     fn call(&mut self) -> Result<expr::Expr, Error> {
@@ -774,13 +1352,22 @@ impl Parser {
 
     fn primary(&mut self) -> Result<expr::Expr, Error> {
         if self.matches(scanner::TokenType::False) {
-            return Ok(expr::Expr::Literal(expr::Literal::False));
+            return Ok(expr::Expr::Literal(
+                self.previous_location(),
+                expr::Literal::False,
+            ));
         }
         if self.matches(scanner::TokenType::True) {
-            return Ok(expr::Expr::Literal(expr::Literal::True));
+            return Ok(expr::Expr::Literal(
+                self.previous_location(),
+                expr::Literal::True,
+            ));
         }
         if self.matches(scanner::TokenType::Nil) {
-            return Ok(expr::Expr::Literal(expr::Literal::Nil));
+            return Ok(expr::Expr::Literal(
+                self.previous_location(),
+                expr::Literal::Nil,
+            ));
         }
         if self.matches(scanner::TokenType::Super) {
             let super_tok = self.previous().clone();
@@ -804,7 +1391,10 @@ impl Parser {
         if self.matches(scanner::TokenType::Number) {
             match &self.previous().literal {
                 Some(scanner::Literal::Number(n)) => {
-                    return Ok(expr::Expr::Literal(expr::Literal::Number(*n)))
+                    return Ok(expr::Expr::Literal(
+                        self.previous_location(),
+                        expr::Literal::Number(*n),
+                    ))
                 }
                 Some(l) => panic!(
                     "internal error in parser: when parsing number, found literal {:?}",
@@ -816,7 +1406,10 @@ impl Parser {
         if self.matches(scanner::TokenType::String) {
             match &self.previous().literal {
                 Some(scanner::Literal::Str(s)) => {
-                    return Ok(expr::Expr::Literal(expr::Literal::String(s.clone())))
+                    return Ok(expr::Expr::Literal(
+                        self.previous_location(),
+                        expr::Literal::String(s.clone()),
+                    ))
                 }
                 Some(l) => panic!(
                     "internal error in parser: when parsing string, found literal {:?}",
@@ -825,6 +1418,9 @@ impl Parser {
                 None => panic!("internal error in parser: when parsing string, found no literal"),
             }
         }
+        if self.matches(scanner::TokenType::Interpolation) {
+            return self.interpolation();
+        }
         if self.matches(scanner::TokenType::This) {
             let prev = self.previous();
             return Ok(expr::Expr::This(expr::SourceLocation {
@@ -852,15 +1448,14 @@ impl Parser {
         }
         if self.matches(scanner::TokenType::LeftParen) {
             let expr = Box::new(self.expression()?);
-            if let Err(err) = self.consume(
+            self.consume(
                 scanner::TokenType::RightParen,
                 "Expected ')' after expression.",
-            ) {
-                return Err(err);
-            }
+            )?;
             return Ok(expr::Expr::Grouping(expr));
         }
         if self.extensions.lists && self.matches(scanner::TokenType::LeftBracket) {
+            let location = self.previous_location();
             let mut list_elements = Vec::new();
 
             if !self.check(scanner::TokenType::RightBracket) {
@@ -874,11 +1469,36 @@ impl Parser {
 
             self.consume(scanner::TokenType::RightBracket, "Expected ].")?;
 
-            return Ok(expr::Expr::List(list_elements));
+            return Ok(expr::Expr::List(location, list_elements));
+        }
+        if self.extensions.lists && self.matches(scanner::TokenType::LeftBrace) {
+            let location = self.previous_location();
+            let mut entries = Vec::new();
+
+            if !self.check(scanner::TokenType::RightBrace) {
+                loop {
+                    let key = self.expression()?;
+                    self.consume(scanner::TokenType::Colon, "Expected : after map key.")?;
+                    entries.push((key, self.expression()?));
+                    if !self.matches(scanner::TokenType::Comma) {
+                        break;
+                    }
+                }
+            }
+
+            self.consume(scanner::TokenType::RightBrace, "Expected }.")?;
+
+            return Ok(expr::Expr::Map(location, entries));
         }
         if self.extensions.lambdas && self.matches(scanner::TokenType::Lambda) {
-            let (params, body) = self.params_and_body(FunctionKind::Lambda)?;
-            return Ok(expr::Expr::Lambda(expr::LambdaDecl { params, body }));
+            let location = self.previous_location();
+            let (params, return_type, body) = self.params_and_body(FunctionKind::Lambda)?;
+            return Ok(expr::Expr::Lambda(expr::LambdaDecl {
+                location,
+                params,
+                return_type,
+                body,
+            }));
         }
 
         Err(Error::ExpectedExpression {
@@ -888,6 +1508,42 @@ impl Parser {
         })
     }
 
+    /// The rest of a string with holes in it, after its first `Interpolation` token.
+    fn interpolation(&mut self) -> Result<expr::Expr, Error> {
+        let location = self.previous_location();
+        let mut parts = Vec::new();
+        loop {
+            let piece = match &self.previous().literal {
+                Some(scanner::Literal::Str(s)) => s.clone(),
+                _ => panic!("internal error in parser: string piece without a literal"),
+            };
+            parts.push(expr::Expr::Literal(
+                self.previous_location(),
+                expr::Literal::String(piece),
+            ));
+            if self.previous().ty == scanner::TokenType::String {
+                return Ok(expr::Expr::Interpolation(location, parts));
+            }
+
+            parts.push(self.expression()?);
+            if !self.matches(scanner::TokenType::Interpolation) {
+                self.consume(
+                    scanner::TokenType::String,
+                    "Expected } after interpolated expression",
+                )?;
+            }
+        }
+    }
+
+    /// Where the token just consumed starts.
+    fn previous_location(&self) -> expr::SourceLocation {
+        let prev = self.previous();
+        expr::SourceLocation {
+            line: prev.line,
+            col: prev.col,
+        }
+    }
+
     fn consume(
         &mut self,
         tok: scanner::TokenType,
@@ -915,6 +1571,11 @@ impl Parser {
                 line: tok.line,
                 col: tok.col,
             }),
+            scanner::TokenType::Tilde => Ok(expr::UnaryOp {
+                ty: expr::UnaryOpTy::Tilde,
+                line: tok.line,
+                col: tok.col,
+            }),
             _ => Err(Error::InvalidTokenInUnaryOp {
                 token_type: tok.ty,
                 line: tok.line,
@@ -947,63 +1608,42 @@ impl Parser {
     }
 
     fn op_token_to_binop(tok: &scanner::Token) -> Result<expr::BinaryOp, Error> {
-        match tok.ty {
-            scanner::TokenType::EqualEqual => Ok(expr::BinaryOp {
-                ty: expr::BinaryOpTy::EqualEqual,
-                line: tok.line,
-                col: tok.col,
-            }),
-            scanner::TokenType::BangEqual => Ok(expr::BinaryOp {
-                ty: expr::BinaryOpTy::NotEqual,
-                line: tok.line,
-                col: tok.col,
-            }),
-            scanner::TokenType::Less => Ok(expr::BinaryOp {
-                ty: expr::BinaryOpTy::Less,
-                line: tok.line,
-                col: tok.col,
-            }),
-            scanner::TokenType::LessEqual => Ok(expr::BinaryOp {
-                ty: expr::BinaryOpTy::LessEqual,
-                line: tok.line,
-                col: tok.col,
-            }),
-            scanner::TokenType::Greater => Ok(expr::BinaryOp {
-                ty: expr::BinaryOpTy::Greater,
-                line: tok.line,
-                col: tok.col,
-            }),
-            scanner::TokenType::GreaterEqual => Ok(expr::BinaryOp {
-                ty: expr::BinaryOpTy::GreaterEqual,
-                line: tok.line,
-                col: tok.col,
-            }),
-            scanner::TokenType::Plus => Ok(expr::BinaryOp {
-                ty: expr::BinaryOpTy::Plus,
-                line: tok.line,
-                col: tok.col,
-            }),
-            scanner::TokenType::Minus => Ok(expr::BinaryOp {
-                ty: expr::BinaryOpTy::Minus,
-                line: tok.line,
-                col: tok.col,
-            }),
-            scanner::TokenType::Star => Ok(expr::BinaryOp {
-                ty: expr::BinaryOpTy::Star,
-                line: tok.line,
-                col: tok.col,
-            }),
-            scanner::TokenType::Slash => Ok(expr::BinaryOp {
-                ty: expr::BinaryOpTy::Slash,
-                line: tok.line,
-                col: tok.col,
-            }),
-            _ => Err(Error::InvalidTokenInBinaryOp {
-                token_type: tok.ty,
-                line: tok.line,
-                col: tok.col,
-            }),
-        }
+        let ty = match tok.ty {
+            scanner::TokenType::EqualEqual => expr::BinaryOpTy::EqualEqual,
+            scanner::TokenType::BangEqual => expr::BinaryOpTy::NotEqual,
+            scanner::TokenType::Less => expr::BinaryOpTy::Less,
+            scanner::TokenType::LessEqual => expr::BinaryOpTy::LessEqual,
+            scanner::TokenType::Greater => expr::BinaryOpTy::Greater,
+            scanner::TokenType::GreaterEqual => expr::BinaryOpTy::GreaterEqual,
+            // compound assignments and increments stand for the operator they apply
+            scanner::TokenType::Plus
+            | scanner::TokenType::PlusEqual
+            | scanner::TokenType::PlusPlus => expr::BinaryOpTy::Plus,
+            scanner::TokenType::Minus
+            | scanner::TokenType::MinusEqual
+            | scanner::TokenType::MinusMinus => expr::BinaryOpTy::Minus,
+            scanner::TokenType::Star | scanner::TokenType::StarEqual => expr::BinaryOpTy::Star,
+            scanner::TokenType::Slash | scanner::TokenType::SlashEqual => expr::BinaryOpTy::Slash,
+            scanner::TokenType::Percent => expr::BinaryOpTy::Percent,
+            scanner::TokenType::StarStar => expr::BinaryOpTy::StarStar,
+            scanner::TokenType::Ampersand => expr::BinaryOpTy::Ampersand,
+            scanner::TokenType::Pipe => expr::BinaryOpTy::Pipe,
+            scanner::TokenType::Caret => expr::BinaryOpTy::Caret,
+            scanner::TokenType::LessLess => expr::BinaryOpTy::LessLess,
+            scanner::TokenType::GreaterGreater => expr::BinaryOpTy::GreaterGreater,
+            _ => {
+                return Err(Error::InvalidTokenInBinaryOp {
+                    token_type: tok.ty,
+                    line: tok.line,
+                    col: tok.col,
+                })
+            }
+        };
+        Ok(expr::BinaryOp {
+            ty,
+            line: tok.line,
+            col: tok.col,
+        })
     }
 
     fn match_one_of(&mut self, types: Vec<scanner::TokenType>) -> bool {
@@ -1023,6 +1663,19 @@ impl Parser {
         false
     }
 
+    /// Whether the current token is the identifier `word`, which means something only where
+    /// the parser checks for it.
+    fn check_word(&self, word: &[u8]) -> bool {
+        self.check(scanner::TokenType::Identifier) && self.peek().lexeme == word
+    }
+
+    /// Like `check`, for the token after the current one.
+    fn check_next(&self, ty: scanner::TokenType) -> bool {
+        self.tokens
+            .get(self.current + 1)
+            .is_some_and(|tok| tok.ty == ty)
+    }
+
     fn check(&self, ty: scanner::TokenType) -> bool {
         if self.is_at_end() {
             return false;
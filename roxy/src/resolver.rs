@@ -0,0 +1,702 @@
+use crate::expr;
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Maps every resolved variable use to the number of scopes between it and its declaration.
+/// Uses that don't appear here are globals.
+pub type Locals = HashMap<expr::Symbol, usize>;
+
+pub enum Error {
+    ReadLocalInOwnInitializer { name: String, line: usize, col: i64 },
+    AlreadyDeclared { name: String, line: usize, col: i64 },
+    ReturnNotInFun { line: usize, col: i64 },
+    ReturnValueFromInitializer { line: usize, col: i64 },
+    ThisOutsideClass { line: usize, col: i64 },
+    SuperOutsideClass { line: usize, col: i64 },
+    SuperWithoutSuperclass { line: usize, col: i64 },
+    ClassInheritsFromItself { name: String, line: usize, col: i64 },
+    ImportNotAtTopLevel { line: usize, col: i64 },
+    BreakNotInLoop { line: usize, col: i64 },
+    ContinueNotInLoop { line: usize, col: i64 },
+    UndefinedLabel { name: String, line: usize, col: i64 },
+    LabelOutsideFunction { name: String, line: usize, col: i64 },
+    SuperInStaticMethod { line: usize, col: i64 },
+    InitializerNotMethod { line: usize, col: i64 },
+    ReturnValueFromSetter { line: usize, col: i64 },
+    YieldNotInGenerator { line: usize, col: i64 },
+    YieldInExpression { line: usize, col: i64 },
+    ReturnValueFromGenerator { line: usize, col: i64 },
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            Error::ReadLocalInOwnInitializer { name, line, col } => write!(
+                f,
+                "cannot read local variable '{}' in its own initializer at line={},col={}",
+                name, line, col
+            ),
+            Error::AlreadyDeclared { name, line, col } => write!(
+                f,
+                "variable '{}' already declared in this scope at line={},col={}",
+                name, line, col
+            ),
+            Error::ReturnNotInFun { line, col } => write!(
+                f,
+                "return statement not enclosed in a FunDecl at line={},col={}",
+                line, col
+            ),
+            Error::ReturnValueFromInitializer { line, col } => write!(
+                f,
+                "cannot return a value from an initializer at line={},col={}",
+                line, col
+            ),
+            Error::ThisOutsideClass { line, col } => write!(
+                f,
+                "cannot use 'this' outside of a class at line={},col={}",
+                line, col
+            ),
+            Error::SuperOutsideClass { line, col } => write!(
+                f,
+                "cannot use 'super' outside of a class at line={},col={}",
+                line, col
+            ),
+            Error::SuperWithoutSuperclass { line, col } => write!(
+                f,
+                "cannot use 'super' in a class with no superclass at line={},col={}",
+                line, col
+            ),
+            Error::ClassInheritsFromItself { name, line, col } => write!(
+                f,
+                "class '{}' cannot inherit from itself at line={},col={}",
+                name, line, col
+            ),
+            Error::ImportNotAtTopLevel { line, col } => write!(
+                f,
+                "import is only allowed at the top level at line={},col={}",
+                line, col
+            ),
+            Error::BreakNotInLoop { line, col } => write!(
+                f,
+                "break statement not enclosed in a loop at line={},col={}",
+                line, col
+            ),
+            Error::ContinueNotInLoop { line, col } => write!(
+                f,
+                "continue statement not enclosed in a loop at line={},col={}",
+                line, col
+            ),
+            Error::UndefinedLabel { name, line, col } => write!(
+                f,
+                "no enclosing loop labeled '{}' at line={},col={}",
+                name, line, col
+            ),
+            Error::LabelOutsideFunction { name, line, col } => write!(
+                f,
+                "loop labeled '{}' is outside the enclosing function at line={},col={}",
+                name, line, col
+            ),
+            Error::SuperInStaticMethod { line, col } => write!(
+                f,
+                "cannot use 'super' in a static method at line={},col={}",
+                line, col
+            ),
+            Error::InitializerNotMethod { line, col } => write!(
+                f,
+                "init declared as a static method, generator, getter or setter at line={},col={}",
+                line, col
+            ),
+            Error::ReturnValueFromSetter { line, col } => write!(
+                f,
+                "cannot return a value from a setter at line={},col={}",
+                line, col
+            ),
+            Error::YieldNotInGenerator { line, col } => write!(
+                f,
+                "yield not enclosed in a generator at line={},col={}",
+                line, col
+            ),
+            Error::YieldInExpression { line, col } => write!(
+                f,
+                "yield inside a larger expression at line={},col={}",
+                line, col
+            ),
+            Error::ReturnValueFromGenerator { line, col } => write!(
+                f,
+                "cannot return a value from a generator at line={},col={}",
+                line, col
+            ),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initializer,
+    Setter,
+    Lambda,
+    Generator,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+    // the body of a static method, where `this` is the class and there is no `super`
+    Static,
+}
+
+struct Resolver {
+    // each scope maps a name to whether its initializer has finished resolving
+    scopes: Vec<HashMap<String, bool>>,
+    locals: Locals,
+    current_function: FunctionType,
+    current_class: ClassType,
+    // how many loops enclose the code being resolved, and the labels among them; both start
+    // over in a function body, since `break` and `continue` cannot leave a function
+    loops: usize,
+    labels: Vec<String>,
+    // the labels of the loops around the functions being resolved
+    outer_labels: Vec<String>,
+}
+
+pub fn resolve(stmts: &[expr::Stmt]) -> Result<Locals, Error> {
+    let mut r = Resolver {
+        scopes: Vec::new(),
+        locals: HashMap::new(),
+        current_function: FunctionType::None,
+        current_class: ClassType::None,
+        loops: 0,
+        labels: Vec::new(),
+        outer_labels: Vec::new(),
+    };
+
+    r.resolve_stmts(stmts)?;
+
+    Ok(r.locals)
+}
+
+impl Resolver {
+    fn resolve_stmts(&mut self, stmts: &[expr::Stmt]) -> Result<(), Error> {
+        for stmt in stmts {
+            self.resolve_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn resolve_stmt(&mut self, stmt: &expr::Stmt) -> Result<(), Error> {
+        match stmt {
+            expr::Stmt::Expr(e) => self.resolve_yielding(e),
+            expr::Stmt::FunDecl(fundecl) => {
+                self.declare(&fundecl.name)?;
+                self.define(&fundecl.name);
+                let function_type = if fundecl.generator {
+                    FunctionType::Generator
+                } else {
+                    FunctionType::Function
+                };
+                self.resolve_function(&fundecl.params, &fundecl.body, function_type)
+            }
+            expr::Stmt::ClassDecl(classdecl) => self.resolve_class(classdecl),
+            expr::Stmt::If(cond, then_branch, maybe_else_branch) => {
+                self.resolve_expr(cond)?;
+                self.resolve_stmt(then_branch)?;
+                if let Some(else_branch) = maybe_else_branch {
+                    self.resolve_stmt(else_branch)?;
+                }
+                Ok(())
+            }
+            expr::Stmt::Print(_, e) => self.resolve_expr(e),
+            expr::Stmt::VarDecl(sym, _, maybe_initializer) => {
+                self.declare(sym)?;
+                if let Some(initializer) = maybe_initializer {
+                    self.resolve_yielding(initializer)?;
+                }
+                self.define(sym);
+                Ok(())
+            }
+            expr::Stmt::Block(_, stmts) => self.resolve_block(stmts),
+            expr::Stmt::Return(loc, maybe_res) => {
+                if self.current_function == FunctionType::None {
+                    return Err(Error::ReturnNotInFun {
+                        line: loc.line,
+                        col: loc.col,
+                    });
+                }
+                if let Some(res) = maybe_res {
+                    if self.current_function == FunctionType::Initializer {
+                        return Err(Error::ReturnValueFromInitializer {
+                            line: loc.line,
+                            col: loc.col,
+                        });
+                    }
+                    if self.current_function == FunctionType::Setter {
+                        return Err(Error::ReturnValueFromSetter {
+                            line: loc.line,
+                            col: loc.col,
+                        });
+                    }
+                    if self.current_function == FunctionType::Generator {
+                        return Err(Error::ReturnValueFromGenerator {
+                            line: loc.line,
+                            col: loc.col,
+                        });
+                    }
+                    self.resolve_expr(res)?;
+                }
+                Ok(())
+            }
+            expr::Stmt::While(cond, body) => {
+                self.resolve_expr(cond)?;
+                self.resolve_loop_body(body)
+            }
+            expr::Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.begin_scope();
+                let res = self.resolve_for(initializer, condition, increment, body);
+                self.end_scope();
+                res
+            }
+            expr::Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                self.resolve_expr(iterable)?;
+                self.begin_scope();
+                self.define(variable);
+                let res = self.resolve_loop_body(body);
+                self.end_scope();
+                res
+            }
+            expr::Stmt::Labeled(label, body) => {
+                self.labels.push(label.name.clone());
+                let res = self.resolve_stmt(body);
+                self.labels.pop();
+                res
+            }
+            expr::Stmt::Break(loc, label) => {
+                if self.loops == 0 {
+                    return Err(Error::BreakNotInLoop {
+                        line: loc.line,
+                        col: loc.col,
+                    });
+                }
+                self.resolve_label(label)
+            }
+            expr::Stmt::Continue(loc, label) => {
+                if self.loops == 0 {
+                    return Err(Error::ContinueNotInLoop {
+                        line: loc.line,
+                        col: loc.col,
+                    });
+                }
+                self.resolve_label(label)
+            }
+            expr::Stmt::Import(import) => {
+                if !self.scopes.is_empty() {
+                    return Err(Error::ImportNotAtTopLevel {
+                        line: import.location.line,
+                        col: import.location.col,
+                    });
+                }
+                Ok(())
+            }
+            expr::Stmt::Throw(_, e) => self.resolve_expr(e),
+            expr::Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.resolve_block(body)?;
+                if let Some((name, handler)) = catch {
+                    self.begin_scope();
+                    self.define(name);
+                    let res = self.resolve_stmts(handler);
+                    self.end_scope();
+                    res?;
+                }
+                match finally {
+                    Some(finally) => self.resolve_block(finally),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    fn resolve_block(&mut self, stmts: &[expr::Stmt]) -> Result<(), Error> {
+        self.begin_scope();
+        let res = self.resolve_stmts(stmts);
+        self.end_scope();
+        res
+    }
+
+    fn resolve_for(
+        &mut self,
+        initializer: &Option<Box<expr::Stmt>>,
+        condition: &Option<expr::Expr>,
+        increment: &Option<expr::Expr>,
+        body: &expr::Stmt,
+    ) -> Result<(), Error> {
+        if let Some(initializer) = initializer {
+            self.resolve_stmt(initializer)?;
+        }
+        if let Some(condition) = condition {
+            self.resolve_expr(condition)?;
+        }
+        if let Some(increment) = increment {
+            self.resolve_expr(increment)?;
+        }
+        self.resolve_loop_body(body)
+    }
+
+    fn resolve_class(&mut self, classdecl: &expr::ClassDecl) -> Result<(), Error> {
+        let enclosing_class = self.current_class;
+        self.current_class = ClassType::Class;
+
+        self.declare(&classdecl.name)?;
+        self.define(&classdecl.name);
+
+        for mixin in &classdecl.mixins {
+            if mixin.name == classdecl.name.name {
+                return Err(Error::ClassInheritsFromItself {
+                    name: mixin.name.clone(),
+                    line: mixin.line,
+                    col: mixin.col,
+                });
+            }
+            self.resolve_local(mixin);
+        }
+
+        if let Some(superclass) = &classdecl.superclass {
+            if superclass.name == classdecl.name.name {
+                return Err(Error::ClassInheritsFromItself {
+                    name: superclass.name.clone(),
+                    line: superclass.line,
+                    col: superclass.col,
+                });
+            }
+            self.current_class = ClassType::Subclass;
+            self.resolve_local(superclass);
+
+            self.begin_scope();
+            self.scopes
+                .last_mut()
+                .unwrap()
+                .insert("super".to_string(), true);
+        }
+
+        self.begin_scope();
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert("this".to_string(), true);
+
+        let mut res = Ok(());
+        for method in &classdecl.methods {
+            res = self.resolve_method(method);
+            if res.is_err() {
+                break;
+            }
+        }
+
+        self.end_scope();
+        if classdecl.superclass.is_some() {
+            self.end_scope();
+        }
+
+        self.current_class = enclosing_class;
+        res
+    }
+
+    fn resolve_method(&mut self, method: &expr::Method) -> Result<(), Error> {
+        let decl = &method.decl;
+        let function_type = match method.kind {
+            expr::MethodKind::Method if decl.name.name == "init" && !decl.generator => {
+                FunctionType::Initializer
+            }
+            _ if decl.name.name == "init" => {
+                return Err(Error::InitializerNotMethod {
+                    line: decl.name.line,
+                    col: decl.name.col,
+                })
+            }
+            expr::MethodKind::Setter => FunctionType::Setter,
+            _ if decl.generator => FunctionType::Generator,
+            _ => FunctionType::Method,
+        };
+        if method.kind != expr::MethodKind::Static {
+            return self.resolve_function(&decl.params, &decl.body, function_type);
+        }
+        let enclosing_class = std::mem::replace(&mut self.current_class, ClassType::Static);
+        let res = self.resolve_function(&decl.params, &decl.body, function_type);
+        self.current_class = enclosing_class;
+        res
+    }
+
+    fn resolve_function(
+        &mut self,
+        params: &[expr::Param],
+        body: &[expr::Stmt],
+        function_type: FunctionType,
+    ) -> Result<(), Error> {
+        let enclosing_function = self.current_function;
+        self.current_function = function_type;
+        let enclosing_loops = std::mem::take(&mut self.loops);
+        let enclosing_labels = std::mem::take(&mut self.labels);
+        let outer_labels = self.outer_labels.len();
+        self.outer_labels.extend(enclosing_labels.iter().cloned());
+
+        self.begin_scope();
+        let mut res = params.iter().try_for_each(|param| {
+            self.declare(&param.name)?;
+            self.define(&param.name);
+            Ok(())
+        });
+        if res.is_ok() {
+            res = self.resolve_stmts(body);
+        }
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+        self.loops = enclosing_loops;
+        self.labels = enclosing_labels;
+        self.outer_labels.truncate(outer_labels);
+        res
+    }
+
+    fn resolve_loop_body(&mut self, body: &expr::Stmt) -> Result<(), Error> {
+        self.loops += 1;
+        let res = self.resolve_stmt(body);
+        self.loops -= 1;
+        res
+    }
+
+    fn resolve_label(&mut self, label: &Option<expr::Symbol>) -> Result<(), Error> {
+        match label {
+            Some(label) if self.labels.contains(&label.name) => Ok(()),
+            Some(label) if self.outer_labels.contains(&label.name) => {
+                Err(Error::LabelOutsideFunction {
+                    name: label.name.clone(),
+                    line: label.line,
+                    col: label.col,
+                })
+            }
+            Some(label) => Err(Error::UndefinedLabel {
+                name: label.name.clone(),
+                line: label.line,
+                col: label.col,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Resolves an expression statement or a variable's initializer, which, unlike other
+    /// expressions, may be a `yield` or an assignment of one to a variable.
+    fn resolve_yielding(&mut self, e: &expr::Expr) -> Result<(), Error> {
+        let (assigned, yielded) = match e {
+            expr::Expr::Assign(sym, val) => (Some(sym), &**val),
+            _ => (None, e),
+        };
+        let maybe_value = match yielded {
+            expr::Expr::Yield(loc, maybe_value) => {
+                self.resolve_yield(loc)?;
+                maybe_value
+            }
+            _ => return self.resolve_expr(e),
+        };
+        if let Some(value) = maybe_value {
+            self.resolve_expr(value)?;
+        }
+        if let Some(sym) = assigned {
+            self.resolve_local(sym);
+        }
+        Ok(())
+    }
+
+    fn resolve_yield(&mut self, loc: &expr::SourceLocation) -> Result<(), Error> {
+        if self.current_function != FunctionType::Generator {
+            return Err(Error::YieldNotInGenerator {
+                line: loc.line,
+                col: loc.col,
+            });
+        }
+        Ok(())
+    }
+
+    fn resolve_expr(&mut self, e: &expr::Expr) -> Result<(), Error> {
+        match e {
+            expr::Expr::Literal(..) => Ok(()),
+            expr::Expr::This(loc) => {
+                if self.current_class == ClassType::None {
+                    return Err(Error::ThisOutsideClass {
+                        line: loc.line,
+                        col: loc.col,
+                    });
+                }
+                self.resolve_local(&expr::Symbol {
+                    name: "this".to_string(),
+                    line: loc.line,
+                    col: loc.col,
+                });
+                Ok(())
+            }
+            expr::Expr::Unary(_, e) => self.resolve_expr(e),
+            expr::Expr::Binary(lhs, _, rhs) => {
+                self.resolve_expr(lhs)?;
+                self.resolve_expr(rhs)
+            }
+            expr::Expr::Call(callee, _, args) => {
+                self.resolve_expr(callee)?;
+                args.iter().try_for_each(|arg| self.resolve_expr(arg))
+            }
+            expr::Expr::Get(e, _) => self.resolve_expr(e),
+            expr::Expr::Grouping(e) => self.resolve_expr(e),
+            expr::Expr::Variable(sym) => {
+                if let Some(scope) = self.scopes.last() {
+                    if scope.get(&sym.name) == Some(&false) {
+                        return Err(Error::ReadLocalInOwnInitializer {
+                            name: sym.name.clone(),
+                            line: sym.line,
+                            col: sym.col,
+                        });
+                    }
+                }
+                self.resolve_local(sym);
+                Ok(())
+            }
+            expr::Expr::Assign(sym, val) => {
+                self.resolve_expr(val)?;
+                self.resolve_local(sym);
+                Ok(())
+            }
+            expr::Expr::Logical(lhs, _, rhs) => {
+                self.resolve_expr(lhs)?;
+                self.resolve_expr(rhs)
+            }
+            expr::Expr::Set(e, _, val) => {
+                self.resolve_expr(val)?;
+                self.resolve_expr(e)
+            }
+            expr::Expr::Super(loc, _) => {
+                match self.current_class {
+                    ClassType::None => {
+                        return Err(Error::SuperOutsideClass {
+                            line: loc.line,
+                            col: loc.col,
+                        })
+                    }
+                    ClassType::Class => {
+                        return Err(Error::SuperWithoutSuperclass {
+                            line: loc.line,
+                            col: loc.col,
+                        })
+                    }
+                    ClassType::Static => {
+                        return Err(Error::SuperInStaticMethod {
+                            line: loc.line,
+                            col: loc.col,
+                        })
+                    }
+                    ClassType::Subclass => {}
+                }
+                self.resolve_local(&expr::Symbol {
+                    name: "super".to_string(),
+                    line: loc.line,
+                    col: loc.col,
+                });
+                Ok(())
+            }
+            expr::Expr::List(_, elements) => elements.iter().try_for_each(|e| self.resolve_expr(e)),
+            expr::Expr::Map(_, entries) => entries.iter().try_for_each(|(key, value)| {
+                self.resolve_expr(key)?;
+                self.resolve_expr(value)
+            }),
+            expr::Expr::Subscript { value, slice, .. } => {
+                self.resolve_expr(value)?;
+                self.resolve_expr(slice)
+            }
+            expr::Expr::SetItem {
+                lhs, slice, rhs, ..
+            } => {
+                self.resolve_expr(lhs)?;
+                self.resolve_expr(slice)?;
+                self.resolve_expr(rhs)
+            }
+            expr::Expr::Lambda(lambda) => {
+                self.resolve_function(&lambda.params, &lambda.body, FunctionType::Lambda)
+            }
+            expr::Expr::CompoundAssign(target, _, val) => {
+                self.resolve_expr(target)?;
+                self.resolve_expr(val)
+            }
+            expr::Expr::Increment { target, .. } => self.resolve_expr(target),
+            expr::Expr::Ternary(cond, then_branch, else_branch) => {
+                self.resolve_expr(cond)?;
+                self.resolve_expr(then_branch)?;
+                self.resolve_expr(else_branch)
+            }
+            expr::Expr::Interpolation(_, parts) => {
+                parts.iter().try_for_each(|part| self.resolve_expr(part))
+            }
+            // the tree-walker resumes a generator by going back into the statement it stopped
+            // in, which only works if nothing in the statement runs before the `yield`
+            expr::Expr::Yield(loc, _) => {
+                self.resolve_yield(loc)?;
+                Err(Error::YieldInExpression {
+                    line: loc.line,
+                    col: loc.col,
+                })
+            }
+        }
+    }
+
+    fn resolve_local(&mut self, sym: &expr::Symbol) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if scope.contains_key(&sym.name) {
+                self.locals.insert(sym.clone(), depth);
+                return;
+            }
+        }
+    }
+
+    fn declare(&mut self, sym: &expr::Symbol) -> Result<(), Error> {
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.contains_key(&sym.name) {
+                return Err(Error::AlreadyDeclared {
+                    name: sym.name.clone(),
+                    line: sym.line,
+                    col: sym.col,
+                });
+            }
+            scope.insert(sym.name.clone(), false);
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, sym: &expr::Symbol) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(sym.name.clone(), true);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+}
@@ -1,49 +1,624 @@
+use crate::compiler;
+use crate::debugger::Debugger;
+use crate::diagnostic::Diagnostic;
+use crate::dump::{self, Dump, DumpFormat};
+use crate::embed;
+use crate::expr;
+use crate::extensions::Extensions;
+use crate::formatter;
+use crate::heap::{self, GcConfig};
+use crate::interpreter::Interpreter;
+use crate::limits::Limits;
+use crate::lint;
+use crate::lsp;
+use crate::modules::Loader;
+use crate::optimize;
+use crate::parser;
+use crate::resolver;
+use crate::scanner::{self, TokenType};
+use crate::syntax;
+use crate::typecheck;
+use crate::value::Value;
+use crate::vm::Vm;
+
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
 use std::{
-    fs,
-    io::{self, BufRead, BufReader},
-    path::PathBuf,
+    fs, io,
+    path::{Path, PathBuf},
     process::exit,
 };
 
+#[derive(clap::ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Backend {
+    /// walk the syntax tree directly
+    Treewalk,
+    /// compile to bytecode and run it on the stack machine
+    Vm,
+}
+
 pub struct Roxy {
     had_error: bool,
+    had_runtime_error: bool,
+    extensions: Extensions,
+    backend: Backend,
+    interpreter: Interpreter,
+    vm: Vm,
+    loader: Loader,
+    checker: typecheck::Checker,
+    debug: bool,
+    optimize: bool,
 }
 
 impl Roxy {
-    pub fn new() -> Self {
-        Roxy { had_error: false }
-    }
-    pub fn run_file(&mut self, path: &PathBuf) {
-        if !path.exists() {
-            panic!("specified {} not existed", (*path).display())
+    pub fn new(extensions: Extensions, backend: Backend, gc_config: GcConfig) -> Self {
+        Roxy {
+            had_error: false,
+            had_runtime_error: false,
+            extensions,
+            backend,
+            interpreter: Interpreter::new(extensions),
+            vm: Vm::new(gc_config, extensions),
+            loader: Loader::new(extensions),
+            checker: typecheck::Checker::new(),
+            debug: false,
+            optimize: false,
         }
-        let code = fs::read_to_string(path).unwrap();
-        self.run(code);
+    }
+    /// Holds whatever runs from now on to `limits`; at the REPL, each input counts afresh.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interpreter.set_limits(limits);
+        self.vm.set_limits(limits);
+        self.loader.set_limits(limits);
+    }
+
+    /// Tells the tree-walker how much stack the thread running it has; see
+    /// `Interpreter::set_stack_size`.
+    pub fn set_stack_size(&mut self, bytes: usize) {
+        self.interpreter.set_stack_size(bytes);
+    }
+
+    /// Has programs optimized, once they have been checked, before they run.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    pub fn run_file(&mut self, path: &Path) {
+        let code = match read_source(path) {
+            Some(code) => code,
+            None => exit(65),
+        };
+        self.run(path, code);
         if self.had_error {
             exit(65);
         }
+        if self.had_runtime_error {
+            exit(70);
+        }
+    }
+
+    /// Like `run_file`, but under the debugger, which stops before the program's first
+    /// statement. Imported modules run without stopping.
+    pub fn debug_file(&mut self, path: &Path) {
+        if self.backend != Backend::Treewalk {
+            eprintln!("--debug only works with the treewalk backend");
+            exit(64);
+        }
+        self.debug = true;
+        self.run_file(path);
     }
 
     pub fn run_prompt(&mut self) {
-        let mut reader = BufReader::new(io::stdin());
+        let mut editor = match Editor::<()>::new() {
+            Ok(editor) => editor,
+            Err(err) => panic!("failed to start line editor: {}", err),
+        };
+        let history = history_path();
+        if let Some(path) = &history {
+            // a missing history file just means this is the first session
+            let _ = editor.load_history(path);
+        }
+
+        println!(
+            "roxy {}, type :help for commands",
+            env!("CARGO_PKG_VERSION")
+        );
+
+        let mut buffer = String::new();
         loop {
-            println!("> ");
-            let mut line = String::new();
-            reader.read_line(&mut line).expect("failed scan to string");
-            if line.is_empty() {
-                break;
+            let prompt = if buffer.is_empty() { "> " } else { "... " };
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    buffer.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(err) => {
+                    eprintln!("failed to read line: {}", err);
+                    break;
+                }
+            };
+
+            if buffer.is_empty() && line.trim_start().starts_with(':') {
+                editor.add_history_entry(line.trim());
+                if !self.command(line.trim()) {
+                    break;
+                }
+                continue;
+            }
+
+            buffer.push_str(&line);
+            buffer.push('\n');
+
+            // an empty line submits whatever has been typed, complete or not
+            let force = line.trim().is_empty();
+            let stmts = match self.parse_prompt_input(&buffer, force) {
+                PromptInput::Incomplete => continue,
+                PromptInput::Invalid => None,
+                PromptInput::Complete(stmts) => Some(stmts),
+            };
+
+            editor.add_history_entry(buffer.trim_end());
+
+            if let Some(stmts) = stmts {
+                self.interpreter.restart_limits();
+                self.vm.restart_limits();
+                if self.run_imports(None, STDIN_NAME, &buffer, &stmts) {
+                    if let Some(echo) = self.execute(STDIN_NAME, &buffer, stmts, true) {
+                        println!("{}", echo);
+                    }
+                }
             }
-            self.run(line);
+            buffer.clear();
             self.had_error = false;
+            self.had_runtime_error = false;
+        }
+
+        if let Some(path) = &history {
+            if let Err(err) = editor.save_history(path) {
+                eprintln!("failed to save history to {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    /// Handles a `:command` line; returns false once the REPL should exit.
+    fn command(&mut self, line: &str) -> bool {
+        let (command, arg) = match line.split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, arg.trim()),
+            None => (line, ""),
+        };
+
+        match command {
+            ":quit" | ":q" => return false,
+            ":help" => {
+                println!(":tokens <code>  print the tokens the scanner produces for <code>");
+                println!(":ast <code>     print the syntax tree the parser produces for <code>");
+                println!(":quit           leave the REPL");
+            }
+            ":tokens" => match scanner::scan_tokens(arg.to_string()) {
+                Ok(tokens) => tokens.iter().for_each(|token| println!("{:?}", token)),
+                Err(err) => report(STDIN_NAME, arg, &err),
+            },
+            ":ast" => match scanner::scan_tokens(arg.to_string()) {
+                Ok(tokens) => match parser::parse(self.extensions, tokens) {
+                    Ok(stmts) => stmts.iter().for_each(|stmt| println!("{:#?}", stmt)),
+                    Err(errs) => errs.iter().for_each(|err| report(STDIN_NAME, arg, err)),
+                },
+                Err(err) => report(STDIN_NAME, arg, &err),
+            },
+            _ => eprintln!("unknown command {}, type :help for commands", command),
         }
+        true
     }
 
-    fn run(&mut self, code: String) {
-        // let mut reader = BufReader::new(code);
-        // let mut tokens: Vec<Token> = Vec::new();
+    fn parse_prompt_input(&mut self, code: &str, force: bool) -> PromptInput {
+        let tokens = match scanner::scan_tokens(code.to_string()) {
+            Ok(tokens) => tokens,
+            Err(err) if err.what == "Unterminated string" && !force => {
+                return PromptInput::Incomplete
+            }
+            Err(err) => {
+                report(STDIN_NAME, code, &err);
+                return PromptInput::Invalid;
+            }
+        };
+
+        let depth: i64 = tokens
+            .iter()
+            .map(|token| match token.ty {
+                TokenType::LeftBrace | TokenType::LeftParen | TokenType::LeftBracket => 1,
+                TokenType::RightBrace | TokenType::RightParen | TokenType::RightBracket => -1,
+                _ => 0,
+            })
+            .sum();
+        if depth > 0 && !force {
+            return PromptInput::Incomplete;
+        }
+
+        let errs = match parser::parse(self.extensions, tokens) {
+            Ok(stmts) => return PromptInput::Complete(stmts),
+            Err(errs) => errs,
+        };
+
+        if errs.last().is_some_and(ends_at_eof) {
+            // let a bare expression like `1 + 2` through without its semicolon
+            if let Ok(tokens) = scanner::scan_tokens(format!("{};", code)) {
+                if let Ok(stmts) = parser::parse(self.extensions, tokens) {
+                    return PromptInput::Complete(stmts);
+                }
+            }
+            if !force {
+                return PromptInput::Incomplete;
+            }
+        }
+
+        errs.iter().for_each(|err| report(STDIN_NAME, code, err));
+        PromptInput::Invalid
+    }
+
+    fn run(&mut self, path: &Path, code: String) {
+        let name = &path.display().to_string();
+        let tokens = match scanner::scan_tokens(code.clone()) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                report(name, &code, &err);
+                self.had_error = true;
+                return;
+            }
+        };
+
+        let stmts = match parser::parse(self.extensions, tokens) {
+            Ok(stmts) => stmts,
+            Err(errs) => {
+                errs.iter().for_each(|err| report(name, &code, err));
+                self.had_error = true;
+                return;
+            }
+        };
+
+        if self.run_imports(Some(path), name, &code, &stmts) {
+            if self.debug {
+                self.interpreter.attach_debugger(Debugger::new(&code));
+            }
+            self.execute(name, &code, stmts, false);
+        }
+    }
+
+    /// Loads and runs the modules `stmts` imports that haven't run yet; false if any failed.
+    fn run_imports(
+        &mut self,
+        path: Option<&Path>,
+        name: &str,
+        source: &str,
+        stmts: &[expr::Stmt],
+    ) -> bool {
+        let modules = match self.loader.load_imports(path, name, source, stmts) {
+            Ok(modules) => modules,
+            Err(err) => {
+                eprint!("{}", err.render());
+                self.had_error = true;
+                return false;
+            }
+        };
+
+        for module in modules {
+            self.execute(&module.name(), &module.source, module.stmts, false);
+            if self.had_error || self.had_runtime_error {
+                return false;
+            }
+        }
+        true
     }
+
+    /// Resolves and runs `stmts` on the selected backend. With `echo`, a trailing expression
+    /// statement is evaluated as the program's result and rendered for the REPL to print.
+    fn execute(
+        &mut self,
+        name: &str,
+        source: &str,
+        mut stmts: Vec<expr::Stmt>,
+        echo: bool,
+    ) -> Option<String> {
+        let locals = match resolver::resolve(&stmts) {
+            Ok(locals) => locals,
+            Err(err) => {
+                report(name, source, &err);
+                self.had_error = true;
+                return None;
+            }
+        };
+        if let Err(errs) = self.checker.check(&stmts) {
+            errs.iter().for_each(|err| report(name, source, err));
+            self.had_error = true;
+            return None;
+        }
+        if self.optimize {
+            stmts = optimize::optimize(stmts);
+        }
+
+        if echo {
+            embed::return_trailing_expr(&mut stmts);
+        }
+
+        let res = match self.backend {
+            Backend::Treewalk => {
+                self.interpreter.resolve(locals);
+                self.interpreter.interpret(&stmts).map(|val| match val {
+                    Some(Value::Nil) | None => None,
+                    Some(val) => Some(format!("{:?}", val)),
+                })
+            }
+            Backend::Vm => self
+                .vm
+                .interpret(compiler::compile(&stmts))
+                .map(|val| match val {
+                    heap::Value::Nil => None,
+                    val => Some(self.vm.format_value(&val)),
+                }),
+        };
+
+        match res {
+            Ok(echo) => echo,
+            Err(err) => {
+                report(name, source, &err);
+                self.had_runtime_error = true;
+                None
+            }
+        }
+    }
+}
+
+/// The source name diagnostics use for code typed at the REPL.
+const STDIN_NAME: &str = "<stdin>";
+
+/// `roxy fmt`: rewrites each file in the canonical layout or, with `check`, only reports the
+/// ones that aren't. Returns the exit code: 1 if a check failed, 65 if a file didn't parse.
+pub fn format_files(paths: &[PathBuf], check: bool) -> i32 {
+    let mut code = 0;
+    for path in paths {
+        let name = path.display().to_string();
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("cannot read {}: {}", name, err);
+                code = 65;
+                continue;
+            }
+        };
+
+        let formatted = match formatter::format_source(&source) {
+            Ok(formatted) => formatted,
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    eprint!("{}", diagnostic.render(&name, &source));
+                }
+                code = 65;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            let line = source
+                .lines()
+                .zip(formatted.lines())
+                .position(|(before, after)| before != after)
+                .unwrap_or_else(|| source.lines().count().min(formatted.lines().count()));
+            println!("would reformat {} (first difference on line {})", name, line + 1);
+            code = code.max(1);
+        } else if let Err(err) = fs::write(path, formatted) {
+            eprintln!("cannot write {}: {}", name, err);
+            code = 65;
+        }
+    }
+    code
+}
+
+/// `--dump-tokens`/`--dump-ast`/`--dump-syntax`/`--dump-bytecode`: prints what the scanner,
+/// parser or compiler makes of the file at `path`, or of stdin, and returns the exit code.
+/// With `optimize`, the AST or bytecode is the one `-O` would run.
+pub fn dump_file(
+    extensions: Extensions,
+    path: Option<&Path>,
+    what: Dump,
+    format: DumpFormat,
+    optimize: bool,
+) -> i32 {
+    if let (Dump::Tokens, Some(path)) = (what, path) {
+        return dump_tokens(path, format);
+    }
+
+    let (name, source) = match path {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(source) => (path.display().to_string(), source),
+            Err(err) => {
+                eprintln!("cannot read {}: {}", path.display(), err);
+                return 65;
+            }
+        },
+        None => match io::read_to_string(io::stdin()) {
+            Ok(source) => (STDIN_NAME.to_string(), source),
+            Err(err) => {
+                eprintln!("cannot read stdin: {}", err);
+                return 65;
+            }
+        },
+    };
+
+    if what == Dump::Syntax {
+        // the tree is whole even when there are mistakes in it, so it is printed either way
+        let tree = syntax::Tree::parse(&source);
+        print!("{}", dump::syntax(&tree, format));
+        let errors = tree.errors();
+        errors.iter().for_each(|err| report(&name, &source, err));
+        return if errors.is_empty() { 0 } else { 65 };
+    }
+
+    let tokens = match scanner::scan_tokens(source.clone()) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            report(&name, &source, &err);
+            return 65;
+        }
+    };
+    if what == Dump::Tokens {
+        print!("{}", dump::tokens(&tokens, format));
+        return 0;
+    }
+
+    let mut stmts = match parser::parse(extensions, tokens) {
+        Ok(stmts) => stmts,
+        Err(errs) => {
+            errs.iter().for_each(|err| report(&name, &source, err));
+            return 65;
+        }
+    };
+    if what == Dump::Bytecode {
+        // the compiler expects a program the resolver has accepted
+        if let Err(err) = resolver::resolve(&stmts) {
+            report(&name, &source, &err);
+            return 65;
+        }
+    }
+    if optimize {
+        stmts = optimize::optimize(stmts);
+    }
+    if what == Dump::Bytecode {
+        // the disassembly is plain text whatever the format
+        let function = compiler::compile(&stmts);
+        print!("{}", function.chunk.disassemble(&function.name));
+    } else {
+        print!("{}", dump::ast(&stmts, format));
+    }
+    0
+}
+
+/// Reads the source in `path`, reporting why not if it can't be read or isn't UTF-8.
+fn read_source(path: &Path) -> Option<String> {
+    let name = path.display().to_string();
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("cannot read {}: {}", name, err);
+            return None;
+        }
+    };
+    match String::from_utf8(bytes) {
+        Ok(source) => Some(source),
+        Err(err) => {
+            // the scanner knows where the bad byte is, as a line and column
+            let bytes = err.into_bytes();
+            if let Some(Err(err)) = scanner::scan_reader(&bytes[..]).find(Result::is_err) {
+                report(&name, &String::from_utf8_lossy(&bytes), &err);
+            }
+            None
+        }
+    }
+}
+
+/// Scans the file as it is read, printing each token as it comes; a mistake stops the dump
+/// part way through.
+fn dump_tokens(path: &Path, format: DumpFormat) -> i32 {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("cannot read {}: {}", path.display(), err);
+            return 65;
+        }
+    };
+    for piece in dump::stream_tokens(scanner::scan_reader(file), format) {
+        match piece {
+            Ok(piece) => print!("{}", piece),
+            Err(err) => {
+                // only now is the source wanted, to show the mistake in
+                let source = fs::read_to_string(path).unwrap_or_default();
+                report(&path.display().to_string(), &source, &err);
+                return 65;
+            }
+        }
+    }
+    0
+}
+
+/// `roxy lint`: prints the warnings for each file. Returns the exit code: 1 if there were
+/// warnings, 65 if a file didn't compile.
+pub fn lint_files(extensions: Extensions, paths: &[PathBuf]) -> i32 {
+    let mut code = 0;
+    let mut warnings = 0;
+    for path in paths {
+        let name = path.display().to_string();
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("cannot read {}: {}", name, err);
+                code = 65;
+                continue;
+            }
+        };
+
+        match lint::lint(extensions, &source) {
+            Ok(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    eprint!("{}", diagnostic.render(&name, &source));
+                }
+                if !diagnostics.is_empty() {
+                    warnings += diagnostics.len();
+                    code = code.max(1);
+                }
+            }
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    eprint!("{}", diagnostic.render(&name, &source));
+                }
+                code = 65;
+            }
+        }
+    }
+    match warnings {
+        0 => {}
+        1 => eprintln!("1 warning"),
+        n => eprintln!("{} warnings", n),
+    }
+    code
+}
+
+/// `roxy lsp`: serves the Language Server Protocol on stdin/stdout and returns the exit code.
+pub fn serve_lsp(extensions: Extensions) -> i32 {
+    let stdin = io::stdin();
+    match lsp::serve(extensions, stdin.lock(), io::stdout()) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("lsp: {}", err);
+            1
+        }
+    }
+}
+
+enum PromptInput {
+    Complete(Vec<expr::Stmt>),
+    Incomplete,
+    Invalid,
+}
+
+fn ends_at_eof(err: &parser::Error) -> bool {
+    match err {
+        parser::Error::TokenMismatch { found, .. } => found.ty == TokenType::Eof,
+        parser::Error::ExpectedExpression { token_type, .. } => *token_type == TokenType::Eof,
+        parser::Error::UnexpectedToken(tok) => tok.ty == TokenType::Eof,
+        _ => false,
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".roxy_history"))
 }
 
-fn report(line_cnt: usize, location: &str, message: &str) {
-    eprintln!("[line {} ] Error {}: {} ", line_cnt, location, message)
+fn report<'a, E>(name: &str, source: &str, err: &'a E)
+where
+    Diagnostic: From<&'a E>,
+{
+    eprint!("{}", Diagnostic::from(err).render(name, source))
 }
@@ -1,5 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
+use std::io::{self, BufReader, Read};
 
 #[derive(Eq, PartialEq, Debug, Copy, Clone)]
 pub enum TokenType {
@@ -15,8 +16,15 @@ pub enum TokenType {
     Minus,
     Plus,
     Semicolon,
+    Colon,
     Slash,
     Star,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    Question,
 
     // One or two character tokens.
     Bang,
@@ -25,12 +33,24 @@ pub enum TokenType {
     EqualEqual,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
+    PlusEqual,
+    PlusPlus,
+    MinusEqual,
+    MinusMinus,
+    StarEqual,
+    StarStar,
+    SlashEqual,
 
     // Literals.
     Identifier,
     String,
+    /// the text of a string up to a `${`; the string goes on after the matching `}`, either
+    /// with another `Interpolation` or with the `String` that ends it
+    Interpolation,
     Number,
 
     // Keywords.
@@ -51,6 +71,15 @@ pub enum TokenType {
     Var,
     While,
     Lambda,
+    Import,
+    Try,
+    Catch,
+    Finally,
+    Throw,
+    In,
+    Break,
+    Continue,
+    Yield,
 
     Eof,
 }
@@ -62,6 +91,8 @@ pub enum Literal {
     Number(f64),
 }
 
+/// A token, located by the 1-based line and 0-based column it starts at. Columns count
+/// characters; `utf16_col` converts one for editors that count UTF-16 code units.
 #[derive(Clone)]
 pub struct Token {
     pub ty: TokenType,
@@ -71,6 +102,13 @@ pub struct Token {
     pub col: i64,
 }
 
+impl Token {
+    /// How many columns the token spans.
+    pub fn width(&self) -> usize {
+        String::from_utf8_lossy(&self.lexeme).chars().count()
+    }
+}
+
 impl fmt::Debug for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -85,17 +123,126 @@ impl fmt::Debug for Token {
     }
 }
 
+/// A `//` comment, which the parser never sees; `text` includes the slashes.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub text: String,
+    pub line: usize,
+    pub col: i64,
+}
+
+/// What sits between tokens: whitespace, a comment, or text the scanner couldn't make a token
+/// of. Only `scan_lossless` keeps it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TriviaKind {
+    Whitespace,
+    /// a `//` comment, without the newline ending it
+    Comment,
+    /// text that didn't scan, with the error it caused; empty for a string left open at the
+    /// end of the input
+    Skipped(String),
+}
+
+/// Scans all of `input`, keeping what `scan_tokens` throws away, so that the text of the
+/// tokens and their trivia put back together is `input` again. It never fails: text that
+/// doesn't scan becomes `Skipped` trivia and scanning carries on after it. Each token comes
+/// with the trivia in front of it; whatever follows the last token goes with the `Eof`.
+pub fn scan_lossless(input: String) -> Vec<(Vec<Trivia>, Token)> {
+    let mut scanner = Scanner::new(input.as_bytes());
+    scanner.lossless = true;
+
+    scanner.scan_tokens();
+
+    scanner.leading.into_iter().zip(scanner.tokens).collect()
+}
+
+/// Like `scan_tokens`, but also hands back the comments, for tools that rewrite source.
+pub fn scan_tokens_with_comments(input: String) -> Result<(Vec<Token>, Vec<Comment>), Error> {
+    let mut scanner = Scanner::new(input.as_bytes());
+
+    scanner.scan_tokens();
+
+    match scanner.err {
+        Some(err) => Err(err),
+        None => Ok((scanner.tokens.into(), scanner.comments)),
+    }
+}
+
 pub fn scan_tokens(input: String) -> Result<Vec<Token>, Error> {
-    let mut scanner: Scanner = Default::default();
+    let mut scanner = Scanner::new(input.as_bytes());
 
-    scanner.scan_tokens(input);
+    scanner.scan_tokens();
 
     match scanner.err {
         Some(err) => Err(err),
-        None => Ok(scanner.tokens),
+        None => Ok(scanner.tokens.into()),
+    }
+}
+
+/// Scans UTF-8 source as it is read from `input`, handing out each token once it is
+/// complete, so that the whole of the source is never held at once. Reading stops at the
+/// first error, which is the last item; text that isn't UTF-8 and a failed read are errors
+/// too.
+pub fn scan_reader<R: Read>(input: R) -> Tokens<R> {
+    Tokens {
+        scanner: Scanner::new(input),
+    }
+}
+
+/// The tokens of `scan_reader`.
+pub struct Tokens<R> {
+    scanner: Scanner<R>,
+}
+
+impl<R: Read> Iterator for Tokens<R> {
+    type Item = Result<Token, Error>;
+
+    fn next(&mut self) -> Option<Result<Token, Error>> {
+        loop {
+            if let Some(token) = self.scanner.tokens.pop_front() {
+                return Some(Ok(token));
+            }
+            if self.scanner.finished {
+                return self.scanner.err.take().map(Err);
+            }
+            self.scanner.scan_next();
+            // nothing hands the comments out, so they aren't left to pile up
+            self.scanner.comments.clear();
+        }
     }
 }
 
+/// The column in UTF-16 code units, as the Language Server Protocol counts them, of the
+/// character column `col` in `line`.
+pub fn utf16_col(line: &str, col: i64) -> i64 {
+    line.chars()
+        .take(col.max(0) as usize)
+        .map(|c| c.len_utf16() as i64)
+        .sum::<i64>()
+        + (col.max(0) - line.chars().count() as i64).max(0)
+}
+
+/// The character column of a column counted in UTF-16 code units in `line`. One in the
+/// middle of a character counts as that character.
+pub fn col_from_utf16(line: &str, utf16_col: i64) -> i64 {
+    let mut units = 0;
+    let mut col = 0;
+    for c in line.chars() {
+        units += c.len_utf16() as i64;
+        if units > utf16_col {
+            return col;
+        }
+        col += 1;
+    }
+    col + (utf16_col - units).max(0)
+}
+
 #[derive(Debug)]
 pub struct Error {
     pub what: String,
@@ -103,79 +250,200 @@ pub struct Error {
     pub col: i64,
 }
 
-struct Scanner {
-    source: Vec<u8>,
-    tokens: Vec<Token>,
+/// Every reserved word and the token it scans to.
+pub const KEYWORDS: [(&str, TokenType); 26] = [
+    ("and", TokenType::And),
+    ("class", TokenType::Class),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("for", TokenType::For),
+    ("fun", TokenType::Fun),
+    ("if", TokenType::If),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("super", TokenType::Super),
+    ("this", TokenType::This),
+    ("true", TokenType::True),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+    ("lambda", TokenType::Lambda),
+    ("import", TokenType::Import),
+    ("try", TokenType::Try),
+    ("catch", TokenType::Catch),
+    ("finally", TokenType::Finally),
+    ("throw", TokenType::Throw),
+    ("in", TokenType::In),
+    ("break", TokenType::Break),
+    ("continue", TokenType::Continue),
+    ("yield", TokenType::Yield),
+];
+
+struct Scanner<R> {
+    input: Chars<R>,
+    // the text of the token being scanned so far
+    lexeme: Vec<u8>,
+    // scanned but not yet handed out
+    tokens: VecDeque<Token>,
+    comments: Vec<Comment>,
     err: Option<Error>,
-    start: usize,
-    current: usize,
+    // set once the `Eof` is added, or scanning stopped at an error
+    finished: bool,
     line: usize,
     col: i64,
+    // the character consumed last
+    previous: char,
+    // where the token being scanned began
+    start_line: usize,
+    start_col: i64,
+    // for each `${` not yet closed, how many `{` inside it are still open
+    interpolations: Vec<usize>,
     keywords: HashMap<String, TokenType>,
+    // set by `scan_lossless`: keep trivia and carry on after errors
+    lossless: bool,
+    // the trivia since the last token, and the trivia in front of each token
+    trivia: Vec<Trivia>,
+    leading: Vec<Vec<Trivia>>,
 }
 
-impl Default for Scanner {
-    fn default() -> Scanner {
+impl<R: Read> Scanner<R> {
+    fn new(input: R) -> Scanner<R> {
         Scanner {
-            source: Vec::new(),
-            tokens: Vec::new(),
+            input: Chars {
+                bytes: BufReader::new(input).bytes(),
+                ahead: VecDeque::new(),
+                err: None,
+            },
+            lexeme: Vec::new(),
+            tokens: VecDeque::new(),
+            comments: Vec::new(),
             err: None,
-            start: 0,
-            current: 0,
+            finished: false,
             line: 1,
             col: -1,
-            keywords: vec![
-                ("and", TokenType::And),
-                ("class", TokenType::Class),
-                ("else", TokenType::Else),
-                ("false", TokenType::False),
-                ("for", TokenType::For),
-                ("fun", TokenType::Fun),
-                ("if", TokenType::If),
-                ("nil", TokenType::Nil),
-                ("or", TokenType::Or),
-                ("print", TokenType::Print),
-                ("return", TokenType::Return),
-                ("super", TokenType::Super),
-                ("this", TokenType::This),
-                ("true", TokenType::True),
-                ("var", TokenType::Var),
-                ("while", TokenType::While),
-                ("lambda", TokenType::Lambda),
-            ]
-                .into_iter()
-                .map(|(k, v)| (String::from(k), v))
+            previous: '\0',
+            start_line: 1,
+            start_col: 0,
+            interpolations: Vec::new(),
+            keywords: KEYWORDS
+                .iter()
+                .map(|(k, v)| (String::from(*k), *v))
                 .collect(),
+            lossless: false,
+            trivia: Vec::new(),
+            leading: Vec::new(),
+        }
+    }
+
+    fn scan_tokens(&mut self) {
+        while !self.finished {
+            self.scan_next();
+        }
+    }
+
+    /// Scans the next token, or whatever is between it and the last; at the end of the input,
+    /// or after an error that stops scanning, adds the `Eof` if there is one to add.
+    fn scan_next(&mut self) {
+        if self.done() {
+            return self.finish();
+        }
+
+        self.lexeme.clear();
+        self.start_line = self.line;
+        self.start_col = self.col + 1;
+        self.scan_token();
+        self.read_error();
+        if self.lossless {
+            if let Some(err) = self.err.take() {
+                self.skip(err);
+            }
         }
     }
-}
 
-impl Scanner {
-    fn scan_tokens(&mut self, input: String) {
-        self.source = input.into_bytes();
+    fn finish(&mut self) {
+        self.finished = true;
+        self.read_error();
 
-        while !self.done() {
-            self.start = self.current;
-            self.scan_token();
+        if self.err.is_none() && !self.interpolations.is_empty() {
+            self.err = Some(Error {
+                what: "Unterminated string".to_string(),
+                line: self.line,
+                col: self.col,
+            });
+            if self.lossless {
+                self.lexeme.clear();
+                let err = self.err.take().unwrap();
+                self.skip(err);
+            }
         }
 
         match self.err {
             Some(_) => {}
-            None => self.tokens.push(Token {
-                ty: TokenType::Eof,
-                lexeme: Vec::new(),
-                literal: None,
+            None => {
+                self.tokens.push_back(Token {
+                    ty: TokenType::Eof,
+                    lexeme: Vec::new(),
+                    literal: None,
+                    line: self.line,
+                    col: self.col + 1,
+                });
+                if self.lossless {
+                    self.leading.push(std::mem::take(&mut self.trivia));
+                }
+            }
+        }
+    }
+
+    /// Input that couldn't be read ends it early, which may have looked like another mistake,
+    /// such as an unterminated string; the read error is the one reported.
+    fn read_error(&mut self) {
+        if let Some(what) = self.input.err.take() {
+            self.err = Some(Error {
+                what,
                 line: self.line,
-                col: self.col,
+                col: self.col + 1,
+            });
+        }
+    }
+
+    /// Keeps the text scanned since the token started as `Skipped` trivia.
+    fn skip(&mut self, err: Error) {
+        self.trivia.push(Trivia {
+            kind: TriviaKind::Skipped(err.what),
+            text: self.lexeme.clone(),
+        });
+    }
+
+    /// Adds the text scanned since the token started to the trivia, if it is being kept.
+    fn add_trivia(&mut self, kind: TriviaKind) {
+        if !self.lossless {
+            return;
+        }
+        let text = &self.lexeme;
+        match self.trivia.last_mut() {
+            Some(last) if kind == TriviaKind::Whitespace && last.kind == kind => {
+                last.text.extend_from_slice(text)
+            }
+            _ => self.trivia.push(Trivia {
+                kind,
+                text: text.to_vec(),
             }),
         }
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
         self.col += 1;
+        let c = match self.input.next() {
+            Some(c) => c,
+            None => return '\0',
+        };
+        let mut buf = [0; 4];
+        self.lexeme
+            .extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+        self.previous = c;
 
-        char::from(self.source[self.current - 1])
+        c
     }
 
     fn scan_token(&mut self) {
@@ -184,16 +452,65 @@ impl Scanner {
         match c {
             '(' => self.add_token(TokenType::LeftParen),
             ')' => self.add_token(TokenType::RightParen),
-            '{' => self.add_token(TokenType::LeftBrace),
-            '}' => self.add_token(TokenType::RightBrace),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token(TokenType::LeftBrace)
+            }
+            '}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string()
+                }
+                Some(depth) => {
+                    *depth -= 1;
+                    self.add_token(TokenType::RightBrace)
+                }
+                None => self.add_token(TokenType::RightBrace),
+            },
             '[' => self.add_token(TokenType::LeftBracket),
             ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
             '.' => self.add_token(TokenType::Dot),
-            '-' => self.add_token(TokenType::Minus),
-            '+' => self.add_token(TokenType::Plus),
+            '-' => {
+                let token_type = if self.matches('-') {
+                    TokenType::MinusMinus
+                } else if self.matches('=') {
+                    TokenType::MinusEqual
+                } else {
+                    TokenType::Minus
+                };
+                self.add_token(token_type)
+            }
+            '+' => {
+                let token_type = if self.matches('+') {
+                    TokenType::PlusPlus
+                } else if self.matches('=') {
+                    TokenType::PlusEqual
+                } else {
+                    TokenType::Plus
+                };
+                self.add_token(token_type)
+            }
             ';' => self.add_token(TokenType::Semicolon),
-            '*' => self.add_token(TokenType::Star),
+            ':' => self.add_token(TokenType::Colon),
+            '*' => {
+                let token_type = if self.matches('*') {
+                    TokenType::StarStar
+                } else if self.matches('=') {
+                    TokenType::StarEqual
+                } else {
+                    TokenType::Star
+                };
+                self.add_token(token_type)
+            }
+            '%' => self.add_token(TokenType::Percent),
+            '&' => self.add_token(TokenType::Ampersand),
+            '|' => self.add_token(TokenType::Pipe),
+            '^' => self.add_token(TokenType::Caret),
+            '~' => self.add_token(TokenType::Tilde),
+            '?' => self.add_token(TokenType::Question),
             '!' => {
                 let matches_eq = self.matches('=');
                 self.add_token(if matches_eq {
@@ -211,40 +528,53 @@ impl Scanner {
                 })
             }
             '<' => {
-                let matches_eq = self.matches('=');
-                self.add_token(if matches_eq {
+                let token_type = if self.matches('=') {
                     TokenType::LessEqual
+                } else if self.matches('<') {
+                    TokenType::LessLess
                 } else {
                     TokenType::Less
-                })
+                };
+                self.add_token(token_type)
             }
             '>' => {
-                let matches_eq = self.matches('=');
-                self.add_token(if matches_eq {
+                let token_type = if self.matches('=') {
                     TokenType::GreaterEqual
+                } else if self.matches('>') {
+                    TokenType::GreaterGreater
                 } else {
                     TokenType::Greater
-                })
+                };
+                self.add_token(token_type)
             }
             '/' => {
                 if self.matches('/') {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    self.comments.push(Comment {
+                        text: String::from_utf8_lossy(&self.lexeme).trim_end().to_string(),
+                        line: self.start_line,
+                        col: self.start_col,
+                    });
+                    self.add_trivia(TriviaKind::Comment);
+                } else if self.matches('=') {
+                    self.add_token(TokenType::SlashEqual)
                 } else {
                     self.add_token(TokenType::Slash)
                 }
             }
-            ' ' | '\r' | '\t' => {}
+            ' ' | '\r' | '\t' => self.add_trivia(TriviaKind::Whitespace),
             '\n' => {
                 self.line += 1;
-                self.col = 0
+                self.col = -1;
+                self.add_trivia(TriviaKind::Whitespace)
             }
             '"' => self.string(),
             _ => {
-                if Scanner::is_decimal_digit(c) {
+                if Scanner::<R>::is_decimal_digit(c) {
                     self.number()
-                } else if Scanner::is_alpha(c) {
+                } else if Scanner::<R>::is_alpha(c) {
                     self.identifier()
                 } else {
                     self.err = Some(Error {
@@ -257,6 +587,8 @@ impl Scanner {
         }
     }
 
+    /// Identifiers start with a letter, from any script, and go on with letters, digits and
+    /// the marks that combine with them.
     fn is_alpha(c: char) -> bool {
         c.is_alphabetic()
     }
@@ -266,16 +598,15 @@ impl Scanner {
     }
 
     fn is_alphanumeric(c: char) -> bool {
-        Scanner::is_alpha(c) || Scanner::is_decimal_digit(c)
+        c.is_alphanumeric() || is_combining_mark(c)
     }
 
     fn identifier(&mut self) {
-        while Scanner::is_alphanumeric(self.peek()) {
+        while Scanner::<R>::is_alphanumeric(self.peek()) {
             self.advance();
         }
 
-        let literal_val =
-            String::from_utf8(self.source[self.start..self.current].to_vec()).unwrap();
+        let literal_val = String::from_utf8(self.lexeme.clone()).unwrap();
 
         let token_type = match self.keywords.get(&literal_val) {
             Some(kw_token_type) => *kw_token_type,
@@ -291,82 +622,145 @@ impl Scanner {
         }
     }
 
+    /// Decimal numbers, or integers in hex after `0x` or binary after `0b`. Underscores may
+    /// separate digits.
     fn number(&mut self) {
-        while Scanner::is_decimal_digit(self.peek()) {
-            self.advance();
-        }
-
-        if self.peek() == '.' && Scanner::is_decimal_digit(self.peek_next()) {
+        let radix = match (self.previous(), self.peek()) {
+            ('0', 'x' | 'X') => 16,
+            ('0', 'b' | 'B') => 2,
+            _ => 10,
+        };
+        if radix != 10 {
             self.advance();
+            if !self.peek().is_digit(radix) {
+                self.error_here(format!(
+                    "expected digits after '{}'",
+                    String::from_utf8_lossy(&self.lexeme)
+                ));
+                return;
+            }
         }
 
-        while Scanner::is_decimal_digit(self.peek()) {
+        self.digits(radix);
+        if radix == 10 && self.peek() == '.' && Scanner::<R>::is_decimal_digit(self.peek_next()) {
             self.advance();
+            self.digits(radix);
         }
 
-        let val: f64 = String::from_utf8(self.source[self.start..self.current].to_vec())
-            .unwrap()
-            .parse()
-            .unwrap();
+        let text: String = String::from_utf8_lossy(&self.lexeme)
+            .chars()
+            .filter(|c| *c != '_')
+            .collect();
+        let val = match radix {
+            10 => text.parse().unwrap(),
+            _ => match u64::from_str_radix(&text[2..], radix) {
+                Ok(n) => n as f64,
+                Err(_) => {
+                    self.error_here(format!("number literal {} is too large", text));
+                    return;
+                }
+            },
+        };
 
         self.add_token_literal(TokenType::Number, Some(Literal::Number(val)))
     }
 
-    fn string(&mut self) {
-        while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1
+    fn digits(&mut self, radix: u32) {
+        loop {
+            if self.peek().is_digit(radix) {
+                self.advance();
+            } else if self.peek() == '_' && self.peek_next().is_digit(radix) {
+                // an underscore has to sit between two digits
+                self.advance();
+            } else {
+                break;
             }
-            self.advance();
         }
+    }
 
-        if self.is_at_end() {
-            self.err = Some(Error {
-                what: "Unterminated string".to_string(),
-                line: self.line,
-                col: self.col,
-            })
+    /// The rest of a string literal, after its opening quote or after the `}` that closes an
+    /// interpolation. Escapes are replaced in the token's literal; its lexeme keeps the source.
+    fn string(&mut self) {
+        let mut value = String::new();
+        loop {
+            if self.is_at_end() {
+                self.err = Some(Error {
+                    what: "Unterminated string".to_string(),
+                    line: self.start_line,
+                    col: self.start_col,
+                });
+                return;
+            }
+
+            match self.advance() {
+                '"' => break,
+                '$' if self.peek() == '{' => {
+                    self.advance();
+                    self.interpolations.push(0);
+                    self.add_token_literal(TokenType::Interpolation, Some(Literal::Str(value)));
+                    return;
+                }
+                '\\' => {
+                    let escaped = match self.peek() {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        '0' => '\0',
+                        '"' => '"',
+                        '\\' => '\\',
+                        '$' => '$',
+                        c => {
+                            self.advance();
+                            self.error_here(format!("unknown escape sequence '\\{}'", c));
+                            return;
+                        }
+                    };
+                    self.advance();
+                    value.push(escaped);
+                }
+                '\n' => {
+                    self.line += 1;
+                    self.col = -1;
+                    value.push('\n');
+                }
+                c => value.push(c),
+            }
         }
 
-        assert_eq!(self.peek(), '"');
+        self.add_token_literal(TokenType::String, Some(Literal::Str(value)))
+    }
 
-        self.advance();
+    /// Reports a mistake at the character just consumed.
+    fn error_here(&mut self, what: String) {
+        self.err = Some(Error {
+            what,
+            line: self.line,
+            col: self.col,
+        });
+    }
 
-        self.add_token_literal(
-            TokenType::String,
-            Some(Literal::Str(
-                String::from_utf8(self.source[self.start + 1..self.current - 1].to_vec()).unwrap(),
-            )),
-        )
+    fn peek_next(&mut self) -> char {
+        self.input.peek(1).unwrap_or('\0')
     }
 
-    fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            '\0'
-        } else {
-            char::from(self.source[self.current + 1])
-        }
+    fn previous(&self) -> char {
+        self.previous
     }
 
-    fn peek(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            char::from(self.source[self.current])
-        }
+    fn peek(&mut self) -> char {
+        self.input.peek(0).unwrap_or('\0')
     }
 
     fn matches(&mut self, c: char) -> bool {
         if self.is_at_end() {
-            return true;
+            return false;
         }
 
-        if char::from(self.source[self.current]) != c {
+        if self.peek() != c {
             return false;
         }
 
-        self.current += 1;
-        self.col += 1;
+        self.advance();
         true
     }
 
@@ -375,22 +769,103 @@ impl Scanner {
     }
 
     fn add_token_literal(&mut self, token_type: TokenType, literal: Option<Literal>) {
-        let text = self.source[self.start..self.current].to_vec();
+        let text = self.lexeme.clone();
 
-        self.tokens.push(Token {
+        self.tokens.push_back(Token {
             ty: token_type,
             lexeme: text,
             literal,
-            line: self.line,
-            col: self.col,
-        })
+            line: self.start_line,
+            col: self.start_col,
+        });
+        if self.lossless {
+            self.leading.push(std::mem::take(&mut self.trivia));
+        }
     }
 
-    fn done(&self) -> bool {
+    fn done(&mut self) -> bool {
         self.err.is_some() || self.is_at_end()
     }
 
-    fn is_at_end(&self) -> bool {
-        self.current >= self.source.len()
+    fn is_at_end(&mut self) -> bool {
+        self.input.peek(0).is_none()
+    }
+}
+
+/// The characters of the UTF-8 read from `bytes`, decoded only as far as the scanner looks
+/// ahead. Text that isn't UTF-8, or a failed read, ends them, leaving the reason in `err`.
+struct Chars<R> {
+    bytes: io::Bytes<BufReader<R>>,
+    ahead: VecDeque<char>,
+    err: Option<String>,
+}
+
+impl<R: Read> Chars<R> {
+    /// The character `n` after the next one.
+    fn peek(&mut self, n: usize) -> Option<char> {
+        while self.ahead.len() <= n {
+            let c = self.decode()?;
+            self.ahead.push_back(c);
+        }
+        self.ahead.get(n).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        self.peek(0)?;
+        self.ahead.pop_front()
+    }
+
+    fn decode(&mut self) -> Option<char> {
+        if self.err.is_some() {
+            return None;
+        }
+        let first = self.byte()?;
+        let len = match first {
+            0x00..=0x7f => return Some(char::from(first)),
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return self.invalid(first),
+        };
+        let mut buf = [first, 0, 0, 0];
+        for byte in &mut buf[1..len] {
+            *byte = match self.byte() {
+                Some(byte) => byte,
+                None if self.err.is_some() => return None,
+                None => return self.invalid(first),
+            };
+        }
+        match std::str::from_utf8(&buf[..len]) {
+            Ok(text) => text.chars().next(),
+            Err(_) => self.invalid(first),
+        }
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        match self.bytes.next()? {
+            Ok(byte) => Some(byte),
+            Err(err) => {
+                self.err = Some(format!("cannot read source: {}", err));
+                None
+            }
+        }
     }
+
+    fn invalid(&mut self, byte: u8) -> Option<char> {
+        self.err = Some(format!("source is not valid UTF-8 at byte 0x{:02x}", byte));
+        None
+    }
+}
+
+/// Whether `c` is a combining mark, which goes on the character before it, as with a
+/// decomposed accent; the standard library has no test for the general category.
+fn is_combining_mark(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036f}'
+            | '\u{1ab0}'..='\u{1aff}'
+            | '\u{1dc0}'..='\u{1dff}'
+            | '\u{20d0}'..='\u{20ff}'
+            | '\u{fe20}'..='\u{fe2f}'
+    )
 }
\ No newline at end of file
@@ -0,0 +1,573 @@
+//! The built-in functions every roxy program starts with, for both backends. Natives report
+//! bad arguments with `RoxyErr::NativeError`, which the caller locates at the call site.
+
+use crate::err::RoxyErr;
+use crate::extensions::Extensions;
+use crate::heap::{self, Object};
+use crate::interpreter::Interpreter;
+use crate::map::{Key, Map};
+use crate::value::{self, Value};
+use crate::vm::Vm;
+
+use std::cell::RefCell;
+use std::io::{self, BufRead};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn since_epoch() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time went backwards")
+        .as_secs_f64()
+}
+
+/// Reads a line from stdin without its line ending, or `None` at end of input.
+fn read_line() -> Result<Option<String>, RoxyErr> {
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) => Ok(None),
+        Ok(_) => {
+            let trimmed = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(trimmed);
+            Ok(Some(line))
+        }
+        Err(err) => Err(RoxyErr::NativeError(format!("input: {}", err))),
+    }
+}
+
+fn parse_number(s: &str) -> Result<f64, RoxyErr> {
+    s.trim()
+        .parse()
+        .map_err(|_| RoxyErr::NativeError(format!("num: cannot convert \"{}\" to a number", s)))
+}
+
+/// Checks `start` and `end` are whole numbers with `0 <= start <= end <= len`.
+fn slice_bounds(start: f64, end: f64, len: usize) -> Result<(usize, usize), RoxyErr> {
+    let whole = |n: f64| n.fract() == 0.0 && n >= 0.0;
+    if whole(start) && whole(end) && start <= end && end as usize <= len {
+        Ok((start as usize, end as usize))
+    } else {
+        Err(RoxyErr::NativeError(format!(
+            "slice: invalid range {}..{} for length {}",
+            start, end, len
+        )))
+    }
+}
+
+fn bad_argument(name: &str, expected: &str, idx: usize, found: &str) -> RoxyErr {
+    RoxyErr::NativeError(format!(
+        "{} expects {} as argument {}, found {}",
+        name,
+        expected,
+        idx + 1,
+        found
+    ))
+}
+
+fn native(
+    name: &str,
+    arity: u8,
+    callable: fn(&mut Interpreter, &[Value]) -> Result<Value, RoxyErr>,
+) -> value::NativeFunction {
+    value::NativeFunction {
+        name: name.to_string(),
+        arity,
+        callable: Rc::new(callable),
+    }
+}
+
+fn number(name: &str, args: &[Value], idx: usize) -> Result<f64, RoxyErr> {
+    match &args[idx] {
+        Value::Number(n) => Ok(*n),
+        val => Err(bad_argument(
+            name,
+            "a Number",
+            idx,
+            &format!("{:?}", val.type_of()),
+        )),
+    }
+}
+
+fn list(name: &str, args: &[Value], idx: usize) -> Result<Rc<RefCell<Vec<Value>>>, RoxyErr> {
+    match &args[idx] {
+        Value::List(elements) => Ok(elements.clone()),
+        val => Err(bad_argument(
+            name,
+            "a List",
+            idx,
+            &format!("{:?}", val.type_of()),
+        )),
+    }
+}
+
+fn map(name: &str, args: &[Value], idx: usize) -> Result<Rc<RefCell<Map<Value>>>, RoxyErr> {
+    match &args[idx] {
+        Value::Map(map) => Ok(map.clone()),
+        val => Err(bad_argument(
+            name,
+            "a Map",
+            idx,
+            &format!("{:?}", val.type_of()),
+        )),
+    }
+}
+
+fn generator(
+    name: &str,
+    args: &[Value],
+    idx: usize,
+) -> Result<Rc<RefCell<value::RoxyGenerator>>, RoxyErr> {
+    match &args[idx] {
+        Value::Generator(generator) => Ok(generator.clone()),
+        val => Err(bad_argument(
+            name,
+            "a Generator",
+            idx,
+            &format!("{:?}", val.type_of()),
+        )),
+    }
+}
+
+fn key(name: &str, args: &[Value], idx: usize) -> Result<Key, RoxyErr> {
+    args[idx].to_key().ok_or_else(|| {
+        bad_argument(
+            name,
+            "a Nil, Bool, Number or String",
+            idx,
+            &format!("{:?}", args[idx].type_of()),
+        )
+    })
+}
+
+/// The natives that reach outside the program, which are left out when `Limits::io` is off.
+pub const IO_NATIVES: [&str; 1] = ["input"];
+
+/// The natives the tree-walking interpreter defines as globals.
+pub fn natives(extensions: Extensions) -> Vec<value::NativeFunction> {
+    let mut natives = vec![
+        native("clock", 0, |_, _| Ok(Value::Number(since_epoch()))),
+        native("len", 1, |_, args| match &args[0] {
+            Value::String(s) => Ok(Value::Number(s.chars().count() as f64)),
+            Value::List(elements) => Ok(Value::Number(elements.borrow().len() as f64)),
+            Value::Map(map) => Ok(Value::Number(map.borrow().len() as f64)),
+            val => Err(bad_argument(
+                "len",
+                "a String, List or Map",
+                0,
+                &format!("{:?}", val.type_of()),
+            )),
+        }),
+        native("str", 1, |_, args| {
+            Ok(Value::String(format!("{}", args[0])))
+        }),
+        native("num", 1, |_, args| match &args[0] {
+            Value::Number(n) => Ok(Value::Number(*n)),
+            Value::String(s) => parse_number(s).map(Value::Number),
+            val => Err(bad_argument(
+                "num",
+                "a String or Number",
+                0,
+                &format!("{:?}", val.type_of()),
+            )),
+        }),
+        native("input", 0, |_, _| {
+            Ok(read_line()?.map(Value::String).unwrap_or(Value::Nil))
+        }),
+        native("type", 1, |_, args| {
+            Ok(Value::String(format!("{:?}", args[0].type_of())))
+        }),
+        native("slice", 3, |_, args| {
+            let (start, end) = (number("slice", args, 1)?, number("slice", args, 2)?);
+            match &args[0] {
+                Value::String(s) => {
+                    let chars: Vec<char> = s.chars().collect();
+                    let (start, end) = slice_bounds(start, end, chars.len())?;
+                    Ok(Value::String(chars[start..end].iter().collect()))
+                }
+                Value::List(elements) => {
+                    let elements = elements.borrow();
+                    let (start, end) = slice_bounds(start, end, elements.len())?;
+                    Ok(Value::List(Rc::new(RefCell::new(
+                        elements[start..end].to_vec(),
+                    ))))
+                }
+                val => Err(bad_argument(
+                    "slice",
+                    "a String or List",
+                    0,
+                    &format!("{:?}", val.type_of()),
+                )),
+            }
+        }),
+        native("abs", 1, |_, args| {
+            Ok(Value::Number(number("abs", args, 0)?.abs()))
+        }),
+        native("floor", 1, |_, args| {
+            Ok(Value::Number(number("floor", args, 0)?.floor()))
+        }),
+        native("ceil", 1, |_, args| {
+            Ok(Value::Number(number("ceil", args, 0)?.ceil()))
+        }),
+        native("round", 1, |_, args| {
+            Ok(Value::Number(number("round", args, 0)?.round()))
+        }),
+        native("sqrt", 1, |_, args| {
+            Ok(Value::Number(number("sqrt", args, 0)?.sqrt()))
+        }),
+        native("pow", 2, |_, args| {
+            Ok(Value::Number(
+                number("pow", args, 0)?.powf(number("pow", args, 1)?),
+            ))
+        }),
+        native("min", 2, |_, args| {
+            Ok(Value::Number(
+                number("min", args, 0)?.min(number("min", args, 1)?),
+            ))
+        }),
+        native("max", 2, |_, args| {
+            Ok(Value::Number(
+                number("max", args, 0)?.max(number("max", args, 1)?),
+            ))
+        }),
+        native("next", 1, |interpreter, args| {
+            let generator = generator("next", args, 0)?;
+            Ok(interpreter
+                .resume(&generator, Value::Nil)?
+                .unwrap_or(Value::Nil))
+        }),
+        native("send", 2, |interpreter, args| {
+            let generator = generator("send", args, 0)?;
+            Ok(interpreter
+                .resume(&generator, args[1].clone())?
+                .unwrap_or(Value::Nil))
+        }),
+        native("done", 1, |_, args| {
+            let generator = generator("done", args, 0)?;
+            let done = matches!(generator.borrow().state, value::GeneratorState::Done);
+            Ok(Value::Bool(done))
+        }),
+    ];
+
+    if extensions.lists {
+        natives.extend(vec![
+            native("push", 2, |interpreter, args| {
+                let elements = list("push", args, 0)?;
+                interpreter.allocate(std::mem::size_of::<Value>())?;
+                elements.borrow_mut().push(args[1].clone());
+                Ok(Value::Nil)
+            }),
+            native("pop", 1, |_, args| {
+                list("pop", args, 0)?
+                    .borrow_mut()
+                    .pop()
+                    .ok_or_else(|| RoxyErr::NativeError("pop from empty list".to_string()))
+            }),
+            native("map", 2, |interpreter, args| {
+                let elements = list("map", args, 0)?;
+                let mut mapped = Vec::new();
+                // re-borrow on every step, the callback may change the list
+                for idx in 0.. {
+                    let element = match elements.borrow().get(idx) {
+                        Some(element) => element.clone(),
+                        None => break,
+                    };
+                    mapped.push(interpreter.call_function(&args[1], vec![element])?);
+                }
+                Ok(Value::List(Rc::new(RefCell::new(mapped))))
+            }),
+            native("filter", 2, |interpreter, args| {
+                let elements = list("filter", args, 0)?;
+                let mut kept = Vec::new();
+                for idx in 0.. {
+                    let element = match elements.borrow().get(idx) {
+                        Some(element) => element.clone(),
+                        None => break,
+                    };
+                    if interpreter
+                        .call_function(&args[1], vec![element.clone()])?
+                        .is_truthy()
+                    {
+                        kept.push(element);
+                    }
+                }
+                Ok(Value::List(Rc::new(RefCell::new(kept))))
+            }),
+            native("keys", 1, |_, args| {
+                let keys = map("keys", args, 0)?
+                    .borrow()
+                    .entries()
+                    .iter()
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                Ok(Value::List(Rc::new(RefCell::new(keys))))
+            }),
+            native("has", 2, |_, args| {
+                let key = key("has", args, 1)?;
+                Ok(Value::Bool(map("has", args, 0)?.borrow().contains(&key)))
+            }),
+            native("remove", 2, |_, args| {
+                let key = key("remove", args, 1)?;
+                Ok(map("remove", args, 0)?
+                    .borrow_mut()
+                    .remove(&key)
+                    .unwrap_or(Value::Nil))
+            }),
+        ]);
+    }
+
+    natives
+}
+
+fn vm_native(
+    name: &'static str,
+    arity: u8,
+    func: fn(&mut Vm, &[heap::Value]) -> Result<heap::Value, RoxyErr>,
+) -> heap::NativeFunction {
+    heap::NativeFunction { name, arity, func }
+}
+
+fn vm_type_name(val: &heap::Value) -> &'static str {
+    match val {
+        // the tree-walker has no separate type for methods bound to an instance
+        heap::Value::BoundMethod(_) => "Function",
+        val => val.type_name(),
+    }
+}
+
+fn vm_number(name: &str, args: &[heap::Value], idx: usize) -> Result<f64, RoxyErr> {
+    match &args[idx] {
+        heap::Value::Number(n) => Ok(*n),
+        val => Err(bad_argument(name, "a Number", idx, vm_type_name(val))),
+    }
+}
+
+fn vm_list(name: &str, args: &[heap::Value], idx: usize) -> Result<heap::HeapId, RoxyErr> {
+    match &args[idx] {
+        heap::Value::List(id) => Ok(*id),
+        val => Err(bad_argument(name, "a List", idx, vm_type_name(val))),
+    }
+}
+
+fn vm_map(name: &str, args: &[heap::Value], idx: usize) -> Result<heap::HeapId, RoxyErr> {
+    match &args[idx] {
+        heap::Value::Map(id) => Ok(*id),
+        val => Err(bad_argument(name, "a Map", idx, vm_type_name(val))),
+    }
+}
+
+fn vm_generator(name: &str, args: &[heap::Value], idx: usize) -> Result<heap::HeapId, RoxyErr> {
+    match &args[idx] {
+        heap::Value::Generator(id) => Ok(*id),
+        val => Err(bad_argument(name, "a Generator", idx, vm_type_name(val))),
+    }
+}
+
+fn vm_key(vm: &Vm, name: &str, args: &[heap::Value], idx: usize) -> Result<Key, RoxyErr> {
+    vm.heap().to_key(&args[idx]).ok_or_else(|| {
+        bad_argument(
+            name,
+            "a Nil, Bool, Number or String",
+            idx,
+            vm_type_name(&args[idx]),
+        )
+    })
+}
+
+fn vm_string(vm: &mut Vm, s: String) -> heap::Value {
+    vm.heap_mut().alloc_string(s)
+}
+
+/// Applies `callback` to every element of the list in `args[0]`, calling `args[1]` on each.
+/// The list being built is kept on the VM stack so a collection during a call can't free it.
+fn vm_each(
+    vm: &mut Vm,
+    args: &[heap::Value],
+    name: &str,
+    callback: fn(&mut Vm, heap::HeapId, heap::Value, heap::Value),
+) -> Result<heap::Value, RoxyErr> {
+    let elements = vm_list(name, args, 0)?;
+    let result = vm.heap_mut().alloc(Object::List(Vec::new()));
+    vm.push(heap::Value::List(result));
+    for idx in 0.. {
+        // look the element up afresh each time, the callback may change the list
+        let element = match vm.heap().get_list(elements).get(idx) {
+            Some(element) => *element,
+            None => break,
+        };
+        let returned = vm.call_function(args[1], &[element])?;
+        callback(vm, result, element, returned);
+    }
+    vm.pop();
+    Ok(heap::Value::List(result))
+}
+
+/// The natives the VM defines as globals; these behave exactly like `natives`.
+pub fn vm_natives(extensions: Extensions) -> Vec<heap::NativeFunction> {
+    let mut natives = vec![
+        vm_native("clock", 0, |_, _| Ok(heap::Value::Number(since_epoch()))),
+        vm_native("len", 1, |vm, args| match &args[0] {
+            heap::Value::String(id) => Ok(heap::Value::Number(
+                vm.heap().get_str(*id).chars().count() as f64,
+            )),
+            heap::Value::List(id) => Ok(heap::Value::Number(vm.heap().get_list(*id).len() as f64)),
+            heap::Value::Map(id) => Ok(heap::Value::Number(vm.heap().get_map(*id).len() as f64)),
+            val => Err(bad_argument(
+                "len",
+                "a String, List or Map",
+                0,
+                vm_type_name(val),
+            )),
+        }),
+        vm_native("str", 1, |vm, args| {
+            let s = vm.heap().display_value(&args[0]);
+            Ok(vm_string(vm, s))
+        }),
+        vm_native("num", 1, |vm, args| match &args[0] {
+            heap::Value::Number(n) => Ok(heap::Value::Number(*n)),
+            heap::Value::String(id) => {
+                parse_number(vm.heap().get_str(*id)).map(heap::Value::Number)
+            }
+            val => Err(bad_argument(
+                "num",
+                "a String or Number",
+                0,
+                vm_type_name(val),
+            )),
+        }),
+        vm_native("input", 0, |vm, _| {
+            Ok(match read_line()? {
+                Some(line) => vm_string(vm, line),
+                None => heap::Value::Nil,
+            })
+        }),
+        vm_native("type", 1, |vm, args| {
+            Ok(vm_string(vm, vm_type_name(&args[0]).to_string()))
+        }),
+        vm_native("slice", 3, |vm, args| {
+            let (start, end) = (vm_number("slice", args, 1)?, vm_number("slice", args, 2)?);
+            match &args[0] {
+                heap::Value::String(id) => {
+                    let chars: Vec<char> = vm.heap().get_str(*id).chars().collect();
+                    let (start, end) = slice_bounds(start, end, chars.len())?;
+                    Ok(vm_string(vm, chars[start..end].iter().collect()))
+                }
+                heap::Value::List(id) => {
+                    let elements = vm.heap().get_list(*id);
+                    let (start, end) = slice_bounds(start, end, elements.len())?;
+                    let sliced = elements[start..end].to_vec();
+                    Ok(heap::Value::List(vm.heap_mut().alloc(Object::List(sliced))))
+                }
+                val => Err(bad_argument(
+                    "slice",
+                    "a String or List",
+                    0,
+                    vm_type_name(val),
+                )),
+            }
+        }),
+        vm_native("abs", 1, |_, args| {
+            Ok(heap::Value::Number(vm_number("abs", args, 0)?.abs()))
+        }),
+        vm_native("floor", 1, |_, args| {
+            Ok(heap::Value::Number(vm_number("floor", args, 0)?.floor()))
+        }),
+        vm_native("ceil", 1, |_, args| {
+            Ok(heap::Value::Number(vm_number("ceil", args, 0)?.ceil()))
+        }),
+        vm_native("round", 1, |_, args| {
+            Ok(heap::Value::Number(vm_number("round", args, 0)?.round()))
+        }),
+        vm_native("sqrt", 1, |_, args| {
+            Ok(heap::Value::Number(vm_number("sqrt", args, 0)?.sqrt()))
+        }),
+        vm_native("pow", 2, |_, args| {
+            Ok(heap::Value::Number(
+                vm_number("pow", args, 0)?.powf(vm_number("pow", args, 1)?),
+            ))
+        }),
+        vm_native("min", 2, |_, args| {
+            Ok(heap::Value::Number(
+                vm_number("min", args, 0)?.min(vm_number("min", args, 1)?),
+            ))
+        }),
+        vm_native("max", 2, |_, args| {
+            Ok(heap::Value::Number(
+                vm_number("max", args, 0)?.max(vm_number("max", args, 1)?),
+            ))
+        }),
+        vm_native("next", 1, |vm, args| {
+            let generator = vm_generator("next", args, 0)?;
+            Ok(vm
+                .resume(generator, heap::Value::Nil)?
+                .unwrap_or(heap::Value::Nil))
+        }),
+        vm_native("send", 2, |vm, args| {
+            let generator = vm_generator("send", args, 0)?;
+            Ok(vm.resume(generator, args[1])?.unwrap_or(heap::Value::Nil))
+        }),
+        vm_native("done", 1, |vm, args| {
+            let generator = vm_generator("done", args, 0)?;
+            let state = &vm.heap().get_generator(generator).state;
+            Ok(heap::Value::Bool(matches!(
+                state,
+                heap::GeneratorState::Done
+            )))
+        }),
+    ];
+
+    if extensions.lists {
+        natives.extend(vec![
+            vm_native("push", 2, |vm, args| {
+                let elements = vm_list("push", args, 0)?;
+                vm.heap_mut().get_list_mut(elements).push(args[1]);
+                Ok(heap::Value::Nil)
+            }),
+            vm_native("pop", 1, |vm, args| {
+                let elements = vm_list("pop", args, 0)?;
+                vm.heap_mut()
+                    .get_list_mut(elements)
+                    .pop()
+                    .ok_or_else(|| RoxyErr::NativeError("pop from empty list".to_string()))
+            }),
+            vm_native("map", 2, |vm, args| {
+                vm_each(vm, args, "map", |vm, result, _, mapped| {
+                    vm.heap_mut().get_list_mut(result).push(mapped)
+                })
+            }),
+            vm_native("filter", 2, |vm, args| {
+                vm_each(vm, args, "filter", |vm, result, element, keep| {
+                    if keep.is_truthy() {
+                        vm.heap_mut().get_list_mut(result).push(element)
+                    }
+                })
+            }),
+            vm_native("keys", 1, |vm, args| {
+                let map = vm_map("keys", args, 0)?;
+                let keys = vm
+                    .heap()
+                    .get_map(map)
+                    .entries()
+                    .iter()
+                    .map(|(key, _)| *key)
+                    .collect();
+                Ok(heap::Value::List(vm.heap_mut().alloc(Object::List(keys))))
+            }),
+            vm_native("has", 2, |vm, args| {
+                let map = vm_map("has", args, 0)?;
+                let key = vm_key(vm, "has", args, 1)?;
+                Ok(heap::Value::Bool(vm.heap().get_map(map).contains(&key)))
+            }),
+            vm_native("remove", 2, |vm, args| {
+                let map = vm_map("remove", args, 0)?;
+                let key = vm_key(vm, "remove", args, 1)?;
+                Ok(vm
+                    .heap_mut()
+                    .get_map_mut(map)
+                    .remove(&key)
+                    .unwrap_or(heap::Value::Nil))
+            }),
+        ]);
+    }
+
+    natives
+}
@@ -0,0 +1,1152 @@
+//! A lossless syntax tree, for editor tooling. `parser` builds the AST the backends run and
+//! gives up on a statement at its first mistake; this tree keeps every byte of the source and
+//! always gets built. Whitespace and comments hang off the token after them as trivia, a token
+//! or expression the grammar wants but the source lacks is a `Missing` placeholder, and tokens
+//! that fit nowhere are wrapped in an `Error` node, so a half-typed file still has a tree.
+//! `Tree::edit` reparses only the identifier or block an edit falls in, when it can.
+//!
+//! The tree takes lists, maps and lambdas whatever the extensions, and leaves the checks that
+//! aren't about the shape of the source, like what can be assigned to, to `parser`.
+
+use crate::scanner::{self, TokenType, Trivia, TriviaKind};
+
+use std::collections::VecDeque;
+use std::ops::Range;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NodeKind {
+    Program,
+    ClassDecl,
+    /// the braces of a class and the members between them
+    ClassBody,
+    /// a method, static method, getter or setter
+    Member,
+    FunDecl,
+    ParamList,
+    Param,
+    /// `: type`
+    Annotation,
+    VarDecl,
+    ImportDecl,
+    ExprStmt,
+    PrintStmt,
+    ReturnStmt,
+    IfStmt,
+    WhileStmt,
+    ForStmt,
+    ForInStmt,
+    LabeledStmt,
+    BreakStmt,
+    ContinueStmt,
+    ThrowStmt,
+    TryStmt,
+    CatchClause,
+    FinallyClause,
+    Block,
+    Literal,
+    Name,
+    This,
+    Super,
+    Grouping,
+    /// a prefix operator, `++` and `--` included
+    Unary,
+    /// an infix operator, `and` and `or` included
+    Binary,
+    /// `=` or a compound assignment
+    Assign,
+    Ternary,
+    Postfix,
+    Call,
+    ArgList,
+    Get,
+    Subscript,
+    List,
+    Map,
+    MapEntry,
+    Lambda,
+    Interpolation,
+    /// `yield` and the value it hands out, if it has one
+    Yield,
+    /// tokens that couldn't start a statement, up to where the parser picked up again
+    Error,
+}
+
+/// What a `Missing` placeholder stands in for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Expected {
+    Token(TokenType),
+    Expression,
+    Statement,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenType,
+    pub text: Vec<u8>,
+    /// the whitespace, comments and skipped text between the previous token and this one
+    pub leading: Vec<Trivia>,
+}
+
+impl Token {
+    /// How many bytes of source the token covers, its trivia included.
+    pub fn byte_len(&self) -> usize {
+        self.trivia_len() + self.text.len()
+    }
+
+    fn trivia_len(&self) -> usize {
+        self.leading.iter().map(|trivia| trivia.text.len()).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Element {
+    Node(Node),
+    Token(Token),
+    /// what the grammar wants here but the source doesn't have; it covers no text
+    Missing(Expected),
+}
+
+impl Element {
+    pub fn byte_len(&self) -> usize {
+        match self {
+            Element::Node(node) => node.byte_len,
+            Element::Token(token) => token.byte_len(),
+            Element::Missing(_) => 0,
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Element::Node(node) => node.write(out),
+            Element::Token(token) => {
+                for trivia in &token.leading {
+                    out.extend_from_slice(&trivia.text);
+                }
+                out.extend_from_slice(&token.text);
+            }
+            Element::Missing(_) => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub kind: NodeKind,
+    children: Vec<Element>,
+    // kept so that finding an offset doesn't mean adding up every token before it
+    byte_len: usize,
+}
+
+impl Node {
+    fn new(kind: NodeKind, children: Vec<Element>) -> Node {
+        let byte_len = children.iter().map(Element::byte_len).sum();
+        Node {
+            kind,
+            children,
+            byte_len,
+        }
+    }
+
+    pub fn children(&self) -> &[Element] {
+        &self.children
+    }
+
+    pub fn byte_len(&self) -> usize {
+        self.byte_len
+    }
+
+    /// The source the node was parsed from, trivia and all.
+    pub fn text(&self) -> String {
+        let mut out = Vec::with_capacity(self.byte_len);
+        self.write(&mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        for child in &self.children {
+            child.write(out);
+        }
+    }
+}
+
+/// How much of the tree an edit had `Tree::edit` rebuild.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Reparse {
+    /// the identifier the edit was inside, scanned again on its own
+    Token,
+    /// the innermost block or class body around the edit
+    Block,
+    Everything,
+}
+
+/// A mistake in the source, located like the parser's errors.
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// text the scanner couldn't make a token of, and why
+    Skipped {
+        what: String,
+        line: usize,
+        col: i64,
+        len: usize,
+    },
+    /// a `Missing` placeholder, with the token found where it is
+    Missing {
+        expected: Expected,
+        found: scanner::Token,
+    },
+    /// the first token of an `Error` node
+    Unexpected(scanner::Token),
+}
+
+/// A program's lossless syntax tree. Its root is a `Program` node whose last child is the
+/// `Eof` token, which holds the trivia after the last statement.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tree {
+    root: Node,
+}
+
+// identifiers the parser treats specially in some places, so renaming to or from one of them
+// can change the shape of the tree
+const CONTEXTUAL_WORDS: [&[u8]; 5] = [b"from", b"with", b"static", b"get", b"set"];
+
+impl Tree {
+    pub fn parse(source: &str) -> Tree {
+        let mut parser = Parser {
+            tokens: lex(source),
+        };
+        Tree {
+            root: parser.program(),
+        }
+    }
+
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    pub fn text(&self) -> String {
+        self.root.text()
+    }
+
+    /// Every mistake in the tree, in the order they appear in the source.
+    pub fn errors(&self) -> Vec<Error> {
+        let mut leaves = Vec::new();
+        collect_leaves(&self.root, &mut leaves);
+
+        let mut errors = Vec::new();
+        let mut position = Position { line: 1, col: 0 };
+        // placeholders and error nodes waiting for the token they are found at
+        let mut pending = Vec::new();
+        for leaf in leaves {
+            let token = match leaf {
+                Leaf::Token(token) => token,
+                Leaf::Missing(expected) => {
+                    pending.push(Some(expected));
+                    continue;
+                }
+                Leaf::Unexpected => {
+                    pending.push(None);
+                    continue;
+                }
+            };
+            for trivia in &token.leading {
+                if let TriviaKind::Skipped(what) = &trivia.kind {
+                    errors.push(Error::Skipped {
+                        what: what.clone(),
+                        line: position.line,
+                        col: position.col,
+                        len: String::from_utf8_lossy(&trivia.text).chars().count(),
+                    });
+                }
+                position.advance(&trivia.text);
+            }
+            let found = scanner::Token {
+                ty: token.kind,
+                lexeme: token.text.clone(),
+                literal: None,
+                line: position.line,
+                col: position.col,
+            };
+            errors.extend(pending.drain(..).map(|expected| match expected {
+                Some(expected) => Error::Missing {
+                    expected,
+                    found: found.clone(),
+                },
+                None => Error::Unexpected(found.clone()),
+            }));
+            position.advance(&token.text);
+        }
+        errors
+    }
+
+    /// The byte offset of a 1-based line and 0-based column, counted in characters, clamped to
+    /// the end of the line, or of the text for a line past its end.
+    pub fn offset(&self, line: usize, col: i64) -> usize {
+        let text = self.text();
+        let mut offset = 0;
+        for _ in 1..line {
+            match text[offset..].find('\n') {
+                Some(newline) => offset += newline + 1,
+                None => return text.len(),
+            }
+        }
+        let line = text[offset..].split('\n').next().unwrap_or_default();
+        offset
+            + line
+                .char_indices()
+                .nth(col.max(0) as usize)
+                .map_or(line.len(), |(at, _)| at)
+    }
+
+    /// Replaces the text in `range` with `replacement`, reparsing as little as it can while
+    /// leaving the same tree as parsing the new text from scratch would. Panics if `range`
+    /// isn't within the text.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &str) -> Reparse {
+        assert!(
+            range.start <= range.end && range.end <= self.root.byte_len,
+            "edit {:?} is outside the text",
+            range
+        );
+        if relex_identifier(&mut self.root, 0, &range, replacement) {
+            return Reparse::Token;
+        }
+        if reparse_block(&mut self.root, 0, &range, replacement) {
+            return Reparse::Block;
+        }
+        let mut text = self.text();
+        text.replace_range(range, replacement);
+        *self = Tree::parse(&text);
+        Reparse::Everything
+    }
+}
+
+fn lex(source: &str) -> VecDeque<Token> {
+    scanner::scan_lossless(source.to_string())
+        .into_iter()
+        .map(|(leading, token)| Token {
+            kind: token.ty,
+            text: token.lexeme,
+            leading,
+        })
+        .collect()
+}
+
+/// Finds the child of `node`, which starts at `start`, that `range` lies within.
+fn child_around<'a>(
+    node: &'a mut Node,
+    start: usize,
+    range: &Range<usize>,
+) -> Option<(&'a mut Element, usize)> {
+    let mut offset = start;
+    for child in &mut node.children {
+        let len = child.byte_len();
+        if range.start >= offset && range.end <= offset + len {
+            return Some((child, offset));
+        }
+        offset += len;
+    }
+    None
+}
+
+/// Edits the identifier `range` is inside, if the edit leaves it an identifier and the
+/// parser would make no more of it than before.
+fn relex_identifier(
+    node: &mut Node,
+    start: usize,
+    range: &Range<usize>,
+    replacement: &str,
+) -> bool {
+    let changed = match child_around(node, start, range) {
+        Some((Element::Node(child), offset)) => relex_identifier(child, offset, range, replacement),
+        Some((Element::Token(token), offset)) => {
+            let text_start = offset + token.trivia_len();
+            // an edit at the very start could join the identifier onto what is in front of it
+            if token.kind != TokenType::Identifier
+                || range.start <= text_start
+                || range.end > text_start + token.text.len()
+            {
+                return false;
+            }
+            let mut text = token.text.clone();
+            text.splice(
+                range.start - text_start..range.end - text_start,
+                replacement.bytes(),
+            );
+            if CONTEXTUAL_WORDS.contains(&token.text.as_slice())
+                || CONTEXTUAL_WORDS.contains(&text.as_slice())
+            {
+                return false;
+            }
+            let mut relexed = match String::from_utf8(text) {
+                Ok(text) => lex(&text),
+                Err(_) => return false,
+            };
+            match (relexed.pop_front(), relexed.pop_front()) {
+                (Some(identifier), Some(eof))
+                    if identifier.kind == TokenType::Identifier
+                        && identifier.leading.is_empty()
+                        && eof.kind == TokenType::Eof
+                        && eof.leading.is_empty() =>
+                {
+                    token.text = identifier.text;
+                    true
+                }
+                _ => false,
+            }
+        }
+        Some((Element::Missing(_), _)) | None => false,
+    };
+    if changed {
+        node.byte_len = node.byte_len - range.len() + replacement.len();
+    }
+    changed
+}
+
+/// Reparses the innermost block or class body that `range` is between the braces of, if the
+/// edit leaves the braces matched the way they were.
+fn reparse_block(node: &mut Node, start: usize, range: &Range<usize>, replacement: &str) -> bool {
+    let changed_inside = match child_around(node, start, range) {
+        Some((Element::Node(child), offset)) => reparse_block(child, offset, range, replacement),
+        _ => false,
+    };
+    if changed_inside {
+        node.byte_len = node.byte_len - range.len() + replacement.len();
+        return true;
+    }
+    match reparse_braces(node, start, range, replacement) {
+        Some(reparsed) => {
+            *node = reparsed;
+            true
+        }
+        None => false,
+    }
+}
+
+fn reparse_braces(
+    node: &Node,
+    start: usize,
+    range: &Range<usize>,
+    replacement: &str,
+) -> Option<Node> {
+    if node.kind != NodeKind::Block && node.kind != NodeKind::ClassBody {
+        return None;
+    }
+    let open = match node.children.first() {
+        Some(Element::Token(open)) => open,
+        _ => return None,
+    };
+    // an unclosed block runs on to wherever the parser stopped, which an edit can change
+    let close = match node.children.last() {
+        Some(Element::Token(close)) if close.kind == TokenType::RightBrace => close,
+        _ => return None,
+    };
+    if range.start < start + open.byte_len() || range.end > start + node.byte_len - close.text.len()
+    {
+        return None;
+    }
+
+    let mut text = Vec::with_capacity(node.byte_len);
+    node.write(&mut text);
+    text.splice(range.start - start..range.end - start, replacement.bytes());
+    let tokens = lex(&String::from_utf8(text).ok()?);
+
+    // the first brace has to be closed by the last token and by nothing before it; anything
+    // after, even trivia, would belong outside the block
+    let last = match tokens.len().checked_sub(2) {
+        Some(last) if tokens[last + 1].leading.is_empty() => last,
+        _ => return None,
+    };
+    let mut depth = 0;
+    for (i, token) in tokens.iter().take(last + 1).enumerate() {
+        match token.kind {
+            TokenType::LeftBrace => depth += 1,
+            TokenType::RightBrace => depth -= 1,
+            _ => {}
+        }
+        if (depth == 0) != (i == last) {
+            return None;
+        }
+    }
+
+    let mut parser = Parser { tokens };
+    let reparsed = match node.kind {
+        NodeKind::Block => parser.block(),
+        _ => parser.class_body(),
+    };
+    match reparsed {
+        Element::Node(reparsed) if parser.at(TokenType::Eof) => Some(reparsed),
+        _ => None,
+    }
+}
+
+enum Leaf<'a> {
+    Token(&'a Token),
+    Missing(Expected),
+    /// where an `Error` node starts
+    Unexpected,
+}
+
+fn collect_leaves<'a>(node: &'a Node, out: &mut Vec<Leaf<'a>>) {
+    if node.kind == NodeKind::Error {
+        out.push(Leaf::Unexpected);
+    }
+    for child in &node.children {
+        match child {
+            Element::Node(node) => collect_leaves(node, out),
+            Element::Token(token) => out.push(Leaf::Token(token)),
+            Element::Missing(expected) => out.push(Leaf::Missing(*expected)),
+        }
+    }
+}
+
+struct Position {
+    line: usize,
+    col: i64,
+}
+
+impl Position {
+    fn advance(&mut self, text: &[u8]) {
+        for c in String::from_utf8_lossy(text).chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.col = 0;
+            } else {
+                self.col += 1;
+            }
+        }
+    }
+}
+
+fn starts_expression(ty: TokenType) -> bool {
+    matches!(
+        ty,
+        TokenType::Identifier
+            | TokenType::Number
+            | TokenType::String
+            | TokenType::Interpolation
+            | TokenType::True
+            | TokenType::False
+            | TokenType::Nil
+            | TokenType::This
+            | TokenType::Super
+            | TokenType::LeftParen
+            | TokenType::LeftBracket
+            | TokenType::LeftBrace
+            | TokenType::Lambda
+            | TokenType::Bang
+            | TokenType::Minus
+            | TokenType::Tilde
+            | TokenType::PlusPlus
+            | TokenType::MinusMinus
+            | TokenType::Yield
+    )
+}
+
+/// The tokens `parser::synchronize` stops in front of.
+fn starts_statement(ty: TokenType) -> bool {
+    matches!(
+        ty,
+        TokenType::Class
+            | TokenType::Fun
+            | TokenType::Var
+            | TokenType::For
+            | TokenType::If
+            | TokenType::While
+            | TokenType::Print
+            | TokenType::Return
+            | TokenType::Import
+            | TokenType::Throw
+            | TokenType::Try
+            | TokenType::Break
+            | TokenType::Continue
+            | TokenType::Yield
+    )
+}
+
+// binary operators from the loosest binding to the tightest; `**` is handled with the unary
+// operators, as in `parser`
+const BINARY_OPERATORS: [&[TokenType]; 10] = [
+    &[TokenType::Or],
+    &[TokenType::And],
+    &[TokenType::BangEqual, TokenType::EqualEqual],
+    &[
+        TokenType::Greater,
+        TokenType::GreaterEqual,
+        TokenType::Less,
+        TokenType::LessEqual,
+    ],
+    &[TokenType::Pipe],
+    &[TokenType::Caret],
+    &[TokenType::Ampersand],
+    &[TokenType::LessLess, TokenType::GreaterGreater],
+    &[TokenType::Minus, TokenType::Plus],
+    &[TokenType::Slash, TokenType::Star, TokenType::Percent],
+];
+
+/// Recursive descent over the grammar in `parser`. Nothing here fails: where a token is
+/// missing, a placeholder goes in its place and parsing carries on as if it had been there.
+struct Parser {
+    // the `Eof` is never taken off the end, except by `program`
+    tokens: VecDeque<Token>,
+}
+
+impl Parser {
+    fn program(&mut self) -> Node {
+        let mut children = Vec::new();
+        while !self.at(TokenType::Eof) {
+            if self.at(TokenType::RightBrace) {
+                children.push(self.recover());
+            } else {
+                children.push(self.declaration());
+            }
+        }
+        children.push(Element::Token(self.tokens.pop_front().unwrap()));
+        Node::new(NodeKind::Program, children)
+    }
+
+    fn declaration(&mut self) -> Element {
+        let node = match self.peek() {
+            TokenType::Var => self.var_decl(),
+            TokenType::Fun => {
+                let mut children = vec![self.bump()];
+                self.eat(TokenType::Star, &mut children);
+                self.expect(TokenType::Identifier, &mut children);
+                self.function(&mut children);
+                Node::new(NodeKind::FunDecl, children)
+            }
+            TokenType::Class => self.class_decl(),
+            TokenType::Import => self.import_decl(),
+            _ => return self.statement(),
+        };
+        Element::Node(node)
+    }
+
+    fn import_decl(&mut self) -> Node {
+        let mut children = vec![self.bump()];
+        if self.eat(TokenType::Identifier, &mut children) {
+            if self.at_word(b"from") {
+                children.push(self.bump());
+            } else {
+                children.push(Element::Missing(Expected::Token(TokenType::Identifier)));
+            }
+        }
+        self.expect(TokenType::String, &mut children);
+        self.expect(TokenType::Semicolon, &mut children);
+        Node::new(NodeKind::ImportDecl, children)
+    }
+
+    fn class_decl(&mut self) -> Node {
+        let mut children = vec![self.bump()];
+        self.expect(TokenType::Identifier, &mut children);
+        if self.eat(TokenType::Less, &mut children) {
+            self.expect(TokenType::Identifier, &mut children);
+        }
+        if self.at_word(b"with") {
+            children.push(self.bump());
+            loop {
+                self.expect(TokenType::Identifier, &mut children);
+                if !self.eat(TokenType::Comma, &mut children) {
+                    break;
+                }
+            }
+        }
+        children.push(self.class_body());
+        Node::new(NodeKind::ClassDecl, children)
+    }
+
+    fn class_body(&mut self) -> Element {
+        if !self.at(TokenType::LeftBrace) {
+            return Element::Missing(Expected::Token(TokenType::LeftBrace));
+        }
+        let mut children = vec![self.bump()];
+        while !self.at(TokenType::RightBrace) && !self.at(TokenType::Eof) {
+            if self.at(TokenType::Identifier) || self.at(TokenType::Star) {
+                children.push(self.member());
+                continue;
+            }
+            // skip to something that could be the next member
+            let mut skipped = vec![self.bump()];
+            while !matches!(
+                self.peek(),
+                TokenType::Identifier | TokenType::Star | TokenType::RightBrace | TokenType::Eof
+            ) {
+                skipped.push(self.bump());
+            }
+            children.push(Element::Node(Node::new(NodeKind::Error, skipped)));
+        }
+        self.expect(TokenType::RightBrace, &mut children);
+        Element::Node(Node::new(NodeKind::ClassBody, children))
+    }
+
+    fn member(&mut self) -> Element {
+        let mut children = Vec::new();
+        let mut getter = false;
+        if self.peek_next() == TokenType::Identifier
+            && (self.at_word(b"static") || self.at_word(b"get") || self.at_word(b"set"))
+            || self.peek_next() == TokenType::Star && self.at_word(b"static")
+        {
+            getter = self.at_word(b"get");
+            children.push(self.bump());
+        }
+        self.eat(TokenType::Star, &mut children);
+        self.expect(TokenType::Identifier, &mut children);
+        if getter {
+            children.extend(self.annotation());
+            children.push(self.block());
+        } else {
+            self.function(&mut children);
+        }
+        Element::Node(Node::new(NodeKind::Member, children))
+    }
+
+    /// The parameters, return type and body of a function, method or lambda.
+    fn function(&mut self, children: &mut Vec<Element>) {
+        children.push(self.params());
+        children.extend(self.annotation());
+        children.push(self.block());
+    }
+
+    fn params(&mut self) -> Element {
+        if !self.at(TokenType::LeftParen) {
+            return Element::Missing(Expected::Token(TokenType::LeftParen));
+        }
+        let mut children = vec![self.bump()];
+        if self.at(TokenType::Identifier) {
+            loop {
+                let mut param = Vec::new();
+                self.expect(TokenType::Identifier, &mut param);
+                param.extend(self.annotation());
+                children.push(Element::Node(Node::new(NodeKind::Param, param)));
+                if !self.eat(TokenType::Comma, &mut children) {
+                    break;
+                }
+            }
+        }
+        self.expect(TokenType::RightParen, &mut children);
+        Element::Node(Node::new(NodeKind::ParamList, children))
+    }
+
+    fn annotation(&mut self) -> Option<Element> {
+        if !self.at(TokenType::Colon) {
+            return None;
+        }
+        let mut children = vec![self.bump()];
+        if self.at(TokenType::Nil) || self.at(TokenType::Fun) {
+            children.push(self.bump());
+        } else {
+            self.expect(TokenType::Identifier, &mut children);
+        }
+        Some(Element::Node(Node::new(NodeKind::Annotation, children)))
+    }
+
+    fn var_decl(&mut self) -> Node {
+        let mut children = vec![self.bump()];
+        self.expect(TokenType::Identifier, &mut children);
+        children.extend(self.annotation());
+        if self.eat(TokenType::Equal, &mut children) {
+            children.push(self.expression());
+        }
+        self.expect(TokenType::Semicolon, &mut children);
+        Node::new(NodeKind::VarDecl, children)
+    }
+
+    fn statement(&mut self) -> Element {
+        let node = match self.peek() {
+            TokenType::Print => self.keyword_statement(NodeKind::PrintStmt),
+            TokenType::Throw => self.keyword_statement(NodeKind::ThrowStmt),
+            TokenType::Return => {
+                let mut children = vec![self.bump()];
+                if !self.at(TokenType::Semicolon) {
+                    children.push(self.expression());
+                }
+                self.expect(TokenType::Semicolon, &mut children);
+                Node::new(NodeKind::ReturnStmt, children)
+            }
+            TokenType::While => self.while_statement(),
+            TokenType::For => self.for_statement(),
+            TokenType::If => {
+                let mut children = vec![self.bump()];
+                self.condition(&mut children);
+                children.push(self.statement());
+                if self.eat(TokenType::Else, &mut children) {
+                    children.push(self.statement());
+                }
+                Node::new(NodeKind::IfStmt, children)
+            }
+            TokenType::LeftBrace => return self.block(),
+            TokenType::Try => self.try_statement(),
+            TokenType::Break => self.jump(NodeKind::BreakStmt),
+            TokenType::Continue => self.jump(NodeKind::ContinueStmt),
+            TokenType::Identifier if self.peek_next() == TokenType::Colon => {
+                let mut children = vec![self.bump(), self.bump()];
+                match self.peek() {
+                    TokenType::While => children.push(Element::Node(self.while_statement())),
+                    TokenType::For => children.push(Element::Node(self.for_statement())),
+                    _ => children.push(Element::Missing(Expected::Token(TokenType::While))),
+                }
+                Node::new(NodeKind::LabeledStmt, children)
+            }
+            ty if starts_expression(ty) => self.expression_statement(),
+            TokenType::RightBrace | TokenType::Eof => return Element::Missing(Expected::Statement),
+            _ => return self.recover(),
+        };
+        Element::Node(node)
+    }
+
+    /// Wraps tokens that can't start a statement in an `Error` node: the current one, and
+    /// the ones after it that `parser` would skip too.
+    fn recover(&mut self) -> Element {
+        let mut children = vec![self.bump()];
+        while !self.at(TokenType::Eof) && !self.at(TokenType::RightBrace) {
+            if let Some(Element::Token(last)) = children.last() {
+                if last.kind == TokenType::Semicolon {
+                    break;
+                }
+            }
+            if starts_statement(self.peek()) {
+                break;
+            }
+            children.push(self.bump());
+        }
+        Element::Node(Node::new(NodeKind::Error, children))
+    }
+
+    /// `keyword expression ;`
+    fn keyword_statement(&mut self, kind: NodeKind) -> Node {
+        let mut children = vec![self.bump()];
+        children.push(self.expression());
+        self.expect(TokenType::Semicolon, &mut children);
+        Node::new(kind, children)
+    }
+
+    /// `break` or `continue`, with its label if it has one.
+    fn jump(&mut self, kind: NodeKind) -> Node {
+        let mut children = vec![self.bump()];
+        self.eat(TokenType::Identifier, &mut children);
+        self.expect(TokenType::Semicolon, &mut children);
+        Node::new(kind, children)
+    }
+
+    /// `( expression )` after `if` or `while`.
+    fn condition(&mut self, children: &mut Vec<Element>) {
+        self.expect(TokenType::LeftParen, children);
+        children.push(self.expression());
+        self.expect(TokenType::RightParen, children);
+    }
+
+    fn while_statement(&mut self) -> Node {
+        let mut children = vec![self.bump()];
+        self.condition(&mut children);
+        children.push(self.statement());
+        Node::new(NodeKind::WhileStmt, children)
+    }
+
+    fn for_statement(&mut self) -> Node {
+        let mut children = vec![self.bump()];
+        self.expect(TokenType::LeftParen, &mut children);
+
+        if self.at(TokenType::Identifier) && self.peek_next() == TokenType::In {
+            children.push(self.bump());
+            children.push(self.bump());
+            children.push(self.expression());
+            self.expect(TokenType::RightParen, &mut children);
+            children.push(self.statement());
+            return Node::new(NodeKind::ForInStmt, children);
+        }
+
+        match self.peek() {
+            TokenType::Semicolon => children.push(self.bump()),
+            TokenType::Var => children.push(Element::Node(self.var_decl())),
+            _ => children.push(Element::Node(self.expression_statement())),
+        }
+        if !self.at(TokenType::Semicolon) {
+            children.push(self.expression());
+        }
+        self.expect(TokenType::Semicolon, &mut children);
+        if !self.at(TokenType::RightParen) {
+            children.push(self.expression());
+        }
+        self.expect(TokenType::RightParen, &mut children);
+        children.push(self.statement());
+        Node::new(NodeKind::ForStmt, children)
+    }
+
+    fn try_statement(&mut self) -> Node {
+        let mut children = vec![self.bump()];
+        children.push(self.block());
+
+        let mut handled = false;
+        if self.at(TokenType::Catch) {
+            let mut catch = vec![self.bump()];
+            self.expect(TokenType::LeftParen, &mut catch);
+            self.expect(TokenType::Identifier, &mut catch);
+            self.expect(TokenType::RightParen, &mut catch);
+            catch.push(self.block());
+            children.push(Element::Node(Node::new(NodeKind::CatchClause, catch)));
+            handled = true;
+        }
+        if self.at(TokenType::Finally) {
+            let finally = vec![self.bump(), self.block()];
+            children.push(Element::Node(Node::new(NodeKind::FinallyClause, finally)));
+            handled = true;
+        }
+        if !handled {
+            children.push(Element::Missing(Expected::Token(TokenType::Catch)));
+        }
+        Node::new(NodeKind::TryStmt, children)
+    }
+
+    fn block(&mut self) -> Element {
+        if !self.at(TokenType::LeftBrace) {
+            return Element::Missing(Expected::Token(TokenType::LeftBrace));
+        }
+        let mut children = vec![self.bump()];
+        while !self.at(TokenType::RightBrace) && !self.at(TokenType::Eof) {
+            children.push(self.declaration());
+        }
+        self.expect(TokenType::RightBrace, &mut children);
+        Element::Node(Node::new(NodeKind::Block, children))
+    }
+
+    fn expression_statement(&mut self) -> Node {
+        let mut children = vec![self.expression()];
+        self.expect(TokenType::Semicolon, &mut children);
+        Node::new(NodeKind::ExprStmt, children)
+    }
+
+    fn expression(&mut self) -> Element {
+        if self.at(TokenType::Yield) {
+            let mut children = vec![self.bump()];
+            if !matches!(
+                self.peek(),
+                TokenType::Semicolon
+                    | TokenType::RightParen
+                    | TokenType::RightBracket
+                    | TokenType::RightBrace
+                    | TokenType::Comma
+                    | TokenType::Colon
+                    | TokenType::Eof
+            ) {
+                children.push(self.expression());
+            }
+            return Element::Node(Node::new(NodeKind::Yield, children));
+        }
+        let target = self.ternary();
+        if matches!(
+            self.peek(),
+            TokenType::Equal
+                | TokenType::PlusEqual
+                | TokenType::MinusEqual
+                | TokenType::StarEqual
+                | TokenType::SlashEqual
+        ) {
+            let mut children = vec![target, self.bump()];
+            children.push(self.expression());
+            return Element::Node(Node::new(NodeKind::Assign, children));
+        }
+        target
+    }
+
+    fn ternary(&mut self) -> Element {
+        let condition = self.binary(0);
+        if !self.at(TokenType::Question) {
+            return condition;
+        }
+        let mut children = vec![condition, self.bump()];
+        children.push(self.expression());
+        self.expect(TokenType::Colon, &mut children);
+        children.push(self.ternary());
+        Element::Node(Node::new(NodeKind::Ternary, children))
+    }
+
+    /// Operators from `BINARY_OPERATORS[level]` on, grouping left.
+    fn binary(&mut self, level: usize) -> Element {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+        let mut expr = self.binary(level + 1);
+        while BINARY_OPERATORS[level].contains(&self.peek()) {
+            let mut children = vec![expr, self.bump()];
+            children.push(self.binary(level + 1));
+            expr = Element::Node(Node::new(NodeKind::Binary, children));
+        }
+        expr
+    }
+
+    fn unary(&mut self) -> Element {
+        if matches!(
+            self.peek(),
+            TokenType::Bang
+                | TokenType::Minus
+                | TokenType::Tilde
+                | TokenType::PlusPlus
+                | TokenType::MinusMinus
+        ) {
+            let mut children = vec![self.bump()];
+            children.push(self.unary());
+            return Element::Node(Node::new(NodeKind::Unary, children));
+        }
+
+        let base = self.postfix();
+        if !self.at(TokenType::StarStar) {
+            return base;
+        }
+        let mut children = vec![base, self.bump()];
+        children.push(self.unary());
+        Element::Node(Node::new(NodeKind::Binary, children))
+    }
+
+    fn postfix(&mut self) -> Element {
+        let expr = self.call();
+        if self.at(TokenType::PlusPlus) || self.at(TokenType::MinusMinus) {
+            let children = vec![expr, self.bump()];
+            return Element::Node(Node::new(NodeKind::Postfix, children));
+        }
+        expr
+    }
+
+    fn call(&mut self) -> Element {
+        let mut expr = self.primary();
+        loop {
+            let node = match self.peek() {
+                TokenType::LeftParen => {
+                    let mut children = vec![expr, self.bump()];
+                    self.list(TokenType::RightParen, &mut children, Parser::expression);
+                    let args = children.split_off(1);
+                    children.push(Element::Node(Node::new(NodeKind::ArgList, args)));
+                    Node::new(NodeKind::Call, children)
+                }
+                TokenType::Dot => {
+                    let mut children = vec![expr, self.bump()];
+                    self.expect(TokenType::Identifier, &mut children);
+                    Node::new(NodeKind::Get, children)
+                }
+                TokenType::LeftBracket => {
+                    let mut children = vec![expr, self.bump()];
+                    children.push(self.expression());
+                    self.expect(TokenType::RightBracket, &mut children);
+                    Node::new(NodeKind::Subscript, children)
+                }
+                _ => return expr,
+            };
+            expr = Element::Node(node);
+        }
+    }
+
+    fn primary(&mut self) -> Element {
+        let node = match self.peek() {
+            TokenType::True
+            | TokenType::False
+            | TokenType::Nil
+            | TokenType::Number
+            | TokenType::String => Node::new(NodeKind::Literal, vec![self.bump()]),
+            TokenType::Identifier => Node::new(NodeKind::Name, vec![self.bump()]),
+            TokenType::This => Node::new(NodeKind::This, vec![self.bump()]),
+            TokenType::Super => {
+                let mut children = vec![self.bump()];
+                self.expect(TokenType::Dot, &mut children);
+                self.expect(TokenType::Identifier, &mut children);
+                Node::new(NodeKind::Super, children)
+            }
+            TokenType::Interpolation => {
+                let mut children = vec![self.bump()];
+                loop {
+                    children.push(self.expression());
+                    if !self.eat(TokenType::Interpolation, &mut children) {
+                        break;
+                    }
+                }
+                self.expect(TokenType::String, &mut children);
+                Node::new(NodeKind::Interpolation, children)
+            }
+            TokenType::LeftParen => {
+                let mut children = vec![self.bump()];
+                children.push(self.expression());
+                self.expect(TokenType::RightParen, &mut children);
+                Node::new(NodeKind::Grouping, children)
+            }
+            TokenType::LeftBracket => {
+                let mut children = vec![self.bump()];
+                self.list(TokenType::RightBracket, &mut children, Parser::expression);
+                Node::new(NodeKind::List, children)
+            }
+            TokenType::LeftBrace => {
+                let mut children = vec![self.bump()];
+                self.list(TokenType::RightBrace, &mut children, Parser::map_entry);
+                Node::new(NodeKind::Map, children)
+            }
+            TokenType::Lambda => {
+                let mut children = vec![self.bump()];
+                self.function(&mut children);
+                Node::new(NodeKind::Lambda, children)
+            }
+            _ => return Element::Missing(Expected::Expression),
+        };
+        Element::Node(node)
+    }
+
+    fn map_entry(&mut self) -> Element {
+        let mut children = vec![self.expression()];
+        self.expect(TokenType::Colon, &mut children);
+        children.push(self.expression());
+        Element::Node(Node::new(NodeKind::MapEntry, children))
+    }
+
+    /// Comma-separated items up to the `close` token, after the token that opened the list.
+    fn list(
+        &mut self,
+        close: TokenType,
+        children: &mut Vec<Element>,
+        item: fn(&mut Parser) -> Element,
+    ) {
+        if !self.at(close) {
+            loop {
+                children.push(item(self));
+                if !self.eat(TokenType::Comma, children) {
+                    break;
+                }
+            }
+        }
+        self.expect(close, children);
+    }
+
+    fn bump(&mut self) -> Element {
+        debug_assert!(!self.at(TokenType::Eof), "only program() takes the Eof");
+        Element::Token(self.tokens.pop_front().unwrap())
+    }
+
+    /// Takes the current token if it is a `ty`.
+    fn eat(&mut self, ty: TokenType, children: &mut Vec<Element>) -> bool {
+        if self.at(ty) {
+            children.push(self.bump());
+            return true;
+        }
+        false
+    }
+
+    /// Takes the current token if it is a `ty`, and puts a placeholder in for it if not.
+    fn expect(&mut self, ty: TokenType, children: &mut Vec<Element>) {
+        if !self.eat(ty, children) {
+            children.push(Element::Missing(Expected::Token(ty)));
+        }
+    }
+
+    fn peek(&self) -> TokenType {
+        self.tokens[0].kind
+    }
+
+    fn peek_next(&self) -> TokenType {
+        self.tokens
+            .get(1)
+            .map_or(TokenType::Eof, |token| token.kind)
+    }
+
+    fn at(&self, ty: TokenType) -> bool {
+        self.peek() == ty
+    }
+
+    /// Whether the current token is the identifier `word`, which means something only where
+    /// the parser checks for it.
+    fn at_word(&self, word: &[u8]) -> bool {
+        self.at(TokenType::Identifier) && self.tokens[0].text == word
+    }
+}
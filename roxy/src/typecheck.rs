@@ -0,0 +1,1084 @@
+//! The optional type checker. Variables, parameters and functions may be annotated, as in
+//! `var x: num = 1;` or `fun f(a: str): bool { ... }`. The checker infers the types of
+//! expressions from literals, operators and annotated declarations, and reports the places
+//! where a value meets an annotation or operator that needs another type.
+//!
+//! Anything unannotated has type `any`, which fits everywhere and accepts everything, so
+//! unannotated code stays as dynamic as it always was.
+
+use crate::expr;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Any,
+    Nil,
+    Bool,
+    Num,
+    Str,
+    List,
+    Map,
+    /// a function; unannotated ones have no signature
+    Fun(Option<Rc<Signature>>),
+    /// the class itself, which is called to make instances
+    Class(String),
+    Instance(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Type>,
+    pub ret: Type,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Nil => write!(f, "nil"),
+            Type::Bool => write!(f, "bool"),
+            Type::Num => write!(f, "num"),
+            Type::Str => write!(f, "str"),
+            Type::List => write!(f, "list"),
+            Type::Map => write!(f, "map"),
+            Type::Fun(None) => write!(f, "fun"),
+            Type::Fun(Some(sig)) => {
+                let params: Vec<String> = sig.params.iter().map(Type::to_string).collect();
+                write!(f, "fun({}): {}", params.join(", "), sig.ret)
+            }
+            Type::Class(name) => write!(f, "class {}", name),
+            Type::Instance(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Mismatch {
+        expected: Type,
+        found: Type,
+        /// where the expectation comes from
+        reason: Option<String>,
+        line: usize,
+        col: i64,
+    },
+    UnknownType {
+        name: String,
+        line: usize,
+        col: i64,
+    },
+    InvalidOperands {
+        op: &'static str,
+        types: Vec<Type>,
+        line: usize,
+        col: i64,
+    },
+    Uninitialized {
+        name: String,
+        ty: Type,
+        line: usize,
+        col: i64,
+    },
+    NotCallable {
+        found: Type,
+        line: usize,
+        col: i64,
+    },
+    WrongArity {
+        name: String,
+        expected: usize,
+        found: usize,
+        line: usize,
+        col: i64,
+    },
+    MissingReturn {
+        name: String,
+        ty: Type,
+        line: usize,
+        col: i64,
+    },
+}
+
+/// Checks a program on its own, returning every type error found in it.
+pub fn check(stmts: &[expr::Stmt]) -> Result<(), Vec<Error>> {
+    Checker::default().check(stmts)
+}
+
+#[derive(Clone)]
+struct Binding {
+    ty: Type,
+    /// the annotation, which assignments have to agree with
+    annotation: Option<expr::Symbol>,
+}
+
+#[derive(Default, Clone)]
+struct ClassInfo {
+    superclass: Option<String>,
+    mixins: Vec<String>,
+    // by kind and then name; unannotated methods have no signature
+    methods: HashMap<expr::MethodKind, HashMap<String, Option<Rc<Signature>>>>,
+}
+
+/// Checks one program after another, the way they run in one session: each sees the globals
+/// and classes the ones checked before it declared, so an annotation made at the REPL, in an
+/// earlier `Engine::eval` or in an imported module still holds.
+#[derive(Default)]
+pub struct Checker {
+    globals: HashMap<String, Binding>,
+    scopes: Vec<HashMap<String, Binding>>,
+    classes: HashMap<String, ClassInfo>,
+    // the function being checked, what it returns and whether it is a generator, innermost
+    // last; a generator's annotation is what it yields
+    functions: Vec<(String, Type, bool)>,
+    // the class whose methods are being checked and the type `this` has in the one being
+    // checked, innermost last
+    class_stack: Vec<(String, Type)>,
+    errors: Vec<Error>,
+}
+
+fn location(sym: &expr::Symbol) -> expr::SourceLocation {
+    expr::SourceLocation {
+        line: sym.line,
+        col: sym.col,
+    }
+}
+
+impl Checker {
+    pub fn new() -> Checker {
+        Checker::default()
+    }
+
+    /// Checks a program, returning every type error found in it. A program with errors does
+    /// not run, so what it declared is forgotten again.
+    pub fn check(&mut self, stmts: &[expr::Stmt]) -> Result<(), Vec<Error>> {
+        let globals = self.globals.clone();
+        let classes = self.classes.clone();
+        self.program(stmts);
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        self.globals = globals;
+        self.classes = classes;
+        Err(std::mem::take(&mut self.errors))
+    }
+
+    fn program(&mut self, stmts: &[expr::Stmt]) {
+        // classes and functions may be used above their declarations, so they are known
+        // before anything is checked: first every class's name, then the signatures, which
+        // may mention any of them
+        for stmt in stmts {
+            if let expr::Stmt::ClassDecl(classdecl) = stmt {
+                self.classes
+                    .insert(classdecl.name.name.clone(), ClassInfo::default());
+            }
+        }
+        for stmt in stmts {
+            match stmt {
+                expr::Stmt::ClassDecl(classdecl) => self.declare_class(classdecl),
+                expr::Stmt::FunDecl(fundecl) => {
+                    let sig = self.signature_of(fundecl);
+                    self.declare_function(&fundecl.name, sig);
+                }
+                _ => {}
+            }
+        }
+
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+    }
+
+    fn declare(&mut self, sym: &expr::Symbol, ty: Type, annotation: Option<expr::Symbol>) {
+        let binding = Binding { ty, annotation };
+        match self.scopes.last_mut() {
+            Some(scope) => scope.insert(sym.name.clone(), binding),
+            None => self.globals.insert(sym.name.clone(), binding),
+        };
+    }
+
+    fn binding(&self, name: &str) -> Option<&Binding> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.globals.get(name))
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Binding> {
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(binding) = scope.get_mut(name) {
+                return Some(binding);
+            }
+        }
+        self.globals.get_mut(name)
+    }
+
+    /// An annotated function's name is bound for good; an unannotated one's may be reassigned.
+    fn declare_function(&mut self, name: &expr::Symbol, sig: Option<Rc<Signature>>) {
+        let annotation = sig.as_ref().map(|_| name.clone());
+        self.declare(name, Type::Fun(sig), annotation);
+    }
+
+    fn declare_class(&mut self, classdecl: &expr::ClassDecl) {
+        let name = &classdecl.name.name;
+        self.classes.entry(name.clone()).or_default();
+        let mut methods: HashMap<expr::MethodKind, HashMap<_, _>> = HashMap::new();
+        for method in &classdecl.methods {
+            let sig = self.signature_of(&method.decl);
+            methods
+                .entry(method.kind)
+                .or_default()
+                .insert(method.decl.name.name.clone(), sig);
+        }
+        self.classes.insert(
+            name.clone(),
+            ClassInfo {
+                superclass: classdecl.superclass.as_ref().map(|sym| sym.name.clone()),
+                mixins: classdecl
+                    .mixins
+                    .iter()
+                    .map(|sym| sym.name.clone())
+                    .collect(),
+                methods,
+            },
+        );
+        self.declare(&classdecl.name, Type::Class(name.clone()), None);
+    }
+
+    /// The type an annotation names.
+    fn resolve(&mut self, sym: &expr::Symbol) -> Type {
+        match sym.name.as_str() {
+            "any" => Type::Any,
+            "nil" => Type::Nil,
+            "bool" => Type::Bool,
+            "num" => Type::Num,
+            "str" => Type::Str,
+            "list" => Type::List,
+            "map" => Type::Map,
+            "fun" => Type::Fun(None),
+            name if self.classes.contains_key(name) => Type::Instance(name.to_string()),
+            _ => {
+                self.errors.push(Error::UnknownType {
+                    name: sym.name.clone(),
+                    line: sym.line,
+                    col: sym.col,
+                });
+                Type::Any
+            }
+        }
+    }
+
+    fn annotation(&mut self, ty: &Option<expr::Symbol>) -> Type {
+        match ty {
+            Some(ty) => self.resolve(ty),
+            None => Type::Any,
+        }
+    }
+
+    /// A function's signature, if anything about it is annotated.
+    fn signature(
+        &mut self,
+        params: &[expr::Param],
+        return_type: &Option<expr::Symbol>,
+    ) -> Option<Rc<Signature>> {
+        if return_type.is_none() && params.iter().all(|param| param.ty.is_none()) {
+            return None;
+        }
+        Some(Rc::new(Signature {
+            params: params
+                .iter()
+                .map(|param| self.annotation(&param.ty))
+                .collect(),
+            ret: self.annotation(return_type),
+        }))
+    }
+
+    /// The signature of a declared function as its callers see it: calling a generator only
+    /// makes one, whatever it yields, so its annotation is left out.
+    fn signature_of(&mut self, decl: &expr::FunDecl) -> Option<Rc<Signature>> {
+        let return_type = if decl.generator {
+            &None
+        } else {
+            &decl.return_type
+        };
+        self.signature(&decl.params, return_type)
+    }
+
+    /// What a generator's annotation says it yields; `None` for other functions.
+    fn yields(&mut self, decl: &expr::FunDecl) -> Option<Type> {
+        if decl.generator {
+            Some(self.annotation(&decl.return_type))
+        } else {
+            None
+        }
+    }
+
+    fn superclass(&self, class: &str) -> Option<&str> {
+        self.classes.get(class)?.superclass.as_deref()
+    }
+
+    /// The classes `class` gets methods from, the ones searched first first: its mixins, last
+    /// one first, and then its superclass.
+    fn ancestors(&self, class: &str) -> Vec<&str> {
+        match self.classes.get(class) {
+            Some(info) => info
+                .mixins
+                .iter()
+                .rev()
+                .chain(&info.superclass)
+                .map(String::as_str)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Whether instances of `class` are instances of `ancestor`, which they are of the classes
+    /// they mix in too.
+    fn inherits(&self, class: &str, ancestor: &str) -> bool {
+        // classes can't inherit in a cycle at runtime, but nothing has checked that yet
+        self.inherits_within(class, ancestor, self.classes.len())
+    }
+
+    fn inherits_within(&self, class: &str, ancestor: &str, depth: usize) -> bool {
+        class == ancestor
+            || depth > 0
+                && self
+                    .ancestors(class)
+                    .iter()
+                    .any(|class| self.inherits_within(class, ancestor, depth - 1))
+    }
+
+    /// The signature of the method of `kind` called `name` that `class` has, looked for in
+    /// the order the backends look: `None` if there is no such method, `Some(None)` if it
+    /// isn't annotated.
+    fn find(
+        &self,
+        class: &str,
+        kind: expr::MethodKind,
+        name: &str,
+    ) -> Option<Option<Rc<Signature>>> {
+        self.find_within(class, kind, name, self.classes.len())
+    }
+
+    fn find_within(
+        &self,
+        class: &str,
+        kind: expr::MethodKind,
+        name: &str,
+        depth: usize,
+    ) -> Option<Option<Rc<Signature>>> {
+        let info = self.classes.get(class)?;
+        if let Some(sig) = info
+            .methods
+            .get(&kind)
+            .and_then(|methods| methods.get(name))
+        {
+            return Some(sig.clone());
+        }
+        if depth == 0 {
+            return None;
+        }
+        self.ancestors(class)
+            .iter()
+            .find_map(|class| self.find_within(class, kind, name, depth - 1))
+    }
+
+    /// The type of the method `name` that instances of `class` have, or `any` if they have
+    /// none, since it may be a field.
+    fn method(&self, class: &str, name: &str) -> Type {
+        match self.find(class, expr::MethodKind::Method, name) {
+            Some(sig) => Type::Fun(sig),
+            None => Type::Any,
+        }
+    }
+
+    /// The type of the property `name` of instances of `class`: what its getter returns, if
+    /// it has one, and otherwise the type of its method.
+    fn property(&self, class: &str, name: &str) -> Type {
+        match self.find(class, expr::MethodKind::Getter, name) {
+            Some(Some(sig)) => sig.ret.clone(),
+            Some(None) => Type::Any,
+            None => self.method(class, name),
+        }
+    }
+
+    /// Whether a value of type `found` can be used where `expected` is.
+    fn accepts(&self, expected: &Type, found: &Type) -> bool {
+        match (expected, found) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            // an unannotated function might be any function
+            (Type::Fun(None), Type::Fun(_) | Type::Class(_)) | (Type::Fun(_), Type::Fun(None)) => {
+                true
+            }
+            (Type::Fun(Some(expected)), Type::Fun(Some(found))) => {
+                expected.params.len() == found.params.len()
+                    && expected
+                        .params
+                        .iter()
+                        .zip(&found.params)
+                        .all(|(expected, found)| self.accepts(found, expected))
+                    && self.accepts(&expected.ret, &found.ret)
+            }
+            (Type::Instance(expected), Type::Instance(found)) => self.inherits(found, expected),
+            (expected, found) => expected == found,
+        }
+    }
+
+    fn expect(
+        &mut self,
+        expected: &Type,
+        found: &Type,
+        at: expr::SourceLocation,
+        reason: impl FnOnce() -> String,
+    ) {
+        if !self.accepts(expected, found) {
+            self.errors.push(Error::Mismatch {
+                expected: expected.clone(),
+                found: found.clone(),
+                reason: Some(reason()),
+                line: at.line,
+                col: at.col,
+            });
+        }
+    }
+
+    fn stmt(&mut self, stmt: &expr::Stmt) {
+        match stmt {
+            expr::Stmt::Expr(e) | expr::Stmt::Print(_, e) => {
+                self.expr(e);
+            }
+            expr::Stmt::FunDecl(fundecl) => {
+                let sig = if self.scopes.is_empty() {
+                    match self.globals.get(&fundecl.name.name) {
+                        Some(Binding {
+                            ty: Type::Fun(sig), ..
+                        }) => sig.clone(),
+                        _ => None,
+                    }
+                } else {
+                    let sig = self.signature_of(fundecl);
+                    self.declare_function(&fundecl.name, sig.clone());
+                    sig
+                };
+                let at = location(&fundecl.name);
+                let yields = self.yields(fundecl);
+                self.function(
+                    &fundecl.name.name,
+                    at,
+                    &fundecl.params,
+                    &sig,
+                    yields,
+                    &fundecl.body,
+                );
+            }
+            expr::Stmt::ClassDecl(classdecl) => {
+                if !self.scopes.is_empty() {
+                    self.declare_class(classdecl);
+                }
+                let class = classdecl.name.name.clone();
+                for method in &classdecl.methods {
+                    let decl = &method.decl;
+                    let sig = self.classes[&class].methods[&method.kind][&decl.name.name].clone();
+                    let this = match method.kind {
+                        expr::MethodKind::Static => Type::Class(class.clone()),
+                        _ => Type::Instance(class.clone()),
+                    };
+                    self.class_stack.push((class.clone(), this));
+                    let at = location(&decl.name);
+                    if method.kind == expr::MethodKind::Method && decl.name.name == "init" {
+                        // an initializer always returns its instance, whatever it says
+                        let sig = sig.map(|sig| {
+                            Rc::new(Signature {
+                                params: sig.params.clone(),
+                                ret: Type::Any,
+                            })
+                        });
+                        self.function("init", at, &decl.params, &sig, None, &decl.body);
+                    } else {
+                        let yields = self.yields(decl);
+                        let name = &decl.name.name;
+                        self.function(name, at, &decl.params, &sig, yields, &decl.body);
+                    }
+                    self.class_stack.pop();
+                }
+            }
+            expr::Stmt::If(cond, then_branch, else_branch) => {
+                self.expr(cond);
+                self.stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.stmt(else_branch);
+                }
+            }
+            expr::Stmt::VarDecl(sym, annotation, initializer) => {
+                let found = initializer.as_ref().map(|e| (e.location(), self.expr(e)));
+                let ty = match annotation {
+                    Some(annotation) => self.resolve(annotation),
+                    None => {
+                        self.declare(sym, Type::Any, None);
+                        return;
+                    }
+                };
+                match found {
+                    Some((at, found)) => self.expect(&ty, &found, at, || {
+                        format!("'{}' is declared {}", sym.name, ty)
+                    }),
+                    None if !self.accepts(&ty, &Type::Nil) => {
+                        self.errors.push(Error::Uninitialized {
+                            name: sym.name.clone(),
+                            ty: ty.clone(),
+                            line: sym.line,
+                            col: sym.col,
+                        })
+                    }
+                    None => {}
+                }
+                self.declare(sym, ty, annotation.clone());
+            }
+            expr::Stmt::Block(_, stmts) => self.block(stmts),
+            expr::Stmt::Return(loc, val) => {
+                let (at, found) = match val {
+                    Some(val) => (val.location(), self.expr(val)),
+                    None => (*loc, Type::Nil),
+                };
+                // the REPL returns its trailing expression from the top level, and a generator
+                // can only return nothing
+                if let Some((name, ret, false)) = self.functions.last().cloned() {
+                    self.expect(&ret, &found, at, || {
+                        format!("'{}' is declared to return {}", name, ret)
+                    });
+                }
+            }
+            expr::Stmt::While(cond, body) => {
+                self.expr(cond);
+                self.stmt(body);
+            }
+            expr::Stmt::For {
+                initializer,
+                condition,
+                increment,
+                body,
+                ..
+            } => {
+                self.scopes.push(HashMap::new());
+                if let Some(initializer) = initializer {
+                    self.stmt(initializer);
+                }
+                if let Some(condition) = condition {
+                    self.expr(condition);
+                }
+                if let Some(increment) = increment {
+                    self.expr(increment);
+                }
+                self.stmt(body);
+                self.scopes.pop();
+            }
+            expr::Stmt::ForIn {
+                variable,
+                iterable,
+                body,
+                ..
+            } => {
+                let checked = self.annotated(iterable);
+                let found = self.expr(iterable);
+                if checked
+                    && !matches!(
+                        found,
+                        Type::Any | Type::Str | Type::List | Type::Map | Type::Instance(_)
+                    )
+                {
+                    let at = iterable.location();
+                    self.errors.push(Error::InvalidOperands {
+                        op: "in",
+                        types: vec![found],
+                        line: at.line,
+                        col: at.col,
+                    });
+                }
+                self.scopes.push(HashMap::new());
+                self.declare(variable, Type::Any, None);
+                self.stmt(body);
+                self.scopes.pop();
+            }
+            expr::Stmt::Labeled(_, body) => self.stmt(body),
+            expr::Stmt::Break(..) | expr::Stmt::Continue(..) => {}
+            expr::Stmt::Import(import) => {
+                if let Some(name) = &import.name {
+                    self.declare(name, Type::Any, None);
+                }
+            }
+            expr::Stmt::Throw(_, e) => {
+                self.expr(e);
+            }
+            expr::Stmt::Try {
+                body,
+                catch,
+                finally,
+                ..
+            } => {
+                self.block(body);
+                if let Some((name, handler)) = catch {
+                    // anything can be thrown
+                    self.scopes.push(HashMap::new());
+                    self.declare(name, Type::Any, None);
+                    for stmt in handler {
+                        self.stmt(stmt);
+                    }
+                    self.scopes.pop();
+                }
+                if let Some(finally) = finally {
+                    self.block(finally);
+                }
+            }
+        }
+    }
+
+    fn block(&mut self, stmts: &[expr::Stmt]) {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.stmt(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    /// Checks a function's body. A generator has what it `yields` checked instead of what it
+    /// returns.
+    fn function(
+        &mut self,
+        name: &str,
+        at: expr::SourceLocation,
+        params: &[expr::Param],
+        sig: &Option<Rc<Signature>>,
+        yields: Option<Type>,
+        body: &[expr::Stmt],
+    ) {
+        self.scopes.push(HashMap::new());
+        for (idx, param) in params.iter().enumerate() {
+            let ty = match sig {
+                Some(sig) => sig.params[idx].clone(),
+                None => Type::Any,
+            };
+            self.declare(&param.name, ty, param.ty.clone());
+        }
+        let generator = yields.is_some();
+        let ret = match yields {
+            Some(yields) => yields,
+            None => sig.as_ref().map_or(Type::Any, |sig| sig.ret.clone()),
+        };
+        self.functions
+            .push((name.to_string(), ret.clone(), generator));
+
+        for stmt in body {
+            self.stmt(stmt);
+        }
+        if !generator && !self.accepts(&ret, &Type::Nil) && !always_returns(body) {
+            self.errors.push(Error::MissingReturn {
+                name: name.to_string(),
+                ty: ret,
+                line: at.line,
+                col: at.col,
+            });
+        }
+
+        self.functions.pop();
+        self.scopes.pop();
+    }
+
+    fn expr(&mut self, e: &expr::Expr) -> Type {
+        match e {
+            expr::Expr::Literal(_, literal) => match literal {
+                expr::Literal::Number(_) => Type::Num,
+                expr::Literal::String(_) => Type::Str,
+                expr::Literal::True | expr::Literal::False => Type::Bool,
+                expr::Literal::Nil => Type::Nil,
+            },
+            expr::Expr::This(_) => match self.class_stack.last() {
+                Some((_, this)) => this.clone(),
+                None => Type::Any,
+            },
+            expr::Expr::Unary(op, operand) => {
+                let ty = self.expr(operand);
+                match op.ty {
+                    expr::UnaryOpTy::Minus | expr::UnaryOpTy::Tilde => {
+                        if self.annotated(operand) && !self.accepts(&Type::Num, &ty) {
+                            self.errors.push(Error::InvalidOperands {
+                                op: match op.ty {
+                                    expr::UnaryOpTy::Minus => "-",
+                                    _ => "~",
+                                },
+                                types: vec![ty],
+                                line: op.line,
+                                col: op.col,
+                            });
+                        }
+                        Type::Num
+                    }
+                    expr::UnaryOpTy::Bang => Type::Bool,
+                }
+            }
+            expr::Expr::Binary(lhs, op, rhs) => {
+                let checked = self.annotated(lhs) || self.annotated(rhs);
+                let lhs = self.expr(lhs);
+                let rhs = self.expr(rhs);
+                self.binary(lhs, *op, rhs, checked)
+            }
+            expr::Expr::Call(callee, loc, args) => {
+                let callee_ty = self.expr(callee);
+                let args: Vec<(expr::SourceLocation, Type)> = args
+                    .iter()
+                    .map(|arg| (arg.location(), self.expr(arg)))
+                    .collect();
+                let name = match callee.as_ref() {
+                    expr::Expr::Variable(sym) | expr::Expr::Get(_, sym) => sym.name.clone(),
+                    _ => "function".to_string(),
+                };
+                match callee_ty {
+                    Type::Any | Type::Fun(None) => Type::Any,
+                    Type::Fun(Some(sig)) => {
+                        self.arguments(&name, &sig, &args, *loc);
+                        sig.ret.clone()
+                    }
+                    Type::Class(class) => {
+                        if let Type::Fun(Some(sig)) = self.method(&class, "init") {
+                            self.arguments(&name, &sig, &args, *loc);
+                        }
+                        Type::Instance(class)
+                    }
+                    _ if !self.annotated(callee) => Type::Any,
+                    found => {
+                        let at = callee.location();
+                        self.errors.push(Error::NotCallable {
+                            found,
+                            line: at.line,
+                            col: at.col,
+                        });
+                        Type::Any
+                    }
+                }
+            }
+            expr::Expr::Get(object, name) => match self.expr(object) {
+                Type::Instance(class) => self.property(&class, &name.name),
+                Type::Class(class) => match self.find(&class, expr::MethodKind::Static, &name.name)
+                {
+                    Some(sig) => Type::Fun(sig),
+                    None => Type::Any,
+                },
+                _ => Type::Any,
+            },
+            expr::Expr::Grouping(e) => self.expr(e),
+            expr::Expr::Variable(sym) => match self.lookup(&sym.name) {
+                Some(binding) => binding.ty.clone(),
+                // natives, and globals the REPL defined in earlier inputs
+                None => Type::Any,
+            },
+            expr::Expr::Assign(sym, val) => {
+                let found = self.expr(val);
+                self.assign(sym, found, val.location())
+            }
+            expr::Expr::Logical(lhs, _, rhs) => {
+                let lhs = self.expr(lhs);
+                let rhs = self.expr(rhs);
+                if lhs == rhs {
+                    lhs
+                } else {
+                    Type::Any
+                }
+            }
+            expr::Expr::Set(object, name, val) => {
+                let object = self.expr(object);
+                let at = val.location();
+                let found = self.expr(val);
+                if let Type::Instance(class) = object {
+                    if let Some(Some(sig)) = self.find(&class, expr::MethodKind::Setter, &name.name)
+                    {
+                        let expected = sig.params[0].clone();
+                        self.expect(&expected, &found, at, || {
+                            format!("the setter for '{}' takes {}", name.name, expected)
+                        });
+                    }
+                }
+                found
+            }
+            expr::Expr::Super(_, method) => {
+                let superclass = self
+                    .class_stack
+                    .last()
+                    .and_then(|(class, _)| self.superclass(class))
+                    .map(str::to_string);
+                match superclass {
+                    Some(superclass) => self.property(&superclass, &method.name),
+                    None => Type::Any,
+                }
+            }
+            expr::Expr::List(_, elements) => {
+                for element in elements {
+                    self.expr(element);
+                }
+                Type::List
+            }
+            expr::Expr::Map(_, entries) => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+                Type::Map
+            }
+            expr::Expr::Subscript {
+                value,
+                slice,
+                source_location,
+            } => {
+                let checked = self.annotated(value);
+                let value = self.expr(value);
+                self.index(slice, &value, checked);
+                match value {
+                    Type::Str => Type::Str,
+                    Type::List | Type::Map | Type::Any => Type::Any,
+                    _ if !checked => Type::Any,
+                    found => {
+                        self.errors.push(Error::InvalidOperands {
+                            op: "[]",
+                            types: vec![found],
+                            line: source_location.line,
+                            col: source_location.col,
+                        });
+                        Type::Any
+                    }
+                }
+            }
+            expr::Expr::SetItem {
+                lhs, slice, rhs, ..
+            } => {
+                let at = lhs.location();
+                let checked = self.annotated(lhs);
+                let found = self.expr(lhs);
+                if checked && found != Type::Map {
+                    self.expect(&Type::List, &found, at, || {
+                        "only list and map items can be assigned to".to_string()
+                    });
+                }
+                self.index(slice, &found, checked);
+                self.expr(rhs)
+            }
+            expr::Expr::Lambda(lambda) => {
+                let sig = self.signature(&lambda.params, &lambda.return_type);
+                self.function(
+                    "lambda",
+                    lambda.location,
+                    &lambda.params,
+                    &sig,
+                    None,
+                    &lambda.body,
+                );
+                Type::Fun(sig)
+            }
+            expr::Expr::CompoundAssign(target, op, val) => {
+                let checked = self.annotated(target) || self.annotated(val);
+                let lhs = self.expr(target);
+                let rhs = self.expr(val);
+                let found = self.binary(lhs, *op, rhs, checked);
+                match target.as_ref() {
+                    expr::Expr::Variable(sym) => self.assign(sym, found, val.location()),
+                    _ => found,
+                }
+            }
+            expr::Expr::Increment { target, op, .. } => {
+                let ty = self.expr(target);
+                if self.annotated(target) && !self.accepts(&Type::Num, &ty) {
+                    self.errors.push(Error::InvalidOperands {
+                        op: match op.ty {
+                            expr::BinaryOpTy::Plus => "++",
+                            _ => "--",
+                        },
+                        types: vec![ty],
+                        line: op.line,
+                        col: op.col,
+                    });
+                }
+                Type::Num
+            }
+            expr::Expr::Ternary(cond, then_branch, else_branch) => {
+                self.expr(cond);
+                let then_ty = self.expr(then_branch);
+                let else_ty = self.expr(else_branch);
+                if then_ty == else_ty {
+                    then_ty
+                } else {
+                    Type::Any
+                }
+            }
+            expr::Expr::Interpolation(_, parts) => {
+                for part in parts {
+                    self.expr(part);
+                }
+                Type::Str
+            }
+            expr::Expr::Yield(loc, val) => {
+                let (at, found) = match val {
+                    Some(val) => (val.location(), self.expr(val)),
+                    None => (*loc, Type::Nil),
+                };
+                if let Some((name, yields, true)) = self.functions.last().cloned() {
+                    self.expect(&yields, &found, at, || {
+                        format!("'{}' is declared to yield {}", name, yields)
+                    });
+                }
+                // whatever the generator is resumed with
+                Type::Any
+            }
+        }
+    }
+
+    /// Gives the variable `sym` a value of type `found`, which came from the expression at `at`.
+    fn assign(&mut self, sym: &expr::Symbol, found: Type, at: expr::SourceLocation) -> Type {
+        let (expected, annotation) = match self.lookup(&sym.name) {
+            Some(binding) if binding.annotation.is_some() => {
+                (binding.ty.clone(), binding.annotation.clone().unwrap())
+            }
+            Some(binding) => {
+                // an unannotated variable holds whatever it is given
+                binding.ty = Type::Any;
+                return found;
+            }
+            None => return found,
+        };
+        self.expect(&expected, &found, at, || {
+            format!(
+                "'{}' is declared {} on line {}",
+                sym.name, expected, annotation.line
+            )
+        });
+        found
+    }
+
+    /// Lists and strings, and whatever else is subscripted by mistake, take numbers. Maps take
+    /// keys of any type, so a container of unknown type might too.
+    fn index(&mut self, slice: &expr::Expr, container: &Type, checked: bool) {
+        let at = slice.location();
+        let checked = checked || self.annotated(slice);
+        let found = self.expr(slice);
+        if checked && !matches!(container, Type::Map | Type::Any) {
+            self.expect(&Type::Num, &found, at, || "indices are numbers".to_string());
+        }
+    }
+
+    /// Whether an annotation has a say in the type of `e`. Mistakes in expressions made only
+    /// of literals and unannotated names are left for the runtime to report, as they always
+    /// were.
+    fn annotated(&self, e: &expr::Expr) -> bool {
+        match e {
+            expr::Expr::Variable(sym) | expr::Expr::Assign(sym, _) => self
+                .binding(&sym.name)
+                .is_some_and(|binding| binding.annotation.is_some()),
+            expr::Expr::Unary(_, e)
+            | expr::Expr::Grouping(e)
+            | expr::Expr::Call(e, _, _)
+            | expr::Expr::Get(e, _) => self.annotated(e),
+            expr::Expr::Binary(lhs, _, rhs)
+            | expr::Expr::Logical(lhs, _, rhs)
+            | expr::Expr::CompoundAssign(lhs, _, rhs) => self.annotated(lhs) || self.annotated(rhs),
+            expr::Expr::Increment { target, .. } => self.annotated(target),
+            expr::Expr::Subscript { value, slice, .. } => {
+                self.annotated(value) || self.annotated(slice)
+            }
+            expr::Expr::Lambda(lambda) => {
+                lambda.return_type.is_some() || lambda.params.iter().any(|param| param.ty.is_some())
+            }
+            _ => false,
+        }
+    }
+
+    fn binary(&mut self, lhs: Type, op: expr::BinaryOp, rhs: Type, checked: bool) -> Type {
+        let (symbol, result) = match op.ty {
+            expr::BinaryOpTy::EqualEqual | expr::BinaryOpTy::NotEqual => return Type::Bool,
+            // + adds numbers and joins strings and lists
+            expr::BinaryOpTy::Plus => match (&lhs, &rhs) {
+                (Type::Any, Type::Any) => return Type::Any,
+                (ty @ (Type::Num | Type::Str | Type::List), other)
+                | (other @ Type::Any, ty @ (Type::Num | Type::Str | Type::List))
+                    if other == ty || *other == Type::Any =>
+                {
+                    return ty.clone()
+                }
+                _ => ("+", Type::Any),
+            },
+            expr::BinaryOpTy::Minus => ("-", Type::Num),
+            expr::BinaryOpTy::Star => ("*", Type::Num),
+            expr::BinaryOpTy::Slash => ("/", Type::Num),
+            expr::BinaryOpTy::Percent => ("%", Type::Num),
+            expr::BinaryOpTy::StarStar => ("**", Type::Num),
+            expr::BinaryOpTy::Ampersand => ("&", Type::Num),
+            expr::BinaryOpTy::Pipe => ("|", Type::Num),
+            expr::BinaryOpTy::Caret => ("^", Type::Num),
+            expr::BinaryOpTy::LessLess => ("<<", Type::Num),
+            expr::BinaryOpTy::GreaterGreater => (">>", Type::Num),
+            expr::BinaryOpTy::Less => ("<", Type::Bool),
+            expr::BinaryOpTy::LessEqual => ("<=", Type::Bool),
+            expr::BinaryOpTy::Greater => (">", Type::Bool),
+            expr::BinaryOpTy::GreaterEqual => (">=", Type::Bool),
+        };
+        let numbers = self.accepts(&Type::Num, &lhs) && self.accepts(&Type::Num, &rhs);
+        if checked && (symbol == "+" || !numbers) {
+            self.errors.push(Error::InvalidOperands {
+                op: symbol,
+                types: vec![lhs, rhs],
+                line: op.line,
+                col: op.col,
+            });
+        }
+        result
+    }
+
+    fn arguments(
+        &mut self,
+        name: &str,
+        sig: &Signature,
+        args: &[(expr::SourceLocation, Type)],
+        call: expr::SourceLocation,
+    ) {
+        if args.len() != sig.params.len() {
+            self.errors.push(Error::WrongArity {
+                name: name.to_string(),
+                expected: sig.params.len(),
+                found: args.len(),
+                line: call.line,
+                col: call.col,
+            });
+            return;
+        }
+        for (idx, ((at, found), expected)) in args.iter().zip(&sig.params).enumerate() {
+            self.expect(expected, found, *at, || {
+                format!("parameter {} of '{}' is {}", idx + 1, name, expected)
+            });
+        }
+    }
+}
+
+/// Whether running `stmts` always ends in a `return` or a `throw`.
+fn always_returns(stmts: &[expr::Stmt]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        expr::Stmt::Return(..) | expr::Stmt::Throw(..) => true,
+        expr::Stmt::Block(_, stmts) => always_returns(stmts),
+        expr::Stmt::Try {
+            body,
+            catch,
+            finally,
+            ..
+        } => {
+            let handled = catch
+                .as_ref()
+                .is_none_or(|(_, handler)| always_returns(handler));
+            (always_returns(body) && handled)
+                || finally
+                    .as_ref()
+                    .is_some_and(|finally| always_returns(finally))
+        }
+        expr::Stmt::If(_, then_branch, Some(else_branch)) => {
+            always_returns(std::slice::from_ref(&**then_branch))
+                && always_returns(std::slice::from_ref(&**else_branch))
+        }
+        _ => false,
+    })
+}
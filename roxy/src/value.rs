@@ -1,4 +1,391 @@
+use crate::err::RoxyErr;
+use crate::expr;
+use crate::interpreter::{Environment, Interpreter, Resume};
+use crate::map::{Key, Map};
+use crate::resolver::Locals;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
 #[derive(Clone)]
 pub enum Value {
     Number(f64),
-}
\ No newline at end of file
+    String(String),
+    Bool(bool),
+    Nil,
+    NativeFunction(NativeFunction),
+    Function(Rc<RoxyFunction>),
+    Class(Rc<RoxyClass>),
+    Instance(Rc<RefCell<RoxyInstance>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Map<Value>>>),
+    Generator(Rc<RefCell<RoxyGenerator>>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Type {
+    Number,
+    String,
+    Bool,
+    Nil,
+    NativeFunction,
+    Function,
+    Class,
+    Instance,
+    List,
+    Map,
+    Generator,
+}
+
+impl Value {
+    pub fn type_of(&self) -> Type {
+        match self {
+            Value::Number(_) => Type::Number,
+            Value::String(_) => Type::String,
+            Value::Bool(_) => Type::Bool,
+            Value::Nil => Type::Nil,
+            Value::NativeFunction(_) => Type::NativeFunction,
+            Value::Function(_) => Type::Function,
+            Value::Class(_) => Type::Class,
+            Value::Instance(_) => Type::Instance,
+            Value::List(_) => Type::List,
+            Value::Map(_) => Type::Map,
+            Value::Generator(_) => Type::Generator,
+        }
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// Numbers, strings, booleans and nil compare by value, everything else by identity.
+    pub fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(n1), Value::Number(n2)) => n1 == n2,
+            (Value::String(s1), Value::String(s2)) => s1 == s2,
+            (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
+            (Value::Nil, Value::Nil) => true,
+            (Value::NativeFunction(f1), Value::NativeFunction(f2)) => f1.name == f2.name,
+            (Value::Function(f1), Value::Function(f2)) => Rc::ptr_eq(f1, f2),
+            (Value::Class(c1), Value::Class(c2)) => Rc::ptr_eq(c1, c2),
+            (Value::Instance(i1), Value::Instance(i2)) => Rc::ptr_eq(i1, i2),
+            (Value::List(l1), Value::List(l2)) => Rc::ptr_eq(l1, l2),
+            (Value::Map(m1), Value::Map(m2)) => Rc::ptr_eq(m1, m2),
+            (Value::Generator(g1), Value::Generator(g2)) => Rc::ptr_eq(g1, g2),
+            (_, _) => false,
+        }
+    }
+
+    /// What the value is looked up by as a map key; only nil, booleans, numbers and strings
+    /// can be keys.
+    pub fn to_key(&self) -> Option<Key> {
+        match self {
+            Value::Nil => Some(Key::Nil),
+            Value::Bool(b) => Some(Key::Bool(*b)),
+            Value::Number(n) => Some(Key::number(*n)),
+            Value::String(s) => Some(Key::String(s.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "\"{}\"", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Nil => write!(f, "nil"),
+            Value::NativeFunction(func) => write!(f, "<native fn {}>", func.name),
+            Value::Function(func) => write!(f, "<fn {}>", func.name.name),
+            Value::Class(class) => write!(f, "<class {}>", class.name),
+            Value::Instance(instance) => write!(f, "<{} instance>", instance.borrow().class.name),
+            Value::List(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}", element)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().entries().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{:?}: {:?}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Generator(generator) => write!(f, "<generator {}>", generator.borrow().name),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+pub trait Callable {
+    fn arity(&self) -> u8;
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, RoxyErr>;
+}
+
+/// The Rust side of a native function; closures let embedders capture their own state.
+pub type NativeCallable = dyn Fn(&mut Interpreter, &[Value]) -> Result<Value, RoxyErr>;
+
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: u8,
+    pub callable: Rc<NativeCallable>,
+}
+
+impl Callable for NativeFunction {
+    fn arity(&self) -> u8 {
+        self.arity
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, RoxyErr> {
+        (self.callable)(interpreter, &args)
+    }
+}
+
+pub struct RoxyFunction {
+    pub name: expr::Symbol,
+    pub params: Vec<expr::Symbol>,
+    pub body: Rc<Vec<expr::Stmt>>,
+    pub closure: Rc<RefCell<Environment>>,
+    /// the scope depths of the program that declared it
+    pub locals: Rc<Locals>,
+    pub is_initializer: bool,
+    /// calling it makes a `RoxyGenerator` instead of running the body
+    pub is_generator: bool,
+}
+
+impl RoxyFunction {
+    /// Returns a copy of this method whose closure has `this` bound to `instance`, or to the
+    /// class for a static method.
+    pub fn bind(&self, instance: Value) -> RoxyFunction {
+        let mut env = Environment::with_enclosing(self.closure.clone());
+        env.define("this".to_string(), instance);
+
+        RoxyFunction {
+            name: self.name.clone(),
+            params: self.params.clone(),
+            body: self.body.clone(),
+            closure: Rc::new(RefCell::new(env)),
+            locals: self.locals.clone(),
+            is_initializer: self.is_initializer,
+            is_generator: self.is_generator,
+        }
+    }
+}
+
+impl Callable for RoxyFunction {
+    fn arity(&self) -> u8 {
+        self.params.len() as u8
+    }
+
+    fn call(&self, interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, RoxyErr> {
+        let mut env = Environment::with_enclosing(self.closure.clone());
+        for (param, arg) in self.params.iter().zip(args) {
+            env.define(param.name.clone(), arg);
+        }
+
+        if self.is_generator {
+            // the body starts at its first statement, in the scope holding the arguments
+            let start = Resume::Block(0, Rc::new(RefCell::new(env)));
+            return Ok(Value::Generator(Rc::new(RefCell::new(RoxyGenerator {
+                name: self.name.name.clone(),
+                body: self.body.clone(),
+                locals: self.locals.clone(),
+                state: GeneratorState::Suspended(vec![start]),
+            }))));
+        }
+
+        let retval = interpreter.call_body(&self.name.name, &self.body, env, &self.locals)?;
+
+        if self.is_initializer {
+            return Ok(self.closure.borrow().get_this());
+        }
+        Ok(retval.unwrap_or(Value::Nil))
+    }
+}
+
+/// What a call to a generator function made: the body it runs and how far it has got.
+pub struct RoxyGenerator {
+    pub name: String,
+    pub body: Rc<Vec<expr::Stmt>>,
+    pub locals: Rc<Locals>,
+    pub state: GeneratorState,
+}
+
+pub enum GeneratorState {
+    /// waiting to be resumed, with what the statements it stopped in need to carry on, the
+    /// `yield` first and the body's block last
+    Suspended(Vec<Resume>),
+    Running,
+    Done,
+}
+
+pub type Methods = HashMap<String, Rc<RoxyFunction>>;
+
+pub struct RoxyClass {
+    pub name: String,
+    pub superclass: Option<Rc<RoxyClass>>,
+    /// the class's own methods of each kind, along with the ones its mixins brought in
+    pub methods: HashMap<expr::MethodKind, Methods>,
+}
+
+impl RoxyClass {
+    /// Looks for a method of `kind` in the class and then up its superclasses.
+    pub fn find(&self, kind: expr::MethodKind, name: &str) -> Option<Rc<RoxyFunction>> {
+        match self
+            .methods
+            .get(&kind)
+            .and_then(|methods| methods.get(name))
+        {
+            Some(method) => Some(method.clone()),
+            None => match &self.superclass {
+                Some(superclass) => superclass.find(kind, name),
+                None => None,
+            },
+        }
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Rc<RoxyFunction>> {
+        self.find(expr::MethodKind::Method, name)
+    }
+
+    /// Every method of `kind` the class has, inherited ones included; what a class mixing
+    /// this one in gets.
+    pub fn all_methods(&self, kind: expr::MethodKind) -> Methods {
+        let mut methods = match &self.superclass {
+            Some(superclass) => superclass.all_methods(kind),
+            None => Methods::new(),
+        };
+        if let Some(own) = self.methods.get(&kind) {
+            methods.extend(
+                own.iter()
+                    .map(|(name, method)| (name.clone(), method.clone())),
+            );
+        }
+        methods
+    }
+}
+
+/// Instantiating a class is a call on the class object itself; `init` (if any) decides the arity.
+pub fn instantiate(
+    class: &Rc<RoxyClass>,
+    interpreter: &mut Interpreter,
+    args: Vec<Value>,
+) -> Result<Value, RoxyErr> {
+    let instance = Value::Instance(Rc::new(RefCell::new(RoxyInstance {
+        class: class.clone(),
+        fields: HashMap::new(),
+    })));
+
+    if let Some(initializer) = class.find_method("init") {
+        initializer.bind(instance.clone()).call(interpreter, args)?;
+    }
+
+    Ok(instance)
+}
+
+pub fn class_arity(class: &RoxyClass) -> u8 {
+    match class.find_method("init") {
+        Some(initializer) => initializer.arity(),
+        None => 0,
+    }
+}
+
+pub struct RoxyInstance {
+    pub class: Rc<RoxyClass>,
+    pub fields: HashMap<String, Value>,
+}
+
+impl RoxyInstance {
+    pub fn get(
+        instance: &Rc<RefCell<RoxyInstance>>,
+        attr: &expr::Symbol,
+    ) -> Result<Value, RoxyErr> {
+        if let Some(value) = instance.borrow().fields.get(&attr.name) {
+            return Ok(value.clone());
+        }
+
+        let class = instance.borrow().class.clone();
+        match class.find_method(&attr.name) {
+            Some(method) => Ok(Value::Function(Rc::new(
+                method.bind(Value::Instance(instance.clone())),
+            ))),
+            None => Err(RoxyErr::RuntimeError {
+                what: format!(
+                    "undefined property '{}' on {} instance",
+                    attr.name, class.name
+                ),
+                line: attr.line,
+                col: attr.col,
+                trace: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// The bitwise operators work on numbers without a fractional part, as 64-bit integers. The
+/// error says why the operands don't suit `op`; both backends locate it at the operator.
+pub fn bitwise(op: expr::BinaryOpTy, lhs: f64, rhs: f64) -> Result<f64, String> {
+    let (a, b) = match (to_integer(lhs), to_integer(rhs)) {
+        (Some(a), Some(b)) => (a, b),
+        _ => {
+            return Err(format!(
+                "bitwise operator {:?} needs integer operands, found {} and {}",
+                op, lhs, rhs
+            ))
+        }
+    };
+    let shift = || {
+        u32::try_from(b)
+            .ok()
+            .filter(|shift| *shift < 64)
+            .ok_or_else(|| format!("cannot shift by {}", rhs))
+    };
+    let n = match op {
+        expr::BinaryOpTy::Ampersand => a & b,
+        expr::BinaryOpTy::Pipe => a | b,
+        expr::BinaryOpTy::Caret => a ^ b,
+        expr::BinaryOpTy::LessLess => a << shift()?,
+        expr::BinaryOpTy::GreaterGreater => a >> shift()?,
+        _ => panic!("internal error: {:?} is not a bitwise operator", op),
+    };
+    Ok(n as f64)
+}
+
+/// `~n`, with the same restriction on `n` as `bitwise`.
+pub fn bitwise_not(n: f64) -> Result<f64, String> {
+    match to_integer(n) {
+        Some(n) => Ok(!n as f64),
+        None => Err(format!(
+            "bitwise operator Tilde needs an integer, found {}",
+            n
+        )),
+    }
+}
+
+fn to_integer(n: f64) -> Option<i64> {
+    if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Some(n as i64)
+    } else {
+        None
+    }
+}
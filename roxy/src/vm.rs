@@ -0,0 +1,1183 @@
+use crate::bytecode::{Constant, Function, Op, UpvalueLoc};
+use crate::err::{Frame, RoxyErr};
+use crate::expr::{BinaryOpTy, MethodKind, SourceLocation};
+use crate::extensions::Extensions;
+use crate::heap::{
+    BoundMethod, Class, Closure, GcConfig, Generator, GeneratorState, Heap, HeapId, Instance,
+    NativeFunction, Object, Suspended, Upvalue, Value,
+};
+use crate::limits::{Budget, Limits};
+use crate::map::{Key, Map};
+use crate::stdlib;
+use crate::value;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+const FRAMES_MAX: usize = 1024;
+
+struct CallFrame {
+    closure: HeapId,
+    function: Rc<Function>,
+    ip: usize,
+    slots_offset: usize,
+    handlers: Vec<Handler>,
+    // errors caught by a finally handler, waiting for the `Rethrow` at the end of the block
+    unwinding: Vec<RoxyErr>,
+    // the generator whose body the frame is running
+    generator: Option<HeapId>,
+}
+
+/// Where to go when an error reaches a `try`, and how far the frame had got when it was entered.
+#[derive(Debug)]
+pub struct Handler {
+    target: usize,
+    stack_len: usize,
+    unwinding_len: usize,
+    finally: bool,
+}
+
+pub struct Vm {
+    heap: Heap,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<String, Value>,
+    // sorted by stack index so closing a scope only has to look at the tail
+    open_upvalues: Vec<HeapId>,
+    // the value behind the `RoxyErr::Thrown` currently unwinding
+    thrown: Option<Value>,
+    budget: Budget,
+}
+
+impl Default for Vm {
+    fn default() -> Vm {
+        Vm::new(GcConfig::default(), Extensions::default())
+    }
+}
+
+impl Vm {
+    /// A VM whose globals hold the natives from `stdlib::vm_natives`.
+    pub fn new(gc_config: GcConfig, extensions: Extensions) -> Vm {
+        let mut vm = Vm {
+            heap: Heap::new(gc_config),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            thrown: None,
+            budget: Budget::default(),
+        };
+        for native in stdlib::vm_natives(extensions) {
+            vm.define_native(native);
+        }
+        vm
+    }
+
+    /// Holds every program run from now on to `limits`, counting from now. Without I/O the
+    /// natives in `stdlib::IO_NATIVES` are removed from the globals.
+    pub fn set_limits(&mut self, limits: Limits) {
+        if !limits.io {
+            for name in stdlib::IO_NATIVES {
+                self.globals.remove(name);
+            }
+        }
+        self.budget = Budget::new(limits);
+    }
+
+    /// Starts counting steps, time and output towards the limits afresh.
+    pub fn restart_limits(&mut self) {
+        self.budget.restart();
+    }
+
+    pub fn define_native(&mut self, native: NativeFunction) {
+        self.globals
+            .insert(native.name.to_string(), Value::NativeFunction(native));
+    }
+
+    /// Runs a compiled script and hands back the value its top-level `return` produced, or nil.
+    pub fn interpret(&mut self, function: Function) -> Result<Value, RoxyErr> {
+        let function = Rc::new(function);
+        let closure = self.heap.alloc(Object::Closure(Closure {
+            function: function.clone(),
+            upvalues: Vec::new(),
+        }));
+        self.stack.push(Value::Closure(closure));
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            slots_offset: 0,
+            handlers: Vec::new(),
+            unwinding: Vec::new(),
+            generator: None,
+        });
+
+        let res = self.run(0);
+        if res.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+            self.thrown = None;
+        }
+        res
+    }
+
+    /// Calls `callee` from inside a native function and runs it to completion. Values the native
+    /// still needs afterwards must be kept on the stack with `push`, a collection may happen
+    /// while the callee runs.
+    pub fn call_function(&mut self, callee: Value, args: &[Value]) -> Result<Value, RoxyErr> {
+        let depth = self.frames.len();
+        self.stack.push(callee);
+        self.stack.extend_from_slice(args);
+        self.call_value(callee, args.len() as u8)?;
+        if self.frames.len() == depth {
+            // natives and classes without an initializer are already done
+            return Ok(self.pop());
+        }
+        self.run(depth)
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    pub fn push(&mut self, val: Value) {
+        self.stack.push(val);
+    }
+
+    pub fn pop(&mut self) -> Value {
+        self.stack
+            .pop()
+            .expect("internal vm error: stack underflow")
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Where the instruction being executed came from.
+    fn location(&self) -> SourceLocation {
+        let frame = self.frame();
+        frame.function.chunk.locations[frame.ip - 1]
+    }
+
+    fn runtime_error(&self, what: String) -> RoxyErr {
+        let loc = self.location();
+        RoxyErr::RuntimeError {
+            what,
+            line: loc.line,
+            col: loc.col,
+            trace: Vec::new(),
+        }
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn read_string(function: &Function, constant: usize) -> &str {
+        match &function.chunk.constants[constant] {
+            Constant::String(s) => s,
+            c => panic!("internal vm error: expected string constant, found {}", c),
+        }
+    }
+
+    /// Everything reachable from here is live: the stack, the globals, the closures of active
+    /// frames and the upvalues that still point into the stack.
+    fn collect_garbage(&mut self) {
+        let roots: Vec<Value> = self
+            .stack
+            .iter()
+            .chain(self.globals.values())
+            .chain(self.thrown.iter())
+            .copied()
+            .collect();
+        let root_ids: Vec<HeapId> = self
+            .frames
+            .iter()
+            .flat_map(|frame| std::iter::once(frame.closure).chain(frame.generator))
+            .chain(self.open_upvalues.iter().copied())
+            .collect();
+        self.heap.collect(&roots, &root_ids);
+    }
+
+    pub fn format_value(&self, val: &Value) -> String {
+        self.heap.format_value(val)
+    }
+
+    /// Executes until the frame that was entered at `depth` returns, handing errors to the
+    /// innermost `try` of the frames entered since.
+    fn run(&mut self, depth: usize) -> Result<Value, RoxyErr> {
+        loop {
+            match self.execute(depth) {
+                Err(err) => self.catch(err, depth)?,
+                res => return res,
+            }
+        }
+    }
+
+    /// Unwinds to the innermost handler above `depth` and resumes there with the caught value
+    /// pushed: whatever was thrown, or the message of a runtime error. Errors no handler takes
+    /// come back with the calls they unwound through added to their trace.
+    fn catch(&mut self, mut err: RoxyErr, depth: usize) -> Result<(), RoxyErr> {
+        let caught = match &err {
+            RoxyErr::Thrown { .. } => self.thrown,
+            RoxyErr::RuntimeError { what, .. } => Some(self.heap.alloc_string(what.clone())),
+            _ => None,
+        };
+
+        while self.frames.len() > depth {
+            if let Some(caught) = caught {
+                if let Some(handler) = self.frame_mut().handlers.pop() {
+                    self.thrown = None;
+                    self.close_upvalues(handler.stack_len);
+                    self.stack.truncate(handler.stack_len);
+                    self.stack.push(caught);
+                    let frame = self.frame_mut();
+                    frame.ip = handler.target;
+                    // errors left behind by finally blocks that were themselves left by an error
+                    frame.unwinding.truncate(handler.unwinding_len);
+                    if handler.finally {
+                        frame.unwinding.push(err);
+                    }
+                    return Ok(());
+                }
+            }
+
+            let frame = self.frames.pop().unwrap();
+            self.close_upvalues(frame.slots_offset);
+            self.stack.truncate(frame.slots_offset);
+            if let (
+                RoxyErr::RuntimeError { trace, .. } | RoxyErr::Thrown { trace, .. },
+                Some(caller),
+            ) = (&mut err, self.frames.last())
+            {
+                let call_site = caller.function.chunk.locations[caller.ip - 1];
+                trace.push(Frame {
+                    function: frame.function.name.clone(),
+                    line: call_site.line,
+                    col: call_site.col,
+                });
+            }
+        }
+        Err(err)
+    }
+
+    fn execute(&mut self, depth: usize) -> Result<Value, RoxyErr> {
+        loop {
+            let frame = self.frame_mut();
+            // a handle on the function of its own, so the instruction can be borrowed while
+            // the frames change under it
+            let function = frame.function.clone();
+            let op = &function.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            if let Err(limit) = self.budget.step() {
+                return Err(limit.exceeded_at(self.location()));
+            }
+            // only collect between instructions, when every live value is reachable from a root.
+            // A heap over its limit gets a collection to find out how much of it is live.
+            let over_limit = self.budget.heap(self.heap.bytes_allocated()).is_err();
+            if over_limit || self.heap.should_collect() {
+                self.collect_garbage();
+                if let Err(limit) = self.budget.heap(self.heap.bytes_allocated()) {
+                    return Err(limit.exceeded_at(self.location()));
+                }
+            }
+
+            match *op {
+                Op::Constant(idx) => {
+                    let val = match &function.chunk.constants[idx] {
+                        Constant::Number(n) => Value::Number(*n),
+                        Constant::String(s) => self.heap.intern(s),
+                        Constant::Function(_) => {
+                            panic!("internal vm error: function constant loaded directly")
+                        }
+                    };
+                    self.stack.push(val);
+                }
+                Op::Nil => self.stack.push(Value::Nil),
+                Op::True => self.stack.push(Value::Bool(true)),
+                Op::False => self.stack.push(Value::Bool(false)),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::Dup(distance) => {
+                    let val = self.peek(distance);
+                    self.stack.push(val);
+                }
+                Op::PopBelow(count) => {
+                    let top = self.pop();
+                    let len = self.stack.len();
+                    self.stack.truncate(len - count);
+                    self.stack.push(top);
+                }
+                Op::GetLocal(slot) => {
+                    let val = self.stack[self.frame().slots_offset + slot];
+                    self.stack.push(val);
+                }
+                Op::SetLocal(slot) => {
+                    let idx = self.frame().slots_offset + slot;
+                    self.stack[idx] = self.peek(0);
+                }
+                Op::GetGlobal(constant) => {
+                    let name = Vm::read_string(&function, constant);
+                    match self.globals.get(name) {
+                        Some(val) => self.stack.push(*val),
+                        None => {
+                            return Err(self.runtime_error(format!("undefined variable '{}'", name)))
+                        }
+                    }
+                }
+                Op::DefineGlobal(constant) => {
+                    let name = Vm::read_string(&function, constant);
+                    let val = self.pop();
+                    self.globals.insert(name.to_string(), val);
+                }
+                Op::SetGlobal(constant) => {
+                    let name = Vm::read_string(&function, constant);
+                    let val = self.peek(0);
+                    match self.globals.get_mut(name) {
+                        Some(global) => *global = val,
+                        None => {
+                            return Err(self.runtime_error(format!(
+                                "attempting to assign to undeclared variable '{}'",
+                                name
+                            )))
+                        }
+                    }
+                }
+                Op::GetUpval(idx) => {
+                    let upvalue = self.heap.get_closure(self.frame().closure).upvalues[idx];
+                    let val = match self.heap.get_upvalue(upvalue) {
+                        Upvalue::Open(stack_idx) => self.stack[*stack_idx],
+                        Upvalue::Closed(val) => *val,
+                    };
+                    self.stack.push(val);
+                }
+                Op::SetUpval(idx) => {
+                    let upvalue = self.heap.get_closure(self.frame().closure).upvalues[idx];
+                    let val = self.peek(0);
+                    match self.heap.get_upvalue_mut(upvalue) {
+                        Upvalue::Open(stack_idx) => {
+                            let stack_idx = *stack_idx;
+                            self.stack[stack_idx] = val;
+                        }
+                        Upvalue::Closed(closed) => *closed = val,
+                    }
+                }
+                Op::GetProperty(constant) => {
+                    let name = Vm::read_string(&function, constant);
+                    match self.peek(0) {
+                        Value::Instance(instance) => self.get_property(instance, name)?,
+                        Value::Class(class) => self.get_static(class, name)?,
+                        val => {
+                            return Err(self.runtime_error(format!(
+                                "only instances and classes have attributes, found {} when getting '{}'",
+                                val.type_name(),
+                                name
+                            )))
+                        }
+                    }
+                }
+                Op::SetProperty(constant) => {
+                    let name = Vm::read_string(&function, constant);
+                    match self.peek(1) {
+                        Value::Instance(instance) => self.set_property(instance, name)?,
+                        val => {
+                            return Err(self.runtime_error(format!(
+                                "only instances have fields, found {} when setting '{}'",
+                                val.type_name(),
+                                name
+                            )))
+                        }
+                    }
+                }
+                Op::GetSuper(constant) => {
+                    let name = Vm::read_string(&function, constant);
+                    let superclass = match self.pop() {
+                        Value::Class(class) => class,
+                        _ => panic!("internal vm error: 'super' is not bound to a class"),
+                    };
+                    let getter = self
+                        .heap
+                        .get_class(superclass)
+                        .find(MethodKind::Getter, name);
+                    match getter {
+                        Some(getter) => self.call(getter, 0)?,
+                        None => self.bind_method(superclass, name)?,
+                    }
+                }
+                Op::Equal => {
+                    let rhs = self.pop();
+                    let lhs = self.pop();
+                    let equal = self.heap.values_equal(&lhs, &rhs);
+                    self.stack.push(Value::Bool(equal));
+                }
+                Op::Greater => self.numeric_binop(|a, b| Value::Bool(a > b), "Greater")?,
+                Op::Less => self.numeric_binop(|a, b| Value::Bool(a < b), "Less")?,
+                Op::Add => match (self.peek(1), self.peek(0)) {
+                    (Value::String(s1), Value::String(s2)) => {
+                        let concatenated =
+                            format!("{}{}", self.heap.get_str(s1), self.heap.get_str(s2));
+                        let val = self.heap.alloc_string(concatenated);
+                        self.pop();
+                        self.pop();
+                        self.stack.push(val);
+                    }
+                    (Value::List(l1), Value::List(l2)) => {
+                        let mut elements = self.heap.get_list(l1).clone();
+                        elements.extend(self.heap.get_list(l2).iter().copied());
+                        let val = Value::List(self.heap.alloc(Object::List(elements)));
+                        self.pop();
+                        self.pop();
+                        self.stack.push(val);
+                    }
+                    _ => self.numeric_binop(|a, b| Value::Number(a + b), "Plus")?,
+                },
+                Op::Subtract => self.numeric_binop(|a, b| Value::Number(a - b), "Minus")?,
+                Op::Multiply => self.numeric_binop(|a, b| Value::Number(a * b), "Star")?,
+                Op::Divide => self.numeric_binop(|a, b| Value::Number(a / b), "Slash")?,
+                Op::Modulo => self.numeric_binop(|a, b| Value::Number(a % b), "Percent")?,
+                Op::Power => self.numeric_binop(|a, b| Value::Number(a.powf(b)), "StarStar")?,
+                Op::BitAnd => self.bitwise_binop(BinaryOpTy::Ampersand)?,
+                Op::BitOr => self.bitwise_binop(BinaryOpTy::Pipe)?,
+                Op::BitXor => self.bitwise_binop(BinaryOpTy::Caret)?,
+                Op::ShiftLeft => self.bitwise_binop(BinaryOpTy::LessLess)?,
+                Op::ShiftRight => self.bitwise_binop(BinaryOpTy::GreaterGreater)?,
+                Op::Not => {
+                    let val = self.pop();
+                    self.stack.push(Value::Bool(!val.is_truthy()));
+                }
+                Op::Negate => match self.pop() {
+                    Value::Number(n) => self.stack.push(Value::Number(-n)),
+                    val => {
+                        return Err(self.runtime_error(format!(
+                            "invalid application of unary op Minus to {}",
+                            self.heap.format_value(&val)
+                        )))
+                    }
+                },
+                Op::BitNot => match self.pop() {
+                    Value::Number(n) => match value::bitwise_not(n) {
+                        Ok(n) => self.stack.push(Value::Number(n)),
+                        Err(what) => return Err(self.runtime_error(what)),
+                    },
+                    val => {
+                        return Err(self.runtime_error(format!(
+                            "invalid application of unary op Tilde to {}",
+                            self.heap.format_value(&val)
+                        )))
+                    }
+                },
+                Op::Interpolate(count) => {
+                    let start = self.stack.len() - count;
+                    let joined: String = self.stack[start..]
+                        .iter()
+                        .map(|val| self.heap.display_value(val))
+                        .collect();
+                    self.stack.truncate(start);
+                    let val = self.heap.alloc_string(joined);
+                    self.stack.push(val);
+                }
+                Op::Print => {
+                    let val = self.pop();
+                    let text = self.heap.display_value(&val);
+                    if let Err(limit) = self.budget.output(text.len() + 1) {
+                        return Err(limit.exceeded_at(self.location()));
+                    }
+                    println!("{}", text);
+                }
+                Op::Jump(target) => self.frame_mut().ip = target,
+                Op::JumpIfFalse(target) => {
+                    if !self.peek(0).is_truthy() {
+                        self.frame_mut().ip = target;
+                    }
+                }
+                Op::Call(arg_count) => self.call_value(self.peek(arg_count as usize), arg_count)?,
+                Op::Closure(constant, ref upvalue_locs) => {
+                    let function = match &function.chunk.constants[constant] {
+                        Constant::Function(function) => function.clone(),
+                        c => panic!("internal vm error: expected function constant, found {}", c),
+                    };
+                    let slots_offset = self.frame().slots_offset;
+                    let enclosing = self.frame().closure;
+                    let mut upvalues = Vec::with_capacity(upvalue_locs.len());
+                    for &loc in upvalue_locs {
+                        upvalues.push(match loc {
+                            UpvalueLoc::Local(idx) => self.capture_upvalue(slots_offset + idx),
+                            UpvalueLoc::Upvalue(idx) => {
+                                self.heap.get_closure(enclosing).upvalues[idx]
+                            }
+                        });
+                    }
+                    let closure = self
+                        .heap
+                        .alloc(Object::Closure(Closure { function, upvalues }));
+                    self.stack.push(Value::Closure(closure));
+                }
+                Op::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                Op::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    if let Some(generator) = frame.generator {
+                        self.heap.get_generator_mut(generator).state = GeneratorState::Done;
+                    }
+                    self.close_upvalues(frame.slots_offset);
+                    self.stack.truncate(frame.slots_offset);
+                    if self.frames.len() == depth {
+                        return Ok(result);
+                    }
+                    self.stack.push(result);
+                }
+                Op::Class(constant) => {
+                    let name = Vm::read_string(&function, constant);
+                    let class = self.heap.alloc(Object::Class(Class {
+                        name: name.to_string(),
+                        methods: HashMap::new(),
+                    }));
+                    self.stack.push(Value::Class(class));
+                }
+                Op::Inherit(constant) => {
+                    let superclass = match self.peek(1) {
+                        Value::Class(superclass) => superclass,
+                        val => {
+                            return Err(self.runtime_error(format!(
+                                "superclass '{}' must be a class, found {}",
+                                Vm::read_string(&function, constant),
+                                val.type_name()
+                            )))
+                        }
+                    };
+                    let subclass = match self.pop() {
+                        Value::Class(subclass) => subclass,
+                        _ => panic!("internal vm error: inheriting into a non-class"),
+                    };
+                    self.copy_methods(superclass, subclass);
+                }
+                Op::Mixin(constant) => {
+                    let class = match self.pop() {
+                        Value::Class(class) => class,
+                        _ => panic!("internal vm error: mixing into a non-class"),
+                    };
+                    let mixin = match self.pop() {
+                        Value::Class(mixin) => mixin,
+                        val => {
+                            return Err(self.runtime_error(format!(
+                                "mixin '{}' must be a class, found {}",
+                                Vm::read_string(&function, constant),
+                                val.type_name()
+                            )))
+                        }
+                    };
+                    self.copy_methods(mixin, class);
+                }
+                Op::Method(constant, kind) => {
+                    let name = Vm::read_string(&function, constant);
+                    let method = match self.pop() {
+                        Value::Closure(closure) => closure,
+                        _ => panic!("internal vm error: method is not a closure"),
+                    };
+                    match self.peek(0) {
+                        Value::Class(class) => {
+                            let methods = &mut self.heap.get_class_mut(class).methods;
+                            methods
+                                .entry(kind)
+                                .or_default()
+                                .insert(name.to_string(), method);
+                        }
+                        _ => panic!("internal vm error: defining a method on a non-class"),
+                    }
+                }
+                Op::BuildList(len) => {
+                    let elements = self.stack.split_off(self.stack.len() - len);
+                    let list = self.heap.alloc(Object::List(elements));
+                    self.stack.push(Value::List(list));
+                }
+                Op::BuildMap(len) => {
+                    let pairs = self.stack.split_off(self.stack.len() - 2 * len);
+                    let mut map = Map::default();
+                    for pair in pairs.chunks(2) {
+                        let key = self.map_key(&pair[0])?;
+                        map.insert(key, pair[0], pair[1]);
+                    }
+                    let map = self.heap.alloc(Object::Map(map));
+                    self.stack.push(Value::Map(map));
+                }
+                Op::Subscript => {
+                    let slice = self.pop();
+                    let value = self.pop();
+                    let val = match value {
+                        Value::List(list) => {
+                            let len = self.heap.get_list(list).len();
+                            let idx = self.list_index(&slice, len)?;
+                            self.heap.get_list(list)[idx]
+                        }
+                        Value::String(s) => {
+                            let chars: Vec<char> = self.heap.get_str(s).chars().collect();
+                            let idx = self.list_index(&slice, chars.len())?;
+                            self.heap.alloc_string(chars[idx].to_string())
+                        }
+                        Value::Map(map) => {
+                            let key = self.map_key(&slice)?;
+                            match self.heap.get_map(map).get(&key) {
+                                Some(val) => *val,
+                                None => {
+                                    return Err(self.runtime_error(format!(
+                                        "no entry for key {}",
+                                        self.heap.format_value(&slice)
+                                    )))
+                                }
+                            }
+                        }
+                        _ => {
+                            return Err(self
+                                .runtime_error(format!("cannot subscript {}", value.type_name())))
+                        }
+                    };
+                    self.stack.push(val);
+                }
+                Op::SetItem => {
+                    let rhs = self.pop();
+                    let slice = self.pop();
+                    let lhs = self.pop();
+                    match lhs {
+                        Value::List(list) => {
+                            let len = self.heap.get_list(list).len();
+                            let idx = self.list_index(&slice, len)?;
+                            self.heap.get_list_mut(list)[idx] = rhs;
+                        }
+                        Value::Map(map) => {
+                            let key = self.map_key(&slice)?;
+                            self.heap.get_map_mut(map).insert(key, slice, rhs);
+                        }
+                        _ => {
+                            return Err(self
+                                .runtime_error(format!("cannot set items on {}", lhs.type_name())))
+                        }
+                    }
+                    self.stack.push(rhs);
+                }
+                Op::GetIter => {
+                    let mut source = self.peek(0);
+                    if let Value::Instance(instance) = source {
+                        source = self.call_method(instance, "iter")?;
+                    }
+                    match source {
+                        Value::List(_)
+                        | Value::Map(_)
+                        | Value::String(_)
+                        | Value::Instance(_)
+                        | Value::Generator(_) => {
+                            self.pop();
+                            self.stack.push(source);
+                        }
+                        _ => {
+                            return Err(self.runtime_error(format!(
+                                "cannot iterate over {}",
+                                source.type_name()
+                            )))
+                        }
+                    }
+                }
+                Op::Next(slot, target) => {
+                    let slot = self.frame().slots_offset + slot;
+                    let idx = match self.stack[slot + 1] {
+                        Value::Number(n) => n as usize,
+                        _ => panic!("internal vm error: iteration position is not a number"),
+                    };
+                    let element = match self.stack[slot] {
+                        Value::List(list) => self.heap.get_list(list).get(idx).copied(),
+                        Value::Map(map) => self
+                            .heap
+                            .get_map(map)
+                            .entries()
+                            .get(idx)
+                            .map(|(key, _)| *key),
+                        Value::String(s) => match self.heap.get_str(s).chars().nth(idx) {
+                            Some(c) => Some(self.heap.alloc_string(c.to_string())),
+                            None => None,
+                        },
+                        Value::Instance(iterator) => match self.call_method(iterator, "next")? {
+                            Value::Nil => None,
+                            element => Some(element),
+                        },
+                        Value::Generator(generator) => self.resume(generator, Value::Nil)?,
+                        source => panic!("internal vm error: iterating over {:?}", source),
+                    };
+                    match element {
+                        Some(element) => {
+                            self.stack[slot + 1] = Value::Number((idx + 1) as f64);
+                            self.stack.push(element);
+                        }
+                        None => self.frame_mut().ip = target,
+                    }
+                }
+                Op::PushHandler(target) => self.push_handler(target, false),
+                Op::PushFinally(target) => self.push_handler(target, true),
+                Op::PopHandler => {
+                    self.frame_mut().handlers.pop();
+                }
+                Op::Throw => {
+                    let val = self.pop();
+                    let loc = self.location();
+                    self.thrown = Some(val);
+                    return Err(RoxyErr::Thrown {
+                        what: self.heap.display_value(&val),
+                        line: loc.line,
+                        col: loc.col,
+                        trace: Vec::new(),
+                    });
+                }
+                Op::Rethrow => {
+                    let val = self.pop();
+                    let err = self
+                        .frame_mut()
+                        .unwinding
+                        .pop()
+                        .expect("internal vm error: rethrow without a caught error");
+                    if let RoxyErr::Thrown { .. } = err {
+                        self.thrown = Some(val);
+                    }
+                    return Err(err);
+                }
+                Op::Yield => {
+                    let val = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    let generator = frame
+                        .generator
+                        .expect("internal vm error: yield outside a generator");
+                    self.suspend(generator, frame);
+                    // `resume` entered the generator's frame at `depth`
+                    return Ok(val);
+                }
+            }
+        }
+    }
+
+    /// Resumes `generator` with `sent` as the value of the `yield` it stopped at, and runs it to
+    /// its next `yield`. `None` means it finished instead, now or before.
+    pub fn resume(&mut self, generator: HeapId, sent: Value) -> Result<Option<Value>, RoxyErr> {
+        let Generator { closure, state } = self.heap.get_generator_mut(generator);
+        let closure = *closure;
+        let suspended = match std::mem::replace(state, GeneratorState::Running) {
+            GeneratorState::Suspended(suspended) => suspended,
+            GeneratorState::Running => {
+                let name = self.heap.get_closure(closure).function.name.clone();
+                return Err(self.runtime_error(format!("generator '{}' is already running", name)));
+            }
+            GeneratorState::Done => {
+                *state = GeneratorState::Done;
+                return Ok(None);
+            }
+        };
+        let failed = if let Err(limit) = self.budget.call(self.frames.len()) {
+            Some(limit.exceeded_at(self.location()))
+        } else if self.frames.len() >= FRAMES_MAX {
+            Some(self.runtime_error("stack overflow".to_string()))
+        } else {
+            None
+        };
+        if let Some(err) = failed {
+            self.heap.get_generator_mut(generator).state = GeneratorState::Suspended(suspended);
+            return Err(err);
+        }
+
+        let slots_offset = self.stack.len();
+        self.stack.extend(suspended.slots);
+        // the slots are back on the stack, so the closures that share them have to look there
+        for (upvalue, slot) in suspended.upvalues {
+            let val = match self.heap.get_upvalue(upvalue) {
+                Upvalue::Closed(val) => *val,
+                Upvalue::Open(_) => panic!("internal vm error: suspended upvalue is open"),
+            };
+            self.stack[slots_offset + slot] = val;
+            *self.heap.get_upvalue_mut(upvalue) = Upvalue::Open(slots_offset + slot);
+            self.open_upvalues.push(upvalue);
+        }
+        let mut handlers = suspended.handlers;
+        for handler in &mut handlers {
+            handler.stack_len += slots_offset;
+        }
+        let depth = self.frames.len();
+        self.frames.push(CallFrame {
+            closure,
+            function: self.heap.get_closure(closure).function.clone(),
+            ip: suspended.ip,
+            slots_offset,
+            handlers,
+            unwinding: suspended.unwinding,
+            generator: Some(generator),
+        });
+        if suspended.ip > 0 {
+            // what the `yield` it stopped at evaluates to
+            self.stack.push(sent);
+        }
+
+        let res = self.run(depth);
+        let generator = self.heap.get_generator_mut(generator);
+        match (res, &generator.state) {
+            (Err(err), _) => {
+                generator.state = GeneratorState::Done;
+                Err(err)
+            }
+            (Ok(val), GeneratorState::Suspended(_)) => Ok(Some(val)),
+            (Ok(_), _) => Ok(None),
+        }
+    }
+
+    /// Takes a generator's frame off the stack and keeps it in the generator.
+    fn suspend(&mut self, generator: HeapId, frame: CallFrame) {
+        let upvalues = self
+            .open_upvalues
+            .iter()
+            .filter_map(|upvalue| match self.heap.get_upvalue(*upvalue) {
+                Upvalue::Open(idx) if *idx >= frame.slots_offset => {
+                    Some((*upvalue, idx - frame.slots_offset))
+                }
+                _ => None,
+            })
+            .collect();
+        self.close_upvalues(frame.slots_offset);
+        let slots = self.stack.split_off(frame.slots_offset);
+        let mut handlers = frame.handlers;
+        for handler in &mut handlers {
+            handler.stack_len -= frame.slots_offset;
+        }
+        self.heap.get_generator_mut(generator).state = GeneratorState::Suspended(Suspended {
+            ip: frame.ip,
+            slots,
+            handlers,
+            unwinding: frame.unwinding,
+            upvalues,
+        });
+    }
+
+    fn push_handler(&mut self, target: usize, finally: bool) {
+        let stack_len = self.stack.len();
+        let frame = self.frame_mut();
+        let unwinding_len = frame.unwinding.len();
+        frame.handlers.push(Handler {
+            target,
+            stack_len,
+            unwinding_len,
+            finally,
+        });
+    }
+
+    fn numeric_binop(&mut self, op: fn(f64, f64) -> Value, name: &str) -> Result<(), RoxyErr> {
+        match (self.peek(1), self.peek(0)) {
+            (Value::Number(n1), Value::Number(n2)) => {
+                self.pop();
+                self.pop();
+                self.stack.push(op(n1, n2));
+                Ok(())
+            }
+            (lhs, rhs) => Err(self.runtime_error(format!(
+                "invalid operands in binary operator {} of types {} and {}",
+                name,
+                lhs.type_name(),
+                rhs.type_name()
+            ))),
+        }
+    }
+
+    fn bitwise_binop(&mut self, op: BinaryOpTy) -> Result<(), RoxyErr> {
+        match (self.peek(1), self.peek(0)) {
+            (Value::Number(n1), Value::Number(n2)) => match value::bitwise(op, n1, n2) {
+                Ok(n) => {
+                    self.pop();
+                    self.pop();
+                    self.stack.push(Value::Number(n));
+                    Ok(())
+                }
+                Err(what) => Err(self.runtime_error(what)),
+            },
+            (lhs, rhs) => Err(self.runtime_error(format!(
+                "invalid operands in binary operator {:?} of types {} and {}",
+                op,
+                lhs.type_name(),
+                rhs.type_name()
+            ))),
+        }
+    }
+
+    fn map_key(&self, key: &Value) -> Result<Key, RoxyErr> {
+        self.heap
+            .to_key(key)
+            .ok_or_else(|| self.runtime_error(format!("invalid map key type {}", key.type_name())))
+    }
+
+    /// Calls the field or method `name` of `instance` with no arguments and runs it to
+    /// completion.
+    fn call_method(&mut self, instance: HeapId, name: &str) -> Result<Value, RoxyErr> {
+        let field = self.heap.get_instance(instance).fields.get(name).copied();
+        let method = match field {
+            Some(field) => field,
+            None => {
+                self.stack.push(Value::Instance(instance));
+                let class = self.heap.get_instance(instance).class;
+                self.bind_method(class, name)?;
+                self.pop()
+            }
+        };
+        self.call_function(method, &[])
+    }
+
+    fn list_index(&self, slice: &Value, len: usize) -> Result<usize, RoxyErr> {
+        match slice {
+            Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 && (*n as usize) < len => {
+                Ok(*n as usize)
+            }
+            Value::Number(n) => {
+                Err(self.runtime_error(format!("index {} out of bounds for length {}", n, len)))
+            }
+            _ => Err(self.runtime_error(format!("invalid index type {}", slice.type_name()))),
+        }
+    }
+
+    /// Replaces the instance on top of the stack with its method `name` bound to it.
+    fn bind_method(&mut self, class: HeapId, name: &str) -> Result<(), RoxyErr> {
+        match self.heap.get_class(class).find(MethodKind::Method, name) {
+            Some(method) => {
+                self.bind(method);
+                Ok(())
+            }
+            None => Err(self.runtime_error(format!(
+                "undefined property '{}' on {} instance",
+                name,
+                self.heap.get_class(class).name
+            ))),
+        }
+    }
+
+    /// Replaces `instance`, on top of the stack, with its property `name`: what its getter
+    /// returns, once it has run, or else its field or method.
+    fn get_property(&mut self, instance: HeapId, name: &str) -> Result<(), RoxyErr> {
+        let class = self.heap.get_instance(instance).class;
+        if let Some(getter) = self.heap.get_class(class).find(MethodKind::Getter, name) {
+            // the getter's result takes the instance's place when it returns
+            return self.call(getter, 0);
+        }
+        let field = self.heap.get_instance(instance).fields.get(name).copied();
+        match field {
+            Some(val) => {
+                self.pop();
+                self.stack.push(val);
+                Ok(())
+            }
+            None => self.bind_method(class, name),
+        }
+    }
+
+    /// Replaces `class`, on top of the stack, with its static method `name` bound to it.
+    fn get_static(&mut self, class: HeapId, name: &str) -> Result<(), RoxyErr> {
+        match self.heap.get_class(class).find(MethodKind::Static, name) {
+            Some(method) => {
+                self.bind(method);
+                Ok(())
+            }
+            None => Err(self.runtime_error(format!(
+                "undefined static method '{}' on class {}",
+                name,
+                self.heap.get_class(class).name
+            ))),
+        }
+    }
+
+    /// Sets the property `name` of `instance` to the value on top of the stack, through its
+    /// setter if it has one, leaving just the value.
+    fn set_property(&mut self, instance: HeapId, name: &str) -> Result<(), RoxyErr> {
+        let class = self.heap.get_instance(instance).class;
+        if let Some(setter) = self.heap.get_class(class).find(MethodKind::Setter, name) {
+            // setters return the value assigned, which takes the instance's place
+            return self.call(setter, 1);
+        }
+        if self
+            .heap
+            .get_class(class)
+            .find(MethodKind::Getter, name)
+            .is_some()
+        {
+            return Err(self.runtime_error(format!(
+                "cannot set '{}' on {} instance, which has a getter but no setter",
+                name,
+                self.heap.get_class(class).name
+            )));
+        }
+        let val = self.pop();
+        let fields = &mut self.heap.get_instance_mut(instance).fields;
+        match fields.get_mut(name) {
+            Some(field) => *field = val,
+            None => {
+                fields.insert(name.to_string(), val);
+            }
+        }
+        self.pop();
+        self.stack.push(val);
+        Ok(())
+    }
+
+    /// Replaces the receiver on top of the stack with `method` bound to it.
+    fn bind(&mut self, method: HeapId) {
+        let bound = self.heap.alloc(Object::BoundMethod(BoundMethod {
+            receiver: self.peek(0),
+            method,
+        }));
+        self.pop();
+        self.stack.push(Value::BoundMethod(bound));
+    }
+
+    /// Copies every method `from` has into `to`, over any of the same name and kind.
+    fn copy_methods(&mut self, from: HeapId, to: HeapId) {
+        let methods = self.heap.get_class(from).methods.clone();
+        let to = &mut self.heap.get_class_mut(to).methods;
+        for (kind, methods) in methods {
+            to.entry(kind).or_default().extend(methods);
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: u8) -> Result<(), RoxyErr> {
+        match callee {
+            Value::Closure(closure) => self.call(closure, arg_count),
+            Value::NativeFunction(native) => {
+                if arg_count != native.arity {
+                    return Err(self.arity_error(&callee, native.arity, arg_count));
+                }
+                // the arguments stay on the stack while the native runs, which keeps them rooted
+                let args_start = self.stack.len() - arg_count as usize;
+                let args = self.stack[args_start..].to_vec();
+                match (native.func)(self, &args) {
+                    Ok(val) => {
+                        self.stack.truncate(args_start - 1);
+                        self.stack.push(val);
+                        Ok(())
+                    }
+                    Err(RoxyErr::NativeError(what)) => Err(self.runtime_error(what)),
+                    Err(err) => Err(err),
+                }
+            }
+            Value::Class(class) => {
+                let instance = self.heap.alloc(Object::Instance(Instance {
+                    class,
+                    fields: HashMap::new(),
+                }));
+                let slot = self.stack.len() - 1 - arg_count as usize;
+                self.stack[slot] = Value::Instance(instance);
+                // a wrong number of arguments is the class's to report, as it is on the
+                // tree-walker, rather than its initializer's
+                match self.heap.get_class(class).find(MethodKind::Method, "init") {
+                    Some(initializer) => {
+                        let arity = self.heap.get_closure(initializer).function.arity;
+                        if arg_count != arity {
+                            return Err(self.arity_error(&callee, arity, arg_count));
+                        }
+                        self.call(initializer, arg_count)
+                    }
+                    None if arg_count != 0 => Err(self.arity_error(&callee, 0, arg_count)),
+                    None => Ok(()),
+                }
+            }
+            Value::BoundMethod(bound) => {
+                let BoundMethod { receiver, method } = *self.heap.get_bound_method(bound);
+                let slot = self.stack.len() - 1 - arg_count as usize;
+                self.stack[slot] = receiver;
+                self.call(method, arg_count)
+            }
+            _ => Err(self.runtime_error(format!(
+                "value {} is not callable",
+                self.heap.format_value(&callee)
+            ))),
+        }
+    }
+
+    fn arity_error(&self, callee: &Value, arity: u8, arg_count: u8) -> RoxyErr {
+        self.runtime_error(format!(
+            "invalid call: callee {} expected {} arguments, but got {}",
+            self.heap.format_value(callee),
+            arity,
+            arg_count
+        ))
+    }
+
+    fn call(&mut self, closure: HeapId, arg_count: u8) -> Result<(), RoxyErr> {
+        let function = self.heap.get_closure(closure).function.clone();
+        if arg_count != function.arity {
+            return Err(self.arity_error(&Value::Closure(closure), function.arity, arg_count));
+        }
+        // the script's own frame is not a call
+        if let Err(limit) = self.budget.call(self.frames.len()) {
+            return Err(limit.exceeded_at(self.location()));
+        }
+        if self.frames.len() >= FRAMES_MAX {
+            return Err(self.runtime_error("stack overflow".to_string()));
+        }
+        let slots_offset = self.stack.len() - 1 - arg_count as usize;
+        if function.generator {
+            // the body waits in the generator, with the arguments, until something resumes it
+            let slots = self.stack.split_off(slots_offset);
+            let generator = self.heap.alloc(Object::Generator(Generator {
+                closure,
+                state: GeneratorState::Suspended(Suspended {
+                    ip: 0,
+                    slots,
+                    handlers: Vec::new(),
+                    unwinding: Vec::new(),
+                    upvalues: Vec::new(),
+                }),
+            }));
+            self.stack.push(Value::Generator(generator));
+            return Ok(());
+        }
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            slots_offset,
+            handlers: Vec::new(),
+            unwinding: Vec::new(),
+            generator: None,
+        });
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, stack_idx: usize) -> HeapId {
+        let mut insert_at = self.open_upvalues.len();
+        for (i, upvalue) in self.open_upvalues.iter().enumerate().rev() {
+            match self.heap.get_upvalue(*upvalue) {
+                Upvalue::Open(idx) if *idx == stack_idx => return *upvalue,
+                Upvalue::Open(idx) if *idx < stack_idx => break,
+                _ => insert_at = i,
+            }
+        }
+
+        let upvalue = self.heap.alloc(Object::Upvalue(Upvalue::Open(stack_idx)));
+        self.open_upvalues.insert(insert_at, upvalue);
+        upvalue
+    }
+
+    fn close_upvalues(&mut self, last: usize) {
+        while let Some(upvalue) = self.open_upvalues.last().copied() {
+            let stack_idx = match self.heap.get_upvalue(upvalue) {
+                Upvalue::Open(idx) => *idx,
+                Upvalue::Closed(_) => panic!("internal vm error: closed upvalue in open list"),
+            };
+            if stack_idx < last {
+                break;
+            }
+            *self.heap.get_upvalue_mut(upvalue) = Upvalue::Closed(self.stack[stack_idx]);
+            self.open_upvalues.pop();
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Runs map literals and `for-in` loops, over the built-in collections and over classes that
+//! implement the `iter()`/`next()` protocol, on both backends, and checks what they print and
+//! the errors they raise.
+
+mod common;
+
+use common::{check_error, check_output};
+
+/// Maps keep their entries in the order the keys were first set, which is how they print and
+/// iterate, and take numbers, strings, booleans and nil as keys.
+#[test]
+fn maps() {
+    let source = "\
+var m = {\"a\": 1, \"b\": 2};
+print m;
+print m[\"a\"];
+m[\"c\"] = 3;
+m[\"a\"] = 10;
+print m;
+print len(m);
+print {};
+var keys = {1: \"one\", true: \"yes\", nil: \"none\"};
+print keys[1] + keys[true] + keys[nil];
+print m[\"b\"] = 20;
+print m;
+";
+    let expected = "\
+{\"a\": 1, \"b\": 2}
+1
+{\"a\": 10, \"b\": 2, \"c\": 3}
+3
+{}
+oneyesnone
+20
+{\"a\": 10, \"b\": 20, \"c\": 3}
+";
+    check_output("maps", source, &[], expected);
+}
+
+#[test]
+fn map_errors() {
+    check_error(
+        "missing_key",
+        "var m = {\"a\": 1};\nprint m[\"b\"];\n",
+        &[],
+        70,
+        "error[E0301]: no entry for key \"b\"\n --> missing_key.lox:2:12",
+    );
+    check_error(
+        "list_key",
+        "var m = {};\nm[[1]] = 1;\n",
+        &[],
+        70,
+        "error[E0301]: invalid map key type List",
+    );
+}
+
+/// A map is iterated over its keys, a list over its elements and a string over its characters.
+#[test]
+fn for_in() {
+    let source = "\
+var m = {\"x\": 1, \"y\": 2};
+for (k in m) print k + \"=\" + str(m[k]);
+for (n in [1, 2]) print n * 10;
+for (c in \"ab\") print c;
+for (n in []) print \"never\";
+";
+    check_output("for_in", source, &[], "x=1\ny=2\n10\n20\na\nb\n");
+}
+
+/// A class takes part in `for-in` with an `iter()` that hands back an object whose `next()`
+/// returns each value in turn and then nil.
+#[test]
+fn iterator_protocol() {
+    let source = "\
+class Countdown {
+    init(n) {
+        this.n = n;
+    }
+
+    iter() {
+        return this;
+    }
+
+    next() {
+        if (this.n == 0) return nil;
+        this.n = this.n - 1;
+        return this.n + 1;
+    }
+}
+
+class Range {
+    init(n) {
+        this.n = n;
+    }
+
+    iter() {
+        return Countdown(this.n);
+    }
+}
+
+for (x in Countdown(3)) print x;
+var range = Range(2);
+for (x in range) print x;
+for (x in range) print \"again \" + str(x);
+";
+    check_output(
+        "iterator_protocol",
+        source,
+        &[],
+        "3\n2\n1\n2\n1\nagain 2\nagain 1\n",
+    );
+}
+
+#[test]
+fn iteration_errors() {
+    check_error(
+        "number",
+        "for (x in 1) print x;\n",
+        &[],
+        70,
+        "error[E0301]: cannot iterate over Number\n --> number.lox:1:11",
+    );
+    check_error(
+        "no_iter",
+        "class A {}\nfor (x in A()) print x;\n",
+        &[],
+        70,
+        "error[E0301]: undefined property 'iter' on A instance",
+    );
+    check_error(
+        "iter_not_iterator",
+        "class A {\n    iter() {\n        return 1;\n    }\n}\nfor (x in A()) print x;\n",
+        &[],
+        70,
+        "error[E0301]: cannot iterate over Number",
+    );
+    check_error(
+        "no_next",
+        "class A {\n    iter() {\n        return this;\n    }\n}\nfor (x in A()) print x;\n",
+        &[],
+        70,
+        "error[E0301]: undefined property 'next' on A instance",
+    );
+}
@@ -0,0 +1,135 @@
+//! What the integration tests share: scratch directories, and running the roxy binary on a
+//! program on both backends to check what it did.
+// each test crate uses its own selection of these
+#![allow(dead_code)]
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const BACKENDS: [&str; 2] = ["treewalk", "vm"];
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory of its own under the system's temporary directory, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "roxy-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes `contents` to `name`, a path relative to the directory.
+    pub fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    pub fn read(&self, name: &str) -> String {
+        fs::read_to_string(self.0.join(name)).unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The roxy binary, run in `dir` with that as its home too, so the REPL keeps its history
+/// there.
+pub fn roxy(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_roxy"));
+    command.current_dir(dir).env("HOME", dir);
+    command
+}
+
+/// Runs `source` as `name.lox` on `backend`, with `args` added to the command line.
+/// Diagnostics refer to the file by that name.
+pub fn run(name: &str, source: &str, backend: &str, args: &[&str]) -> Output {
+    let dir = TempDir::new();
+    let file = format!("{}.lox", name);
+    dir.write(&file, source);
+    roxy(dir.path())
+        .args(["--lists", "--lambdas", "--backend", backend])
+        .args(args)
+        .args(["-f", &file])
+        .output()
+        .expect("failed to start roxy")
+}
+
+/// Runs roxy in `dir` with `args`, typing `input` into it.
+pub fn run_stdin(dir: &Path, args: &[&str], input: &str) -> Output {
+    let mut child = roxy(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start roxy");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Checks `source` runs to completion on both backends and prints `expected`.
+pub fn check_output(name: &str, source: &str, args: &[&str], expected: &str) {
+    for backend in BACKENDS {
+        let output = run(name, source, backend, args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}: {}", backend, stderr);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            expected,
+            "{}",
+            backend
+        );
+    }
+}
+
+/// Checks `source` fails on both backends with exit code `code` and a message containing
+/// `expected`, and hands back what each printed before it stopped.
+pub fn check_error(
+    name: &str,
+    source: &str,
+    args: &[&str],
+    code: i32,
+    expected: &str,
+) -> Vec<String> {
+    BACKENDS
+        .iter()
+        .map(|backend| {
+            let output = run(name, source, backend, args);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert_eq!(output.status.code(), Some(code), "{}: {}", backend, stderr);
+            assert!(
+                stderr.contains(expected),
+                "{}: expected {:?} in\n{}",
+                backend,
+                expected,
+                stderr
+            );
+            String::from_utf8(output.stdout).unwrap()
+        })
+        .collect()
+}
@@ -0,0 +1,89 @@
+//! Drives `roxy --debug` with commands piped to its stdin and checks where it stops.
+
+mod common;
+
+use common::{run_stdin, TempDir};
+
+/// Debugs `source` with the commands in `input`, and hands back what the session printed.
+fn debug(source: &str, input: &str) -> String {
+    let dir = TempDir::new();
+    dir.write("debugged.lox", source);
+    let output = run_stdin(dir.path(), &["--debug", "-f", "debugged.lox"], input);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// A breakpoint on the only statement of a loop body stops on every iteration.
+#[test]
+fn breakpoint_in_loop() {
+    let source = "\
+var i = 0;
+while (i < 3) {
+  i = i + 1;
+}
+print i;
+";
+    let expected = "\
+roxy debugger, type help for commands
+<script> at line 1
+   1 | var i = 0;
+(debug) breakpoint set at line 3
+(debug) breakpoint at line 3
+<script> at line 3
+   3 |   i = i + 1;
+(debug) i = 0
+(debug) breakpoint at line 3
+<script> at line 3
+   3 |   i = i + 1;
+(debug) i = 1
+(debug) breakpoint at line 3
+<script> at line 3
+   3 |   i = i + 1;
+(debug) i = 2
+(debug) 3
+";
+    assert_eq!(debug(source, "b 3\nc\np i\nc\np i\nc\np i\nc\n"), expected);
+}
+
+/// A breakpoint stops once for each visit to its line, not for each statement on it, and
+/// again when a later call comes back to it.
+#[test]
+fn breakpoint_per_visit() {
+    let source = "\
+fun twice(n) {
+  var a = n; var b = a * 2;
+  return b;
+}
+print twice(1);
+print twice(2);
+";
+    let expected = "\
+roxy debugger, type help for commands
+<script> at line 1
+   1 | fun twice(n) {
+(debug) breakpoint set at line 2
+(debug) breakpoint at line 2
+twice at line 2
+   2 |   var a = n; var b = a * 2;
+(debug) #0 twice at line 2
+#1 <script> at line 5
+(debug) twice at line 2
+   2 |   var a = n; var b = a * 2;
+(debug) a = 1
+n = 1
+(debug) 2
+breakpoint at line 2
+twice at line 2
+   2 |   var a = n; var b = a * 2;
+(debug) breakpoint at line 2 deleted
+(debug) 4
+";
+    assert_eq!(
+        debug(source, "b 2\nc\nbt\nn\nlocals\nc\nd 2\nc\n"),
+        expected
+    );
+}
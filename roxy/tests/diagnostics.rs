@@ -0,0 +1,74 @@
+//! Golden tests of how errors are reported: the programs named `diagnostic_*` in tests/golden
+//! are run on both backends and what they print to stderr compared with the `.stderr` file
+//! next to them. Run with UPDATE_GOLDEN=1 set to write the current output instead.
+
+mod common;
+
+use common::{roxy, BACKENDS};
+use std::fs;
+use std::path::PathBuf;
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// Runs `name.lox` on both backends, checking it exits with `code`, and compares its stderr
+/// with `name.stderr`.
+fn check(name: &str, code: i32) {
+    let source = format!("{}.lox", name);
+    let expected_path = golden_dir().join(format!("{}.stderr", name));
+    for backend in BACKENDS {
+        let output = roxy(&golden_dir())
+            .args(["--backend", backend, "-f", &source])
+            .output()
+            .expect("failed to start roxy");
+        let actual = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(output.status.code(), Some(code), "{}: {}", backend, actual);
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&expected_path)
+            .unwrap_or_else(|err| panic!("cannot read {}: {}", expected_path.display(), err));
+        assert!(
+            actual == expected,
+            "{} does not match on {}; rerun with UPDATE_GOLDEN=1 if the change is intended\n{}",
+            expected_path.display(),
+            backend,
+            actual
+        );
+    }
+}
+
+/// The header, location, source line, carets under tab-indented code and the note.
+#[test]
+fn layout() {
+    check("diagnostic_layout", 65);
+}
+
+/// The parser recovers at the next statement after an error, and every error it found in the
+/// file is reported.
+#[test]
+fn recovery() {
+    check("diagnostic_recovery", 65);
+}
+
+/// A runtime error points at the failing operator, past a multibyte character, and notes each
+/// call it was raised through.
+#[test]
+fn trace() {
+    check("diagnostic_trace", 70);
+}
+
+/// Runaway recursion notes its repeated call once, with how many times it was made.
+#[test]
+fn recursion() {
+    check("diagnostic_recursion", 70);
+}
+
+/// Of a long trace without repeats, only the calls at either end are noted.
+#[test]
+fn long_trace() {
+    check("diagnostic_long_trace", 70);
+}
@@ -0,0 +1,191 @@
+//! Golden tests of the grammar: the `--dump-tokens`, `--dump-ast` and `--dump-syntax` output
+//! for the programs in tests/golden, compared with the files next to them, of what `-O`
+//! makes of the AST, and of the `--dump-bytecode` disassembly. Run with UPDATE_GOLDEN=1 set to write the current output instead.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn roxy(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_roxy"))
+        .args(["--lists", "--lambdas"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to start roxy");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Dumps `name.lox` with `flag` in both formats and compares against `name.<what>.<format>`.
+fn check(name: &str, flag: &str, what: &str) {
+    check_with(name, &[flag], what)
+}
+
+/// `check` with more than one flag.
+fn check_with(name: &str, flags: &[&str], what: &str) {
+    let source = golden_dir().join(format!("{}.lox", name));
+    for format in ["json", "sexpr"] {
+        let mut args = flags.to_vec();
+        args.extend(["--dump-format", format, "-f", source.to_str().unwrap()]);
+        let output = roxy(&args, "");
+        assert!(
+            output.status.success(),
+            "dumping {} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let actual = String::from_utf8(output.stdout).unwrap();
+        compare(
+            &golden_dir().join(format!("{}.{}.{}", name, what, format)),
+            &actual,
+        );
+    }
+}
+
+/// Checks `actual` is what `expected_path` holds, or writes it there with UPDATE_GOLDEN set.
+fn compare(expected_path: &Path, actual: &str) {
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(expected_path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(expected_path)
+        .unwrap_or_else(|err| panic!("cannot read {}: {}", expected_path.display(), err));
+    assert!(
+        actual == expected,
+        "{} does not match; rerun with UPDATE_GOLDEN=1 if the change is intended\n{}",
+        expected_path.display(),
+        actual
+    );
+}
+
+#[test]
+fn tokens() {
+    check("tokens", "--dump-tokens", "tokens");
+}
+
+#[test]
+fn expressions() {
+    check("expressions", "--dump-ast", "ast");
+}
+
+#[test]
+fn statements() {
+    check("statements", "--dump-ast", "ast");
+}
+
+#[test]
+fn classes() {
+    check("classes", "--dump-ast", "ast");
+}
+
+#[test]
+fn extensions() {
+    check("extensions", "--dump-ast", "ast");
+}
+
+#[test]
+fn types() {
+    check("types", "--dump-ast", "ast");
+}
+
+#[test]
+fn exceptions() {
+    check("exceptions", "--dump-ast", "ast");
+}
+
+#[test]
+fn operators() {
+    check("operators", "--dump-ast", "ast");
+}
+
+#[test]
+fn collections() {
+    check("collections", "--dump-ast", "ast");
+}
+
+#[test]
+fn loops() {
+    check("loops", "--dump-ast", "ast");
+}
+
+#[test]
+fn generators() {
+    check("generators", "--dump-ast", "ast");
+}
+
+/// The same program before and after `-O`.
+#[test]
+fn optimize() {
+    check("optimize", "--dump-ast", "ast");
+    check_with("optimize", &["--dump-ast", "-O"], "optimized");
+}
+
+#[test]
+fn syntax() {
+    check("syntax", "--dump-syntax", "syntax");
+}
+
+/// The disassembly is plain text, so there is one file whatever `--dump-format` says.
+#[test]
+fn bytecode() {
+    let source = golden_dir().join("bytecode.lox");
+    let output = roxy(&["--dump-bytecode", "-f", source.to_str().unwrap()], "");
+    assert!(
+        output.status.success(),
+        "dumping bytecode failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let actual = String::from_utf8(output.stdout).unwrap();
+    compare(&golden_dir().join("bytecode.bytecode"), &actual);
+}
+
+#[test]
+fn bytecode_needs_a_resolved_program() {
+    let output = roxy(&["--dump-bytecode"], "return 1;");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("error[E02"));
+}
+
+#[test]
+fn reads_stdin_without_a_file() {
+    let output = roxy(&["--dump-ast", "--dump-format", "sexpr"], "print 1;");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "(print @1:0 (literal @1:6 1))\n"
+    );
+}
+
+#[test]
+fn parse_errors_are_reported() {
+    let output = roxy(&["--dump-ast"], "print ;");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("E0106"));
+}
+
+#[test]
+fn syntax_trees_are_dumped_despite_errors() {
+    let output = roxy(&["--dump-syntax", "--dump-format", "sexpr"], "print ;");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("(missing expression)"));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("E0106"));
+}
@@ -0,0 +1,68 @@
+//! Drives an `Engine` from Rust the way a host program would.
+
+use roxy::embed::Error;
+use roxy::{Engine, RoxyErr, Value};
+use std::thread;
+
+fn is_stack_overflow<T>(res: &Result<T, Error>) -> bool {
+    matches!(res, Err(Error::Runtime(RoxyErr::RuntimeError { what, .. })) if what == "stack overflow")
+}
+
+/// Unbounded recursion comes back as an error rather than taking the host down, and the
+/// engine can be used again afterwards.
+#[test]
+fn runaway_recursion() {
+    let mut engine = Engine::new();
+    let res = engine.eval("fun down(n) { return down(n + 1); } down(0)");
+    assert!(is_stack_overflow(&res), "{:?}", res);
+    let res = engine.call::<Value>("down", vec![Value::Number(0.0)]);
+    assert!(is_stack_overflow(&res), "{:?}", res);
+
+    engine
+        .eval("fun depth(n) { if (n == 0) return 0; return depth(n - 1) + 1; }")
+        .unwrap();
+    assert_eq!(engine.eval("depth(10)").unwrap().to_string(), "10");
+}
+
+/// On a thread with a bigger stack, and told so, calls can nest as deep as the VM allows.
+#[test]
+fn stack_size() {
+    const STACK_SIZE: usize = 256 * 1024 * 1024;
+    thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| {
+            let mut engine = Engine::new();
+            engine.set_stack_size(STACK_SIZE);
+            engine
+                .eval("fun depth(n) { if (n == 0) return 0; return depth(n - 1) + 1; }")
+                .unwrap();
+            assert_eq!(engine.eval("depth(1000)").unwrap().to_string(), "1000");
+            let res = engine.eval("depth(2000)");
+            assert!(is_stack_overflow(&res), "{:?}", res);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+/// Annotations made by one `eval` hold in the next, and a program refused for its types
+/// declares nothing.
+#[test]
+fn types_kept() {
+    let mut engine = Engine::new();
+    engine
+        .eval("var limit: num = 1; fun twice(n: num): num { return n * 2; }")
+        .unwrap();
+    for source in ["limit = \"high\";", "twice(\"x\");"] {
+        let res = engine.eval(source);
+        assert!(matches!(res, Err(Error::Type(_))), "{}: {:?}", source, res);
+    }
+    assert_eq!(engine.eval("twice(limit)").unwrap().to_string(), "2");
+
+    assert!(matches!(
+        engine.eval("var other: num = \"x\";"),
+        Err(Error::Type(_))
+    ));
+    engine.eval("var other = \"x\";").unwrap();
+    engine.eval("other = 1;").unwrap();
+}
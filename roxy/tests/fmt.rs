@@ -0,0 +1,153 @@
+//! Runs `roxy fmt` over programs and checks what it writes: the same again when run on its
+//! own output, with every comment kept, and the exit codes of `--check`.
+
+mod common;
+
+use common::{roxy, TempDir};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Output;
+
+/// Formats `source` as a file in a directory of its own and hands back what the file holds
+/// afterwards.
+fn format(source: &str) -> String {
+    let dir = TempDir::new();
+    dir.write("f.lox", source);
+    let output = fmt(&dir, &["f.lox"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    dir.read("f.lox")
+}
+
+fn fmt(dir: &TempDir, args: &[&str]) -> Output {
+    roxy(dir.path())
+        .arg("fmt")
+        .args(args)
+        .output()
+        .expect("failed to start roxy")
+}
+
+/// Formatting what the formatter wrote changes nothing, for the golden programs.
+#[test]
+fn idempotent() {
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for name in [
+        "classes",
+        "collections",
+        "exceptions",
+        "expressions",
+        "extensions",
+        "generators",
+        "loops",
+        "operators",
+        "optimize",
+        "statements",
+        "syntax",
+        "types",
+    ] {
+        let source = fs::read_to_string(golden.join(format!("{}.lox", name))).unwrap();
+        let once = format(&source);
+        assert_eq!(format(&once), once, "{}", name);
+    }
+}
+
+/// Comments at the end of a line stay there, one space after the code, and the lines of a
+/// statement a comment breaks up are indented one level more than its first.
+#[test]
+fn comments() {
+    let source = "\
+// header
+
+
+fun f(x, // first
+   y) {
+  return x + // mid
+ y;
+}
+var l = [
+  // note
+  1,  // one
+  2];
+if (x) {
+  print 1;
+}   // after if
+else {
+  print 2;  // after stmt
+}
+try {
+  f();
+} // after try
+catch (e) {
+  // only a comment
+}
+print f(1, // one
+  g(2, // two
+    3));
+";
+    let expected = "\
+// header
+
+fun f(x, // first
+    y) {
+    return x + // mid
+        y;
+}
+var l = [
+    // note
+    1, // one
+    2];
+if (x) {
+    print 1;
+} // after if
+else {
+    print 2; // after stmt
+}
+try {
+    f();
+} // after try
+catch (e) {
+    // only a comment
+}
+print f(1, // one
+    g(2, // two
+    3));
+";
+    let formatted = format(source);
+    assert_eq!(formatted, expected);
+    assert_eq!(format(&formatted), formatted);
+}
+
+/// `--check` leaves files alone, exiting with 0 when they are formatted already, 1 when one
+/// would change and 65 when one doesn't parse.
+#[test]
+fn check() {
+    let dir = TempDir::new();
+    dir.write("good.lox", "print 1;\n");
+    dir.write("bad.lox", "print   1 ;\n");
+    dir.write("broken.lox", "print (;\n");
+
+    let output = fmt(&dir, &["--check", "good.lox"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    let output = fmt(&dir, &["--check", "good.lox", "bad.lox"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "would reformat bad.lox (first difference on line 1)\n"
+    );
+    assert_eq!(dir.read("bad.lox"), "print   1 ;\n");
+
+    let output = fmt(&dir, &["--check", "bad.lox", "broken.lox"]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("--> broken.lox:1:8"));
+    assert_eq!(dir.read("broken.lox"), "print (;\n");
+
+    assert_eq!(fmt(&dir, &["bad.lox"]).status.code(), Some(0));
+    assert_eq!(dir.read("bad.lox"), "print 1;\n");
+}
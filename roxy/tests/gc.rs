@@ -0,0 +1,125 @@
+//! Checks the VM's collector frees what is unreachable, keeps what natives and cycles still
+//! hold, and spaces its collections out by the growth factor.
+
+mod common;
+
+use common::{check_output, run};
+
+/// One `--gc-log` line: the bytes allocated before and after the collection, and the size the
+/// heap may reach before the next.
+struct Collection {
+    freed: usize,
+    before: usize,
+    after: usize,
+    next: usize,
+}
+
+/// Runs `source` on the VM with `args` and `--gc-log`, checking it prints `expected`, and
+/// hands back each collection it logged.
+fn collections(name: &str, source: &str, args: &[&str], expected: &str) -> Vec<Collection> {
+    let output = run(name, source, "vm", &[args, &["--gc-log"]].concat());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}", stderr);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    stderr
+        .lines()
+        .map(|line| {
+            let numbers: Vec<usize> = line
+                .split(|c: char| !c.is_ascii_digit())
+                .filter(|word| !word.is_empty())
+                .map(|word| word.parse().unwrap())
+                .collect();
+            match numbers[..] {
+                [freed, before, after, next] => Collection {
+                    freed,
+                    before,
+                    after,
+                    next,
+                },
+                _ => panic!("unexpected line on stderr: {}", line),
+            }
+        })
+        .collect()
+}
+
+/// Instances that refer to each other are freed together once nothing else refers to them,
+/// and the ones still reachable keep their fields.
+#[test]
+fn cycles() {
+    let source = "\
+class Node {}
+var kept;
+for (var i = 0; i < 50; i = i + 1) {
+    var a = Node();
+    var b = Node();
+    a.other = b;
+    b.other = a;
+    a.name = i;
+    kept = a;
+}
+// one more allocation, to collect after the last pair but one is let go
+Node();
+print kept.other.other.name;
+print kept.other.other == kept;
+";
+    let expected = "49\ntrue\n";
+    check_output("cycles", source, &["--gc-stress"], expected);
+    let logged = collections("cycles", source, &["--gc-stress"], expected);
+    // every pair but the one still kept
+    assert_eq!(logged.iter().map(|c| c.freed).sum::<usize>(), 98);
+}
+
+/// After each collection the next is put off until the heap is the growth factor times what
+/// survived, or the initial threshold if that is larger, so a larger factor collects less.
+#[test]
+fn growth_factor() {
+    let source = "\
+var keep = [];
+for (var i = 0; i < 20000; i = i + 1) push(keep, \"item \" + str(i));
+var churn = 0;
+for (var i = 0; i < 20000; i = i + 1) churn = churn + len(\"x\" + str(i));
+print len(keep);
+print churn;
+";
+    let expected = "20000\n108890\n";
+    let mut counts = Vec::new();
+    for factor in ["1.5", "4"] {
+        let logged = collections(
+            "growth_factor",
+            source,
+            &["--gc-growth-factor", factor],
+            expected,
+        );
+        let factor: f64 = factor.parse().unwrap();
+        for collection in &logged {
+            let grown = (collection.after as f64 * factor) as usize;
+            assert_eq!(collection.next, grown.max(1024 * 1024));
+        }
+        for pair in logged.windows(2) {
+            assert!(pair[1].before > pair[0].next);
+        }
+        counts.push(logged.len());
+    }
+    assert!(counts[0] > counts[1], "{:?}", counts);
+}
+
+/// What `map` and `filter` have built so far, and the function they were handed, live only on
+/// the native's side while the callback runs, and survive collections made in it.
+#[test]
+fn natives_hold_values() {
+    let source = "\
+var words = map([1, 2, 3, 4], lambda (n) { return \"w\" + str(n); });
+print words;
+var long = filter(words, lambda (w) { return [w, w + w][1] != \"w2w2\"; });
+print long;
+print map(filter([1, 2, 3], lambda (n) { return n != 2; }), lambda (n) {
+    return {\"n\": n, \"list\": [n]};
+});
+";
+    let expected = "\
+[\"w1\", \"w2\", \"w3\", \"w4\"]
+[\"w1\", \"w3\", \"w4\"]
+[{\"n\": 1, \"list\": [1]}, {\"n\": 3, \"list\": [3]}]
+";
+    check_output("natives", source, &["--gc-stress"], expected);
+}
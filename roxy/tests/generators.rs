@@ -0,0 +1,190 @@
+//! Runs generator programs on both backends, and on the VM again with the collector running at
+//! every allocation, and checks they all print the same thing.
+
+mod common;
+
+use common::run;
+
+const RUNS: [(&str, &[&str]); 3] = [("treewalk", &[]), ("vm", &[]), ("vm", &["--gc-stress"])];
+
+/// Checks `source` runs to completion everywhere and prints `expected`.
+fn check(name: &str, source: &str, expected: &str) {
+    for (backend, args) in RUNS {
+        let output = run(name, source, backend, args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            output.status.success(),
+            "{} {:?}: {}",
+            backend,
+            args,
+            stderr
+        );
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            expected,
+            "{} {:?}",
+            backend,
+            args
+        );
+    }
+}
+
+/// Checks `source` fails everywhere with exit code `code` and a message containing `expected`.
+fn check_error(name: &str, source: &str, code: i32, expected: &str) {
+    for (backend, args) in RUNS {
+        let output = run(name, source, backend, args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(code), "{}: {}", backend, stderr);
+        assert!(
+            stderr.contains(expected),
+            "{}: expected {:?} in\n{}",
+            backend,
+            expected,
+            stderr
+        );
+    }
+}
+
+#[test]
+fn lazy() {
+    let source = "\
+fun* naturals() {
+    var n = 0;
+    while (true) {
+        yield n;
+        n = n + 1;
+    }
+}
+var evens = naturals();
+for (var i = 0; i < 3; i = i + 1) print next(evens) * 2;
+print type(evens);
+print evens;
+";
+    check("lazy", source, "0\n2\n4\nGenerator\n<generator naturals>\n");
+}
+
+#[test]
+fn send_and_done() {
+    let source = "\
+fun* averager() {
+    var total = 0;
+    var count = 0;
+    var value = yield;
+    while (value != nil) {
+        total = total + value;
+        count = count + 1;
+        value = yield total / count;
+    }
+    print \"finished\";
+}
+var avg = averager();
+next(avg);
+print send(avg, 4);
+print send(avg, 8);
+print done(avg);
+print send(avg, nil);
+print done(avg);
+print next(avg);
+";
+    check("send", source, "4\n6\nfalse\nfinished\nnil\ntrue\nnil\n");
+}
+
+#[test]
+fn closures_keep_locals() {
+    let source = "\
+fun* counters() {
+    var count = 0;
+    fun bump() {
+        count = count + 1;
+        return count;
+    }
+    yield bump;
+    yield count;
+    bump();
+    yield count;
+}
+var g = counters();
+var bump = next(g);
+bump();
+bump();
+print next(g);
+print next(g);
+";
+    check("closures", source, "2\n3\n");
+}
+
+#[test]
+fn finally_runs() {
+    let source = "\
+fun* guarded() {
+    try {
+        yield 1;
+        yield 2;
+        return;
+    } finally {
+        print \"cleanup\";
+        yield 3;
+    }
+}
+for (x in guarded()) print x;
+";
+    check("finally", source, "1\n2\ncleanup\n3\n");
+}
+
+#[test]
+fn for_in() {
+    let source = "\
+class Pair {
+    init(a, b) {
+        this.a = a;
+        this.b = b;
+    }
+
+    *iter() {
+        yield this.a;
+        yield this.b;
+    }
+
+    static *of(list) {
+        for (item in list) yield item;
+    }
+}
+for (x in Pair(\"a\", \"b\")) print x;
+for (x in Pair.of([1, 2])) print x;
+fun* outer() {
+    for (x in Pair(3, 4)) yield x * 10;
+}
+for (x in outer()) print x;
+";
+    check("for_in", source, "a\nb\n1\n2\n30\n40\n");
+}
+
+#[test]
+fn errors() {
+    check_error(
+        "running",
+        "fun* g() { next(self); }\nvar self = g();\nnext(self);\n",
+        70,
+        "generator 'g' is already running",
+    );
+    check_error("outside", "fun f() { yield 1; }\n", 65, "error[E0216]");
+    for nested in [
+        "print yield 1;",
+        "1 + (yield 2);",
+        "f(yield 3);",
+        "var l = [yield 4];",
+    ] {
+        check_error(
+            "nested",
+            &format!("fun f(x) {{}}\nfun* g() {{ {} }}\n", nested),
+            65,
+            "error[E0217]: cannot use 'yield' inside a larger expression",
+        );
+    }
+    check_error(
+        "return_value",
+        "fun* g() { return 1; }\n",
+        65,
+        "error[E0218]",
+    );
+}
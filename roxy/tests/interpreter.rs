@@ -0,0 +1,391 @@
+//! Runs small programs through the interpreter, and the VM alongside it, and checks what they
+//! print.
+
+mod common;
+
+use common::{check_error, check_output};
+
+#[test]
+fn closures() {
+    let source = "\
+fun counter() {
+    var count = 0;
+    fun increment() {
+        count = count + 1;
+        return count;
+    }
+    return increment;
+}
+var a = counter();
+var b = counter();
+a();
+a();
+print a();
+print b();
+
+var global = \"global\";
+{
+    fun show() {
+        print global;
+    }
+    show();
+    var global = \"local\";
+    show();
+}
+";
+    check_output("closures", source, &[], "3\n1\nglobal\nglobal\n");
+}
+
+#[test]
+fn classes() {
+    let source = "\
+class Animal {
+    init(name) {
+        this.name = name;
+    }
+
+    speak() {
+        return this.name + \" makes a sound\";
+    }
+}
+
+class Dog < Animal {
+    speak() {
+        return super.speak() + \", woof\";
+    }
+}
+
+var dog = Dog(\"Rex\");
+print dog.speak();
+var speak = dog.speak;
+print speak();
+dog.name = \"Max\";
+print dog.speak();
+print dog.init(\"Bo\") == dog;
+print dog.name;
+";
+    let expected = "\
+Rex makes a sound, woof
+Rex makes a sound, woof
+Max makes a sound, woof
+true
+Bo
+";
+    check_output("classes", source, &[], expected);
+}
+
+/// Static methods are called on the class and are not there on its instances, nor are
+/// ordinary methods on the class.
+#[test]
+fn static_methods() {
+    let source = "\
+class Point {
+    init(x, y) {
+        this.x = x;
+        this.y = y;
+    }
+
+    static origin() {
+        return Point(0, 0);
+    }
+}
+
+class Point3 < Point {}
+
+print Point.origin().x;
+print Point3.origin().y;
+";
+    check_output("static_methods", source, &[], "0\n0\n");
+    check_error(
+        "static_on_instance",
+        "class S { static make() { return 1; } }\nS().make();\n",
+        &[],
+        70,
+        "undefined property 'make' on S instance",
+    );
+    check_error(
+        "method_on_class",
+        "class S { m() { return 1; } }\nS.m();\n",
+        &[],
+        70,
+        "undefined static method 'm' on class S",
+    );
+}
+
+/// Reading a property with a getter runs it, and assigning one with a setter runs that,
+/// evaluating to the value assigned; a getter on its own makes the property read-only.
+#[test]
+fn getters_and_setters() {
+    let source = "\
+class Temperature {
+    init(celsius) {
+        this.celsius = celsius;
+    }
+
+    get fahrenheit {
+        return this.celsius * 9 / 5 + 32;
+    }
+
+    set fahrenheit(f) {
+        this.celsius = (f - 32) * 5 / 9;
+    }
+}
+
+var t = Temperature(100);
+print t.fahrenheit;
+print t.fahrenheit = 50;
+print t.celsius;
+";
+    check_output("getters_and_setters", source, &[], "212\n50\n10\n");
+    check_error(
+        "getter_only",
+        "class S { get g { return 1; } }\nvar s = S();\nprint s.g;\ns.g = 2;\n",
+        &[],
+        70,
+        "cannot set 'g' on S instance, which has a getter but no setter",
+    );
+}
+
+/// A class's own methods come first, then those of its mixins, the later ones before the
+/// earlier, then those of its superclass.
+#[test]
+fn mixin_precedence() {
+    let source = "\
+class First {
+    name() { return \"First\"; }
+    shared() { return \"First\"; }
+    first() { return \"first\"; }
+}
+
+class Second {
+    name() { return \"Second\"; }
+    shared() { return \"Second\"; }
+}
+
+class Base {
+    name() { return \"Base\"; }
+    shared() { return \"Base\"; }
+    base() { return \"base\"; }
+}
+
+class Mixed < Base with First, Second {
+    name() { return \"Mixed\"; }
+}
+
+var m = Mixed();
+print m.name();
+print m.shared();
+print m.first();
+print m.base();
+";
+    check_output(
+        "mixin_precedence",
+        source,
+        &[],
+        "Mixed\nSecond\nfirst\nbase\n",
+    );
+}
+
+/// Calling `init` again runs it on the same instance and hands that instance back, and a bare
+/// `return` in it does the same; returning a value from it is refused before anything runs.
+#[test]
+fn init() {
+    let source = "\
+class Counter {
+    init(start) {
+        this.count = start;
+        print \"init \" + str(start);
+        if (start > 5) return;
+        this.small = true;
+    }
+}
+
+var c = Counter(1);
+print c.init(10) == c;
+print c.count;
+print c.small;
+print Counter(7).init(2).count;
+";
+    let expected = "\
+init 1
+init 10
+true
+10
+true
+init 7
+init 2
+2
+";
+    check_output("init", source, &[], expected);
+    let printed = check_error(
+        "init_return",
+        "print 1;\nclass C {\n    init() {\n        return 1;\n    }\n}\n",
+        &[],
+        65,
+        "error[E0204]: cannot return a value from an initializer",
+    );
+    assert!(printed.iter().all(|out| out.is_empty()));
+}
+
+/// A class called with the wrong number of arguments is named in the error, whether its
+/// initializer is its own, inherited or missing.
+#[test]
+fn class_arity() {
+    let source = "\
+class Base {
+    init(x) {
+        this.x = x;
+    }
+}
+
+class Derived < Base {}
+
+class Empty {}
+";
+    for (call, expected) in [
+        (
+            "Base();",
+            "callee <class Base> expected 1 arguments, but got 0",
+        ),
+        (
+            "Derived();",
+            "callee <class Derived> expected 1 arguments, but got 0",
+        ),
+        (
+            "Empty(1);",
+            "callee <class Empty> expected 0 arguments, but got 1",
+        ),
+    ] {
+        check_error(
+            "class_arity",
+            &format!("{}{}\n", source, call),
+            &[],
+            70,
+            &format!("error[E0301]: invalid call: {}", expected),
+        );
+    }
+}
+
+/// A superclass or mixin that turns out not to be a class is named in the error, with what
+/// it is instead.
+#[test]
+fn not_a_class() {
+    check_error(
+        "superclass",
+        "var X = 1;\nclass A < X {}\n",
+        &[],
+        70,
+        "error[E0301]: superclass 'X' must be a class, found Number\n --> superclass.lox:2:11",
+    );
+    check_error(
+        "mixin",
+        "var X = \"s\";\nclass B {}\nclass A < B with B, X {}\n",
+        &[],
+        70,
+        "error[E0301]: mixin 'X' must be a class, found String\n --> mixin.lox:3:21",
+    );
+}
+
+#[test]
+fn control_flow() {
+    let source = "\
+var i = 0;
+while (i < 3) {
+    if (i == 1) print \"one\"; else print i;
+    i = i + 1;
+}
+for (var j = 0; j < 2; j = j + 1) print j * 10;
+print nil or \"default\";
+print false and unreachable();
+print 0 and \"zero is true\";
+fun sign(n) {
+    if (n < 0) return -1;
+    if (n > 0) return 1;
+}
+print sign(-5);
+print sign(0);
+";
+    let expected = "0\none\n2\n0\n10\ndefault\nfalse\nzero is true\n-1\nnil\n";
+    check_output("control_flow", source, &[], expected);
+}
+
+#[test]
+fn printing() {
+    let source = "\
+print 1;
+print 2.5;
+print -0.5;
+print 0.1 + 0.2;
+print 1 / 0;
+print nil;
+print true;
+print \"text\";
+print [1, \"a\", nil, [2]];
+print {\"a\": 1, 2: \"b\"};
+fun f() {}
+print f;
+class A {}
+print A;
+print A();
+print clock;
+print lambda () {};
+";
+    let expected = "\
+1
+2.5
+-0.5
+0.30000000000000004
+inf
+nil
+true
+text
+[1, \"a\", nil, [2]]
+{\"a\": 1, 2: \"b\"}
+<fn f>
+<class A>
+<A instance>
+<native fn clock>
+<fn lambda>
+";
+    check_output("printing", source, &[], expected);
+}
+
+#[test]
+fn runtime_errors() {
+    check_error(
+        "operands",
+        "print 1 + \"a\";\n",
+        &[],
+        70,
+        "invalid operands in binary operator Plus of types Number and String",
+    );
+    check_error(
+        "undefined",
+        "print missing;\n",
+        &[],
+        70,
+        "undefined variable 'missing'",
+    );
+    check_error(
+        "arity",
+        "fun f(a) {}\nf();\n",
+        &[],
+        70,
+        "expected 1 arguments, but got 0",
+    );
+}
+
+#[test]
+fn stack_overflow() {
+    let source = "\
+fun down(n) { return down(n + 1); }
+try {
+    down(0);
+} catch (err) {
+    print \"caught \" + err;
+}
+down(0);
+";
+    let printed = check_error("stack_overflow", source, &[], 70, "stack overflow");
+    assert!(printed.iter().all(|out| out == "caught stack overflow\n"));
+}
@@ -0,0 +1,144 @@
+//! Runs programs that misbehave under the sandbox limits, on both backends, and checks they
+//! are stopped with the right diagnostic instead of running on.
+
+mod common;
+
+use common::{check_error, run, BACKENDS};
+
+#[test]
+fn steps() {
+    let source = "var i = 0;\nwhile (true) { i = i + 1; }\n";
+    check_error(
+        "steps",
+        source,
+        &["--max-steps", "500"],
+        70,
+        "error[E0303]: exceeded the limit of 500 steps",
+    );
+}
+
+#[test]
+fn time() {
+    check_error(
+        "time",
+        "while (true) {}\n",
+        &["--max-time-ms", "50"],
+        70,
+        "exceeded the time limit of 50ms",
+    );
+}
+
+#[test]
+fn call_depth() {
+    let source = "fun down(n) { return down(n + 1); }\ndown(0);\n";
+    check_error(
+        "call_depth",
+        source,
+        &["--max-call-depth", "20"],
+        70,
+        "exceeded the call depth limit of 20",
+    );
+}
+
+/// Without a call depth limit, runaway recursion is still stopped, with the stack overflow
+/// error a `catch` can handle, rather than crashing either backend.
+#[test]
+fn no_call_depth_limit() {
+    let source = "fun down(n) { return down(n + 1); }\ndown(0);\n";
+    check_error(
+        "no_call_depth",
+        source,
+        &[],
+        70,
+        "error[E0301]: stack overflow",
+    );
+}
+
+/// The VM measures what is live after a collection; the tree-walker can only count what it
+/// has allocated, and says so.
+#[test]
+fn heap() {
+    let source = "var s = \"roxy\";\nwhile (true) { s = s + s; }\n";
+    check_error(
+        "heap",
+        source,
+        &["--max-heap-bytes", "65536"],
+        70,
+        "error[E0303]: exceeded the ",
+    );
+    for (backend, limit) in [("treewalk", "allocation"), ("vm", "heap")] {
+        let output = run("heap", source, backend, &["--max-heap-bytes", "65536"]);
+        let expected = format!("exceeded the {} limit of 65536 bytes", limit);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(&expected), "{}: {}", backend, stderr);
+    }
+}
+
+/// Strings dropped as soon as they are made never add up on the VM, but still use up the
+/// tree-walker's allocation budget.
+#[test]
+fn short_lived_allocations() {
+    let source = "\
+var total = 0;
+for (var i = 0; i < 20000; i = i + 1) total = total + len(\"item \" + str(i));
+print total;
+";
+    let args = ["--max-heap-bytes", "65536"];
+    let output = run("short_lived", source, "vm", &args);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "188890\n");
+
+    let output = run("short_lived", source, "treewalk", &args);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(70), "{}", stderr);
+    assert!(
+        stderr.contains("exceeded the allocation limit of 65536 bytes"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn output_stops_before_the_line_that_does_not_fit() {
+    let source = "var i = 0;\nwhile (true) { print i; i = i + 1; }\n";
+    let printed = check_error(
+        "output",
+        source,
+        &["--max-output-bytes", "8"],
+        70,
+        "exceeded the output limit of 8 bytes",
+    );
+    for stdout in printed {
+        assert_eq!(stdout, "0\n1\n2\n3\n");
+    }
+}
+
+#[test]
+fn limits_cannot_be_caught() {
+    let source = "try {\n  while (true) {}\n} catch (e) {\n  print \"caught\";\n} finally {\n  print \"finally\";\n}\n";
+    let printed = check_error("catch", source, &["--max-steps", "100"], 70, "E0303");
+    for stdout in printed {
+        assert_eq!(stdout, "");
+    }
+}
+
+#[test]
+fn no_io() {
+    for backend in BACKENDS {
+        let output = run("input", "input();\n", backend, &["--no-io"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("undefined variable 'input'"), "{}", stderr);
+
+        let output = run("import", "import \"other.lox\";\n", backend, &["--no-io"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("cannot import \"other.lox\": I/O is disabled"),
+            "{}",
+            stderr
+        );
+    }
+}
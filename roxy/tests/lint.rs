@@ -0,0 +1,122 @@
+//! Runs `roxy lint` on small programs and checks the warnings it gives, one test per lint,
+//! and that `// lint: allow` comments silence them.
+
+mod common;
+
+use common::{roxy, TempDir};
+use std::process::Output;
+
+fn lint(source: &str) -> Output {
+    let dir = TempDir::new();
+    dir.write("linted.lox", source);
+    roxy(dir.path())
+        .args(["lint", "linted.lox"])
+        .output()
+        .expect("failed to start roxy")
+}
+
+/// Checks linting `source` gives exactly one warning, which begins with `expected`, and exits
+/// with 1.
+fn check_warning(source: &str, expected: &str) {
+    let output = lint(source);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let printed = stdout + &stderr;
+    assert_eq!(output.status.code(), Some(1), "{}", printed);
+    assert!(
+        printed.starts_with(expected),
+        "expected {:?} in\n{}",
+        expected,
+        printed
+    );
+    assert_eq!(printed.matches("warning[").count(), 1, "{}", printed);
+    assert!(printed.ends_with("1 warning\n"), "{}", printed);
+}
+
+/// Checks linting `source` gives no warnings.
+fn check_clean(source: &str) {
+    let output = lint(source);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(0), "{}{}", stdout, stderr);
+    assert_eq!(stdout + &stderr, "");
+}
+
+#[test]
+fn unused_variable() {
+    check_warning(
+        "fun f() {\n    var x = 1;\n}\nf();\n",
+        "warning[W0001]: unused variable 'x'\n --> linted.lox:2:9\n  |\n2 |     var x = 1;\n  \
+         |         ^\n  = note: silence with `// lint: allow unused-variable`\n",
+    );
+}
+
+#[test]
+fn unused_parameter() {
+    check_warning(
+        "fun f(unused) {}\nf(1);\n",
+        "warning[W0002]: unused parameter 'unused'\n --> linted.lox:1:7",
+    );
+}
+
+#[test]
+fn shadowing() {
+    check_warning(
+        "fun f() {\n    var a = 1;\n    {\n        var a = 2;\n        print a;\n    }\n    \
+         print a;\n}\nf();\n",
+        "warning[W0003]: 'a' shadows the local declared on line 2\n --> linted.lox:4:13",
+    );
+}
+
+#[test]
+fn unreachable_code() {
+    check_warning(
+        "fun f() {\n    return 2;\n    print \"after\";\n}\nf();\n",
+        "warning[W0004]: unreachable statement\n --> linted.lox:3:5",
+    );
+}
+
+#[test]
+fn assignment_in_condition() {
+    check_warning(
+        "var b = nil;\nif (b = 1) print b;\n",
+        "warning[W0005]: assignment to 'b' used as a condition\n --> linted.lox:2:5",
+    );
+    check_clean("var b = nil;\nif ((b = 1)) print b;\n");
+}
+
+#[test]
+fn nil_comparison() {
+    check_warning(
+        "var n = 1;\nif (n == nil) print n;\n",
+        "warning[W0006]: comparing a number with nil is always false\n --> linted.lox:2:7",
+    );
+    check_warning(
+        "var n = 1;\nif (nil != n) print n;\n",
+        "warning[W0006]: comparing a number with nil is always true\n --> linted.lox:2:9",
+    );
+}
+
+#[test]
+fn wrong_arity() {
+    check_warning(
+        "fun g(p, q) { return p + q; }\ng(1);\n",
+        "warning[W0007]: 'g' takes 2 arguments but 1 was given\n --> linted.lox:2:1",
+    );
+}
+
+/// `// lint: allow` silences every lint on its line, `// lint: allow NAME, ...` just the ones
+/// it names, and neither reaches other lines.
+#[test]
+fn suppression() {
+    check_clean("fun f(unused) { return 1; } // lint: allow\nf(1);\n");
+    check_clean("var n = 1;\nif (n == nil) print n; // lint: allow shadowing, nil-comparison\n");
+    check_warning(
+        "var n = 1;\nif (n == nil) print n; // lint: allow shadowing\n",
+        "warning[W0006]",
+    );
+    check_warning(
+        "fun f(unused) { // lint: allow\n    var x = 1;\n}\nf(1);\n",
+        "warning[W0001]: unused variable 'x'\n --> linted.lox:2:9",
+    );
+}
@@ -0,0 +1,104 @@
+//! Runs `break` and `continue`, with and without labels, on both backends, and checks where
+//! each one leaves the loops around it.
+
+mod common;
+
+use common::check_output;
+
+/// `continue` in a `for` loop still runs the increment before the next check, and in a
+/// `while` loop goes straight back to the condition.
+#[test]
+fn continue_runs_increment() {
+    let source = "\
+for (var i = 0; i < 4; i = i + 1) {
+    if (i == 1) continue;
+    print i;
+}
+var n = 0;
+while (n < 5) {
+    n = n + 1;
+    if (n % 2 == 0) continue;
+    if (n == 5) break;
+    print n;
+}
+for (var i = 0; i < 3; i++) {
+    if (i < 2) continue;
+    print \"last \" + str(i);
+}
+";
+    check_output("continue", source, &[], "0\n2\n3\n1\n3\nlast 2\n");
+}
+
+/// A labelled `break` or `continue` leaves every loop up to the one with that label; an
+/// unlabelled one only the innermost.
+#[test]
+fn labels() {
+    let source = "\
+outer: for (var i = 0; i < 3; i = i + 1) {
+    for (var j = 0; j < 3; j = j + 1) {
+        if (j == 1) continue outer;
+        if (i == 2) break outer;
+        print \"${i},${j}\";
+    }
+    print \"never\";
+}
+rows: for (r in [1, 2, 3]) {
+    var c = 0;
+    while (true) {
+        c = c + 1;
+        if (c > r) continue rows;
+        if (r == 3) break rows;
+        print \"${r}:${c}\";
+    }
+}
+for (var i = 0; i < 2; i = i + 1) {
+    inner: while (true) {
+        break;
+    }
+    print \"after inner \" + str(i);
+}
+";
+    let expected = "\
+0,0
+1,0
+1:1
+2:1
+2:2
+after inner 0
+after inner 1
+";
+    check_output("labels", source, &[], expected);
+}
+
+/// Leaving a loop from inside a `try` runs its `finally` block first.
+#[test]
+fn finally() {
+    let source = "\
+for (var i = 0; i < 3; i = i + 1) {
+    try {
+        if (i == 1) continue;
+        if (i == 2) break;
+        print \"body \" + str(i);
+    } finally {
+        print \"finally \" + str(i);
+    }
+}
+outer: while (true) {
+    while (true) {
+        try {
+            break outer;
+        } finally {
+            print \"left both\";
+        }
+    }
+}
+";
+    let expected = "\
+body 0
+finally 0
+finally 1
+finally 2
+left both
+";
+    check_output("finally", source, &[], expected);
+}
@@ -0,0 +1,388 @@
+//! Drives `roxy lsp` through its stdin/stdout the way an editor would.
+
+use serde_json::{json, Value};
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+const URI: &str = "file:///test.lox";
+
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl Client {
+    /// Starts a server and completes the initialize handshake.
+    fn start() -> Client {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_roxy"))
+            .args(["--lists", "--lambdas", "lsp"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to start roxy lsp");
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut client = Client {
+            child,
+            stdin,
+            stdout,
+            next_id: 1,
+        };
+        client.request("initialize", json!({ "capabilities": {} }));
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut len = 0;
+        loop {
+            let mut header = String::new();
+            self.stdout.read_line(&mut header).unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length: ") {
+                len = value.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; len];
+        self.stdout.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Sends a request and returns its response, skipping notifications sent in between.
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let message = self.receive();
+            if message["id"] == id {
+                return message;
+            }
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Opens the test document and returns the diagnostics published for it.
+    fn open(&mut self, text: &str) -> Value {
+        self.notify(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "roxy", "version": 1, "text": text } }),
+        );
+        self.diagnostics()
+    }
+
+    fn diagnostics(&mut self) -> Value {
+        let message = self.receive();
+        assert_eq!(message["method"], "textDocument/publishDiagnostics");
+        assert_eq!(message["params"]["uri"], URI);
+        message["params"]["diagnostics"].clone()
+    }
+
+    fn at(&mut self, method: &str, line: u64, character: u64) -> Value {
+        self.request(
+            method,
+            json!({
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": true },
+            }),
+        )["result"]
+            .clone()
+    }
+
+    /// Shuts the server down cleanly and returns its exit code.
+    fn exit(mut self) -> i32 {
+        let response = self.request("shutdown", Value::Null);
+        assert_eq!(response["result"], Value::Null);
+        self.notify("exit", Value::Null);
+        self.child.wait().unwrap().code().unwrap()
+    }
+}
+
+fn range(line: u64, start: u64, end: u64) -> Value {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    })
+}
+
+const PROGRAM: &str = "\
+var total = 0;
+fun add(a, b) {
+    var total = a + b;
+    return total;
+}
+class Counter < Base {
+    bump(n) {
+        total = add(total, n);
+    }
+}
+print clock();
+";
+
+#[test]
+fn initialize_advertises_capabilities() {
+    let mut client = Client::start();
+    let response = client.request("initialize", json!({ "capabilities": {} }));
+    let capabilities = &response["result"]["capabilities"];
+    assert_eq!(capabilities["textDocumentSync"], 2);
+    assert_eq!(capabilities["definitionProvider"], true);
+    assert_eq!(capabilities["hoverProvider"], true);
+    assert!(capabilities["completionProvider"].is_object());
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn exit_without_shutdown_fails() {
+    let mut client = Client::start();
+    client.notify("exit", Value::Null);
+    assert_eq!(client.child.wait().unwrap().code(), Some(1));
+}
+
+#[test]
+fn parse_errors_are_published_and_cleared() {
+    let mut client = Client::start();
+    let diagnostics = client.open("var a = 1;\nprint a\n");
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["source"], "roxy");
+    assert!(diagnostics[0]["code"].as_str().unwrap().starts_with("E01"));
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 2);
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "var a = 1;\nprint a;\n" }],
+        }),
+    );
+    assert_eq!(client.diagnostics(), json!([]));
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn ranged_changes_are_applied_in_place() {
+    let mut client = Client::start();
+    let diagnostics = client.open("var a = 1;\nprint a\n");
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [
+                { "range": range(1, 7, 7), "text": ";" },
+                { "range": range(0, 4, 5), "text": "b" },
+                { "range": range(1, 6, 7), "text": "b" },
+            ],
+        }),
+    );
+    assert_eq!(client.diagnostics(), json!([]));
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 3 },
+            "contentChanges": [{ "range": range(1, 6, 6), "text": "(" }],
+        }),
+    );
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+    assert_eq!(diagnostics[0]["range"]["start"]["line"], 1);
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn resolver_errors_are_published() {
+    let mut client = Client::start();
+    let diagnostics = client.open("return 1;\n");
+    assert_eq!(diagnostics[0]["code"], "E0203");
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn definition_follows_scopes() {
+    let mut client = Client::start();
+    assert_eq!(client.open(PROGRAM), json!([]));
+
+    // `total` inside add is the local declared on line 3
+    let local = client.at("textDocument/definition", 3, 12);
+    assert_eq!(local["uri"], URI);
+    assert_eq!(local["range"], range(2, 8, 13));
+
+    // inside the method it is the global, as is `add`, declared below the use
+    assert_eq!(
+        client.at("textDocument/definition", 7, 8)["range"],
+        range(0, 4, 9)
+    );
+    assert_eq!(
+        client.at("textDocument/definition", 7, 16)["range"],
+        range(1, 4, 7)
+    );
+
+    // parameters
+    assert_eq!(
+        client.at("textDocument/definition", 2, 16)["range"],
+        range(1, 8, 9)
+    );
+
+    // natives and undeclared globals have no definition in the file
+    assert_eq!(client.at("textDocument/definition", 10, 7), Value::Null);
+    assert_eq!(client.at("textDocument/definition", 5, 17), Value::Null);
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn positions_count_utf16_code_units() {
+    let mut client = Client::start();
+    let diagnostics = client.open("print \"😀\" +;\n");
+    assert_eq!(diagnostics[0]["range"], range(0, 12, 13));
+
+    client.open("var s = \"😀\"; var x = 1;\nprint x;\n");
+    // the emoji is one character but two code units
+    assert_eq!(
+        client.at("textDocument/definition", 1, 6)["range"],
+        range(0, 18, 19)
+    );
+    assert_eq!(
+        client.at("textDocument/definition", 0, 18)["range"],
+        range(0, 18, 19)
+    );
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "range": range(0, 18, 19), "text": "y" }],
+        }),
+    );
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics, json!([]));
+    assert_eq!(client.at("textDocument/definition", 1, 6), Value::Null);
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn references_include_every_use() {
+    let mut client = Client::start();
+    client.open(PROGRAM);
+
+    let references = client.at("textDocument/references", 0, 5);
+    let ranges: Vec<Value> = references
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|location| location["range"].clone())
+        .collect();
+    assert_eq!(
+        ranges,
+        vec![range(0, 4, 9), range(7, 20, 25), range(7, 8, 13)]
+    );
+
+    let without_declaration = client.request(
+        "textDocument/references",
+        json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": 1, "character": 4 },
+            "context": { "includeDeclaration": false },
+        }),
+    );
+    assert_eq!(
+        without_declaration["result"],
+        json!([{ "uri": URI, "range": range(7, 16, 19) }])
+    );
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn hover_describes_declarations() {
+    let mut client = Client::start();
+    client.open(PROGRAM);
+
+    let hover = client.at("textDocument/hover", 7, 17);
+    assert_eq!(hover["contents"]["kind"], "markdown");
+    assert_eq!(hover["contents"]["value"], "```roxy\nfun add(a, b)\n```");
+    assert_eq!(hover["range"], range(7, 16, 19));
+
+    let class = client.at("textDocument/hover", 5, 7);
+    assert_eq!(
+        class["contents"]["value"],
+        "```roxy\nclass Counter < Base\n```"
+    );
+
+    let native = client.at("textDocument/hover", 10, 8);
+    assert!(native["contents"]["value"]
+        .as_str()
+        .unwrap()
+        .contains("native fun clock"));
+
+    assert_eq!(client.at("textDocument/hover", 0, 0), Value::Null);
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn document_symbols_nest_methods_in_classes() {
+    let mut client = Client::start();
+    client.open(PROGRAM);
+
+    let symbols = client.request(
+        "textDocument/documentSymbol",
+        json!({ "textDocument": { "uri": URI } }),
+    )["result"]
+        .clone();
+    let symbols = symbols.as_array().unwrap();
+    assert_eq!(symbols.len(), 2);
+    assert_eq!(symbols[0]["name"], "add");
+    assert_eq!(symbols[0]["kind"], 12);
+    assert_eq!(symbols[0]["selectionRange"], range(1, 4, 7));
+    assert_eq!(symbols[1]["name"], "Counter");
+    assert_eq!(symbols[1]["kind"], 5);
+    assert_eq!(symbols[1]["children"][0]["name"], "bump");
+    assert_eq!(symbols[1]["children"][0]["kind"], 6);
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn completion_offers_keywords_natives_and_globals() {
+    let mut client = Client::start();
+    client.open(PROGRAM);
+
+    let items = client.at("textDocument/completion", 11, 0);
+    let labels: Vec<&str> = items
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    for label in [
+        "while", "import", "lambda", "clock", "push", "add", "Counter", "total",
+    ] {
+        assert!(labels.contains(&label), "missing completion {}", label);
+    }
+    // locals aren't visible at the top level
+    assert!(!labels.contains(&"a"));
+    assert_eq!(client.exit(), 0);
+}
+
+#[test]
+fn unknown_requests_are_rejected() {
+    let mut client = Client::start();
+    let response = client.request("textDocument/rename", json!({}));
+    assert_eq!(response["error"]["code"], -32601);
+    assert_eq!(client.exit(), 0);
+}
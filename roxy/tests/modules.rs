@@ -0,0 +1,176 @@
+//! Runs programs that import other files, on both backends, and checks what gets loaded, how
+//! often, and what is refused.
+
+mod common;
+
+use common::{roxy, TempDir, BACKENDS};
+
+/// Runs `main.lox` in `dir` on each backend with `args`, and hands back its exit code,
+/// stdout and stderr.
+fn run_main(dir: &TempDir, args: &[&str]) -> Vec<(Option<i32>, String, String)> {
+    BACKENDS
+        .iter()
+        .map(|backend| {
+            let output = roxy(dir.path())
+                .args(["--backend", backend])
+                .args(args)
+                .args(["-f", "main.lox"])
+                .output()
+                .expect("failed to start roxy");
+            (
+                output.status.code(),
+                String::from_utf8(output.stdout).unwrap(),
+                String::from_utf8(output.stderr).unwrap(),
+            )
+        })
+        .collect()
+}
+
+/// Checks `main.lox` in `dir` runs to completion on both backends and prints `expected`.
+fn check_main(dir: &TempDir, expected: &str) {
+    for (backend, (code, stdout, stderr)) in BACKENDS.iter().zip(run_main(dir, &[])) {
+        assert_eq!(code, Some(0), "{}: {}", backend, stderr);
+        assert_eq!(stdout, expected, "{}", backend);
+    }
+}
+
+/// Checks `main.lox` in `dir` is refused with `expected` before anything runs.
+fn check_refused(dir: &TempDir, args: &[&str], expected: &str) {
+    for (backend, (code, stdout, stderr)) in BACKENDS.iter().zip(run_main(dir, args)) {
+        assert_eq!(code, Some(65), "{}: {}", backend, stderr);
+        assert_eq!(stdout, "", "{}", backend);
+        assert!(
+            stderr.contains(expected),
+            "{}: expected {:?} in\n{}",
+            backend,
+            expected,
+            stderr
+        );
+    }
+}
+
+/// Paths are relative to the file doing the importing, not to where roxy was started.
+#[test]
+fn paths() {
+    let dir = TempDir::new();
+    dir.write(
+        "main.lox",
+        "import \"lib/a.lox\";\nprint a();\nprint b();\n",
+    );
+    dir.write(
+        "lib/a.lox",
+        "import \"b.lox\";\nfun a() { return \"a\" + b(); }\n",
+    );
+    dir.write("lib/b.lox", "fun b() { return \"b\"; }\n");
+    check_main(&dir, "ab\nb\n");
+}
+
+/// A module imported from several places runs once, before the first program that imports
+/// it, and later imports of it see the state it was left in.
+#[test]
+fn runs_once() {
+    let dir = TempDir::new();
+    dir.write(
+        "main.lox",
+        "import \"counter.lox\";\nimport \"user.lox\";\nimport \"counter.lox\";\nprint count;\n",
+    );
+    dir.write(
+        "counter.lox",
+        "print \"loading counter\";\nvar count = 0;\n",
+    );
+    dir.write(
+        "user.lox",
+        "import \"counter.lox\";\nprint \"loading user\";\ncount = count + 1;\n",
+    );
+    check_main(&dir, "loading counter\nloading user\n1\n");
+}
+
+/// `import name from` checks the module declares `name`, and otherwise imports it like the
+/// plain form: everything the module declares is a global.
+#[test]
+fn named() {
+    let dir = TempDir::new();
+    dir.write(
+        "main.lox",
+        "import greet from \"greet.lox\";\nprint greet();\nprint other;\n",
+    );
+    dir.write(
+        "greet.lox",
+        "fun greet() { return \"hello\"; }\nvar other = 2;\n",
+    );
+    check_main(&dir, "hello\n2\n");
+
+    dir.write("main.lox", "import missing from \"greet.lox\";\n");
+    check_refused(
+        &dir,
+        &[],
+        "error[E0403]: module \"greet.lox\" has no top-level declaration 'missing'\n \
+         --> main.lox:1:8",
+    );
+}
+
+/// What a module declares is type checked in the programs that import it, as it would be
+/// had they declared it themselves.
+#[test]
+fn types() {
+    let dir = TempDir::new();
+    dir.write(
+        "main.lox",
+        "import \"typed.lox\";\nprint twice(2);\ntwice(\"s\");\n",
+    );
+    dir.write(
+        "typed.lox",
+        "fun twice(n: num): num { return n * 2; }\nvar count: num = 0;\n",
+    );
+    check_refused(
+        &dir,
+        &[],
+        "error[E0501]: expected num, found str\n --> main.lox:3:7",
+    );
+
+    dir.write("main.lox", "import \"typed.lox\";\ncount = \"many\";\n");
+    check_refused(
+        &dir,
+        &[],
+        "error[E0501]: expected num, found str\n --> main.lox:2:9",
+    );
+}
+
+#[test]
+fn missing() {
+    let dir = TempDir::new();
+    dir.write("main.lox", "print 1;\nimport \"nowhere.lox\";\n");
+    check_refused(
+        &dir,
+        &[],
+        "error[E0401]: cannot load module \"nowhere.lox\"",
+    );
+}
+
+#[test]
+fn cycles() {
+    let dir = TempDir::new();
+    dir.write("main.lox", "import \"a.lox\";\n");
+    dir.write("a.lox", "import \"b.lox\";\n");
+    dir.write("b.lox", "import \"a.lox\";\n");
+    check_refused(&dir, &[], "error[E0402]: cyclic import of \"a.lox\"");
+    for (_, _, stderr) in run_main(&dir, &[]) {
+        assert!(stderr.contains("import chain: "), "{}", stderr);
+        assert!(stderr.contains("a.lox -> "), "{}", stderr);
+    }
+
+    dir.write("main.lox", "import \"main.lox\";\n");
+    check_refused(&dir, &[], "error[E0402]: cyclic import of \"main.lox\"");
+}
+
+#[test]
+fn no_io() {
+    let dir = TempDir::new();
+    dir.write("main.lox", "import \"lib.lox\";\n");
+    dir.write("lib.lox", "print \"loaded\";\n");
+    check_refused(
+        &dir,
+        &["--no-io"],
+        "error[E0404]: cannot import \"lib.lox\": I/O is disabled\n --> main.lox:1:8",
+    );
+}
@@ -0,0 +1,240 @@
+//! Runs the arithmetic, bitwise, assignment and conditional operators, number literals and
+//! string escapes and interpolation on both backends, and checks what they evaluate to and
+//! the errors they raise.
+
+mod common;
+
+use common::{check_error, check_output};
+
+#[test]
+fn modulo() {
+    let source = "\
+print 7 % 3;
+print -7 % 3;
+print 7.5 % 2;
+print 1 % 0;
+";
+    check_output("modulo", source, &[], "1\n-1\n1.5\nNaN\n");
+}
+
+/// `**` binds tighter than a unary minus on its left and groups to the right.
+#[test]
+fn power() {
+    let source = "\
+print 2 ** 10;
+print 2 ** -1;
+print -2 ** 2;
+print 2 ** 3 ** 2;
+";
+    check_output("power", source, &[], "1024\n0.5\n-4\n512\n");
+}
+
+#[test]
+fn bitwise() {
+    let source = "\
+print 6 & 3;
+print 6 | 3;
+print 6 ^ 3;
+print ~5;
+print 1 << 4;
+print -16 >> 2;
+print 1 << 63 >> 63;
+";
+    check_output("bitwise", source, &[], "2\n7\n5\n-6\n16\n-4\n-1\n");
+}
+
+#[test]
+fn number_literals() {
+    let source = "\
+print 0xff;
+print 0b1010;
+print 1_000_000;
+print 0xff_ff;
+print 0b1111_0000;
+";
+    check_output(
+        "number_literals",
+        source,
+        &[],
+        "255\n10\n1000000\n65535\n240\n",
+    );
+}
+
+/// Compound assignments read the target once and write the result back, whether that is a
+/// variable, a field or an element, and evaluate to what they wrote.
+#[test]
+fn compound_assignment() {
+    let source = "\
+var a = 10;
+a += 5;
+print a;
+a -= 3;
+print a;
+a *= 2;
+print a;
+print a /= 4;
+class P {}
+var p = P();
+p.n = 1;
+p.n += 2;
+print p.n;
+p.n -= 1;
+p.n *= 5;
+p.n /= 2;
+print p.n;
+var l = [1, 2];
+l[1] -= 5;
+l[0] += 1;
+l[0] *= 3;
+l[0] /= 4;
+print l;
+var s = \"a\";
+s += \"b\";
+print s;
+var reads = 0;
+fun index() {
+    reads = reads + 1;
+    return 1;
+}
+l[index()] += 10;
+print l[1];
+print reads;
+";
+    check_output(
+        "compound_assignment",
+        source,
+        &[],
+        "15\n12\n24\n6\n3\n5\n[1.5, -3]\nab\n7\n1\n",
+    );
+}
+
+/// Prefix forms evaluate to the new value and postfix forms to the old one.
+#[test]
+fn increment() {
+    let source = "\
+var i = 0;
+print i++;
+print i;
+print ++i;
+print i--;
+print --i;
+class P {}
+var p = P();
+p.n = 0;
+print p.n++;
+print ++p.n;
+var l = [5];
+print l[0]--;
+print --l[0];
+print l;
+";
+    check_output("increment", source, &[], "0\n1\n2\n2\n0\n0\n2\n5\n3\n[3]\n");
+}
+
+/// Only the branch chosen is evaluated, and `?:` groups to the right.
+#[test]
+fn ternary() {
+    let source = "\
+print true ? \"yes\" : \"no\";
+print nil ? 1 : 0 ? 2 : 3;
+print false ? 1 : false ? 2 : 3;
+var calls = 0;
+fun bump() {
+    calls = calls + 1;
+    return calls;
+}
+print true ? \"left\" : bump();
+print calls;
+";
+    check_output("ternary", source, &[], "yes\n2\n3\nleft\n0\n");
+}
+
+#[test]
+fn escapes() {
+    let source = "\
+print \"tab\\there\";
+print \"quote \\\" backslash \\\\ newline\\nend\";
+print \"dollar \\${not interpolated}\";
+print len(\"\\r\\0\");
+";
+    check_output(
+        "escapes",
+        source,
+        &[],
+        "tab\there\nquote \" backslash \\ newline\nend\ndollar ${not interpolated}\n2\n",
+    );
+}
+
+/// The expressions in `${}` are evaluated in order and printed the way `print` shows them,
+/// and may hold strings with holes of their own.
+#[test]
+fn interpolation() {
+    let source = "\
+var name = \"roxy\";
+print \"hi ${name}, ${1 + 2} and ${\"nested ${name}\"}\";
+print \"${[1, \"a\"]} ${nil} ${true}\";
+var n = 0;
+print \"${n++}${n++}${n}\";
+print \"${name}\";
+";
+    check_output(
+        "interpolation",
+        source,
+        &[],
+        "hi roxy, 3 and nested roxy\n[1, \"a\"] nil true\n012\nroxy\n",
+    );
+}
+
+#[test]
+fn bitwise_errors() {
+    check_error(
+        "fraction",
+        "print 1.5 & 1;\n",
+        &[],
+        70,
+        "error[E0301]: bitwise operator Ampersand needs integer operands, found 1.5 and 1",
+    );
+    check_error(
+        "fraction_shifted",
+        "print 1.5 << 1;\n",
+        &[],
+        70,
+        "needs integer operands, found 1.5 and 1",
+    );
+    check_error(
+        "complement",
+        "print ~0.5;\n",
+        &[],
+        70,
+        "error[E0301]: bitwise operator Tilde needs an integer, found 0.5",
+    );
+    check_error(
+        "string",
+        "print 1 | \"a\";\n",
+        &[],
+        70,
+        "invalid operands in binary operator Pipe of types Number and String",
+    );
+}
+
+#[test]
+fn shift_range() {
+    for (source, expected) in [
+        ("print 1 << 64;\n", "error[E0301]: cannot shift by 64"),
+        ("print 1 >> -1;\n", "error[E0301]: cannot shift by -1"),
+    ] {
+        check_error("shift_range", source, &[], 70, expected);
+    }
+}
+
+#[test]
+fn unknown_escape() {
+    let printed = check_error(
+        "unknown_escape",
+        "print 1;\nprint \"bad \\q\";\n",
+        &[],
+        65,
+        "error[E0001]: unknown escape sequence '\\q'\n --> unknown_escape.lox:2:13",
+    );
+    assert!(printed.iter().all(String::is_empty), "{:?}", printed);
+}
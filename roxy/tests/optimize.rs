@@ -0,0 +1,90 @@
+//! `-O` mustn't change what a program does: each program here prints the same, fails the same
+//! way and exits with the same code, on both backends, optimized or not. What the optimizer
+//! makes of the tree is in the `optimize` golden dump.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+fn roxy(path: &Path, backend: &str, optimize: bool) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_roxy"));
+    command.args(["--lists", "--lambdas", "--backend", backend]);
+    if optimize {
+        command.arg("-O");
+    }
+    command
+        .args(["-f", path.to_str().unwrap()])
+        .output()
+        .expect("failed to start roxy")
+}
+
+fn check_same(path: &Path) {
+    for backend in ["treewalk", "vm"] {
+        let plain = roxy(path, backend, false);
+        let optimized = roxy(path, backend, true);
+        assert_eq!(
+            (plain.status.code(), &plain.stdout, &plain.stderr),
+            (
+                optimized.status.code(),
+                &optimized.stdout,
+                &optimized.stderr
+            ),
+            "{} on {}:\n{}\n-O:\n{}",
+            path.display(),
+            backend,
+            String::from_utf8_lossy(&plain.stdout),
+            String::from_utf8_lossy(&optimized.stdout)
+        );
+    }
+}
+
+#[test]
+fn golden_programs_behave_the_same() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "lox") {
+            check_same(&path);
+        }
+    }
+}
+
+#[test]
+fn folded_code_runs_as_it_did() {
+    let source = "\
+var double = (lambda(x) { return x * 2; })(21);
+var shout = lambda(s) { return s + \"!\"; };
+print double + 0.5 * 4;
+print shout(\"hi\" + \" \" + \"there\");
+print lambda(a, b) { return a > b ? a : b; }(3, 4);
+print lambda() { return; }();
+
+fun sign(n) {
+    if (n < 0) return -1;
+    else if (n == 0) return 0;
+    return 1;
+    print \"never\";
+}
+print sign(-5) + sign(0) * 10 + sign(2 ** 3) * 100;
+
+var i = 0;
+while (1 > 2) i = i + 1;
+outer: for (var j = 0; j < 3; j = j + 1) {
+    if (true and j == 1) continue outer;
+    if (false or nil) print \"never\";
+    print j;
+}
+print -(2 - 7) % 3 == 2 or \"odd\";
+print ~5 & 0xff;
+";
+    let path = std::env::temp_dir().join(format!("roxy-optimize-{}.lox", std::process::id()));
+    fs::write(&path, source).unwrap();
+    let output = roxy(&path, "vm", true);
+    check_same(&path);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "44\nhi there!\n4\nnil\n99\n0\n2\ntrue\n250\n"
+    );
+}
@@ -0,0 +1,187 @@
+//! Types input into the REPL, as a script piped to its stdin, and checks what it answers.
+
+mod common;
+
+use common::{run_stdin, TempDir, BACKENDS};
+use std::process::Output;
+
+const BANNER: &str = concat!(
+    "roxy ",
+    env!("CARGO_PKG_VERSION"),
+    ", type :help for commands\n"
+);
+
+/// Types `input` into a REPL on `backend`, checking it leaves cleanly, and hands back what it
+/// printed after its banner, and to stderr.
+fn session(backend: &str, input: &str) -> (String, String) {
+    let dir = TempDir::new();
+    let Output {
+        status,
+        stdout,
+        stderr,
+    } = run_stdin(dir.path(), &["--lists", "--backend", backend], input);
+    let stderr = String::from_utf8(stderr).unwrap();
+    assert!(status.success(), "{}: {}", backend, stderr);
+    let stdout = String::from_utf8(stdout).unwrap();
+    let stdout = stdout.strip_prefix(BANNER).unwrap_or_else(|| {
+        panic!("{}: no banner in {:?}", backend, stdout);
+    });
+    (stdout.to_string(), stderr)
+}
+
+/// Checks typing `input` prints `expected` and nothing on stderr, on both backends.
+fn check(input: &str, expected: &str) {
+    for backend in BACKENDS {
+        let (stdout, stderr) = session(backend, input);
+        assert_eq!(stderr, "", "{}", backend);
+        assert_eq!(stdout, expected, "{}", backend);
+    }
+}
+
+/// Unclosed brackets and strings wait for more lines before anything runs, and an empty line
+/// submits what there is.
+#[test]
+fn continuation() {
+    let input = "\
+fun add(a,
+        b) {
+    return a + b;
+}
+print add(1, 2);
+var s = \"two
+lines\";
+print s;
+print [1,
+2];
+";
+    check(input, "3\ntwo\nlines\n[1, 2]\n");
+
+    for backend in BACKENDS {
+        let (stdout, stderr) = session(backend, "print 1 +\n\nprint \"next\";\n");
+        assert_eq!(stdout, "next\n", "{}", backend);
+        assert!(
+            stderr.starts_with("error[E0106]: expected expression, found Eof"),
+            "{}: {}",
+            backend,
+            stderr
+        );
+    }
+}
+
+/// A bare expression has its value printed, as `print` would show it, without needing a
+/// semicolon, unless that is nil; statements print nothing of their own.
+#[test]
+fn echo() {
+    let input = "\
+1 + 2
+\"text\"
+[1, \"a\"];
+var x = 4;
+x * 2
+nil
+print nil;
+";
+    check(input, "3\n\"text\"\n[1, \"a\"]\n8\nnil\n");
+}
+
+#[test]
+fn commands() {
+    let input = "\
+:tokens 1 +
+:ast print 1;
+:help
+";
+    let expected = "\
+Token { ty: Number, lexeme: \"1\", literal: Some(Number(1.0)), line: 1, col: 0}
+Token { ty: Plus, lexeme: \"+\", literal: None, line: 1, col: 2}
+Token { ty: Eof, lexeme: \"\", literal: None, line: 1, col: 3}
+Print(
+    SourceLocation {
+        line: 1,
+        col: 0,
+    },
+    Literal(
+        SourceLocation {
+            line: 1,
+            col: 6,
+        },
+        Number(
+            1.0,
+        ),
+    ),
+)
+:tokens <code>  print the tokens the scanner produces for <code>
+:ast <code>     print the syntax tree the parser produces for <code>
+:quit           leave the REPL
+";
+    check(input, expected);
+
+    for backend in BACKENDS {
+        let (stdout, stderr) = session(backend, ":bogus\nprint 1;\n:quit\nprint 2;\n");
+        assert_eq!(stdout, "1\n", "{}", backend);
+        assert_eq!(
+            stderr, "unknown command :bogus, type :help for commands\n",
+            "{}",
+            backend
+        );
+    }
+}
+
+/// Variables, functions and classes declared by one input are there for the next, and an
+/// error in one input leaves the rest of the session as it was.
+#[test]
+fn state_kept() {
+    let input = "\
+var count = 0;
+fun bump() { count = count + 1; return count; }
+class Point { init(x) { this.x = x; } }
+bump();
+print missing;
+var p = Point(bump());
+print p.x;
+print count;
+";
+    for backend in BACKENDS {
+        let (stdout, stderr) = session(backend, input);
+        assert_eq!(stdout, "1\n2\n2\n", "{}", backend);
+        assert!(
+            stderr.contains("undefined variable 'missing'"),
+            "{}: {}",
+            backend,
+            stderr
+        );
+    }
+}
+
+/// Annotations made by one input hold in the next; an input refused for its types declares
+/// nothing.
+#[test]
+fn types_kept() {
+    let input = "\
+var x: num = 1;
+x = \"s\";
+fun f(a: str) { return a; }
+f(2);
+var y: num = \"bad\";
+var y = \"fine\";
+print x;
+print y;
+";
+    for backend in BACKENDS {
+        let (stdout, stderr) = session(backend, input);
+        assert_eq!(stdout, "1\nfine\n", "{}", backend);
+        assert_eq!(
+            stderr.matches("error[E0501]: expected").count(),
+            3,
+            "{}: {}",
+            backend,
+            stderr
+        );
+        assert!(
+            stderr.contains("note: parameter 1 of 'f' is str"),
+            "{}: {}",
+            backend,
+            stderr
+        );
+    }
+}
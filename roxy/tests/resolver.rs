@@ -0,0 +1,244 @@
+//! Checks what the resolver rejects, where it says so, and that its scope depths stay with the
+//! program they were computed for, wherever programs come from.
+
+mod common;
+
+use common::{check_error, check_output, roxy, run, run_stdin, TempDir, BACKENDS};
+use roxy::{Engine, Value};
+
+/// Checks `source` is rejected before it runs with `expected`, the first lines of the error
+/// down to where it points.
+fn check_rejected(name: &str, source: &str, expected: &str) {
+    let printed = check_error(name, source, &[], 65, expected);
+    assert!(printed.iter().all(String::is_empty), "{:?}", printed);
+}
+
+#[test]
+fn own_initializer() {
+    check_rejected(
+        "own_initializer",
+        "{ var a = 1; { var a = a; } }\n",
+        "error[E0201]: cannot read local variable 'a' in its own initializer\n \
+         --> own_initializer.lox:1:24",
+    );
+}
+
+#[test]
+fn already_declared() {
+    check_rejected(
+        "already_declared",
+        "fun f() { var a = 1; var a = 2; }\n",
+        "error[E0202]: variable 'a' is already declared in this scope\n \
+         --> already_declared.lox:1:26",
+    );
+}
+
+#[test]
+fn top_level_return() {
+    check_rejected(
+        "top_level_return",
+        "print 1;\nreturn 1;\n",
+        "error[E0203]: cannot return from top-level code\n --> top_level_return.lox:2:1",
+    );
+}
+
+#[test]
+fn return_from_initializer() {
+    check_rejected(
+        "return_from_initializer",
+        "class A { init() { return 1; } }\n",
+        "error[E0204]: cannot return a value from an initializer\n \
+         --> return_from_initializer.lox:1:20",
+    );
+}
+
+#[test]
+fn this_outside_class() {
+    check_rejected(
+        "this_outside_class",
+        "print this;\n",
+        "error[E0205]: cannot use 'this' outside of a class\n --> this_outside_class.lox:1:7",
+    );
+}
+
+#[test]
+fn super_outside_class() {
+    check_rejected(
+        "super_outside_class",
+        "print super.x;\n",
+        "error[E0206]: cannot use 'super' outside of a class\n \
+         --> super_outside_class.lox:1:7",
+    );
+}
+
+#[test]
+fn super_without_superclass() {
+    check_rejected(
+        "super_without_superclass",
+        "class A { f() { return super.f(); } }\n",
+        "error[E0207]: cannot use 'super' in a class with no superclass\n \
+         --> super_without_superclass.lox:1:24",
+    );
+}
+
+#[test]
+fn inherits_from_itself() {
+    check_rejected(
+        "inherits_from_itself",
+        "class A < A {}\n",
+        "error[E0208]: class 'A' cannot inherit from itself\n \
+         --> inherits_from_itself.lox:1:11",
+    );
+}
+
+#[test]
+fn nested_import() {
+    check_rejected(
+        "nested_import",
+        "{ import \"x.lox\"; }\n",
+        "error[E0209]: import is only allowed at the top level\n --> nested_import.lox:1:10",
+    );
+}
+
+#[test]
+fn break_outside_loop() {
+    check_rejected(
+        "break_outside_loop",
+        "break;\n",
+        "error[E0210]: cannot break outside of a loop\n --> break_outside_loop.lox:1:1",
+    );
+}
+
+#[test]
+fn continue_outside_loop() {
+    check_rejected(
+        "continue_outside_loop",
+        "continue;\n",
+        "error[E0211]: cannot continue outside of a loop\n --> continue_outside_loop.lox:1:1",
+    );
+}
+
+#[test]
+fn label_outside_function() {
+    check_rejected(
+        "label_outside_function",
+        "outer: while (true) { fun f() { while (true) break outer; } }\n",
+        "error[E0212]: no enclosing loop is labeled 'outer'\n \
+         --> label_outside_function.lox:1:52",
+    );
+    check_rejected(
+        "label_outside_function",
+        "outer: while (true) { fun f() { while (true) break outer; } }\n",
+        "= note: a function cannot leave the loops around it",
+    );
+}
+
+/// A label no loop has is just undefined; the note about functions is only for labels a
+/// loop outside the function has.
+#[test]
+fn undefined_label() {
+    let source = "a: while (true) { break b; }\n";
+    check_rejected(
+        "undefined_label",
+        source,
+        "error[E0212]: no enclosing loop is labeled 'b'\n --> undefined_label.lox:1:25",
+    );
+    for backend in BACKENDS {
+        let stderr =
+            String::from_utf8(run("undefined_label", source, backend, &[]).stderr).unwrap();
+        assert!(!stderr.contains("note"), "{}: {}", backend, stderr);
+    }
+}
+
+/// A closure sees the variable that was in scope where it was declared, even once another
+/// of the same name is declared closer to where it is called.
+#[test]
+fn closure_capture() {
+    let source = "\
+var a = \"global\";
+{
+    fun show() {
+        print a;
+    }
+    show();
+    var a = \"block\";
+    show();
+    print a;
+}
+var fns = [];
+for (var i = 0; i < 2; i = i + 1) {
+    var j = i;
+    fns = fns + [lambda () { return j; }];
+}
+print fns[0]() + fns[1]();
+";
+    check_output("closure_capture", source, &[], "global\nglobal\nblock\n1\n");
+}
+
+/// A local and a later global at the same line and column, and a function declared by one
+/// input reading a global defined by the next.
+#[test]
+fn repl_inputs() {
+    let input = "\
+{ var x = 1; print x; }
+var x = 5;
+{ var q = 1; print x; }
+fun add(n) { var m = n; return m + x; }
+print add(1);
+";
+    let dir = TempDir::new();
+    for backend in BACKENDS {
+        let output = run_stdin(dir.path(), &["--backend", backend], input);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.is_empty(), "{}: {}", backend, stderr);
+        assert!(
+            String::from_utf8(output.stdout)
+                .unwrap()
+                .ends_with("1\n5\n6\n"),
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn engine_evals() {
+    let mut engine = Engine::new();
+    engine.eval("var y; { var x = 1; y = x; }").unwrap();
+    engine.eval("var x = 5;").unwrap();
+    engine.eval("var z; { var q = 1; y = x; }").unwrap();
+    assert_eq!(engine.get::<f64>("y").unwrap(), 5.0);
+    engine
+        .eval("fun add(n) { var m = n; return m + x; }")
+        .unwrap();
+    engine.eval("x = 6;").unwrap();
+    assert_eq!(
+        engine.call::<f64>("add", vec![Value::Number(1.0)]).unwrap(),
+        7.0
+    );
+    assert_eq!(engine.eval("add(2)").unwrap().to_string(), "8");
+}
+
+#[test]
+fn modules() {
+    let dir = TempDir::new();
+    dir.write("m.lox", "\n\nfun hel() { var x = 1; return x; }\n");
+    dir.write(
+        "main.lox",
+        "import \"m.lox\";\nvar x = 42;\nfun use() { var y = 1; return x; }\nprint use();\nprint hel();\n",
+    );
+    for backend in BACKENDS {
+        let output = roxy(dir.path())
+            .args(["--backend", backend, "-f", "main.lox"])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}: {}", backend, stderr);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "42\n1\n",
+            "{}",
+            backend
+        );
+    }
+}
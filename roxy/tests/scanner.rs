@@ -0,0 +1,160 @@
+//! The scanner on source that isn't ASCII, and scanning as the source is read.
+
+mod common;
+
+use common::{roxy, TempDir, BACKENDS};
+use roxy::embed::Engine;
+use roxy::scanner::{self, Literal, Token, TokenType};
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+fn scan(source: &str) -> Vec<Token> {
+    scanner::scan_tokens(source.to_string()).unwrap()
+}
+
+/// Hands out its bytes one at a time, so characters are split across reads.
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.split_first() {
+            Some((byte, rest)) if !buf.is_empty() => {
+                buf[0] = *byte;
+                self.0 = rest;
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+/// Fails partway through.
+struct Broken;
+
+impl Read for Broken {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::other("disk on fire"))
+    }
+}
+
+#[test]
+fn identifiers_and_strings_keep_their_characters() {
+    let tokens = scan("var größe = \"日本語 ✓\"; var ñandú2 = größe;");
+    assert_eq!(tokens[1].ty, TokenType::Identifier);
+    assert_eq!(tokens[1].lexeme, "größe".as_bytes());
+    match &tokens[3].literal {
+        Some(Literal::Str(s)) => assert_eq!(s, "日本語 ✓"),
+        other => panic!("expected a string, found {:?}", other),
+    }
+    assert_eq!(tokens[6].lexeme, "ñandú2".as_bytes());
+
+    // a decomposed accent goes on the letter before it
+    let tokens = scan("var cafe\u{301} = 1;");
+    assert_eq!(tokens[1].lexeme, "cafe\u{301}".as_bytes());
+    assert_eq!(tokens[2].ty, TokenType::Equal);
+
+    let mut engine = Engine::new();
+    engine
+        .eval("var größe = \"日本語\"; var 名前 = \"${größe} ✓\";")
+        .unwrap();
+    assert_eq!(engine.get::<String>("名前").unwrap(), "日本語 ✓");
+}
+
+#[test]
+fn columns_count_characters() {
+    let tokens = scan("var é = \"ü\"; print é;\n  \"😀\" é");
+    let cols: Vec<i64> = tokens.iter().map(|token| token.col).collect();
+    assert_eq!(cols, [0, 4, 6, 8, 11, 13, 19, 20, 2, 6, 7]);
+    assert_eq!(tokens[1].width(), 1);
+
+    let err = scanner::scan_tokens("print \"ß\" @;".to_string()).unwrap_err();
+    assert_eq!((err.line, err.col), (1, 10));
+}
+
+#[test]
+fn columns_convert_to_utf16() {
+    let line = "var s = \"😀\"; é";
+    // the emoji takes two UTF-16 code units, é one
+    assert_eq!(scanner::utf16_col(line, 9), 9);
+    assert_eq!(scanner::utf16_col(line, 10), 11);
+    assert_eq!(scanner::utf16_col(line, 14), 15);
+    assert_eq!(scanner::utf16_col(line, 20), 21);
+    assert_eq!(scanner::col_from_utf16(line, 11), 10);
+    // the middle of the surrogate pair is the emoji
+    assert_eq!(scanner::col_from_utf16(line, 10), 9);
+    assert_eq!(scanner::col_from_utf16(line, 21), 20);
+}
+
+#[test]
+fn reading_gives_the_same_tokens_as_a_string() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut sources: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .map(|path| fs::read_to_string(path).unwrap())
+        .collect();
+    sources.push("var größe = \"日本語 ${1 + 2} ✓\"; // ünïcödé\n".to_string());
+
+    for source in sources {
+        let expected = format!("{:?}", scan(&source));
+        let read: Result<Vec<Token>, _> =
+            scanner::scan_reader(Trickle(source.as_bytes())).collect();
+        assert_eq!(format!("{:?}", read.unwrap()), expected);
+    }
+}
+
+#[test]
+fn reading_stops_at_the_first_error() {
+    let mut tokens = scanner::scan_reader("print 1; @ print 2;".as_bytes());
+    assert_eq!(tokens.next().unwrap().unwrap().ty, TokenType::Print);
+    assert_eq!(tokens.by_ref().filter(Result::is_ok).count(), 2);
+    assert!(tokens.next().is_none());
+
+    let errors: Vec<_> = scanner::scan_reader(Trickle(b"print \"a\xff\";"))
+        .filter_map(Result::err)
+        .collect();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].what.contains("not valid UTF-8"), "{:?}", errors);
+
+    let last = scanner::scan_reader(Broken).last().unwrap();
+    assert!(last.unwrap_err().what.contains("disk on fire"));
+}
+
+/// A file that isn't UTF-8 is refused with a diagnostic at the bad byte, whatever is done with
+/// it, and nothing in it runs.
+#[test]
+fn files_that_are_not_utf8() {
+    let dir = TempDir::new();
+    fs::write(dir.path().join("bad.lox"), b"print 1;\nprint \"\xff\";\n").unwrap();
+    let mut commands: Vec<Vec<&str>> = BACKENDS
+        .iter()
+        .map(|backend| vec!["--backend", backend, "-f", "bad.lox"])
+        .collect();
+    commands.push(vec!["--dump-tokens", "-f", "bad.lox"]);
+    for args in commands {
+        let output = roxy(dir.path()).args(&args).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(65), "{:?}: {}", args, stderr);
+        assert!(
+            stderr.starts_with(
+                "error[E0001]: source is not valid UTF-8 at byte 0xff\n --> bad.lox:2:8"
+            ),
+            "{:?}: {}",
+            args,
+            stderr
+        );
+        if args[0] == "--backend" {
+            assert!(output.stdout.is_empty(), "{:?}", args);
+        }
+    }
+    for command in ["fmt", "lint"] {
+        let output = roxy(dir.path())
+            .args([command, "bad.lox"])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(65), "{}", command);
+    }
+}
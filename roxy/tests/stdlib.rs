@@ -0,0 +1,212 @@
+//! Calls every native on both backends, each of which has its own copy of them, and checks
+//! they give the same results and reject the same arguments.
+
+mod common;
+
+use common::{check_error, check_output, run_stdin, TempDir, BACKENDS};
+use roxy::stdlib;
+use roxy::Extensions;
+
+/// Both backends define natives of the same names and arities, with the extensions on or off.
+#[test]
+fn same_natives() {
+    for lists in [false, true] {
+        let extensions = Extensions {
+            lists,
+            lambdas: false,
+        };
+        let treewalk: Vec<(String, u8)> = stdlib::natives(extensions)
+            .into_iter()
+            .map(|native| (native.name, native.arity))
+            .collect();
+        let vm: Vec<(String, u8)> = stdlib::vm_natives(extensions)
+            .into_iter()
+            .map(|native| (native.name.to_string(), native.arity))
+            .collect();
+        assert_eq!(treewalk, vm, "lists: {}", lists);
+    }
+}
+
+#[test]
+fn results() {
+    let source = "\
+print clock() > 0;
+print len(\"héllo\");
+print len([1, 2]);
+print len({\"a\": 1});
+print str(1.5) + str(nil) + str([true]);
+print num(\"42\") + num(\" 1.5 \");
+print num(7);
+print type(1);
+print type(\"s\");
+print type(nil);
+print type(true);
+print type([]);
+print type({});
+print type(clock);
+print type(len);
+print type(lambda () {});
+class A { m() {} }
+print type(A);
+print type(A());
+print type(A().m);
+print slice(\"héllo\", 1, 3);
+print slice([1, 2, 3, 4], 1, 4);
+print slice([1, 2, 3], 2, 2);
+print abs(-2.5);
+print floor(1.7);
+print floor(-1.2);
+print ceil(1.2);
+print round(2.5);
+print round(-2.5);
+print sqrt(16);
+print pow(2, 10);
+print min(3, -1);
+print max(3, -1);
+fun* gen() {
+    var got = yield 1;
+    yield got * 2;
+}
+var g = gen();
+print next(g);
+print send(g, 21);
+print done(g);
+print next(g);
+print done(g);
+var list = [1, 2];
+print push(list, 3);
+print list;
+print pop(list);
+print list;
+print map(list, lambda (n) { return n * 10; });
+print filter([1, 2, 3, 4], lambda (n) { return n > 2; });
+var m = {\"b\": 1, 2: nil, true: \"t\"};
+print keys(m);
+print has(m, 2);
+print has(m, \"c\");
+print remove(m, \"b\");
+print remove(m, \"b\");
+print m;
+";
+    let expected = "\
+true
+5
+2
+1
+1.5nil[true]
+43.5
+7
+Number
+String
+Nil
+Bool
+List
+Map
+NativeFunction
+NativeFunction
+Function
+Class
+Instance
+Function
+él
+[2, 3, 4]
+[]
+2.5
+1
+-2
+2
+3
+-3
+4
+1024
+-1
+3
+1
+42
+false
+nil
+true
+nil
+[1, 2, 3]
+3
+[1, 2]
+[10, 20]
+[3, 4]
+[\"b\", 2, true]
+true
+false
+1
+nil
+{2: nil, true: \"t\"}
+";
+    check_output("results", source, &[], expected);
+}
+
+/// `input` reads a line from stdin without its line ending, and nil once there are none left.
+#[test]
+fn input() {
+    let dir = TempDir::new();
+    dir.write(
+        "input.lox",
+        "print input();\nprint input();\nprint input();\n",
+    );
+    for backend in BACKENDS {
+        let output = run_stdin(
+            dir.path(),
+            &["--backend", backend, "-f", "input.lox"],
+            "first line\r\nsecond\n",
+        );
+        assert!(output.status.success(), "{}", backend);
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "first line\nsecond\nnil\n",
+            "{}",
+            backend
+        );
+    }
+}
+
+#[test]
+fn bad_arguments() {
+    let errors = [
+        (
+            "len(1);",
+            "len expects a String, List or Map as argument 1, found Number",
+        ),
+        ("num(\"x\");", "num: cannot convert \"x\" to a number"),
+        (
+            "slice(\"abc\", 2, 1);",
+            "slice: invalid range 2..1 for length 3",
+        ),
+        (
+            "abs(\"a\");",
+            "abs expects a Number as argument 1, found String",
+        ),
+        (
+            "pow(2, nil);",
+            "pow expects a Number as argument 2, found Nil",
+        ),
+        (
+            "next(1);",
+            "next expects a Generator as argument 1, found Number",
+        ),
+        (
+            "push(1, 2);",
+            "push expects a List as argument 1, found Number",
+        ),
+        ("pop([]);", "pop from empty list"),
+        (
+            "map(1, clock);",
+            "map expects a List as argument 1, found Number",
+        ),
+        ("map([1], 2);", "value 2 is not callable"),
+        ("keys([]);", "keys expects a Map as argument 1, found List"),
+        (
+            "has({}, []);",
+            "has expects a Nil, Bool, Number or String as argument 2, found List",
+        ),
+    ];
+    for (source, expected) in errors {
+        check_error("bad_arguments", source, &[], 70, expected);
+    }
+}
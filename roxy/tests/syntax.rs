@@ -0,0 +1,252 @@
+//! The lossless syntax tree: that it gives back its source exactly, that it is built whatever
+//! mistakes the source has, and that edits leave the same tree a fresh parse would.
+
+use roxy::extensions::Extensions;
+use roxy::parser;
+use roxy::scanner::{self, TokenType};
+use roxy::syntax::{Element, Error, Expected, Node, NodeKind, Reparse, Tree};
+
+use std::fs;
+use std::path::PathBuf;
+
+const PROGRAM: &str = "\
+// counts things
+var total = 0;
+
+fun add(a: num, b) {
+    var sum = a + b; // not total
+    if (sum > 10) {
+        print \"big ${sum}\";
+    }
+    return sum;
+}
+
+class Counter < Base with Mixin {
+    static make() { return Counter(); }
+    get count { return this.n; }
+    bump(n) {
+        total = add(total, n);
+    }
+}
+print [1, {\"a\": lambda(x) { return x; }}];
+";
+
+fn kinds(node: &Node) -> Vec<NodeKind> {
+    node.children()
+        .iter()
+        .filter_map(|child| match child {
+            Element::Node(node) => Some(node.kind),
+            _ => None,
+        })
+        .collect()
+}
+
+fn find(node: &Node, kind: NodeKind) -> Option<&Node> {
+    if node.kind == kind {
+        return Some(node);
+    }
+    node.children().iter().find_map(|child| match child {
+        Element::Node(node) => find(node, kind),
+        _ => None,
+    })
+}
+
+fn contains_missing(node: &Node, expected: Expected) -> bool {
+    node.children().iter().any(|child| match child {
+        Element::Node(node) => contains_missing(node, expected),
+        Element::Missing(missing) => *missing == expected,
+        Element::Token(_) => false,
+    })
+}
+
+/// Applies the edit, and checks the tree against parsing the edited text from scratch.
+fn edit(tree: &mut Tree, text: &mut String, start: usize, end: usize, with: &str) -> Reparse {
+    let reparse = tree.edit(start..end, with);
+    text.replace_range(start..end, with);
+    assert_eq!(tree.text(), *text);
+    assert!(
+        *tree == Tree::parse(text),
+        "editing {}..{} to {:?} ({:?}) left a different tree for\n{}",
+        start,
+        end,
+        with,
+        reparse,
+        text
+    );
+    reparse
+}
+
+/// The tree has mistakes in it exactly when the parser rejects the program.
+#[test]
+fn golden_programs_round_trip() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "lox") {
+            let source = fs::read_to_string(&path).unwrap();
+            let tree = Tree::parse(&source);
+            assert_eq!(tree.text(), source, "{}", path.display());
+            let extensions = Extensions {
+                lists: true,
+                lambdas: true,
+            };
+            let parses = scanner::scan_tokens(source.clone())
+                .is_ok_and(|tokens| parser::parse(extensions, tokens).is_ok());
+            assert_eq!(
+                tree.errors().is_empty(),
+                parses,
+                "{}: {:?}",
+                path.display(),
+                tree.errors()
+            );
+        }
+    }
+}
+
+#[test]
+fn trivia_is_kept_on_the_token_after_it() {
+    let tree = Tree::parse(PROGRAM);
+    assert_eq!(tree.text(), PROGRAM);
+    let var = match &tree.root().children()[0] {
+        Element::Node(node) => node,
+        other => panic!("expected a node, found {:?}", other),
+    };
+    assert_eq!(var.kind, NodeKind::VarDecl);
+    match &var.children()[0] {
+        Element::Token(token) => {
+            assert_eq!(token.kind, TokenType::Var);
+            assert_eq!(token.leading[0].text, b"// counts things");
+        }
+        other => panic!("expected a token, found {:?}", other),
+    }
+}
+
+#[test]
+fn half_typed_files_still_have_a_tree() {
+    let source = "fun f(a, b) {\n    return a +\n}\nvar x = ;\nclass A {\n    get y { @ }\n";
+    let tree = Tree::parse(source);
+    assert_eq!(tree.text(), source);
+    assert_eq!(
+        kinds(tree.root()),
+        [NodeKind::FunDecl, NodeKind::VarDecl, NodeKind::ClassDecl]
+    );
+    assert!(contains_missing(tree.root(), Expected::Expression));
+    assert!(contains_missing(
+        find(tree.root(), NodeKind::ClassBody).unwrap(),
+        Expected::Token(TokenType::RightBrace)
+    ));
+}
+
+#[test]
+fn errors_are_located_at_what_was_found() {
+    let tree = Tree::parse("print ;\n) x;\nvar s = \"open");
+    let errors = tree.errors();
+    assert_eq!(errors.len(), 5, "{:?}", errors);
+    match &errors[0] {
+        Error::Missing { expected, found } => {
+            assert_eq!(*expected, Expected::Expression);
+            assert_eq!((found.line, found.col), (1, 6));
+        }
+        other => panic!("expected a missing expression, found {:?}", other),
+    }
+    match &errors[1] {
+        Error::Unexpected(found) => assert_eq!(
+            (found.ty, found.line, found.col),
+            (TokenType::RightParen, 2, 0)
+        ),
+        other => panic!("expected an unexpected token, found {:?}", other),
+    }
+    match &errors[2] {
+        Error::Skipped {
+            what, line, col, ..
+        } => {
+            assert_eq!(what, "Unterminated string");
+            assert_eq!((*line, *col), (3, 8));
+        }
+        other => panic!("expected skipped text, found {:?}", other),
+    }
+}
+
+#[test]
+fn renaming_relexes_only_the_identifier() {
+    let mut text = PROGRAM.to_string();
+    let mut tree = Tree::parse(&text);
+    let at = text.find("sum =").unwrap();
+    assert_eq!(
+        edit(&mut tree, &mut text, at + 1, at + 3, "ubtotal"),
+        Reparse::Token
+    );
+    // a keyword, or a word that means something in a class body, is more than a rename
+    let at = text.find("get count").unwrap();
+    assert_eq!(
+        edit(&mut tree, &mut text, at + 1, at + 2, "u"),
+        Reparse::Block
+    );
+    let at = text.find("total = 0").unwrap();
+    assert_eq!(
+        edit(&mut tree, &mut text, at + 1, at + 5, "rue"),
+        Reparse::Everything
+    );
+}
+
+#[test]
+fn edits_inside_a_block_reparse_only_the_block() {
+    let mut text = PROGRAM.to_string();
+    let mut tree = Tree::parse(&text);
+    let at = text.find("return sum;").unwrap();
+    assert_eq!(
+        edit(&mut tree, &mut text, at, at, "print sum; "),
+        Reparse::Block
+    );
+    let at = text.find("bump(n)").unwrap();
+    assert_eq!(
+        edit(&mut tree, &mut text, at, at, "reset() {} "),
+        Reparse::Block
+    );
+    // a comment ends with its line, but an unmatched brace or a string can run past the block
+    let at = text.find("print sum;").unwrap();
+    assert_eq!(edit(&mut tree, &mut text, at, at, "// "), Reparse::Block);
+    assert_eq!(
+        edit(&mut tree, &mut text, at, at + 3, "{ "),
+        Reparse::Everything
+    );
+    assert_eq!(
+        edit(&mut tree, &mut text, at, at + 2, "\""),
+        Reparse::Everything
+    );
+}
+
+#[test]
+fn every_edit_leaves_the_tree_a_fresh_parse_would() {
+    let mut text = PROGRAM.to_string();
+    let mut tree = Tree::parse(&text);
+    for at in 0..PROGRAM.len() {
+        for insert in ["x", "{", "}", "\"", "//", ";", "\n"] {
+            edit(&mut tree, &mut text, at, at, insert);
+            edit(&mut tree, &mut text, at, at + insert.len(), "");
+        }
+        if at < PROGRAM.len() - 1 {
+            let removed = text[at..at + 1].to_string();
+            edit(&mut tree, &mut text, at, at + 1, "");
+            edit(&mut tree, &mut text, at, at, &removed);
+        }
+    }
+    assert_eq!(text, PROGRAM);
+}
+
+#[test]
+fn offsets_come_from_lines_and_columns() {
+    let tree = Tree::parse("var a;\nprint a;\n");
+    assert_eq!(tree.offset(1, 0), 0);
+    assert_eq!(tree.offset(2, 6), 13);
+    assert_eq!(tree.offset(2, 100), 15);
+    assert_eq!(tree.offset(9, 0), 16);
+
+    // columns count characters, not bytes
+    let tree = Tree::parse("var é = ;\n");
+    assert_eq!(tree.offset(1, 5), 6);
+    match &tree.errors()[0] {
+        Error::Missing { found, .. } => assert_eq!((found.line, found.col), (1, 8)),
+        other => panic!("expected a missing expression, found {:?}", other),
+    }
+}
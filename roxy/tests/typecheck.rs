@@ -0,0 +1,148 @@
+//! Checks what the type checker rejects, where it says so, and that code without annotations
+//! runs as dynamically as it would without the checker.
+
+mod common;
+
+use common::{check_error, check_output};
+
+/// Checks `source` is rejected before it runs with `expected`, the first lines of the error
+/// down to where it points.
+fn check_rejected(name: &str, source: &str, expected: &str) {
+    let printed = check_error(name, source, &[], 65, expected);
+    assert!(printed.iter().all(String::is_empty), "{:?}", printed);
+}
+
+#[test]
+fn initializer() {
+    check_rejected(
+        "initializer",
+        "print 1;\nvar x: num = \"one\";\n",
+        "error[E0501]: expected num, found str\n --> initializer.lox:2:14",
+    );
+}
+
+#[test]
+fn assignment() {
+    check_rejected(
+        "assignment",
+        "var x: num = 1;\nx = \"two\";\n",
+        "error[E0501]: expected num, found str\n --> assignment.lox:2:5",
+    );
+}
+
+#[test]
+fn argument() {
+    check_rejected(
+        "argument",
+        "fun f(a: num) { return a; }\nf(\"s\");\n",
+        "error[E0501]: expected num, found str\n --> argument.lox:2:3",
+    );
+    check_rejected(
+        "subclass",
+        "class A {}\nclass B < A {}\nfun f(b: B) {}\nf(B());\nf(A());\n",
+        "error[E0501]: expected B, found A\n --> subclass.lox:5:3",
+    );
+}
+
+#[test]
+fn return_value() {
+    check_rejected(
+        "return_value",
+        "fun f(): num { return \"s\"; }\n",
+        "error[E0501]: expected num, found str\n --> return_value.lox:1:23",
+    );
+}
+
+#[test]
+fn unknown_type() {
+    check_rejected(
+        "unknown_type",
+        "var x: int = 1;\n",
+        "error[E0502]: unknown type 'int'\n --> unknown_type.lox:1:8",
+    );
+    check_rejected(
+        "unknown_type_note",
+        "var x: int = 1;\n",
+        "= note: types are any, nil, bool, num, str, list, map, fun and class names",
+    );
+}
+
+#[test]
+fn bad_operand() {
+    check_rejected(
+        "bad_operand",
+        "var x: num = 1;\nvar y = x - \"s\";\n",
+        "error[E0503]: cannot apply '-' to num and str\n --> bad_operand.lox:2:11",
+    );
+}
+
+#[test]
+fn uninitialized() {
+    check_rejected(
+        "uninitialized",
+        "var y: bool;\n",
+        "error[E0504]: 'y' is declared bool but has no initializer\n \
+         --> uninitialized.lox:1:5",
+    );
+}
+
+#[test]
+fn not_callable() {
+    check_rejected(
+        "not_callable",
+        "var n: num = 1;\nn();\n",
+        "error[E0505]: cannot call a value of type num\n --> not_callable.lox:2:1",
+    );
+}
+
+#[test]
+fn wrong_arity() {
+    check_rejected(
+        "wrong_arity",
+        "fun f(a: num, b: num) { return a + b; }\nf(1);\n",
+        "error[E0506]: 'f' takes 2 arguments but 1 was given\n --> wrong_arity.lox:2:4",
+    );
+}
+
+#[test]
+fn missing_return() {
+    check_rejected(
+        "missing_return",
+        "fun f(a: num): num { if (a > 0) return a; }\n",
+        "error[E0507]: 'f' may finish without returning num\n --> missing_return.lox:1:5",
+    );
+}
+
+/// Every error in a program is reported, not just the first.
+#[test]
+fn all_reported() {
+    let source = "var a: num = \"1\";\nvar b: str = 2;\nvar c: bool = nil;\n";
+    for line in 1..=3 {
+        check_rejected(
+            "all_reported",
+            source,
+            &format!("--> all_reported.lox:{}:", line),
+        );
+    }
+}
+
+/// Unannotated values have type `any`, which fits every annotation and accepts everything,
+/// so they are only checked when they run.
+#[test]
+fn unannotated_stays_dynamic() {
+    let source = "\
+fun f(a) {
+    return a;
+}
+var y: num = f(\"s\");
+print y;
+var z = 1;
+z = \"now a string\";
+print z;
+fun g(m: map, extra: any) {
+    return m;
+}
+print g({\"k\": 1}, 2);
+";
+    check_output("unannotated", source, &[], "s\nnow a string\n{\"k\": 1}\n");
+}
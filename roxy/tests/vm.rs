@@ -0,0 +1,105 @@
+//! Runs programs through the compiler and VM and through the tree-walker, and checks both
+//! print the same thing.
+
+mod common;
+
+use common::check_output;
+
+#[test]
+fn upvalues() {
+    let source = "\
+fun outer() {
+    var x = \"before\";
+    fun get() {
+        return x;
+    }
+    fun set(value) {
+        x = value;
+    }
+    print get();
+    set(\"after\");
+    print x;
+    return get;
+}
+var get = outer();
+print get();
+
+fun nested() {
+    var a = 1;
+    fun middle() {
+        fun inner() {
+            a = a + 1;
+            return a;
+        }
+        return inner;
+    }
+    return middle();
+}
+var inner = nested();
+inner();
+print inner();
+";
+    check_output("upvalues", source, &[], "before\nafter\nafter\n3\n");
+}
+
+/// Each iteration of a loop gets its own variables, and closures made in it keep the ones of
+/// the iteration that made them once the loop has moved on.
+#[test]
+fn closures_in_loops() {
+    let source = "\
+var fns = [];
+for (var i = 0; i < 3; i = i + 1) {
+    var j = i * 10;
+    fns = fns + [lambda () { return j; }];
+}
+for (f in fns) print f();
+
+var counters = [];
+var n = 0;
+while (n < 2) {
+    var count = n;
+    fun bump() {
+        count = count + 1;
+        return count;
+    }
+    counters = counters + [bump];
+    n = n + 1;
+}
+counters[0]();
+print counters[0]();
+print counters[1]();
+
+var seen = [];
+for (item in [\"a\", \"b\"]) {
+    seen = seen + [lambda () { return item; }];
+}
+print seen[0]() + seen[1]();
+";
+    check_output("closures_in_loops", source, &[], "0\n10\n20\n2\n2\nab\n");
+}
+
+/// String constants, globals and fields read and written in a loop, the way the VM's
+/// interned strings and name lookups get the most use.
+#[test]
+fn strings_and_globals() {
+    let source = "\
+class Box {}
+var box = Box();
+box.text = \"\";
+var total = 0;
+for (var i = 0; i < 5; i = i + 1) {
+    var s = \"ab\";
+    box.text = box.text + s;
+    total = total + 1;
+}
+print box.text;
+print total;
+print \"ab\" == \"a\" + \"b\";
+";
+    check_output(
+        "strings_and_globals",
+        source,
+        &["--gc-stress"],
+        "ababababab\n5\ntrue\n",
+    );
+}